@@ -1,77 +1,433 @@
 use anyhow::Result;
-use config::{Config, ConfigError, Environment, File};
-use serde::Deserialize;
-use std::path::PathBuf;
+use config::{Config, ConfigError, Environment, File, Source, Value, ValueKind};
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::watch;
 
-#[derive(Debug, Deserialize)]
+use crate::config_watch;
+use crate::secrets::{self, SecretStore};
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct BaseConfig {
     pub vehicle_id: String,
     pub mqtt_host: String,
     pub mqtt_port: u16,
     pub health_report_interval: u64,
     pub aws: AwsConfig,
+    #[serde(default)]
+    pub remote: RemoteConfig,
+    #[serde(default)]
+    pub provisioning: ProvisioningConfig,
+    #[serde(default)]
+    pub certificate: CertificateConfig,
+    #[serde(default)]
+    pub secrets: SecretsConfig,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
     // pub iot: IotConfig,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AwsConfig {
     pub region: String,
     pub iot: AwsIotConfig,
     pub lambda: AwsLambdaConfig,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AwsIotConfig {
     pub root_ca_path: String,
     pub endpoint: String,
     pub port: u16,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AwsLambdaConfig {
     pub register: String,
 }
 
-#[derive(Debug, Deserialize)]
+/// Cloud broker the remote link connects to, selected by `backend`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum RemoteConfig {
+    /// AWS IoT Core, using the `[aws]` settings.
+    #[default]
+    Aws,
+    /// Any MQTT broker, e.g. a self-hosted Mosquitto or EMQX.
+    Mqtt(MqttBrokerConfig),
+}
+
+/// How the device gets its certificate, selected by `channel`. The key pair
+/// is always generated on the device and only a CSR is sent.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "channel", rename_all = "lowercase")]
+pub enum ProvisioningConfig {
+    /// The registration Lambda in `[aws.lambda]`.
+    #[default]
+    Lambda,
+    /// AWS IoT fleet provisioning by claim.
+    Fleet(FleetProvisioningConfig),
+    /// A signing endpoint of our own.
+    Http(HttpProvisioningConfig),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FleetProvisioningConfig {
+    /// Name of the provisioning template.
+    pub template: String,
+    /// Claim certificate and key shared by the fleet.
+    pub claim_cert_path: String,
+    pub claim_key_path: String,
+    /// Seconds to wait for the whole exchange.
+    #[serde(default = "default_provisioning_timeout")]
+    pub timeout: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpProvisioningConfig {
+    pub url: String,
+    /// Sent as a bearer token.
+    pub token: Option<String>,
+    /// Where replaced certificates are revoked, if supported.
+    pub revoke_url: Option<String>,
+}
+
+/// Encrypted store for `secret:<name>` config values and the device key.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecretsConfig {
+    /// Store file, `secrets.enc` in the config dir by default.
+    pub path: Option<String>,
+    /// File holding the passphrase. Without it the key is derived from the
+    /// machine id.
+    pub passphrase_file: Option<String>,
+}
+
+/// Expiry checks and rotation of the device certificate.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CertificateConfig {
+    pub enable: bool,
+    /// Rotate this many days before the certificate expires.
+    pub rotate_before_days: i64,
+    /// Seconds between expiry checks.
+    pub check_interval: u64,
+    /// Seconds to wait for the link to connect with a new certificate.
+    pub connect_timeout: u64,
+    /// Seconds before a replaced certificate is revoked. Services that do
+    /// not rotate pick up the new one on their next check, so keep this
+    /// above their `check_interval`.
+    pub revoke_delay: u64,
+}
+
+impl Default for CertificateConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            rotate_before_days: 30,
+            check_interval: 3600,
+            connect_timeout: 30,
+            revoke_delay: 7200,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    pub shipping: LogShippingConfig,
+    pub otlp: OtlpConfig,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable text.
+    #[default]
+    Pretty,
+    /// One JSON object per line, with `service` and `vehicle_id`.
+    Json,
+}
+
+/// Shipping of log events to `{vehicle_id}/logs` over the remote link.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogShippingConfig {
+    pub enable: bool,
+    /// Filter of the events shipped, e.g. `warn` or `warn,luffy_media=info`.
+    pub level: String,
+    /// Most events per message.
+    pub batch_size: usize,
+    /// Seconds between messages.
+    pub interval: u64,
+    /// Events kept while offline; beyond it the oldest are dropped.
+    pub buffer_size: usize,
+    /// Most events shipped per minute; the rest wait in the buffer.
+    pub max_per_minute: usize,
+}
+
+impl Default for LogShippingConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            level: "warn".to_string(),
+            batch_size: 50,
+            interval: 10,
+            buffer_size: 1000,
+            max_per_minute: 120,
+        }
+    }
+}
+
+/// Export of spans to an OpenTelemetry collector over OTLP/HTTP.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OtlpConfig {
+    pub enable: bool,
+    /// The collector's traces endpoint.
+    pub endpoint: String,
+    /// Share of new traces recorded, 0 to 1. Traces started by another
+    /// service follow its decision.
+    pub sample_ratio: f64,
+    /// Seconds to wait for the collector.
+    pub timeout: u64,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            endpoint: "http://127.0.0.1:4318/v1/traces".to_string(),
+            sample_ratio: 1.0,
+            timeout: 10,
+        }
+    }
+}
+
+/// The Prometheus endpoint of a service, `http://{host}:{port}/metrics`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enable: bool,
+    pub host: String,
+    /// Each service has its own, see [`crate::metrics::default_port`].
+    pub port: Option<u16>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            host: "127.0.0.1".to_string(),
+            port: None,
+        }
+    }
+}
+
+fn default_provisioning_timeout() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MqttProtocol {
+    #[default]
+    V4,
+    V5,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MqttBrokerConfig {
+    pub host: String,
+    pub port: u16,
+    #[serde(default)]
+    pub protocol: MqttProtocol,
+    #[serde(default = "default_keep_alive")]
+    pub keep_alive: u64,
+    /// Connect over TLS. Without `ca_path` the system roots are trusted.
+    #[serde(default)]
+    pub tls: bool,
+    pub ca_path: Option<String>,
+    /// Client certificate and key for mTLS.
+    pub client_cert_path: Option<String>,
+    pub client_key_path: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+fn default_keep_alive() -> u64 {
+    30
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct IotConfig {
     pub local_interval: u64,
     pub remote_interval: u64,
 }
 
 pub trait LoadConfig {
+    /// Load `{service_name}.toml` over `base.toml`, with `LUFFY_*`
+    /// environment overrides and `secret:` references resolved.
     fn load_config(service_name: &str) -> Result<Self, ConfigError>
     where
         Self: Sized + serde::de::DeserializeOwned,
     {
-        // Try development path first, relative to project root
-        let dev_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .parent()
-            .unwrap_or(PathBuf::from(".").as_path())
-            .join("luffy-deploy/config/development");
-        let prod_path = PathBuf::from("/etc/luffy");
-
-        let config_dir = if dev_path.join(format!("{}.toml", service_name)).exists() {
-            dev_path
-        } else if prod_path.join(format!("{}.toml", service_name)).exists() {
-            prod_path
-        } else {
-            return Err(ConfigError::NotFound(format!(
-                "Config file not found in {:?}",
-                prod_path.join(format!("{}.toml", service_name))
-            )));
+        resolve_secrets(build_config(service_name)?)?.try_deserialize()
+    }
+
+    /// [`Self::load_config`], reloaded whenever `base.toml` or
+    /// `{service_name}.toml` changes, see [`config_watch::watch_files`].
+    fn watch_config(service_name: &str) -> Result<watch::Receiver<Arc<Self>>>
+    where
+        Self: Sized + serde::de::DeserializeOwned + Send + Sync + 'static,
+    {
+        let dir = config_dir();
+        let files = vec![
+            dir.join("base.toml"),
+            dir.join(format!("{}.toml", service_name)),
+        ];
+        let service_name = service_name.to_string();
+        config_watch::watch_files(files, move || Ok(Self::load_config(&service_name)?))
+    }
+
+    /// The config of a service, watched with [`Self::watch_config`]. When the
+    /// files cannot be watched the config is loaded once and never changes.
+    fn service_config(service_name: &str) -> Result<ServiceConfig<Self>, ConfigError>
+    where
+        Self: Sized + serde::de::DeserializeOwned + Send + Sync + 'static,
+    {
+        let live = match Self::watch_config(service_name) {
+            Ok(live) => live,
+            Err(e) => {
+                let config = Self::load_config(service_name)?;
+                // Logging is not set up yet.
+                eprintln!(
+                    "Not following changes to the {} config: {:#}",
+                    service_name, e
+                );
+                watch::channel(Arc::new(config)).1
+            }
         };
+        let initial = live.borrow().clone();
+        Ok(ServiceConfig { initial, live })
+    }
+}
+
+/// A service's config as loaded at startup, which is what it derefs to,
+/// and as it changes on disk for settings applied without a restart.
+pub struct ServiceConfig<T> {
+    initial: Arc<T>,
+    live: watch::Receiver<Arc<T>>,
+}
+
+impl<T> ServiceConfig<T> {
+    /// Every version that loads from now on, see [`config_watch::follow`].
+    pub fn watch(&self) -> watch::Receiver<Arc<T>> {
+        self.live.clone()
+    }
+
+    /// The latest version that loaded.
+    pub fn current(&self) -> Arc<T> {
+        self.live.borrow().clone()
+    }
+}
+
+impl<T> Deref for ServiceConfig<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.initial
+    }
+}
+
+/// The layered config of `service_name`, without resolving secrets.
+pub fn build_config(service_name: &str) -> Result<Config, ConfigError> {
+    let config_dir = config_dir();
+    layered_config(
+        &config_dir,
+        File::from(config_dir.join(format!("{}.toml", service_name))),
+    )
+}
+
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use `dir` for every config loaded from now on, e.g. from `--config-dir`.
+/// Takes precedence over `LUFFY_CONFIG_DIR`; only the first call counts.
+pub fn set_config_dir(dir: PathBuf) {
+    let _ = CONFIG_DIR.set(dir);
+}
+
+/// The directory holding the config files and the device credentials: the
+/// one set with [`set_config_dir`] or `LUFFY_CONFIG_DIR`, else `/etc/luffy`.
+pub fn config_dir() -> PathBuf {
+    CONFIG_DIR
+        .get()
+        .cloned()
+        .or_else(|| std::env::var_os("LUFFY_CONFIG_DIR").map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("/etc/luffy"))
+}
+
+/// `service` over `base.toml` in `config_dir`, with environment overrides.
+pub fn layered_config<S>(config_dir: &Path, service: S) -> Result<Config, ConfigError>
+where
+    S: Source + Send + Sync + 'static,
+{
+    Config::builder()
+        // Base config first (if it exists)
+        .add_source(File::from(config_dir.join("base.toml")).required(false))
+        // Service-specific config (required)
+        .add_source(service)
+        // Environment variables override
+        .add_source(Environment::with_prefix("LUFFY"))
+        .build()
+}
+
+/// Replace `secret:<name>` values with the secret from the store. The store
+/// is only opened when a value refers to it.
+pub fn resolve_secrets(config: Config) -> Result<Config, ConfigError> {
+    let mut references = Vec::new();
+    for (key, value) in config.collect()? {
+        find_references(key, value, &mut references);
+    }
+    if references.is_empty() {
+        return Ok(config);
+    }
+
+    let secrets_config: SecretsConfig = config.get("secrets").unwrap_or_default();
+    let store = SecretStore::from_config(&secrets_config)
+        .map_err(|e| ConfigError::Message(format!("Failed to open secret store: {:#}", e)))?;
+    let mut builder = Config::builder().add_source(config);
+    for (path, name) in references {
+        let secret = store.get(&name).ok_or_else(|| {
+            ConfigError::Message(format!("Secret {} referenced by {} not found", name, path))
+        })?;
+        builder = builder.set_override(path, secret)?;
+    }
+    builder.build()
+}
 
-        let config = Config::builder()
-            // Base config first (if it exists)
-            .add_source(File::from(config_dir.join("base.toml")).required(false))
-            // Service-specific config (required)
-            .add_source(File::from(
-                config_dir.join(format!("{}.toml", service_name)),
-            ))
-            // Environment variables override
-            .add_source(Environment::with_prefix("LUFFY"))
-            .build()?;
-
-        config.try_deserialize()
+fn find_references(path: String, value: Value, references: &mut Vec<(String, String)>) {
+    match value.kind {
+        ValueKind::String(value) => {
+            if let Some(name) = secrets::reference(&value) {
+                references.push((path, name.to_string()));
+            }
+        }
+        ValueKind::Table(table) => {
+            for (key, value) in table {
+                find_references(format!("{}.{}", path, key), value, references);
+            }
+        }
+        ValueKind::Array(values) => {
+            for (index, value) in values.into_iter().enumerate() {
+                find_references(format!("{}[{}]", path, index), value, references);
+            }
+        }
+        _ => {}
     }
 }
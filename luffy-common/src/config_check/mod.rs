@@ -0,0 +1,326 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::{bail, Result};
+use config::{ConfigError, File};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self as layers, resolve_secrets};
+use crate::config_update::redact;
+
+/// Outcome of checking the config files of a service.
+#[derive(Debug, Default)]
+pub struct ConfigReport {
+    pub files: Vec<PathBuf>,
+    /// Syntax errors, type errors and missing secrets; the service would not
+    /// start.
+    pub errors: Vec<String>,
+    /// Keys the service does not know, usually typos. They are ignored when
+    /// loading.
+    pub unknown_keys: Vec<String>,
+    /// The merged config with defaults filled in and secrets redacted.
+    pub effective: Option<Value>,
+}
+
+impl ConfigReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Check the files `LoadConfig` would load for `service` as `T`.
+pub fn check<T: DeserializeOwned + Serialize>(service: &str) -> ConfigReport {
+    check_dir::<T>(&layers::config_dir(), service)
+}
+
+/// Check `base.toml` and `{service}.toml` in `dir` as `T`.
+pub fn check_dir<T: DeserializeOwned + Serialize>(dir: &Path, service: &str) -> ConfigReport {
+    let service_file = dir.join(format!("{}.toml", service));
+    let mut report = ConfigReport::default();
+    let mut sources = Vec::new();
+    for file in [dir.join("base.toml"), service_file.clone()] {
+        if !file.exists() && file != service_file {
+            continue;
+        }
+        match fs::read_to_string(&file) {
+            Ok(contents) => match contents.parse::<toml::Table>() {
+                Ok(_) => sources.push((file.clone(), contents)),
+                Err(e) => {
+                    report
+                        .errors
+                        .push(format!("{}: {}", file.display(), e.to_string().trim_end()))
+                }
+            },
+            Err(e) => report.errors.push(format!("{}: {}", file.display(), e)),
+        }
+        report.files.push(file);
+    }
+    if !report.is_ok() {
+        return report;
+    }
+
+    let config = match layers::layered_config(dir, File::from(service_file)) {
+        Ok(config) => config,
+        Err(e) => {
+            report.errors.push(e.to_string());
+            return report;
+        }
+    };
+    let mut ignored = Vec::new();
+    let parsed: T =
+        match serde_ignored::deserialize(config.clone(), |path| ignored.push(key_path(&path))) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                report.errors.push(locate_error(&e, &sources));
+                return report;
+            }
+        };
+    if let Err(e) = resolve_secrets(config).and_then(|config| config.try_deserialize::<T>()) {
+        report.errors.push(e.to_string());
+    }
+    let mut effective = match serde_json::to_value(&parsed) {
+        Ok(effective) => effective,
+        Err(e) => {
+            report.errors.push(e.to_string());
+            return report;
+        }
+    };
+
+    let keys: Vec<(&PathBuf, Vec<(String, usize)>)> = sources
+        .iter()
+        .map(|(file, contents)| {
+            let keys = toml_keys(contents)
+                .into_iter()
+                .map(|(path, line)| (strip_indices(&path), line))
+                .collect();
+            (file, keys)
+        })
+        .collect();
+    // In file order, keys set only through the environment last.
+    let mut unknown: Vec<_> = ignored
+        .into_iter()
+        .map(|path| {
+            let located = keys.iter().enumerate().find_map(|(index, (file, keys))| {
+                keys.iter()
+                    .find(|(key, _)| *key == path)
+                    .map(|(_, line)| (index, *line, *file))
+            });
+            (located, path)
+        })
+        .collect();
+    unknown.sort_by_key(|(located, _)| {
+        located.map_or((usize::MAX, 0), |(index, line, _)| (index, line))
+    });
+    for (located, path) in unknown {
+        report.unknown_keys.push(match located {
+            Some((_, line, file)) => format!("{}:{}: unknown key `{}`", file.display(), line, path),
+            None => format!("unknown key `{}`", path),
+        });
+    }
+
+    redact(&mut effective);
+    report.effective = Some(effective);
+    report
+}
+
+/// `error`, prefixed with the file and line of the offending key when known.
+fn locate_error(error: &ConfigError, sources: &[(PathBuf, String)]) -> String {
+    if let ConfigError::Type {
+        origin: Some(origin),
+        key: Some(key),
+        ..
+    } = error
+    {
+        // The origin is relative to the working directory; the file names
+        // are distinct.
+        let origin = Path::new(origin).file_name();
+        let located = sources
+            .iter()
+            .find(|(file, _)| file.file_name() == origin)
+            .and_then(|(file, contents)| {
+                toml_keys(contents)
+                    .into_iter()
+                    .find(|(path, _)| path == key)
+                    .map(|(_, line)| (file, line))
+            });
+        if let Some((file, line)) = located {
+            return format!("{}:{}: {}", file.display(), line, error);
+        }
+    }
+    error.to_string()
+}
+
+/// The dotted key of a path reported by `serde_ignored`, without array
+/// indices, e.g. `cameras.name`.
+fn key_path(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => String::new(),
+        Path::Map { parent, key } => match key_path(parent) {
+            parent if parent.is_empty() => key.clone(),
+            parent => format!("{}.{}", parent, key),
+        },
+        Path::Seq { parent, .. }
+        | Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => key_path(parent),
+    }
+}
+
+/// The table headers and keys of a TOML file with their line numbers, e.g.
+/// `("cameras[1].url", 12)`. Only looks at the start of each line, which is
+/// enough for the flat files we ship.
+pub fn toml_keys(contents: &str) -> Vec<(String, usize)> {
+    let mut keys = Vec::new();
+    let mut table = String::new();
+    let mut arrays: HashMap<String, usize> = HashMap::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if let Some(name) = line
+            .strip_prefix("[[")
+            .and_then(|rest| rest.split_once("]]"))
+            .map(|(name, _)| name.trim())
+        {
+            let count = arrays.entry(name.to_string()).or_insert(0);
+            table = format!("{}[{}]", name, count);
+            *count += 1;
+            keys.push((table.clone(), index + 1));
+        } else if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|rest| rest.split_once(']'))
+            .map(|(name, _)| name.trim())
+        {
+            table = name.to_string();
+            keys.push((table.clone(), index + 1));
+        } else if let Some((key, _)) = line.split_once('=') {
+            let key = key.trim();
+            let key = match key.strip_prefix('"') {
+                Some(quoted) => quoted.strip_suffix('"').unwrap_or_default(),
+                None => key,
+            };
+            let valid = !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+            if !valid {
+                continue;
+            }
+            let path = if table.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", table, key)
+            };
+            keys.push((path, index + 1));
+        }
+    }
+    keys
+}
+
+fn strip_indices(path: &str) -> String {
+    let mut stripped = String::with_capacity(path.len());
+    let mut in_index = false;
+    for c in path.chars() {
+        match c {
+            '[' => in_index = true,
+            ']' => in_index = false,
+            c if !in_index => stripped.push(c),
+            _ => {}
+        }
+    }
+    stripped
+}
+
+/// Command line of the service binaries.
+#[derive(Debug, Default, PartialEq)]
+pub struct ServiceArgs {
+    pub config_dir: Option<PathBuf>,
+    pub check_config: bool,
+    pub help: bool,
+}
+
+impl ServiceArgs {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "check-config" => parsed.check_config = true,
+                "-h" | "--help" => parsed.help = true,
+                "--config-dir" => match args.next() {
+                    Some(dir) => parsed.config_dir = Some(PathBuf::from(dir)),
+                    None => bail!("--config-dir needs a directory"),
+                },
+                _ => match arg.strip_prefix("--config-dir=") {
+                    Some(dir) => parsed.config_dir = Some(PathBuf::from(dir)),
+                    None => bail!("Unknown argument {}", arg),
+                },
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Handle the command line of `luffy-{service}` before any config is loaded:
+/// apply `--config-dir` and run `check-config`, which exits.
+pub fn handle_args<T: DeserializeOwned + Serialize>(service: &str) {
+    let usage = format!(
+        "Usage: luffy-{} [--config-dir DIR] [check-config]\n\n\
+         The config dir defaults to LUFFY_CONFIG_DIR or /etc/luffy.\n\
+         check-config validates the config files and prints the effective config.",
+        service
+    );
+    let args = match ServiceArgs::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, usage);
+            std::process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", usage);
+        std::process::exit(0);
+    }
+    if let Some(dir) = args.config_dir {
+        layers::set_config_dir(dir);
+    }
+    if args.check_config {
+        std::process::exit(run::<T>(service));
+    }
+}
+
+/// Print the report of [`check`]; returns the exit code.
+pub fn run<T: DeserializeOwned + Serialize>(service: &str) -> i32 {
+    let report = check::<T>(service);
+    let files: Vec<String> = report
+        .files
+        .iter()
+        .map(|file| file.display().to_string())
+        .collect();
+    eprintln!("Checking {}", files.join(", "));
+    for key in &report.unknown_keys {
+        eprintln!("warning: {}", key);
+    }
+    for error in &report.errors {
+        eprintln!("error: {}", error);
+    }
+    if let Some(effective) = &report.effective {
+        // Usually piped, e.g. into `head` or `jq`.
+        let _ = writeln!(
+            std::io::stdout(),
+            "{}",
+            serde_json::to_string_pretty(effective).unwrap_or_default()
+        );
+    }
+    if report.is_ok() {
+        eprintln!("{} config OK", service);
+        0
+    } else {
+        1
+    }
+}
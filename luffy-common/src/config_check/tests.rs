@@ -0,0 +1,133 @@
+use super::*;
+use serde::Deserialize;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TestConfig {
+    vehicle_id: String,
+    #[serde(default)]
+    password: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    web: TestWeb,
+    #[serde(default)]
+    cameras: Vec<TestCamera>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TestWeb {
+    port: u16,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TestCamera {
+    id: String,
+}
+
+fn config_dir(service: &str) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("base.toml"), "vehicle_id = \"vessel-1\"\n").unwrap();
+    fs::write(dir.path().join("test.toml"), service).unwrap();
+    dir
+}
+
+#[test]
+fn test_toml_keys() {
+    let keys = toml_keys(
+        "# comment\nlevel = \"info\"\n\n[web]\nport = 80 # = 81\n\n[[cameras]]\nid = \"a\"\n[[cameras]]\nid = \"b\"\nurls = [\n  \"x=1\",\n]\n",
+    );
+    let keys: Vec<(&str, usize)> = keys.iter().map(|(k, l)| (k.as_str(), *l)).collect();
+    assert_eq!(
+        keys,
+        vec![
+            ("level", 2),
+            ("web", 4),
+            ("web.port", 5),
+            ("cameras[0]", 7),
+            ("cameras[0].id", 8),
+            ("cameras[1]", 9),
+            ("cameras[1].id", 10),
+            ("cameras[1].urls", 11),
+        ]
+    );
+}
+
+#[test]
+fn test_valid_config() {
+    let dir =
+        config_dir("password = \"hunter2\"\n\n[web]\nport = 80\n\n[[cameras]]\nid = \"front\"\n");
+    // Known even though the effective config leaves it out.
+    fs::write(
+        dir.path().join("base.toml"),
+        "vehicle_id = \"vessel-1\"\ntags = []\n",
+    )
+    .unwrap();
+    let report = check_dir::<TestConfig>(dir.path(), "test");
+    assert!(report.is_ok(), "{:?}", report.errors);
+    assert!(report.unknown_keys.is_empty(), "{:?}", report.unknown_keys);
+    assert_eq!(report.files.len(), 2);
+    let effective = report.effective.unwrap();
+    assert_eq!(effective["vehicle_id"], "vessel-1");
+    assert_eq!(effective["password"], "***");
+    assert_eq!(effective["cameras"][0]["id"], "front");
+}
+
+#[test]
+fn test_unknown_keys() {
+    let dir = config_dir("[web]\nport = 80\nprot = 81\n\n[extra]\na = 1\nb = 2\n\n[[cameras]]\nid = \"a\"\nname = \"x\"\n");
+    let report = check_dir::<TestConfig>(dir.path(), "test");
+    assert!(report.is_ok(), "{:?}", report.errors);
+    let file = dir.path().join("test.toml");
+    assert_eq!(
+        report.unknown_keys,
+        vec![
+            format!("{}:3: unknown key `web.prot`", file.display()),
+            format!("{}:5: unknown key `extra`", file.display()),
+            format!("{}:11: unknown key `cameras.name`", file.display()),
+        ]
+    );
+}
+
+#[test]
+fn test_type_error_has_line() {
+    let dir = config_dir("\n[web]\nport = \"eighty\"\n");
+    let report = check_dir::<TestConfig>(dir.path(), "test");
+    assert_eq!(report.errors.len(), 1);
+    let prefix = format!("{}:3: ", dir.path().join("test.toml").display());
+    assert!(
+        report.errors[0].starts_with(&prefix),
+        "{}",
+        report.errors[0]
+    );
+    assert!(report.effective.is_none());
+}
+
+#[test]
+fn test_syntax_error() {
+    let dir = config_dir("[web\nport = 80\n");
+    let report = check_dir::<TestConfig>(dir.path(), "test");
+    assert_eq!(report.errors.len(), 1);
+    assert!(
+        report.errors[0].contains("test.toml"),
+        "{}",
+        report.errors[0]
+    );
+
+    let report = check_dir::<TestConfig>(dir.path(), "missing");
+    assert!(!report.is_ok());
+}
+
+#[test]
+fn test_parse_args() {
+    let args = |args: &[&str]| ServiceArgs::parse(args.iter().map(|arg| arg.to_string()));
+    assert_eq!(args(&[]).unwrap(), ServiceArgs::default());
+    let parsed = args(&["--config-dir", "/tmp/luffy", "check-config"]).unwrap();
+    assert_eq!(parsed.config_dir, Some(PathBuf::from("/tmp/luffy")));
+    assert!(parsed.check_config);
+    assert_eq!(
+        args(&["--config-dir=/etc/x"]).unwrap().config_dir,
+        Some(PathBuf::from("/etc/x"))
+    );
+    assert!(args(&["--help"]).unwrap().help);
+    assert!(args(&["--config-dir"]).is_err());
+    assert!(args(&["--verbose"]).is_err());
+}
@@ -0,0 +1,189 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::Result;
+use notify::{RecursiveMode, Watcher};
+use similar::{ChangeTag, TextDiff};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::{config_update, secrets};
+
+/// Saving a file is often several events (truncate, write, rename); reload
+/// once they stop.
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// What makes a watcher thread look at its files.
+enum Trigger {
+    /// Paths changed on disk, not necessarily watched ones.
+    Changed(Vec<PathBuf>),
+    /// [`reload`]: load again even when the files are unchanged.
+    Reload,
+}
+
+/// The watcher threads, for [`reload`].
+static WATCHERS: Mutex<Vec<mpsc::Sender<Trigger>>> = Mutex::new(Vec::new());
+
+/// Load every watched config again now, e.g. on SIGHUP. Also picks up
+/// changed secrets, which do not touch the config files.
+pub fn reload() {
+    WATCHERS
+        .lock()
+        .unwrap()
+        .retain(|watcher| watcher.send(Trigger::Reload).is_ok());
+}
+
+/// Load with `load` now and again whenever one of `files` changes,
+/// publishing every version that loads. A version that fails to load is
+/// logged with its diff to the last good one and the current value is kept.
+///
+/// The files are watched on a thread of their own until every receiver is
+/// dropped, so this works outside a runtime, e.g. from a `LazyLock`.
+pub fn watch_files<T, F>(files: Vec<PathBuf>, load: F) -> Result<watch::Receiver<Arc<T>>>
+where
+    T: Send + Sync + 'static,
+    F: Fn() -> Result<T> + Send + 'static,
+{
+    // Read before loading, so a change in between is reloaded.
+    let mut contents = read_all(&files);
+    let (tx, rx) = watch::channel(Arc::new(load()?));
+    let (events_tx, events) = mpsc::channel();
+    WATCHERS.lock().unwrap().push(events_tx.clone());
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            let _ = events_tx.send(Trigger::Changed(event.paths));
+        }
+    })?;
+    // Editors replace files rather than write them, so watch the directories.
+    let mut dirs: Vec<&Path> = files.iter().filter_map(|file| file.parent()).collect();
+    dirs.sort();
+    dirs.dedup();
+    for dir in dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+
+    std::thread::Builder::new()
+        .name("config-watch".to_string())
+        .spawn(move || {
+            let _watcher = watcher;
+            while let Ok(trigger) = events.recv() {
+                let mut forced = match trigger {
+                    Trigger::Changed(paths) => {
+                        if !paths.iter().any(|path| is_watched(&files, path)) {
+                            continue;
+                        }
+                        false
+                    }
+                    Trigger::Reload => true,
+                };
+                while let Ok(trigger) = events.recv_timeout(SETTLE) {
+                    forced |= matches!(trigger, Trigger::Reload);
+                }
+                if tx.is_closed() {
+                    break;
+                }
+                let changed = read_all(&files);
+                if changed == contents && !forced {
+                    continue;
+                }
+                match load() {
+                    Ok(config) => {
+                        info!("Reloaded config from {:?}", files);
+                        tx.send_replace(Arc::new(config));
+                        contents = changed;
+                    }
+                    Err(e) => warn!(
+                        "Rejected config change: {:#}\n{}",
+                        e,
+                        diff_all(&files, &contents, &changed)
+                    ),
+                }
+            }
+            debug!("Stopped watching {:?}", files);
+        })?;
+    Ok(rx)
+}
+
+/// Call `on_change` with the previous and the new version after every
+/// reload, for as long as the config is watched.
+pub async fn follow<T, F, Fut>(mut config: watch::Receiver<Arc<T>>, mut on_change: F)
+where
+    F: FnMut(Arc<T>, Arc<T>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut current = config.borrow_and_update().clone();
+    while config.changed().await.is_ok() {
+        let new = config.borrow_and_update().clone();
+        on_change(current, new.clone()).await;
+        current = new;
+    }
+}
+
+fn is_watched(files: &[PathBuf], path: &Path) -> bool {
+    files
+        .iter()
+        .any(|file| file.file_name() == path.file_name())
+}
+
+/// The contents of each file, empty when it is missing.
+fn read_all(files: &[PathBuf]) -> Vec<String> {
+    files
+        .iter()
+        .map(|file| std::fs::read_to_string(file).unwrap_or_default())
+        .collect()
+}
+
+fn diff_all(files: &[PathBuf], old: &[String], new: &[String]) -> String {
+    files
+        .iter()
+        .zip(old.iter().zip(new))
+        .filter(|(_, (old, new))| old != new)
+        .map(|(file, (old, new))| format!("--- {}\n{}", file.display(), diff(old, new)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The changed lines between `old` and `new`, prefixed with `-` and `+`.
+/// Values of sensitive keys, inline tables and URL passwords are replaced
+/// with `***`, so the diff can be logged.
+pub fn diff(old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .iter_all_changes()
+        .filter_map(|change| {
+            let sign = match change.tag() {
+                ChangeTag::Delete => '-',
+                ChangeTag::Insert => '+',
+                ChangeTag::Equal => return None,
+            };
+            Some(format!(
+                "{}{}",
+                sign,
+                redact_line(change.value().trim_end_matches('\n'))
+            ))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A `key = value` line of TOML with the value hidden if it may hold a
+/// secret.
+fn redact_line(line: &str) -> String {
+    let Some((key, value)) = line.split_once('=') else {
+        return line.to_string();
+    };
+    let text = value.trim().trim_matches(|c| c == '"' || c == '\'');
+    let name = key.trim().trim_matches('"').to_lowercase();
+    if value.contains('{')
+        || (config_update::is_sensitive(&name) && secrets::reference(text).is_none())
+    {
+        return format!("{}= ***", key);
+    }
+    match config_update::redact_url(text) {
+        Some(text) => format!("{}= \"{}\"", key, text),
+        None => line.to_string(),
+    }
+}
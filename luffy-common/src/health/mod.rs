@@ -0,0 +1,241 @@
+#[cfg(test)]
+mod tests;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+
+/// Service-specific health fields, e.g. `mavlink_rate` or `webrtc_peers`.
+pub type HealthFields = BTreeMap<String, Value>;
+
+/// Payload of `luffy/{name}/health`. Everything but `version` is optional on
+/// the wire so reports from older services still parse.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub version: String,
+    #[serde(default)]
+    pub pid: u32,
+    #[serde(default)]
+    pub uptime_secs: u64,
+    /// Resident set size.
+    #[serde(default)]
+    pub memory_bytes: u64,
+    #[serde(default)]
+    pub cpu_percent: f32,
+    /// Whether the service's MQTT connection is up.
+    #[serde(default)]
+    pub connected: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Set when the service cannot recover by itself and wants a restart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fatal_error: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: HealthFields,
+}
+
+/// Adds service-specific fields to every health report.
+///
+/// Implemented for async closures returning [`HealthFields`].
+#[async_trait]
+pub trait HealthContributor: Send + Sync {
+    async fn fields(&self) -> HealthFields;
+
+    /// Whether the service is making progress. Gates the systemd watchdog
+    /// while the service runs.
+    fn is_alive(&self) -> bool {
+        true
+    }
+}
+
+#[async_trait]
+impl<F, Fut> HealthContributor for F
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = HealthFields> + Send,
+{
+    async fn fields(&self) -> HealthFields {
+        self().await
+    }
+}
+
+/// Whether a loop makes progress. The loop calls [`Self::step`] each time
+/// round, which must come round again in time, and [`Self::idle`] before
+/// waiting for input that may take any time. Cheap to clone; clones share
+/// the same state.
+#[derive(Debug, Clone, Default)]
+pub struct Liveness {
+    deadline: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Liveness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The loop is working and comes round again `within` this long.
+    pub fn step(&self, within: Duration) {
+        *self.deadline.lock().unwrap() = Some(Instant::now() + within);
+    }
+
+    /// The loop waits for input, or has stopped.
+    pub fn idle(&self) {
+        *self.deadline.lock().unwrap() = None;
+    }
+
+    /// False once a step overran its deadline.
+    pub fn is_alive(&self) -> bool {
+        self.deadline
+            .lock()
+            .unwrap()
+            .is_none_or(|deadline| Instant::now() <= deadline)
+    }
+}
+
+/// The probes of a service's loops, alive while all of them are.
+#[async_trait]
+impl HealthContributor for Vec<Liveness> {
+    async fn fields(&self) -> HealthFields {
+        HealthFields::new()
+    }
+
+    fn is_alive(&self) -> bool {
+        self.iter().all(Liveness::is_alive)
+    }
+}
+
+/// Collects the state that goes into a service's health report. Cheap to
+/// clone; clones share the same state.
+#[derive(Clone)]
+pub struct HealthReporter {
+    version: String,
+    connected: Arc<AtomicBool>,
+    last_error: Arc<Mutex<Option<String>>>,
+    fatal_error: Arc<Mutex<Option<String>>>,
+    contributors: Arc<RwLock<Vec<Arc<dyn HealthContributor>>>>,
+}
+
+impl fmt::Debug for HealthReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HealthReporter")
+            .field("version", &self.version)
+            .field("connected", &self.connected)
+            .field("last_error", &self.last_error)
+            .field("fatal_error", &self.fatal_error)
+            .finish()
+    }
+}
+
+impl HealthReporter {
+    pub fn new(version: impl Into<String>) -> Self {
+        Self {
+            version: version.into(),
+            connected: Arc::new(AtomicBool::new(false)),
+            last_error: Arc::new(Mutex::new(None)),
+            fatal_error: Arc::new(Mutex::new(None)),
+            contributors: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::SeqCst);
+    }
+
+    pub fn record_error(&self, error: impl fmt::Display) {
+        *self.last_error.lock().unwrap() = Some(error.to_string());
+    }
+
+    /// Report a state the service cannot recover from; the launcher watchdog
+    /// restarts it.
+    pub fn record_fatal(&self, error: impl fmt::Display) {
+        let error = error.to_string();
+        *self.last_error.lock().unwrap() = Some(error.clone());
+        *self.fatal_error.lock().unwrap() = Some(error);
+    }
+
+    pub fn add_contributor(&self, contributor: impl HealthContributor + 'static) {
+        self.contributors
+            .write()
+            .unwrap()
+            .push(Arc::new(contributor));
+    }
+
+    /// Build a report, sampling process metrics from `process`.
+    pub async fn report(&self, process: &mut ProcessMonitor) -> HealthReport {
+        let contributors = self.contributors.read().unwrap().clone();
+        let mut fields = HealthFields::new();
+        for contributor in contributors {
+            fields.extend(contributor.fields().await);
+        }
+
+        let sample = process.sample();
+        HealthReport {
+            version: self.version.clone(),
+            pid: sample.pid,
+            uptime_secs: sample.uptime_secs,
+            memory_bytes: sample.memory_bytes,
+            cpu_percent: sample.cpu_percent,
+            connected: self.connected.load(Ordering::SeqCst),
+            last_error: self.last_error.lock().unwrap().clone(),
+            fatal_error: self.fatal_error.lock().unwrap().clone(),
+            fields,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ProcessSample {
+    pub pid: u32,
+    pub uptime_secs: u64,
+    pub memory_bytes: u64,
+    pub cpu_percent: f32,
+}
+
+/// Samples metrics of the current process. CPU usage is averaged over the
+/// time since the previous sample, so the first one reports 0.
+pub struct ProcessMonitor {
+    system: System,
+    pid: Pid,
+}
+
+impl Default for ProcessMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProcessMonitor {
+    pub fn new() -> Self {
+        Self {
+            system: System::new(),
+            pid: Pid::from_u32(std::process::id()),
+        }
+    }
+
+    pub fn sample(&mut self) -> ProcessSample {
+        self.system.refresh_processes_specifics(
+            ProcessesToUpdate::Some(&[self.pid]),
+            true,
+            ProcessRefreshKind::nothing().with_cpu().with_memory(),
+        );
+        match self.system.process(self.pid) {
+            Some(process) => ProcessSample {
+                pid: self.pid.as_u32(),
+                uptime_secs: process.run_time(),
+                memory_bytes: process.memory(),
+                cpu_percent: process.cpu_usage(),
+            },
+            None => ProcessSample {
+                pid: self.pid.as_u32(),
+                ..Default::default()
+            },
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use super::*;
+use serde_json::json;
+
+#[test]
+fn test_minimal_report_parses() {
+    let report: HealthReport = serde_json::from_str(r#"{"version":"0.5.0"}"#).unwrap();
+    assert_eq!(report.version, "0.5.0");
+    assert!(!report.connected);
+    assert!(report.fields.is_empty());
+}
+
+#[tokio::test]
+async fn test_report_includes_state_and_fields() {
+    let health = HealthReporter::new("1.2.3");
+    health.set_connected(true);
+    health.record_error("broker went away");
+    health
+        .add_contributor(|| async { HealthFields::from([("webrtc_peers".to_string(), json!(2))]) });
+
+    let report = health.report(&mut ProcessMonitor::new()).await;
+    assert_eq!(report.version, "1.2.3");
+    assert_eq!(report.pid, std::process::id());
+    assert!(report.memory_bytes > 0);
+    assert!(report.connected);
+    assert_eq!(report.last_error.as_deref(), Some("broker went away"));
+    assert_eq!(report.fields["webrtc_peers"], json!(2));
+
+    let parsed: HealthReport =
+        serde_json::from_str(&serde_json::to_string(&report).unwrap()).unwrap();
+    assert_eq!(parsed, report);
+}
+
+#[test]
+fn test_liveness() {
+    let loops = vec![Liveness::new(), Liveness::new()];
+    // Not started yet.
+    assert!(loops.is_alive());
+
+    loops[0].step(Duration::from_secs(60));
+    loops[1].step(Duration::ZERO);
+    std::thread::sleep(Duration::from_millis(5));
+    assert!(loops[0].is_alive());
+    assert!(!loops[1].is_alive());
+    assert!(!loops.is_alive());
+
+    loops[1].idle();
+    assert!(loops.is_alive());
+}
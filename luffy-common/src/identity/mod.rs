@@ -0,0 +1,129 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config::{self, BaseConfig};
+use crate::util;
+
+static IDENTITY: OnceLock<DeviceIdentity> = OnceLock::new();
+
+const IDENTITY_FILE: &str = "identity.json";
+
+/// Files that hold a board or OS serial, in order of preference.
+const HARDWARE_ID_SOURCES: [&str; 2] = [
+    "/sys/firmware/devicetree/base/serial-number",
+    "/etc/machine-id",
+];
+
+const MODEL_SOURCE: &str = "/sys/firmware/devicetree/base/model";
+
+/// Who this device is. Used for cloud registration, MQTT client ids and
+/// telemetry topics.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceIdentity {
+    /// Configured name of the vehicle, also the IoT thing name.
+    pub vehicle_id: String,
+    /// Derived from the board serial (or generated) on first start and kept
+    /// in the config dir, so it survives NIC and OS changes.
+    pub hardware_id: String,
+    pub mac_address: Option<String>,
+    pub model: String,
+}
+
+/// The persisted part of the identity.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredIdentity {
+    hardware_id: String,
+}
+
+impl DeviceIdentity {
+    /// The identity of this device, loaded once per process.
+    pub fn get(config: &BaseConfig) -> &'static DeviceIdentity {
+        IDENTITY.get_or_init(|| {
+            let vehicle_id = util::get_vehicle_id(config);
+            match Self::load(&config::config_dir(), vehicle_id.clone()) {
+                Ok(identity) => identity,
+                Err(e) => {
+                    warn!(
+                        "Failed to load device identity, using a temporary one: {:#}",
+                        e
+                    );
+                    Self::detect(vehicle_id)
+                }
+            }
+        })
+    }
+
+    /// Load the identity stored in `dir`, creating it on first start.
+    pub fn load(dir: &Path, vehicle_id: String) -> Result<Self> {
+        let path = dir.join(IDENTITY_FILE);
+        let mut identity = Self::detect(vehicle_id);
+        if path.exists() {
+            let stored: StoredIdentity = serde_json::from_slice(
+                &fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?,
+            )
+            .with_context(|| format!("Invalid identity file {}", path.display()))?;
+            identity.hardware_id = stored.hardware_id;
+        } else {
+            let stored = StoredIdentity {
+                hardware_id: identity.hardware_id.clone(),
+            };
+            util::write_atomic(&path, &serde_json::to_vec_pretty(&stored)?, 0o644)?;
+            info!(
+                "Created device identity {} in {}",
+                identity.hardware_id,
+                path.display()
+            );
+        }
+        Ok(identity)
+    }
+
+    /// Identity from the hardware, without reading or writing the config dir.
+    fn detect(vehicle_id: String) -> Self {
+        let mac_address = util::get_mac_address();
+        let hardware_id = HARDWARE_ID_SOURCES
+            .iter()
+            .find_map(|path| read_id(Path::new(path)))
+            .or_else(|| mac_address.clone())
+            .unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+        let model = read_id(Path::new(MODEL_SOURCE))
+            .unwrap_or_else(|| format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH));
+        Self {
+            vehicle_id,
+            hardware_id,
+            mac_address,
+            model,
+        }
+    }
+
+    /// MQTT client id of `service` on this device. Stable across restarts and
+    /// distinct per service, so services do not take over each other's
+    /// session.
+    pub fn client_id(&self, service: &str) -> String {
+        format!("{}-{}", self.vehicle_id, service)
+    }
+}
+
+/// Ids that stay with the machine rather than the config dir, joined; used
+/// to derive keys bound to this device.
+pub(crate) fn machine_secret() -> Option<String> {
+    let ids: Vec<String> = HARDWARE_ID_SOURCES
+        .iter()
+        .filter_map(|path| read_id(Path::new(path)))
+        .collect();
+    (!ids.is_empty()).then(|| ids.join(":"))
+}
+
+/// Trimmed contents of a sysfs/procfs id file, `None` when missing or empty.
+fn read_id(path: &Path) -> Option<String> {
+    let id = fs::read_to_string(path).ok()?;
+    let id = id.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+    (!id.is_empty()).then(|| id.to_string())
+}
@@ -0,0 +1,36 @@
+use super::*;
+
+#[test]
+fn test_identity_is_persisted() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let first = DeviceIdentity::load(dir.path(), "vessel-1".to_string()).unwrap();
+    assert_eq!(first.vehicle_id, "vessel-1");
+    assert!(!first.hardware_id.is_empty());
+    assert!(dir.path().join(IDENTITY_FILE).exists());
+
+    fs::write(
+        dir.path().join(IDENTITY_FILE),
+        r#"{"hardware_id":"board-42"}"#,
+    )
+    .unwrap();
+    let second = DeviceIdentity::load(dir.path(), "vessel-2".to_string()).unwrap();
+    assert_eq!(second.hardware_id, "board-42");
+    assert_eq!(second.vehicle_id, "vessel-2");
+    assert_eq!(second.client_id("gateway"), "vessel-2-gateway");
+}
+
+#[test]
+fn test_read_id_trims_device_tree_strings() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model");
+    fs::write(&path, "Raspberry Pi 4 Model B Rev 1.4\0").unwrap();
+    assert_eq!(
+        read_id(&path).as_deref(),
+        Some("Raspberry Pi 4 Model B Rev 1.4")
+    );
+
+    fs::write(&path, "\n").unwrap();
+    assert_eq!(read_id(&path), None);
+    assert_eq!(read_id(&dir.path().join("missing")), None);
+}
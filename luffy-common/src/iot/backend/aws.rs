@@ -0,0 +1,88 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use rumqttc::{TlsConfiguration, Transport};
+use std::time::Duration;
+use tokio::fs;
+
+use super::{ConnectOptions, RemoteBackend};
+use crate::config::{AwsConfig, MqttProtocol, SecretsConfig};
+use crate::identity::DeviceIdentity;
+use crate::provisioning::{CredentialStore, Provisioner, ProvisioningChannel};
+
+/// AWS IoT Core with the device certificate in the config directory. The
+/// device provisions itself on first start.
+pub struct AwsIotBackend {
+    endpoint: String,
+    port: u16,
+    secrets: SecretsConfig,
+    identity: DeviceIdentity,
+    provisioning: Box<dyn ProvisioningChannel>,
+}
+
+impl AwsIotBackend {
+    pub fn new(
+        config: &AwsConfig,
+        secrets: SecretsConfig,
+        identity: DeviceIdentity,
+        provisioning: Box<dyn ProvisioningChannel>,
+    ) -> Self {
+        Self {
+            endpoint: config.iot.endpoint.clone(),
+            port: config.iot.port,
+            secrets,
+            identity,
+            provisioning,
+        }
+    }
+}
+
+#[async_trait]
+impl RemoteBackend for AwsIotBackend {
+    fn name(&self) -> &str {
+        "aws-iot"
+    }
+
+    fn protocol(&self) -> MqttProtocol {
+        MqttProtocol::V4
+    }
+
+    async fn prepare(&self) -> Result<()> {
+        Provisioner::new(
+            CredentialStore::open(&self.secrets)?,
+            self.provisioning.as_ref(),
+        )
+        .provision(&self.identity)
+        .await
+        .context("Failed to provision device")?;
+        Ok(())
+    }
+
+    fn credentials(&self) -> Option<CredentialStore> {
+        CredentialStore::open(&self.secrets).ok()
+    }
+
+    async fn options(&self) -> Result<ConnectOptions> {
+        let store = CredentialStore::open(&self.secrets)?;
+        let cert_pem = fs::read(store.certificate_path())
+            .await
+            .context("Failed to read device certificate")?;
+        let key_pem = store
+            .key_pem()
+            .context("Failed to read device key")?
+            .into_bytes();
+        let aws_root_cert = include_bytes!("../../../certs/AmazonRootCA.pem");
+
+        Ok(ConnectOptions {
+            host: self.endpoint.clone(),
+            port: self.port,
+            protocol: self.protocol(),
+            transport: Transport::Tls(TlsConfiguration::Simple {
+                ca: aws_root_cert.to_vec(),
+                alpn: Some(vec!["mqtt".as_bytes().to_vec()]),
+                client_auth: Some((cert_pem, key_pem)),
+            }),
+            credentials: None,
+            keep_alive: Duration::from_secs(30),
+        })
+    }
+}
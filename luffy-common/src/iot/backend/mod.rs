@@ -0,0 +1,64 @@
+#[cfg(test)]
+mod tests;
+
+mod aws;
+mod mqtt;
+
+pub use aws::AwsIotBackend;
+pub use mqtt::GenericMqttBackend;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rumqttc::Transport;
+use std::time::Duration;
+
+use crate::config::{BaseConfig, MqttProtocol, RemoteConfig};
+use crate::identity::DeviceIdentity;
+use crate::provisioning::{self, CredentialStore};
+
+/// A cloud broker the remote link can connect to.
+#[async_trait]
+pub trait RemoteBackend: Send + Sync {
+    /// Short name used in logs.
+    fn name(&self) -> &str;
+
+    /// MQTT version of the broker, known before [`Self::prepare`].
+    fn protocol(&self) -> MqttProtocol;
+
+    /// Make sure the credentials needed to connect exist, e.g. by registering
+    /// the device. Called before connecting, and again after a failure.
+    async fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Where and how to connect.
+    async fn options(&self) -> Result<ConnectOptions>;
+
+    /// Store of the client certificate, for backends that connect with the
+    /// device certificate.
+    fn credentials(&self) -> Option<CredentialStore> {
+        None
+    }
+}
+
+pub struct ConnectOptions {
+    pub host: String,
+    pub port: u16,
+    pub protocol: MqttProtocol,
+    pub transport: Transport,
+    pub credentials: Option<(String, String)>,
+    pub keep_alive: Duration,
+}
+
+/// Backend selected by the `[remote]` config section.
+pub fn from_config(config: &BaseConfig) -> Box<dyn RemoteBackend> {
+    match &config.remote {
+        RemoteConfig::Aws => Box::new(AwsIotBackend::new(
+            &config.aws,
+            config.secrets.clone(),
+            DeviceIdentity::get(config).clone(),
+            provisioning::from_config(config),
+        )),
+        RemoteConfig::Mqtt(broker) => Box::new(GenericMqttBackend::new(broker.clone())),
+    }
+}
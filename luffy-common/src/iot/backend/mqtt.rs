@@ -0,0 +1,113 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use rumqttc::tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use rumqttc::{TlsConfiguration, Transport};
+use std::time::Duration;
+use tokio::fs;
+
+use super::{ConnectOptions, RemoteBackend};
+use crate::config::{MqttBrokerConfig, MqttProtocol};
+
+/// Any MQTT 3.1.1 or 5 broker, authenticated by username/password and/or a
+/// client certificate.
+pub struct GenericMqttBackend {
+    config: MqttBrokerConfig,
+}
+
+impl GenericMqttBackend {
+    pub fn new(config: MqttBrokerConfig) -> Self {
+        Self { config }
+    }
+
+    async fn transport(&self) -> Result<Transport> {
+        if !self.config.tls {
+            return Ok(Transport::Tcp);
+        }
+
+        let client_auth = match (&self.config.client_cert_path, &self.config.client_key_path) {
+            (Some(cert), Some(key)) => Some((
+                fs::read(cert)
+                    .await
+                    .with_context(|| format!("Failed to read client certificate {}", cert))?,
+                fs::read(key)
+                    .await
+                    .with_context(|| format!("Failed to read client key {}", key))?,
+            )),
+            (None, None) => None,
+            _ => {
+                return Err(anyhow!(
+                    "client_cert_path and client_key_path must be set together"
+                ))
+            }
+        };
+
+        let tls = match &self.config.ca_path {
+            Some(ca) => TlsConfiguration::Simple {
+                ca: fs::read(ca)
+                    .await
+                    .with_context(|| format!("Failed to read CA {}", ca))?,
+                alpn: None,
+                client_auth,
+            },
+            None => system_roots(client_auth)?,
+        };
+        Ok(Transport::Tls(tls))
+    }
+}
+
+/// TLS trusting the system roots, presenting the client certificate when
+/// given.
+fn system_roots(client_auth: Option<(Vec<u8>, Vec<u8>)>) -> Result<TlsConfiguration> {
+    let mut roots = RootCertStore::empty();
+    let certs =
+        rustls_native_certs::load_native_certs().context("Failed to load the system roots")?;
+    roots.add_parsable_certificates(certs);
+    let config = ClientConfig::builder().with_root_certificates(roots);
+    let config = match client_auth {
+        Some((cert, key)) => {
+            let certs = rustls_pemfile::certs(&mut cert.as_slice())
+                .collect::<Result<Vec<_>, _>>()
+                .context("Invalid client certificate")?;
+            if certs.is_empty() {
+                return Err(anyhow!("No certificate in the client certificate file"));
+            }
+            let key = rustls_pemfile::private_key(&mut key.as_slice())
+                .context("Invalid client key")?
+                .ok_or_else(|| anyhow!("No private key in the client key file"))?;
+            config
+                .with_client_auth_cert(certs, key)
+                .context("Invalid client certificate or key")?
+        }
+        None => config.with_no_client_auth(),
+    };
+    Ok(config.into())
+}
+
+#[async_trait]
+impl RemoteBackend for GenericMqttBackend {
+    fn name(&self) -> &str {
+        "mqtt"
+    }
+
+    fn protocol(&self) -> MqttProtocol {
+        self.config.protocol
+    }
+
+    async fn options(&self) -> Result<ConnectOptions> {
+        let credentials = match (&self.config.username, &self.config.password) {
+            (Some(username), password) => {
+                Some((username.clone(), password.clone().unwrap_or_default()))
+            }
+            (None, _) => None,
+        };
+
+        Ok(ConnectOptions {
+            host: self.config.host.clone(),
+            port: self.config.port,
+            protocol: self.config.protocol,
+            transport: self.transport().await?,
+            credentials,
+            keep_alive: Duration::from_secs(self.config.keep_alive),
+        })
+    }
+}
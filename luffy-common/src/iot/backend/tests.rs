@@ -0,0 +1,185 @@
+use super::*;
+use crate::config::MqttBrokerConfig;
+use crate::iot::remote::RemoteIotClient;
+use crate::iot::router::Message;
+use crate::iot::testing::start_broker;
+use rumqttc::TlsConfiguration;
+use tokio::sync::mpsc;
+
+fn broker_config(port: u16, protocol: MqttProtocol) -> MqttBrokerConfig {
+    MqttBrokerConfig {
+        host: "127.0.0.1".to_string(),
+        port,
+        protocol,
+        keep_alive: 5,
+        tls: false,
+        ca_path: None,
+        client_cert_path: None,
+        client_key_path: None,
+        username: Some("fleet".to_string()),
+        password: Some("secret".to_string()),
+    }
+}
+
+async fn round_trip(port: u16, protocol: MqttProtocol, vehicle_id: &str) {
+    let backend = GenericMqttBackend::new(broker_config(port, protocol));
+    let mut client = RemoteIotClient::new(vehicle_id.to_string(), Box::new(backend));
+    let mut connections = client.connections();
+    client.start().await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), connections.recv())
+        .await
+        .expect("not connected")
+        .unwrap();
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let topic = format!("{}/command/ping", vehicle_id);
+    client
+        .on(&topic, move |message: Message| {
+            let tx = tx.clone();
+            async move {
+                tx.send(message)?;
+                Ok(())
+            }
+        })
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    client.publish(&topic, "hello").await.unwrap();
+
+    let message = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("message not received")
+        .unwrap();
+    assert_eq!(message, Message::new(topic.clone(), "hello"));
+
+    // Reconnecting keeps the handlers and the client handle usable.
+    client
+        .reconnector()
+        .unwrap()
+        .reconnect(Duration::from_secs(5))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    client.publish(&topic, "again").await.unwrap();
+    let message = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("message not received after reconnect")
+        .unwrap();
+    assert_eq!(message, Message::new(topic, "again"));
+    client.stop().await;
+}
+
+#[tokio::test]
+async fn test_generic_backend_round_trip() {
+    let (v4_port, v5_port) = start_broker(true);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    round_trip(v4_port, MqttProtocol::V4, "vessel-v4").await;
+    round_trip(v5_port, MqttProtocol::V5, "vessel-v5").await;
+}
+
+#[tokio::test]
+async fn test_mtls_without_ca_uses_system_roots() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = rcgen::KeyPair::generate().unwrap();
+    let cert = rcgen::CertificateParams::new(vec!["vessel-1".to_string()])
+        .unwrap()
+        .self_signed(&key)
+        .unwrap();
+    let cert_path = dir.path().join("client.crt");
+    let key_path = dir.path().join("client.key");
+    std::fs::write(&cert_path, cert.pem()).unwrap();
+    std::fs::write(&key_path, key.serialize_pem()).unwrap();
+
+    let mut config = broker_config(8883, MqttProtocol::V5);
+    config.tls = true;
+    config.client_cert_path = Some(cert_path.to_string_lossy().to_string());
+    config.client_key_path = Some(key_path.to_string_lossy().to_string());
+    let options = GenericMqttBackend::new(config.clone())
+        .options()
+        .await
+        .unwrap();
+    let Transport::Tls(TlsConfiguration::Rustls(tls)) = options.transport else {
+        panic!("Expected TLS from the system roots");
+    };
+    assert!(tls.client_auth_cert_resolver.has_certs());
+
+    config.client_key_path = Some(cert_path.to_string_lossy().to_string());
+    assert!(GenericMqttBackend::new(config).options().await.is_err());
+}
+
+#[test]
+fn test_remote_config() {
+    let config: RemoteConfig = serde_json::from_value(serde_json::json!({
+        "backend": "mqtt",
+        "host": "broker.example.com",
+        "port": 8883,
+        "protocol": "v5",
+        "tls": true,
+        "username": "fleet"
+    }))
+    .unwrap();
+    let RemoteConfig::Mqtt(broker) = config else {
+        panic!("expected mqtt backend");
+    };
+    assert_eq!(broker.protocol, MqttProtocol::V5);
+    assert_eq!(broker.keep_alive, 30);
+
+    let config: RemoteConfig =
+        serde_json::from_value(serde_json::json!({ "backend": "aws" })).unwrap();
+    assert!(matches!(config, RemoteConfig::Aws));
+}
+
+/// Fails to prepare the first time, like a vehicle still offline.
+struct OfflineAtFirst {
+    inner: GenericMqttBackend,
+    attempts: std::sync::atomic::AtomicU32,
+}
+
+#[async_trait]
+impl RemoteBackend for OfflineAtFirst {
+    fn name(&self) -> &str {
+        "offline-at-first"
+    }
+
+    fn protocol(&self) -> MqttProtocol {
+        self.inner.protocol()
+    }
+
+    async fn prepare(&self) -> Result<()> {
+        if self
+            .attempts
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+            == 0
+        {
+            anyhow::bail!("Provisioning endpoint unreachable");
+        }
+        Ok(())
+    }
+
+    async fn options(&self) -> Result<ConnectOptions> {
+        self.inner.options().await
+    }
+}
+
+#[tokio::test]
+async fn test_start_does_not_wait_for_prepare() {
+    let (v4_port, _) = start_broker(true);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let backend = OfflineAtFirst {
+        inner: GenericMqttBackend::new(broker_config(v4_port, MqttProtocol::V4)),
+        attempts: Default::default(),
+    };
+    let mut client = RemoteIotClient::new("vessel-offline".to_string(), Box::new(backend));
+    let mut connections = client.connections();
+    client.start().await.unwrap();
+    assert!(client.client().is_some());
+
+    // Connected once the retry prepares it.
+    tokio::time::timeout(Duration::from_secs(5), connections.recv())
+        .await
+        .expect("not connected")
+        .unwrap();
+    client.stop().await;
+}
@@ -0,0 +1,132 @@
+use anyhow::Result;
+use rumqttc::v5::mqttbytes::v5::PublishProperties;
+use rumqttc::v5::mqttbytes::QoS as QoSV5;
+use rumqttc::QoS;
+
+use crate::iot::router::MessageProperties;
+use crate::metrics;
+use crate::otel;
+
+/// Handle on a broker connection, whichever MQTT version it speaks.
+#[derive(Debug, Clone)]
+pub enum MqttClient {
+    V4(rumqttc::AsyncClient),
+    V5(rumqttc::v5::AsyncClient),
+}
+
+impl MqttClient {
+    pub async fn publish(&self, topic: &str, payload: impl Into<Vec<u8>>) -> Result<()> {
+        self.publish_with(topic, payload, &MessageProperties::default())
+            .await
+    }
+
+    /// Publish with MQTT v5 properties. They are dropped on v4 connections,
+    /// so anything that must arrive belongs in the payload as well. On v5 the
+    /// trace context of the current span is added to the user properties;
+    /// on v4 only RPC requests carry it, in their headers.
+    pub async fn publish_with(
+        &self,
+        topic: &str,
+        payload: impl Into<Vec<u8>>,
+        properties: &MessageProperties,
+    ) -> Result<()> {
+        let mut properties = properties.clone();
+        if let Self::V5(_) = self {
+            otel::inject(&mut properties.user_properties);
+        }
+        let result = self.send(topic, payload.into(), &properties).await;
+        if result.is_err() {
+            metrics::MQTT_PUBLISH_FAILURES.inc();
+        }
+        result
+    }
+
+    async fn send(
+        &self,
+        topic: &str,
+        payload: Vec<u8>,
+        properties: &MessageProperties,
+    ) -> Result<()> {
+        match self {
+            Self::V4(client) => {
+                client
+                    .publish(topic, QoS::AtLeastOnce, false, payload)
+                    .await?
+            }
+            Self::V5(client) if properties.is_empty() => {
+                client
+                    .publish(topic, QoSV5::AtLeastOnce, false, payload)
+                    .await?
+            }
+            Self::V5(client) => {
+                client
+                    .publish_with_properties(
+                        topic,
+                        QoSV5::AtLeastOnce,
+                        false,
+                        payload,
+                        properties.clone().into(),
+                    )
+                    .await?
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn subscribe(&self, topic: &str) -> Result<()> {
+        match self {
+            Self::V4(client) => client.subscribe(topic, QoS::AtLeastOnce).await?,
+            Self::V5(client) => client.subscribe(topic, QoSV5::AtLeastOnce).await?,
+        }
+        Ok(())
+    }
+
+    pub async fn unsubscribe(&self, topic: &str) -> Result<()> {
+        match self {
+            Self::V4(client) => client.unsubscribe(topic).await?,
+            Self::V5(client) => client.unsubscribe(topic).await?,
+        }
+        Ok(())
+    }
+
+    pub async fn disconnect(&self) -> Result<()> {
+        match self {
+            Self::V4(client) => client.disconnect().await?,
+            Self::V5(client) => client.disconnect().await?,
+        }
+        Ok(())
+    }
+}
+
+impl From<rumqttc::AsyncClient> for MqttClient {
+    fn from(client: rumqttc::AsyncClient) -> Self {
+        Self::V4(client)
+    }
+}
+
+impl From<rumqttc::v5::AsyncClient> for MqttClient {
+    fn from(client: rumqttc::v5::AsyncClient) -> Self {
+        Self::V5(client)
+    }
+}
+
+impl From<MessageProperties> for PublishProperties {
+    fn from(properties: MessageProperties) -> Self {
+        PublishProperties {
+            response_topic: properties.response_topic,
+            correlation_data: properties.correlation_data,
+            user_properties: properties.user_properties,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<PublishProperties> for MessageProperties {
+    fn from(properties: PublishProperties) -> Self {
+        MessageProperties {
+            response_topic: properties.response_topic,
+            correlation_data: properties.correlation_data,
+            user_properties: properties.user_properties,
+        }
+    }
+}
@@ -1,25 +1,54 @@
-use anyhow::Result;
-use rumqttc::{AsyncClient, Event, Packet, QoS};
-use serde_json::json;
+use crate::health::{HealthContributor, HealthReporter, Liveness, ProcessMonitor};
+use crate::iot::client::MqttClient;
+use crate::iot::router::{HandlerId, Message, MessageHandler, TopicRouter};
+use crate::iot::rpc::{self, Caller, RpcClient};
+use crate::metrics;
+use anyhow::{anyhow, Result};
+use rumqttc::{AsyncClient, Event, LastWill, Outgoing, Packet, QoS};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::cmp::min;
+use std::future::Future;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration};
 use tracing::{debug, error, info};
 
+/// The `link` label of this client's metrics.
+const LINK: &str = "local";
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// How much longer than two keep-alive intervals the event loop may take to
+/// come round, e.g. for a reconnect.
+const LIVENESS_MARGIN: Duration = Duration::from_secs(30);
+
+/// Retained payloads of [`status_topic`]. `offline` is also registered as
+/// the Last Will, so the broker publishes it when a service dies.
+pub const STATUS_ONLINE: &str = "online";
+pub const STATUS_OFFLINE: &str = "offline";
+/// Set by a clean shutdown; `offline` comes from the Last Will.
+pub const STATUS_STOPPED: &str = "stopped";
+
+/// Retained presence topic of the service `name`.
+pub fn status_topic(name: &str) -> String {
+    format!("luffy/{}/status", name)
+}
+
 #[derive(Clone, Debug)]
 pub struct LocalIotClient {
     host: String,
     port: u16,
     name: String,
-    on_message: Option<fn(topic: String, payload: String)>,
+    client_id: String,
+    router: TopicRouter,
+    rpc: RpcClient,
     pub connected: bool,
     client: Option<AsyncClient>,
     health_report_interval: u64,
-    version: String,
+    health: HealthReporter,
     subscriptions: Arc<Mutex<Vec<String>>>,
     log_on: bool,
+    liveness: Liveness,
 }
 
 impl Default for LocalIotClient {
@@ -28,13 +57,16 @@ impl Default for LocalIotClient {
             name: "mqtt-client".to_string(),
             host: "localhost".to_string(),
             port: 9183,
-            on_message: None,
+            client_id: "mqtt-client".to_string(),
+            router: TopicRouter::new(),
+            rpc: RpcClient::new("luffy/mqtt-client"),
             connected: false,
             client: None,
             health_report_interval: 60,
-            version: env!("CARGO_PKG_VERSION").to_string(),
+            health: HealthReporter::new(env!("CARGO_PKG_VERSION")),
             subscriptions: Arc::new(Mutex::new(Vec::new())),
             log_on: false,
+            liveness: Liveness::new(),
         }
     }
 }
@@ -44,24 +76,40 @@ impl LocalIotClient {
         name: String,
         host: String,
         port: u16,
-        on_message: Option<fn(topic: String, payload: String)>,
         health_report_interval: u64,
         version: String,
     ) -> Self {
         Self {
+            rpc: RpcClient::new(&format!("luffy/{}", name)),
+            client_id: name.clone(),
             name,
             host,
             port,
-            on_message,
+            router: TopicRouter::new(),
             connected: false,
             client: None,
             health_report_interval,
-            version,
+            health: HealthReporter::new(version),
             subscriptions: Arc::new(Mutex::new(Vec::new())),
             log_on: false,
+            liveness: Liveness::new(),
         }
     }
 
+    /// Connect with a fixed client id, see
+    /// [`DeviceIdentity::client_id`](crate::identity::DeviceIdentity::client_id).
+    /// Without one the service name is used.
+    pub fn with_client_id(mut self, client_id: String) -> Self {
+        self.client_id = client_id;
+        self
+    }
+
+    /// Probe of the event loop, which turns at least once per keep-alive
+    /// interval while connected and after each retry while not.
+    pub fn liveness(&self) -> Liveness {
+        self.liveness.clone()
+    }
+
     pub fn set_log_on(&mut self, log_on: bool) {
         self.log_on = log_on;
     }
@@ -70,7 +118,18 @@ impl LocalIotClient {
         if let Some(client) = &self.client {
             client
                 .publish(topic, QoS::AtLeastOnce, false, payload)
-                .await?;
+                .await
+                .inspect_err(|_| metrics::MQTT_PUBLISH_FAILURES.inc())?;
+        }
+        Ok(())
+    }
+
+    pub async fn publish_bytes(&self, topic: &str, payload: Vec<u8>) -> Result<()> {
+        if let Some(client) = &self.client {
+            client
+                .publish(topic, QoS::AtLeastOnce, false, payload)
+                .await
+                .inspect_err(|_| metrics::MQTT_PUBLISH_FAILURES.inc())?;
         }
         Ok(())
     }
@@ -98,19 +157,27 @@ impl LocalIotClient {
         info!("Starting broker client {}...", self.name);
 
         let mut mqtt_options =
-            rumqttc::MqttOptions::new(self.name.clone(), self.host.clone(), self.port);
+            rumqttc::MqttOptions::new(self.client_id.clone(), self.host.clone(), self.port);
         mqtt_options
-            .set_keep_alive(Duration::from_secs(30))
-            .set_clean_session(true);
+            .set_keep_alive(KEEP_ALIVE)
+            .set_clean_session(true)
+            .set_last_will(LastWill::new(
+                status_topic(&self.name),
+                STATUS_OFFLINE,
+                QoS::AtLeastOnce,
+                true,
+            ));
 
         info!("Connecting to MQTT broker at {}:{}", self.host, self.port);
         let (client, mut eventloop) = rumqttc::AsyncClient::new(mqtt_options, 10);
         self.client = Some(client.clone());
 
-        let on_message = self.on_message;
+        let router = self.router.clone();
+        let health = self.health.clone();
         let name = self.name.clone();
         let subscriptions = self.subscriptions.clone();
         let log_on = self.log_on;
+        let liveness = self.liveness.clone();
         // Spawn the connection handling task
         let connection_handle = tokio::spawn(async move {
             info!("🚀 Starting broker connection event loop for {}", name);
@@ -119,25 +186,48 @@ impl LocalIotClient {
             let max_interval = Duration::from_secs(60);
 
             loop {
-                match eventloop.poll().await {
+                liveness.step(KEEP_ALIVE * 2 + LIVENESS_MARGIN);
+                let event = eventloop.poll().await;
+                metrics::MQTT_REPLAY
+                    .with_label_values(&[LINK])
+                    .set(eventloop.pending.len() as i64);
+                match event {
                     Ok(Event::Incoming(Packet::SubAck(ack))) => {
                         info!("✅ Subscription confirmed: {:?}", ack);
                     }
                     Ok(Event::Incoming(Packet::ConnAck(ack))) => {
                         connection_established = true;
+                        health.set_connected(true);
                         retry_interval = Duration::from_secs(1);
                         info!("🔗 Connected to broker: {:?}", ack);
-                        let subs = subscriptions.lock().await;
-                        info!("Resubscribing to {} topics", subs.len());
-                        for topic in subs.iter() {
-                            if let Err(e) = client.subscribe(topic, QoS::AtLeastOnce).await {
-                                error!("❌ Failed to resubscribe to {}: {:?}", topic, e);
-                            } else {
-                                info!("✅ Resubscribed to {}", topic);
-                            }
+                        // The will may have replaced our status while we were
+                        // disconnected.
+                        if let Err(e) = client.try_publish(
+                            status_topic(&name),
+                            QoS::AtLeastOnce,
+                            true,
+                            STATUS_ONLINE,
+                        ) {
+                            error!("❌ Failed to publish online status: {:?}", e);
                         }
+                        // Subscribing waits for room in the request queue,
+                        // which only this loop drains.
+                        let client = client.clone();
+                        let subscriptions = subscriptions.clone();
+                        tokio::spawn(async move {
+                            let subs = subscriptions.lock().await;
+                            info!("Resubscribing to {} topics", subs.len());
+                            for topic in subs.iter() {
+                                if let Err(e) = client.subscribe(topic, QoS::AtLeastOnce).await {
+                                    error!("❌ Failed to resubscribe to {}: {:?}", topic, e);
+                                } else {
+                                    info!("✅ Resubscribed to {}", topic);
+                                }
+                            }
+                        });
                     }
                     Ok(Event::Incoming(Packet::Publish(p))) => {
+                        metrics::MQTT_RECEIVED.with_label_values(&[LINK]).inc();
                         if log_on {
                             debug!(
                                 "📨 Received message - Topic: {}, Payload: {:?}",
@@ -146,11 +236,10 @@ impl LocalIotClient {
                             );
                         }
 
-                        if let Some(callback) = on_message {
-                            callback(p.topic, String::from_utf8_lossy(&p.payload).to_string());
-                        } else {
-                            info!("📝 No message handler set");
-                        }
+                        router.route(Message::new(p.topic, p.payload));
+                    }
+                    Ok(Event::Outgoing(Outgoing::Publish(_))) => {
+                        metrics::MQTT_PUBLISHED.with_label_values(&[LINK]).inc();
                     }
                     Ok(event) => {
                         if log_on {
@@ -158,12 +247,18 @@ impl LocalIotClient {
                         }
                     }
                     Err(e) => {
+                        metrics::MQTT_CONNECTION_ERRORS
+                            .with_label_values(&[LINK])
+                            .inc();
                         error!("❌ Connection error: {:?}", e);
+                        health.set_connected(false);
+                        health.record_error(format!("MQTT connection error: {}", e));
                         if connection_established {
                             error!("📡 Connection lost, attempting to reconnect...");
                             connection_established = false;
                         }
 
+                        liveness.idle();
                         sleep(retry_interval).await;
                         retry_interval = min(retry_interval * 2, max_interval);
                     }
@@ -176,10 +271,10 @@ impl LocalIotClient {
         for attempt in 1..=30 {
             info!("Connection attempt {}/30", attempt);
             match self.client.as_ref().unwrap().try_publish(
-                format!("/luffy/{}/connected", self.name),
+                status_topic(&self.name),
                 QoS::AtLeastOnce,
-                false,
-                "true",
+                true,
+                STATUS_ONLINE,
             ) {
                 Ok(_) => {
                     info!(
@@ -190,21 +285,12 @@ impl LocalIotClient {
                     let client = self.client.clone();
                     let name = self.name.clone();
                     let interval = self.health_report_interval;
-                    let health_report_payload = json!({
-                        "version": self.version
-                    })
-                    .to_string();
+                    let health = self.health.clone();
                     // Spawn health report task
                     tokio::spawn(async move {
                         info!("🏥 Starting health report task for {}", name);
-                        if let Err(e) = Self::health_report_task(
-                            client,
-                            name,
-                            interval,
-                            health_report_payload,
-                            log_on,
-                        )
-                        .await
+                        if let Err(e) =
+                            Self::health_report_task(client, name, interval, health, log_on).await
                         {
                             error!("❌ Health report task failed: {:?}", e);
                         }
@@ -222,6 +308,7 @@ impl LocalIotClient {
         // If we get here, connection failed
         error!("❌ Failed to connect after 30 attempts, aborting connection handle");
         connection_handle.abort();
+        self.liveness.idle();
         Err(anyhow::anyhow!(
             "Failed to connect to broker after 30 attempts"
         ))
@@ -231,14 +318,16 @@ impl LocalIotClient {
         client: Option<AsyncClient>,
         name: String,
         interval: u64,
-        health_report_payload: String,
+        health: HealthReporter,
         log_on: bool,
     ) -> Result<()> {
         info!("🏥 Health report task started for {}", name);
+        let mut process = ProcessMonitor::new();
         let mut interval = tokio::time::interval(Duration::from_secs(interval));
         loop {
             interval.tick().await;
             if let Some(client) = &client {
+                let report = health.report(&mut process).await;
                 if log_on {
                     debug!("📤 Sending health report for {}", name);
                 }
@@ -247,7 +336,7 @@ impl LocalIotClient {
                         &format!("luffy/{}/health", name),
                         QoS::AtLeastOnce,
                         false,
-                        health_report_payload.clone(),
+                        serde_json::to_vec(&report)?,
                     )
                     .await
                 {
@@ -266,7 +355,113 @@ impl LocalIotClient {
         }
     }
 
-    pub fn set_on_message(&mut self, on_message: fn(topic: String, payload: String)) {
-        self.on_message = Some(on_message);
+    /// Publish a retained stopped status and disconnect cleanly. A clean
+    /// disconnect discards the Last Will, so the status is set explicitly.
+    pub async fn disconnect(&mut self) -> Result<()> {
+        if let Some(client) = self.client.take() {
+            client
+                .publish(
+                    status_topic(&self.name),
+                    QoS::AtLeastOnce,
+                    true,
+                    STATUS_STOPPED,
+                )
+                .await?;
+            client.disconnect().await?;
+        }
+        self.connected = false;
+        Ok(())
+    }
+
+    /// Shared health state, for recording errors.
+    pub fn health(&self) -> HealthReporter {
+        self.health.clone()
+    }
+
+    /// Add service-specific fields to the periodic health reports.
+    pub fn add_health_fields(&self, contributor: impl HealthContributor + 'static) {
+        self.health.add_contributor(contributor);
+    }
+
+    pub fn client(&self) -> Option<MqttClient> {
+        self.client.clone().map(MqttClient::from)
+    }
+
+    pub fn router(&self) -> TopicRouter {
+        self.router.clone()
+    }
+
+    /// Handle messages matching `filter`, subscribing to it if needed. The
+    /// subscription is restored after reconnects.
+    pub async fn on(
+        &self,
+        filter: &str,
+        handler: impl MessageHandler + 'static,
+    ) -> Result<HandlerId> {
+        let subscribed = self.router.has_filter(filter);
+        let id = self.router.add(filter, handler);
+        if !subscribed {
+            let mut subs = self.subscriptions.lock().await;
+            if !subs.iter().any(|topic| topic == filter) {
+                subs.push(filter.to_string());
+            }
+            if let Some(client) = &self.client {
+                client.subscribe(filter, QoS::AtLeastOnce).await?;
+            }
+        }
+        Ok(id)
+    }
+
+    /// Remove a handler added with [`Self::on`], unsubscribing once no
+    /// handler is left for its filter.
+    pub async fn off(&self, id: HandlerId) -> Result<()> {
+        let Some(filter) = self.router.remove(id) else {
+            return Ok(());
+        };
+        if !self.router.has_filter(&filter) {
+            self.subscriptions
+                .lock()
+                .await
+                .retain(|topic| topic != &filter);
+            if let Some(client) = &self.client {
+                client.unsubscribe(&filter).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Call the RPC method served on `topic`, see [`crate::iot::rpc`].
+    pub async fn call<P, R>(&self, topic: &str, params: &P, timeout: Duration) -> Result<R>
+    where
+        P: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        self.caller().await?.call(topic, params, timeout).await
+    }
+
+    /// A handle for making calls after the client is unlocked again.
+    pub async fn caller(&self) -> Result<Caller> {
+        let client = self
+            .client()
+            .ok_or_else(|| anyhow!("Cannot call RPC: client not connected"))?;
+        let reply_topic = self.rpc.reply_topic();
+        if !self.router.has_filter(reply_topic) {
+            self.on(reply_topic, self.rpc.reply_handler()).await?;
+        }
+        Ok(Caller::new(client, self.rpc.clone()))
+    }
+
+    /// Answer RPC calls on `topic` with `handler`.
+    pub async fn serve<P, R, F, Fut>(&self, topic: &str, handler: F) -> Result<HandlerId>
+    where
+        P: DeserializeOwned + Send + 'static,
+        R: Serialize + Send + 'static,
+        F: Fn(P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R>> + Send + 'static,
+    {
+        let client = self
+            .client()
+            .ok_or_else(|| anyhow!("Cannot serve {}: client not connected", topic))?;
+        self.on(topic, rpc::serve(client, handler)).await
     }
 }
@@ -1,2 +1,9 @@
+pub mod backend;
+pub mod client;
 pub mod local;
 pub mod remote;
+pub mod router;
+pub mod rpc;
+
+#[cfg(test)]
+pub(crate) mod testing;
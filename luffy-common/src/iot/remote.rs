@@ -1,156 +1,485 @@
-use crate::aws::AwsClient;
-use anyhow::{Context, Result};
+use crate::config::MqttProtocol;
+use crate::health::Liveness;
+use crate::iot::backend::{ConnectOptions, RemoteBackend};
+use crate::iot::client::MqttClient;
+use crate::iot::router::{HandlerId, Message, MessageHandler, TopicRouter};
+use crate::iot::rpc::{self, RpcClient};
+use crate::metrics;
+use crate::provisioning::CredentialStore;
+use anyhow::{anyhow, Result};
 use derivative::Derivative;
-use rumqttc::{AsyncClient, QoS};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::future::Future;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::fs;
+use tokio::sync::{broadcast, mpsc, watch, Mutex};
 use tokio::time::Duration;
 use tracing::{debug, error, info};
 use uuid::Uuid;
 
+/// The `link` label of this client's metrics.
+const LINK: &str = "remote";
+
+/// How much longer than two keep-alive intervals the event loop may take to
+/// come round, e.g. for a reconnect.
+const LIVENESS_MARGIN: Duration = Duration::from_secs(30);
+
+/// Longest wait between attempts to prepare the backend.
+const MAX_PREPARE_BACKOFF: Duration = Duration::from_secs(300);
+
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct RemoteIotClient {
-    client: Option<AsyncClient>,
+    client: Option<MqttClient>,
     vehicle_id: String,
+    client_id: Option<String>,
+    #[derivative(Debug = "ignore")]
+    backend: Arc<dyn RemoteBackend>,
+    #[derivative(Debug = "ignore")]
+    reload: Option<mpsc::UnboundedSender<ConnectOptions>>,
+    running: Arc<AtomicBool>,
+    router: TopicRouter,
+    rpc: RpcClient,
+    subscriptions: Arc<Mutex<Vec<String>>>,
+    connections: broadcast::Sender<()>,
+    connected: watch::Sender<bool>,
+    liveness: Liveness,
+}
+
+/// State the event loop needs to keep the link usable across reconnects.
+struct Link {
+    client_id: String,
+    client: MqttClient,
     running: Arc<AtomicBool>,
-    on_message: fn(topic: String, payload: String),
-    aws_iot_endpoint: String,
-    aws_iot_port: u16,
+    router: TopicRouter,
+    subscriptions: Arc<Mutex<Vec<String>>>,
+    connections: broadcast::Sender<()>,
+    connected: watch::Sender<bool>,
+    liveness: Liveness,
 }
 
 impl RemoteIotClient {
-    pub fn new(
-        on_message: fn(topic: String, payload: String),
-        vehicle_id: String,
-        aws_iot_endpoint: String,
-        aws_iot_port: u16,
-    ) -> Self {
+    pub fn new(vehicle_id: String, backend: Box<dyn RemoteBackend>) -> Self {
         Self {
             client: None,
+            rpc: RpcClient::new(&vehicle_id),
             vehicle_id,
+            client_id: None,
+            backend: Arc::from(backend),
+            reload: None,
             running: Arc::new(AtomicBool::new(true)),
-            on_message,
-            aws_iot_endpoint,
-            aws_iot_port,
+            router: TopicRouter::new(),
+            subscriptions: Arc::new(Mutex::new(Vec::new())),
+            connections: broadcast::channel(4).0,
+            connected: watch::channel(false).0,
+            liveness: Liveness::new(),
         }
     }
 
-    pub async fn start(&mut self) -> Result<()> {
-        info!("Starting IoT client...");
-        if !self.is_registered() {
-            let aws_client = AwsClient::instance().await;
-            aws_client.register_device().await?;
-        }
+    /// Connect with a fixed client id, see
+    /// [`DeviceIdentity::client_id`](crate::identity::DeviceIdentity::client_id).
+    /// Without one a random id is used per start.
+    pub fn with_client_id(mut self, client_id: String) -> Self {
+        self.client_id = Some(client_id);
+        self
+    }
 
-        let mqtt_client = self.connect().await?;
-        self.client = Some(mqtt_client.clone());
+    /// Start the link. The backend is prepared, e.g. the device provisioned,
+    /// in the background and retried with backoff, so an offline or not yet
+    /// provisioned vehicle still starts; messages wait until it connects.
+    pub async fn start(&mut self) -> Result<()> {
+        info!("Starting IoT client ({})...", self.backend.name());
+        let (reload, reloads) = mpsc::unbounded_channel();
+        let mqtt_client = self.connect(reloads);
+        self.client = Some(mqtt_client);
+        self.reload = Some(reload);
 
         Ok(())
     }
 
-    async fn connect(&self) -> Result<AsyncClient> {
-        let config_dir = match std::env::var("RUST_ENV").as_deref() {
-            Ok("dev") => dirs::config_dir()
-                .context("Failed to get config directory")?
-                .join("luffy"),
-            _ => std::path::PathBuf::from("/etc/luffy"),
-        };
-
-        let cert_path = config_dir.join("certificate.pem");
-        let key_path = config_dir.join("private.key");
+    /// Handle to reconnect the running link with fresh options from the
+    /// backend, e.g. after the certificate changed. `None` before start.
+    pub fn reconnector(&self) -> Option<Reconnector> {
+        Some(Reconnector {
+            backend: self.backend.clone(),
+            reload: self.reload.clone()?,
+            connections: self.connections.clone(),
+        })
+    }
 
-        let cert_pem = fs::read(&cert_path).await?;
-        let key_pem = fs::read(&key_path).await?;
-        let aws_root_cert = include_bytes!("../../certs/AmazonRootCA.pem");
+    /// Certificate store of the backend, if it connects with one.
+    pub fn credentials(&self) -> Option<CredentialStore> {
+        self.backend.credentials()
+    }
 
-        let client_id = format!("{}_{}", self.vehicle_id, Uuid::new_v4());
-        let mut mqtt_options =
-            rumqttc::MqttOptions::new(client_id, self.aws_iot_endpoint.clone(), self.aws_iot_port);
+    pub fn client(&self) -> Option<MqttClient> {
+        self.client.clone()
+    }
 
-        mqtt_options
-            .set_keep_alive(Duration::from_secs(30))
-            .set_clean_session(true);
+    /// Notified on every (re)connection to the broker.
+    pub fn connections(&self) -> broadcast::Receiver<()> {
+        self.connections.subscribe()
+    }
 
-        let transport = rumqttc::Transport::Tls(rumqttc::TlsConfiguration::Simple {
-            ca: aws_root_cert.to_vec(),
-            alpn: Some(vec!["mqtt".as_bytes().to_vec()]),
-            client_auth: Some((cert_pem, key_pem)),
-        });
+    /// Probe of the event loop, which turns at least once per keep-alive
+    /// interval while it runs.
+    pub fn liveness(&self) -> Liveness {
+        self.liveness.clone()
+    }
 
-        mqtt_options.set_transport(transport);
+    /// Whether the link is connected to the broker right now.
+    pub fn connection_state(&self) -> watch::Receiver<bool> {
+        self.connected.subscribe()
+    }
 
-        let (client, mut eventloop) = rumqttc::AsyncClient::new(mqtt_options, 10);
+    fn connect(&self, reloads: mpsc::UnboundedReceiver<ConnectOptions>) -> MqttClient {
+        let client_id = self
+            .client_id
+            .clone()
+            .unwrap_or_else(|| format!("{}_{}", self.vehicle_id, Uuid::new_v4()));
+        let link = |client: MqttClient| Link {
+            client_id: client_id.clone(),
+            client,
+            running: self.running.clone(),
+            router: self.router.clone(),
+            subscriptions: self.subscriptions.clone(),
+            connections: self.connections.clone(),
+            connected: self.connected.clone(),
+            liveness: self.liveness.clone(),
+        };
 
-        let on_message = self.on_message;
-        tokio::spawn(async move {
-            debug!("Starting iot event loop...");
-            loop {
-                match eventloop.poll().await {
-                    Ok(rumqttc::Event::Incoming(rumqttc::Packet::SubAck(_))) => {
-                        debug!("Subscription confirmed by iot");
-                    }
-                    Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_))) => {
-                        debug!("[IOT]Connected..... ");
-                    }
-                    Ok(rumqttc::Event::Incoming(rumqttc::Packet::Publish(p))) => {
-                        debug!(
-                            "[IOT]Received message - Topic: {}, Payload: {:?}",
-                            p.topic,
-                            String::from_utf8_lossy(&p.payload)
-                        );
-
-                        let payload_str = String::from_utf8_lossy(&p.payload).to_string();
-                        on_message(p.topic, payload_str);
+        // The event loop gets its real options once the backend is prepared.
+        let backend = self.backend.clone();
+        let running = self.running.clone();
+        match backend.protocol() {
+            MqttProtocol::V4 => {
+                let mqtt_options = rumqttc::MqttOptions::new(client_id.clone(), "localhost", 0);
+                let (client, mut eventloop) = rumqttc::AsyncClient::new(mqtt_options, 10);
+                let client = MqttClient::from(client);
+                let link = link(client.clone());
+                tokio::spawn(async move {
+                    if let Some(options) = prepare(backend.as_ref(), &running).await {
+                        eventloop.mqtt_options = v4_options(link.client_id.clone(), options);
+                        link.run_v4(eventloop, reloads).await;
                     }
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("[IOT]MQTT Error: {:?}", e);
-                        tokio::time::sleep(Duration::from_secs(1)).await;
+                });
+                client
+            }
+            MqttProtocol::V5 => {
+                let mqtt_options = rumqttc::v5::MqttOptions::new(client_id.clone(), "localhost", 0);
+                let (client, mut eventloop) = rumqttc::v5::AsyncClient::new(mqtt_options, 10);
+                let client = MqttClient::from(client);
+                let link = link(client.clone());
+                tokio::spawn(async move {
+                    if let Some(options) = prepare(backend.as_ref(), &running).await {
+                        eventloop.options = v5_options(link.client_id.clone(), options);
+                        link.run_v5(eventloop, reloads).await;
                     }
-                }
+                });
+                client
             }
-        });
+        }
+    }
 
-        Ok(client)
+    pub fn router(&self) -> TopicRouter {
+        self.router.clone()
     }
 
-    fn is_registered(&self) -> bool {
-        let config_dir = match std::env::var("RUST_ENV").as_deref() {
-            Ok("dev") => dirs::config_dir()
-                .context("Failed to get config directory")
-                .unwrap()
-                .join("luffy"),
-            _ => std::path::PathBuf::from("/etc/luffy"),
-        };
+    /// Handle messages matching `filter`, subscribing to it if needed. The
+    /// subscription is restored after reconnects.
+    pub async fn on(
+        &self,
+        filter: &str,
+        handler: impl MessageHandler + 'static,
+    ) -> Result<HandlerId> {
+        let subscribed = self.router.has_filter(filter);
+        let id = self.router.add(filter, handler);
+        if !subscribed {
+            let mut subs = self.subscriptions.lock().await;
+            if !subs.iter().any(|topic| topic == filter) {
+                subs.push(filter.to_string());
+            }
+            if let Some(client) = &self.client {
+                client.subscribe(filter).await?;
+            }
+        }
+        Ok(id)
+    }
 
-        let cert_path = config_dir.join("certificate.pem");
-        cert_path.exists()
+    /// Remove a handler added with [`Self::on`], unsubscribing once no
+    /// handler is left for its filter.
+    pub async fn off(&self, id: HandlerId) -> Result<()> {
+        let Some(filter) = self.router.remove(id) else {
+            return Ok(());
+        };
+        if !self.router.has_filter(&filter) {
+            self.subscriptions
+                .lock()
+                .await
+                .retain(|topic| topic != &filter);
+            if let Some(client) = &self.client {
+                client.unsubscribe(&filter).await?;
+            }
+        }
+        Ok(())
     }
 
     pub async fn subscribe(&self, topic: &str) -> Result<()> {
         if let Some(client) = &self.client {
-            client.subscribe(topic, QoS::AtLeastOnce).await?;
+            client.subscribe(topic).await?;
+            let mut subs = self.subscriptions.lock().await;
+            if !subs.iter().any(|t| t == topic) {
+                subs.push(topic.to_string());
+            }
         }
         Ok(())
     }
 
     pub async fn publish(&self, topic: &str, payload: &str) -> Result<()> {
-        if let Some(client) = &self.client {
-            client
-                .publish(topic, QoS::AtLeastOnce, false, payload)
-                .await?;
-        }
-        Ok(())
+        self.publish_bytes(topic, payload.as_bytes().to_vec()).await
+    }
+
+    pub async fn publish_bytes(&self, topic: &str, payload: Vec<u8>) -> Result<()> {
+        let client = self
+            .client
+            .as_ref()
+            .ok_or_else(|| anyhow!("Remote client not started"))?;
+        client.publish(topic, payload).await
     }
 
     pub async fn stop(&self) {
         self.running.store(false, Ordering::SeqCst);
         if let Some(client) = &self.client {
             if let Err(e) = client.disconnect().await {
-                error!("Failed to disconnect from AWS IoT broker: {}", e);
+                error!("Failed to disconnect from {}: {}", self.backend.name(), e);
             }
         }
     }
+
+    /// Call the RPC method served on `topic`, see [`crate::iot::rpc`].
+    pub async fn call<P, R>(&self, topic: &str, params: &P, timeout: Duration) -> Result<R>
+    where
+        P: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        let client = self
+            .client()
+            .ok_or_else(|| anyhow!("Cannot call {}: client not connected", topic))?;
+        let reply_topic = self.rpc.reply_topic();
+        if !self.router.has_filter(reply_topic) {
+            self.on(reply_topic, self.rpc.reply_handler()).await?;
+        }
+        self.rpc.call(&client, topic, params, timeout).await
+    }
+
+    /// Answer RPC calls on `topic` with `handler`.
+    pub async fn serve<P, R, F, Fut>(&self, topic: &str, handler: F) -> Result<HandlerId>
+    where
+        P: DeserializeOwned + Send + 'static,
+        R: Serialize + Send + 'static,
+        F: Fn(P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R>> + Send + 'static,
+    {
+        let client = self
+            .client()
+            .ok_or_else(|| anyhow!("Cannot serve {}: client not connected", topic))?;
+        self.on(topic, rpc::serve(client, handler)).await
+    }
+}
+
+/// Prepare `backend` and get its options, retrying with backoff until it
+/// works. `None` once the client is stopped.
+async fn prepare(backend: &dyn RemoteBackend, running: &AtomicBool) -> Option<ConnectOptions> {
+    let mut delay = Duration::from_secs(1);
+    while running.load(Ordering::SeqCst) {
+        let options = match backend.prepare().await {
+            Ok(()) => backend.options().await,
+            Err(e) => Err(e),
+        };
+        match options {
+            Ok(options) => return Some(options),
+            Err(e) => {
+                error!(
+                    "Failed to prepare {}, retrying in {}s: {:#}",
+                    backend.name(),
+                    delay.as_secs(),
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_PREPARE_BACKOFF);
+            }
+        }
+    }
+    None
+}
+
+fn v4_options(client_id: String, options: ConnectOptions) -> rumqttc::MqttOptions {
+    let mut mqtt_options = rumqttc::MqttOptions::new(client_id, options.host, options.port);
+    mqtt_options
+        .set_keep_alive(options.keep_alive)
+        .set_clean_session(true)
+        .set_transport(options.transport);
+    if let Some((username, password)) = options.credentials {
+        mqtt_options.set_credentials(username, password);
+    }
+    mqtt_options
+}
+
+fn v5_options(client_id: String, options: ConnectOptions) -> rumqttc::v5::MqttOptions {
+    let mut mqtt_options = rumqttc::v5::MqttOptions::new(client_id, options.host, options.port);
+    mqtt_options
+        .set_keep_alive(options.keep_alive)
+        .set_clean_start(true)
+        .set_transport(options.transport);
+    if let Some((username, password)) = options.credentials {
+        mqtt_options.set_credentials(username, password);
+    }
+    mqtt_options
+}
+
+/// Reconnects a [`RemoteIotClient`] without invalidating its client handles.
+#[derive(Clone)]
+pub struct Reconnector {
+    backend: Arc<dyn RemoteBackend>,
+    reload: mpsc::UnboundedSender<ConnectOptions>,
+    connections: broadcast::Sender<()>,
+}
+
+impl Reconnector {
+    /// Drop the connection, connect again with the backend's current
+    /// options and wait up to `timeout` for the broker to accept it.
+    pub async fn reconnect(&self, timeout: Duration) -> Result<()> {
+        let options = self.backend.options().await?;
+        let mut connections = self.connections.subscribe();
+        self.reload
+            .send(options)
+            .map_err(|_| anyhow!("Remote link stopped"))?;
+        tokio::time::timeout(timeout, connections.recv())
+            .await
+            .map_err(|_| anyhow!("Not connected within {}s", timeout.as_secs()))?
+            .map_err(|e| anyhow!("Connection events lost: {}", e))
+    }
+}
+
+impl Link {
+    async fn run_v4(
+        self,
+        mut eventloop: rumqttc::EventLoop,
+        mut reloads: mpsc::UnboundedReceiver<ConnectOptions>,
+    ) {
+        use rumqttc::{Event, Outgoing, Packet};
+
+        debug!("Starting iot event loop...");
+        while self.running.load(Ordering::SeqCst) {
+            self.liveness
+                .step(eventloop.mqtt_options.keep_alive() * 2 + LIVENESS_MARGIN);
+            let event = tokio::select! {
+                event = eventloop.poll() => event,
+                Some(options) = reloads.recv() => {
+                    info!("[IOT]Reconnecting with new options");
+                    eventloop.mqtt_options = v4_options(self.client_id.clone(), options);
+                    eventloop.clean();
+                    continue;
+                }
+            };
+            metrics::MQTT_REPLAY
+                .with_label_values(&[LINK])
+                .set(eventloop.pending.len() as i64);
+            match event {
+                Ok(Event::Incoming(Packet::SubAck(_))) => {
+                    debug!("Subscription confirmed by iot");
+                }
+                Ok(Event::Incoming(Packet::ConnAck(_))) => self.on_connected().await,
+                Ok(Event::Incoming(Packet::Publish(p))) => {
+                    self.on_publish(Message::new(p.topic, p.payload));
+                }
+                Ok(Event::Outgoing(Outgoing::Publish(_))) => self.on_published(),
+                Ok(_) => {}
+                Err(e) => self.on_error(e).await,
+            }
+        }
+        self.liveness.idle();
+    }
+
+    async fn run_v5(
+        self,
+        mut eventloop: rumqttc::v5::EventLoop,
+        mut reloads: mpsc::UnboundedReceiver<ConnectOptions>,
+    ) {
+        use rumqttc::v5::mqttbytes::v5::Packet;
+        use rumqttc::v5::Event;
+        use rumqttc::Outgoing;
+
+        debug!("Starting iot event loop (MQTT v5)...");
+        while self.running.load(Ordering::SeqCst) {
+            self.liveness
+                .step(eventloop.options.keep_alive() * 2 + LIVENESS_MARGIN);
+            let event = tokio::select! {
+                event = eventloop.poll() => event,
+                Some(options) = reloads.recv() => {
+                    info!("[IOT]Reconnecting with new options");
+                    eventloop.options = v5_options(self.client_id.clone(), options);
+                    eventloop.clean();
+                    continue;
+                }
+            };
+            metrics::MQTT_REPLAY
+                .with_label_values(&[LINK])
+                .set(eventloop.pending.len() as i64);
+            match event {
+                Ok(Event::Incoming(Packet::SubAck(_))) => {
+                    debug!("Subscription confirmed by iot");
+                }
+                Ok(Event::Incoming(Packet::ConnAck(_))) => self.on_connected().await,
+                Ok(Event::Incoming(Packet::Publish(p))) => {
+                    let topic = String::from_utf8_lossy(&p.topic).to_string();
+                    let message = Message::new(topic, p.payload);
+                    self.on_publish(match p.properties {
+                        Some(properties) => message.with_properties(properties.into()),
+                        None => message,
+                    });
+                }
+                Ok(Event::Outgoing(Outgoing::Publish(_))) => self.on_published(),
+                Ok(_) => {}
+                Err(e) => self.on_error(e).await,
+            }
+        }
+        self.liveness.idle();
+    }
+
+    async fn on_connected(&self) {
+        debug!("[IOT]Connected..... ");
+        // Clean sessions drop subscriptions on reconnect.
+        for topic in self.subscriptions.lock().await.iter() {
+            if let Err(e) = self.client.subscribe(topic).await {
+                error!("[IOT]Failed to resubscribe to {}: {:?}", topic, e);
+            }
+        }
+        self.connected.send_replace(true);
+        let _ = self.connections.send(());
+    }
+
+    fn on_published(&self) {
+        metrics::MQTT_PUBLISHED.with_label_values(&[LINK]).inc();
+    }
+
+    fn on_publish(&self, message: Message) {
+        metrics::MQTT_RECEIVED.with_label_values(&[LINK]).inc();
+        debug!(
+            "[IOT]Received message - Topic: {}, Payload: {:?}",
+            message.topic,
+            message.text_lossy()
+        );
+        self.router.route(message);
+    }
+
+    async fn on_error(&self, e: impl std::fmt::Debug) {
+        metrics::MQTT_CONNECTION_ERRORS
+            .with_label_values(&[LINK])
+            .inc();
+        self.connected.send_replace(false);
+        error!("[IOT]MQTT Error: {:?}", e);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
 }
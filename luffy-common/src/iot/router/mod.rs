@@ -0,0 +1,257 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info_span, Instrument, Span};
+
+use crate::otel;
+
+/// An incoming MQTT message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Bytes,
+    /// Only set on MQTT v5 connections.
+    pub properties: MessageProperties,
+}
+
+/// The MQTT v5 publish properties luffy uses.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessageProperties {
+    pub response_topic: Option<String>,
+    pub correlation_data: Option<Bytes>,
+    pub user_properties: Vec<(String, String)>,
+}
+
+impl MessageProperties {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+impl Message {
+    pub fn new(topic: impl Into<String>, payload: impl Into<Bytes>) -> Self {
+        Self {
+            topic: topic.into(),
+            payload: payload.into(),
+            properties: MessageProperties::default(),
+        }
+    }
+
+    pub fn with_properties(mut self, properties: MessageProperties) -> Self {
+        self.properties = properties;
+        self
+    }
+
+    /// The payload as text, `None` for binary payloads that are not UTF-8.
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.payload).ok()
+    }
+
+    /// The payload as text, with invalid UTF-8 replaced.
+    pub fn text_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.payload).to_string()
+    }
+}
+
+/// Handles messages routed to it by a [`TopicRouter`].
+///
+/// Implemented for async closures taking a [`Message`], so handlers can
+/// capture the state they work on.
+#[async_trait]
+pub trait MessageHandler: Send + Sync {
+    async fn handle(&self, message: Message) -> Result<()>;
+}
+
+#[async_trait]
+impl<F, Fut> MessageHandler for F
+where
+    F: Fn(Message) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<()>> + Send,
+{
+    async fn handle(&self, message: Message) -> Result<()> {
+        self(message).await
+    }
+}
+
+/// Identifies a registered handler so it can be removed again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HandlerId(u64);
+
+type Queue = mpsc::UnboundedSender<(Message, Span)>;
+
+struct Route {
+    id: HandlerId,
+    filter: String,
+    handler: Arc<dyn MessageHandler>,
+    /// Feeds the task running the handler, started by the first message
+    /// routed to it. The task ends when the route is removed.
+    queue: OnceLock<Queue>,
+}
+
+impl Route {
+    fn queue(&self) -> &Queue {
+        self.queue.get_or_init(|| {
+            let (tx, mut rx) = mpsc::unbounded_channel::<(Message, Span)>();
+            let handler = self.handler.clone();
+            tokio::spawn(async move {
+                while let Some((message, span)) = rx.recv().await {
+                    handle(handler.as_ref(), message).instrument(span).await;
+                }
+            });
+            tx
+        })
+    }
+}
+
+async fn handle(handler: &dyn MessageHandler, message: Message) {
+    let topic = message.topic.clone();
+    if let Err(e) = handler.handle(message).await {
+        error!("Failed to handle message on {}: {:#}", topic, e);
+    }
+}
+
+/// The span handlers run in, continuing the trace carried in the message's
+/// user properties.
+fn receive_span(message: &Message) -> Span {
+    let span = info_span!(parent: None, "mqtt.receive", topic = %message.topic);
+    otel::set_parent(&span, &message.properties.user_properties);
+    span
+}
+
+/// Dispatches messages to the handlers whose MQTT topic filter matches.
+///
+/// Cheap to clone; clones share the same routes.
+#[derive(Clone, Default)]
+pub struct TopicRouter {
+    routes: Arc<RwLock<Vec<Route>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl fmt::Debug for TopicRouter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TopicRouter")
+            .field("filters", &self.filters())
+            .finish()
+    }
+}
+
+impl TopicRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, filter: &str, handler: impl MessageHandler + 'static) -> HandlerId {
+        let id = HandlerId(self.next_id.fetch_add(1, Ordering::SeqCst));
+        self.routes.write().unwrap().push(Route {
+            id,
+            filter: filter.to_string(),
+            handler: Arc::new(handler),
+            queue: OnceLock::new(),
+        });
+        id
+    }
+
+    /// Remove a handler, returning the filter it was registered for.
+    pub fn remove(&self, id: HandlerId) -> Option<String> {
+        let mut routes = self.routes.write().unwrap();
+        let index = routes.iter().position(|route| route.id == id)?;
+        Some(routes.remove(index).filter)
+    }
+
+    /// Whether any handler is registered for exactly this filter.
+    pub fn has_filter(&self, filter: &str) -> bool {
+        self.routes
+            .read()
+            .unwrap()
+            .iter()
+            .any(|route| route.filter == filter)
+    }
+
+    /// Distinct filters with at least one handler.
+    pub fn filters(&self) -> Vec<String> {
+        let mut filters: Vec<String> = Vec::new();
+        for route in self.routes.read().unwrap().iter() {
+            if !filters.contains(&route.filter) {
+                filters.push(route.filter.clone());
+            }
+        }
+        filters
+    }
+
+    /// Run every matching handler, in registration order. Handler errors are
+    /// logged. Returns the number of handlers that ran.
+    ///
+    /// Handlers run in an `mqtt.receive` span, continuing the trace carried
+    /// in the message's user properties.
+    pub async fn dispatch(&self, message: Message) -> usize {
+        let handlers: Vec<Arc<dyn MessageHandler>> = self
+            .routes
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|route| topic_matches(&route.filter, &message.topic))
+            .map(|route| route.handler.clone())
+            .collect();
+
+        if handlers.is_empty() {
+            debug!("No handler for {}", message.topic);
+            return 0;
+        }
+        async {
+            for handler in &handlers {
+                handle(handler.as_ref(), message.clone()).await;
+            }
+        }
+        .instrument(receive_span(&message))
+        .await;
+        handlers.len()
+    }
+
+    /// Hand the message to each matching handler's task, so a slow handler
+    /// neither holds up the MQTT event loop nor the other handlers. Each
+    /// handler still gets its messages one at a time, in the order they
+    /// arrived.
+    pub fn route(&self, message: Message) {
+        let routes = self.routes.read().unwrap();
+        let mut matched = routes
+            .iter()
+            .filter(|route| topic_matches(&route.filter, &message.topic))
+            .peekable();
+        if matched.peek().is_none() {
+            debug!("No handler for {}", message.topic);
+            return;
+        }
+        let span = receive_span(&message);
+        for route in matched {
+            // Only fails after the handler's task panicked.
+            let _ = route.queue().send((message.clone(), span.clone()));
+        }
+    }
+}
+
+/// MQTT topic filter matching with `+` and `#` wildcards. Topics starting
+/// with `$`, like `$aws/...`, only match filters naming their first level
+/// (MQTT 3.1.1 §4.7.2).
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+    let mut topic_levels = topic.split('/');
+    for filter_level in filter.split('/') {
+        match (filter_level, topic_levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (level, Some(topic_level)) if level == topic_level => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
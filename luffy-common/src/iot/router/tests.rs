@@ -0,0 +1,116 @@
+use super::*;
+use anyhow::anyhow;
+use tokio::sync::Mutex;
+
+#[test]
+fn test_topic_matches() {
+    assert!(topic_matches("luffy/+/health", "luffy/gateway/health"));
+    assert!(!topic_matches("luffy/+/health", "luffy/a/b/health"));
+    assert!(topic_matches("+/telemetry", "vessel-1/telemetry"));
+    assert!(!topic_matches("+/telemetry", "vessel-1/telemetry/cbor"));
+    assert!(topic_matches("vessel-1/command/#", "vessel-1/command/mode"));
+    assert!(topic_matches("vessel-1/command/#", "vessel-1/command"));
+    assert!(!topic_matches(
+        "vessel-1/command/#",
+        "vessel-2/command/mode"
+    ));
+    assert!(topic_matches("#", "anything/at/all"));
+    assert!(!topic_matches("#", "$aws/things/vessel-1/shadow/update"));
+    assert!(!topic_matches("+/things/#", "$aws/things/vessel-1/jobs"));
+    assert!(topic_matches(
+        "$aws/things/+/jobs",
+        "$aws/things/vessel-1/jobs"
+    ));
+}
+
+#[tokio::test]
+async fn test_dispatch_to_matching_handlers() {
+    let router = TopicRouter::new();
+    let received = Arc::new(Mutex::new(Vec::new()));
+
+    let log = received.clone();
+    router.add("+/telemetry", move |message: Message| {
+        let log = log.clone();
+        async move {
+            log.lock().await.push(message.text().unwrap().to_string());
+            Ok(())
+        }
+    });
+    router.add("vessel-1/#", |_: Message| async {
+        Err(anyhow!("failing handler"))
+    });
+
+    assert_eq!(
+        router
+            .dispatch(Message::new("vessel-1/telemetry", "state"))
+            .await,
+        2
+    );
+    assert_eq!(
+        router.dispatch(Message::new("vessel-2/health", "ok")).await,
+        0
+    );
+    assert_eq!(*received.lock().await, vec!["state".to_string()]);
+}
+
+#[tokio::test]
+async fn test_remove_handler() {
+    let router = TopicRouter::new();
+    let first = router.add("a/b", |_: Message| async { Ok(()) });
+    let second = router.add("a/b", |_: Message| async { Ok(()) });
+    assert_eq!(router.filters(), vec!["a/b".to_string()]);
+
+    assert_eq!(router.remove(first), Some("a/b".to_string()));
+    assert!(router.has_filter("a/b"));
+    assert_eq!(router.remove(first), None);
+
+    router.remove(second);
+    assert!(!router.has_filter("a/b"));
+    assert_eq!(router.dispatch(Message::new("a/b", "x")).await, 0);
+}
+
+#[tokio::test]
+async fn test_route_keeps_order_per_handler() {
+    let router = TopicRouter::new();
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    router.add("vessel-1/command/+", move |message: Message| {
+        let tx = tx.clone();
+        async move {
+            // The first message is the slowest.
+            if message.text() == Some("0") {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+            tx.send(message.text_lossy())?;
+            Ok(())
+        }
+    });
+    let (other_tx, mut other_rx) = tokio::sync::mpsc::unbounded_channel();
+    router.add("vessel-1/#", move |message: Message| {
+        let other_tx = other_tx.clone();
+        async move {
+            other_tx.send(message.text_lossy())?;
+            Ok(())
+        }
+    });
+
+    for i in 0..20 {
+        router.route(Message::new("vessel-1/command/mode", i.to_string()));
+    }
+    // The other handler is not held up by the slow one.
+    assert_eq!(other_rx.recv().await.unwrap(), "0");
+    assert!(rx.try_recv().is_err());
+
+    let mut received = Vec::new();
+    for _ in 0..20 {
+        received.push(rx.recv().await.unwrap());
+    }
+    let expected: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn test_binary_payload() {
+    let message = Message::new("t", vec![0xff, 0x00]);
+    assert_eq!(message.text(), None);
+    assert_eq!(message.payload.as_ref(), &[0xff, 0x00]);
+}
@@ -0,0 +1,260 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tracing::{debug, info_span, warn, Instrument};
+use uuid::Uuid;
+
+use crate::iot::client::MqttClient;
+use crate::iot::router::{Message, MessageHandler, MessageProperties};
+use crate::otel;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Published on the method topic. `reply_to` is always set so v4 servers can
+/// answer; v5 connections also carry it as the response topic. `headers`
+/// carry the caller's trace context, which v4 connections cannot send as
+/// user properties.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request<P> {
+    pub id: String,
+    pub reply_to: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    pub params: P,
+}
+
+/// Published on the caller's reply topic; exactly one of `result` and
+/// `error` is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    fn ok(id: String, result: Value) -> Self {
+        Self {
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    fn err(id: String, error: &anyhow::Error) -> Self {
+        Self {
+            id,
+            result: None,
+            error: Some(format!("{:#}", error)),
+        }
+    }
+
+    pub fn into_result<R: DeserializeOwned>(self) -> Result<R> {
+        if let Some(error) = self.error {
+            return Err(anyhow!(error));
+        }
+        serde_json::from_value(self.result.unwrap_or(Value::Null)).context("Invalid RPC result")
+    }
+}
+
+type Pending = Arc<Mutex<HashMap<String, oneshot::Sender<Response>>>>;
+
+/// Caller side of the RPC layer. Tracks the calls waiting for a reply on
+/// `reply_topic`, which the owning client subscribes with
+/// [`Self::reply_handler`].
+#[derive(Debug, Clone)]
+pub struct RpcClient {
+    reply_topic: String,
+    pending: Pending,
+}
+
+impl RpcClient {
+    /// `prefix` namespaces the reply topic, a random suffix keeps clients
+    /// with the same name apart.
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            reply_topic: format!("{}/rpc/reply/{}", prefix, Uuid::new_v4().simple()),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn reply_topic(&self) -> &str {
+        &self.reply_topic
+    }
+
+    pub fn reply_handler(&self) -> impl MessageHandler {
+        let pending = self.pending.clone();
+        move |message: Message| {
+            let pending = pending.clone();
+            async move {
+                let response: Response =
+                    serde_json::from_slice(&message.payload).context("Invalid RPC response")?;
+                match pending.lock().unwrap().remove(&response.id) {
+                    Some(caller) => {
+                        let _ = caller.send(response);
+                    }
+                    None => debug!("Dropping late RPC response {}", response.id),
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Call the method served on `topic` and wait up to `timeout` for its
+    /// result.
+    pub async fn call<P, R>(
+        &self,
+        client: &MqttClient,
+        topic: &str,
+        params: &P,
+        timeout: Duration,
+    ) -> Result<R>
+    where
+        P: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        let id = Uuid::new_v4().to_string();
+        let span = info_span!("rpc.call", topic, id = %id);
+        self.send(client, topic, params, timeout, id)
+            .instrument(span)
+            .await
+    }
+
+    async fn send<P, R>(
+        &self,
+        client: &MqttClient,
+        topic: &str,
+        params: &P,
+        timeout: Duration,
+        id: String,
+    ) -> Result<R>
+    where
+        P: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        let request = Request {
+            id: id.clone(),
+            reply_to: self.reply_topic.clone(),
+            headers: otel::current_context().into_iter().collect(),
+            params,
+        };
+        let properties = MessageProperties {
+            response_topic: Some(self.reply_topic.clone()),
+            correlation_data: Some(Bytes::from(id.clone())),
+            ..Default::default()
+        };
+
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id.clone(), tx);
+        debug!("RPC call {} on {}", id, topic);
+
+        let published = client
+            .publish_with(topic, serde_json::to_vec(&request)?, &properties)
+            .await;
+        if let Err(e) = published {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e.context(format!("Failed to send RPC request to {}", topic)));
+        }
+
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(response)) => response
+                .into_result()
+                .with_context(|| format!("RPC {} failed", topic)),
+            Ok(Err(_)) => Err(anyhow!("RPC {} was dropped", topic)),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err(anyhow!("RPC {} timed out after {:?}", topic, timeout))
+            }
+        }
+    }
+}
+
+/// An [`RpcClient`] bound to a connection, for calls that should not hold on
+/// to the client owning it while waiting for the result.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    client: MqttClient,
+    rpc: RpcClient,
+}
+
+impl Caller {
+    pub fn new(client: MqttClient, rpc: RpcClient) -> Self {
+        Self { client, rpc }
+    }
+
+    pub async fn call<P, R>(&self, topic: &str, params: &P, timeout: Duration) -> Result<R>
+    where
+        P: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        self.rpc.call(&self.client, topic, params, timeout).await
+    }
+}
+
+/// Wrap `handler` into a [`MessageHandler`] that answers requests on the
+/// topic it is registered for, replying through `client`.
+pub fn serve<P, R, F, Fut>(client: MqttClient, handler: F) -> impl MessageHandler
+where
+    P: DeserializeOwned + Send + 'static,
+    R: Serialize + Send + 'static,
+    F: Fn(P) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<R>> + Send + 'static,
+{
+    let handler = Arc::new(handler);
+    move |message: Message| {
+        let client = client.clone();
+        let handler = handler.clone();
+        async move {
+            let request: Request<Value> =
+                serde_json::from_slice(&message.payload).context("Invalid RPC request")?;
+            let reply_to = message
+                .properties
+                .response_topic
+                .clone()
+                .unwrap_or(request.reply_to);
+
+            // The headers also carry the trace over v4 connections.
+            let span = info_span!("rpc.serve", topic = %message.topic, id = %request.id);
+            let headers: Vec<_> = request.headers.into_iter().collect();
+            otel::set_parent(&span, &headers);
+
+            async move {
+                let result = match serde_json::from_value::<P>(request.params) {
+                    Ok(params) => handler(params)
+                        .await
+                        .and_then(|result| Ok(serde_json::to_value(result)?)),
+                    Err(e) => Err(anyhow!(e).context("Invalid RPC params")),
+                };
+                let response = match result {
+                    Ok(result) => Response::ok(request.id, result),
+                    Err(e) => {
+                        warn!("RPC {} on {} failed: {:#}", request.id, message.topic, e);
+                        Response::err(request.id, &e)
+                    }
+                };
+
+                let properties = MessageProperties {
+                    correlation_data: message.properties.correlation_data,
+                    ..Default::default()
+                };
+                client
+                    .publish_with(&reply_to, serde_json::to_vec(&response)?, &properties)
+                    .await
+            }
+            .instrument(span)
+            .await
+        }
+    }
+}
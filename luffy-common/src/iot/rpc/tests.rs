@@ -0,0 +1,130 @@
+use super::*;
+use crate::config::{MqttBrokerConfig, MqttProtocol};
+use crate::iot::backend::GenericMqttBackend;
+use crate::iot::local::LocalIotClient;
+use crate::iot::remote::RemoteIotClient;
+use crate::iot::testing::start_broker;
+use serde_json::json;
+use std::sync::LazyLock;
+
+/// The v4 and v5 ports of the broker shared by the tests.
+fn broker() -> (u16, u16) {
+    static BROKER: LazyLock<(u16, u16)> = LazyLock::new(|| start_broker(false));
+    *BROKER
+}
+
+async fn local_client(name: &str) -> LocalIotClient {
+    let (v4_port, _) = broker();
+    let mut client = LocalIotClient::new(
+        name.to_string(),
+        "127.0.0.1".to_string(),
+        v4_port,
+        60,
+        "test".to_string(),
+    );
+    client.connect().await.unwrap();
+    client
+}
+
+async fn remote_client(vehicle_id: &str) -> RemoteIotClient {
+    let (_, v5_port) = broker();
+    let backend = GenericMqttBackend::new(MqttBrokerConfig {
+        host: "127.0.0.1".to_string(),
+        port: v5_port,
+        protocol: MqttProtocol::V5,
+        keep_alive: 5,
+        tls: false,
+        ca_path: None,
+        client_cert_path: None,
+        client_key_path: None,
+        username: None,
+        password: None,
+    });
+    let mut client = RemoteIotClient::new(vehicle_id.to_string(), Box::new(backend));
+    let mut connections = client.connections();
+    client.start().await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), connections.recv())
+        .await
+        .expect("not connected")
+        .unwrap();
+    client
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Add {
+    a: i64,
+    b: i64,
+}
+
+#[tokio::test]
+async fn test_call_over_v4() {
+    let server = local_client("rpc-server-v4").await;
+    let caller = local_client("rpc-caller-v4").await;
+
+    server
+        .serve("vessel-1/rpc/add", |params: Add| async move {
+            Ok(params.a + params.b)
+        })
+        .await
+        .unwrap();
+    server
+        .serve("vessel-1/rpc/fail", |_: Value| async move {
+            Err::<(), _>(anyhow!("vehicle is armed"))
+        })
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let sum: i64 = caller
+        .call("vessel-1/rpc/add", &Add { a: 2, b: 3 }, DEFAULT_TIMEOUT)
+        .await
+        .unwrap();
+    assert_eq!(sum, 5);
+
+    let error = caller
+        .call::<_, ()>("vessel-1/rpc/fail", &json!({}), DEFAULT_TIMEOUT)
+        .await
+        .unwrap_err();
+    assert!(format!("{:#}", error).contains("vehicle is armed"));
+
+    let error = caller
+        .call::<_, i64>("vessel-1/rpc/add", &json!({ "a": 1 }), DEFAULT_TIMEOUT)
+        .await
+        .unwrap_err();
+    assert!(format!("{:#}", error).contains("Invalid RPC params"));
+}
+
+#[tokio::test]
+async fn test_call_times_out_without_server() {
+    let caller = local_client("rpc-caller-timeout").await;
+
+    let error = caller
+        .call::<_, ()>(
+            "vessel-1/rpc/nobody",
+            &json!({}),
+            Duration::from_millis(300),
+        )
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("timed out"));
+}
+
+#[tokio::test]
+async fn test_call_over_v5() {
+    let server = remote_client("vessel-v5-server").await;
+    let caller = remote_client("vessel-v5-caller").await;
+
+    server
+        .serve("vessel-2/rpc/add", |params: Add| async move {
+            Ok(params.a + params.b)
+        })
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let sum: i64 = caller
+        .call("vessel-2/rpc/add", &Add { a: 40, b: 2 }, DEFAULT_TIMEOUT)
+        .await
+        .unwrap();
+    assert_eq!(sum, 42);
+}
@@ -0,0 +1,67 @@
+use std::net::TcpListener;
+
+/// Ports free at the time of the call, for brokers that cannot bind to
+/// port 0 and report the one they got.
+pub(crate) fn free_ports<const N: usize>() -> [u16; N] {
+    // Held until all are picked so they differ.
+    let listeners: Vec<_> = (0..N)
+        .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+        .collect();
+    std::array::from_fn(|i| listeners[i].local_addr().unwrap().port())
+}
+
+/// Start a broker with a v4 and a v5 listener, returning their ports. With
+/// `auth`, both require user `fleet` with password `secret`.
+pub(crate) fn start_broker(auth: bool) -> (u16, u16) {
+    let [v4_port, v5_port] = free_ports();
+    let auth = if auth {
+        r#"auth = { fleet = "secret" }"#
+    } else {
+        ""
+    };
+    let toml = format!(
+        r#"
+        id = 0
+        [router]
+        id = 0
+        max_connections = 100
+        max_outgoing_packet_count = 200
+        max_segment_size = 104857600
+        max_segment_count = 10
+        [v4.1]
+        name = "v4-1"
+        listen = "127.0.0.1:{}"
+        next_connection_delay_ms = 1
+        [v4.1.connections]
+        connection_timeout_ms = 60000
+        max_payload_size = 20480
+        max_inflight_count = 100
+        dynamic_filters = true
+        {auth}
+        [v5.1]
+        name = "v5-1"
+        listen = "127.0.0.1:{}"
+        next_connection_delay_ms = 1
+        [v5.1.connections]
+        connection_timeout_ms = 60000
+        max_payload_size = 20480
+        max_inflight_count = 100
+        dynamic_filters = true
+        {auth}
+        "#,
+        v4_port,
+        v5_port,
+        auth = auth
+    );
+    let config: rumqttd::Config = config::Config::builder()
+        .add_source(config::File::from_str(&toml, config::FileFormat::Toml))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+    std::thread::spawn(move || {
+        let mut broker = rumqttd::Broker::new(config);
+        broker.start().unwrap();
+    });
+    (v4_port, v5_port)
+}
@@ -1,6 +1,21 @@
 pub mod config;
+pub mod config_check;
+pub mod config_update;
+pub mod config_watch;
+pub mod health;
+pub mod identity;
 pub mod iot;
+pub mod log_filter;
+pub mod logging;
+pub mod metrics;
+pub mod otel;
+pub mod provisioning;
+pub mod secrets;
+pub mod supervisor;
+pub mod systemd;
 pub mod aws;
+pub mod telemetry;
+pub mod upload;
 pub mod util;
 
 pub mod ota;
@@ -0,0 +1,256 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::iot::local::LocalIotClient;
+use crate::iot::router::HandlerId;
+
+/// The filter of the service's log, set up by [`crate::util::setup_logging`].
+pub static LOG_FILTER: LogFilter = LogFilter::new();
+
+/// How long directives stay when the call gives no duration.
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(600);
+/// Directives never stay longer, so a forgotten `trace` does not fill the
+/// disk.
+pub const MAX_DURATION: Duration = Duration::from_secs(24 * 3600);
+
+/// Always added below the level, which they would otherwise flood.
+const BASE_DIRECTIVES: [&str; 4] = [
+    "tokio=debug",
+    "runtime=debug",
+    "rumqttc=info",
+    "rumqttd=info",
+];
+
+/// Served by each service, changing its own filter.
+pub fn topic(vehicle_id: &str, service: &str) -> String {
+    format!("{}/rpc/log/{}", vehicle_id, service)
+}
+
+/// Served by the gateway on the remote link, forwarding to the service named
+/// in the call.
+pub fn remote_topic(vehicle_id: &str) -> String {
+    format!("{}/rpc/log/filter", vehicle_id)
+}
+
+/// Params of the log filter RPCs. Without `directives` the call only reads
+/// the filter; an empty string reverts to the configured level.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LogFilterChange {
+    /// Which service, for the forwarded call.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub service: String,
+    /// `EnvFilter` directives, e.g. `luffy_gateway::mav_server=trace`.
+    #[serde(default)]
+    pub directives: Option<String>,
+    /// Seconds until they are reverted, 600 by default.
+    #[serde(default)]
+    pub duration: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogFilterStatus {
+    /// The configured level.
+    pub level: String,
+    /// Directives applied on top, until reverted.
+    pub directives: Option<String>,
+    /// Seconds until they are.
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Override {
+    directives: String,
+    expires: Instant,
+    generation: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    level: String,
+    directives: Option<Override>,
+    generation: u64,
+}
+
+/// A reloadable `EnvFilter`: the configured level, plus directives raised
+/// for a while and then reverted on their own.
+pub struct LogFilter {
+    handle: OnceLock<reload::Handle<EnvFilter, Registry>>,
+    state: Mutex<State>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogFilter {
+    pub const fn new() -> Self {
+        Self {
+            handle: OnceLock::new(),
+            state: Mutex::new(State {
+                level: String::new(),
+                directives: None,
+                generation: 0,
+            }),
+        }
+    }
+
+    /// The filter, at `log_level`, for the layers under the service's level.
+    /// Only the first one is controlled.
+    pub fn layer(&self, log_level: &str) -> reload::Layer<EnvFilter, Registry> {
+        let (layer, handle) =
+            reload::Layer::new(build_filter(log_level, None).expect("Invalid log level"));
+        if self.handle.set(handle).is_ok() {
+            self.state.lock().unwrap().level = log_level.to_string();
+        }
+        layer
+    }
+
+    /// Change the configured level, keeping directives applied on top.
+    pub fn set_level(&self, log_level: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let directives = state.directives.as_ref().map(|o| o.directives.as_str());
+        self.reload(build_filter(log_level, directives)?)?;
+        state.level = log_level.to_string();
+        Ok(())
+    }
+
+    /// Apply `directives` on top of the level for `duration`, replacing
+    /// those applied before. Must be called within a Tokio runtime.
+    pub fn raise(&'static self, directives: &str, duration: Duration) -> Result<LogFilterStatus> {
+        if duration.is_zero() || duration > MAX_DURATION {
+            bail!(
+                "Duration must be between 1 and {} seconds",
+                MAX_DURATION.as_secs()
+            );
+        }
+        let mut state = self.state.lock().unwrap();
+        self.reload(build_filter(&state.level, Some(directives))?)?;
+        state.generation += 1;
+        let generation = state.generation;
+        state.directives = Some(Override {
+            directives: directives.to_string(),
+            expires: Instant::now() + duration,
+            generation,
+        });
+        info!("Log directives {} applied for {:?}", directives, duration);
+
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            self.expire(generation);
+        });
+        Ok(status(&state))
+    }
+
+    /// Go back to the configured level.
+    pub fn revert(&self) -> Result<LogFilterStatus> {
+        let mut state = self.state.lock().unwrap();
+        self.clear(&mut state)?;
+        Ok(status(&state))
+    }
+
+    pub fn status(&self) -> LogFilterStatus {
+        status(&self.state.lock().unwrap())
+    }
+
+    /// Answer a [`LogFilterChange`].
+    pub fn apply(&'static self, change: &LogFilterChange) -> Result<LogFilterStatus> {
+        match change.directives.as_deref().map(str::trim) {
+            None => Ok(self.status()),
+            Some("") => self.revert(),
+            Some(directives) => self.raise(
+                directives,
+                change
+                    .duration
+                    .map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_DURATION),
+            ),
+        }
+    }
+
+    /// Revert the directives applied as `generation`, unless replaced since.
+    fn expire(&self, generation: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .directives
+            .as_ref()
+            .is_some_and(|o| o.generation == generation)
+        {
+            if let Err(e) = self.clear(&mut state) {
+                warn!("Failed to revert log filter: {:#}", e);
+            }
+        }
+    }
+
+    fn clear(&self, state: &mut State) -> Result<()> {
+        if state.directives.is_some() {
+            self.reload(build_filter(&state.level, None)?)?;
+            state.directives = None;
+            info!("Log filter reverted to {}", state.level);
+        }
+        Ok(())
+    }
+
+    fn reload(&self, filter: EnvFilter) -> Result<()> {
+        self.handle
+            .get()
+            .context("Logging is not set up")?
+            .reload(filter)?;
+        Ok(())
+    }
+}
+
+fn status(state: &State) -> LogFilterStatus {
+    LogFilterStatus {
+        level: state.level.clone(),
+        directives: state.directives.as_ref().map(|o| o.directives.clone()),
+        expires_in: state.directives.as_ref().map(|o| {
+            o.expires
+                .saturating_duration_since(Instant::now())
+                .as_secs()
+        }),
+    }
+}
+
+/// `RUST_LOG`, then the level, the base directives and `directives`, which
+/// win over the others for the targets they name.
+fn build_filter(log_level: &str, directives: Option<&str>) -> Result<EnvFilter> {
+    let mut filter = EnvFilter::from_default_env().add_directive(
+        log_level
+            .parse()
+            .with_context(|| format!("Invalid log level {}", log_level))?,
+    );
+    for directive in BASE_DIRECTIVES {
+        filter = filter.add_directive(directive.parse()?);
+    }
+    for directive in directives.unwrap_or_default().split(',') {
+        let directive = directive.trim();
+        if directive.is_empty() {
+            continue;
+        }
+        filter = filter.add_directive(
+            directive
+                .parse()
+                .with_context(|| format!("Invalid log directive {}", directive))?,
+        );
+    }
+    Ok(filter)
+}
+
+/// Serve [`topic`] for `service`.
+pub async fn serve(client: &LocalIotClient, vehicle_id: &str, service: &str) -> Result<HandlerId> {
+    client
+        .serve(
+            &topic(vehicle_id, service),
+            |change: LogFilterChange| async move { LOG_FILTER.apply(&change) },
+        )
+        .await
+}
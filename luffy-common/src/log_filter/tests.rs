@@ -0,0 +1,92 @@
+use super::*;
+
+/// A filter and its layer, which the handle needs alive.
+fn log_filter(level: &str) -> (&'static LogFilter, reload::Layer<EnvFilter, Registry>) {
+    let filter = Box::leak(Box::new(LogFilter::new()));
+    let layer = filter.layer(level);
+    (filter, layer)
+}
+
+fn current(filter: &LogFilter) -> String {
+    filter
+        .handle
+        .get()
+        .unwrap()
+        .with_current(|f| f.to_string())
+        .unwrap()
+}
+
+fn change(directives: Option<&str>, duration: Option<u64>) -> LogFilterChange {
+    LogFilterChange {
+        directives: directives.map(str::to_string),
+        duration,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_raise_and_revert() {
+    let (filter, _layer) = log_filter("info");
+    let status = filter
+        .apply(&change(Some("luffy_gateway::mav_server=trace"), None))
+        .unwrap();
+    assert_eq!(status.level, "info");
+    assert_eq!(
+        status.directives.as_deref(),
+        Some("luffy_gateway::mav_server=trace")
+    );
+    assert!(status.expires_in.unwrap() > DEFAULT_DURATION.as_secs() - 5);
+    assert!(current(filter).contains("luffy_gateway::mav_server=trace"));
+
+    // The level changes underneath, the directives stay.
+    filter.set_level("warn").unwrap();
+    assert!(current(filter).contains("luffy_gateway::mav_server=trace"));
+    assert_eq!(filter.status().level, "warn");
+
+    let status = filter.apply(&change(Some(""), None)).unwrap();
+    assert_eq!(status.directives, None);
+    assert_eq!(status.expires_in, None);
+    assert!(!current(filter).contains("mav_server"));
+}
+
+#[tokio::test]
+async fn test_invalid_change() {
+    let (filter, _layer) = log_filter("info");
+    assert!(filter
+        .apply(&change(Some("luffy_gateway=loud"), None))
+        .is_err());
+    assert!(filter.apply(&change(Some("luffy=debug"), Some(0))).is_err());
+    assert!(filter
+        .apply(&change(
+            Some("luffy=debug"),
+            Some(MAX_DURATION.as_secs() + 1)
+        ))
+        .is_err());
+    // A rejected change leaves the filter as it was.
+    assert_eq!(filter.status().directives, None);
+    assert!(!current(filter).contains("luffy"));
+
+    let unset = Box::leak(Box::new(LogFilter::new()));
+    assert!(unset.apply(&change(Some("luffy=debug"), None)).is_err());
+}
+
+#[tokio::test]
+async fn test_automatic_revert() {
+    let (filter, _layer) = log_filter("info");
+    filter
+        .raise("luffy_media=debug", Duration::from_millis(50))
+        .unwrap();
+    // Replaced before it expires: the first revert must leave it alone.
+    filter
+        .raise("luffy_media=trace", Duration::from_millis(200))
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(
+        filter.status().directives.as_deref(),
+        Some("luffy_media=trace")
+    );
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(filter.status().directives, None);
+    assert!(!current(filter).contains("luffy_media"));
+}
@@ -0,0 +1,332 @@
+#[cfg(test)]
+mod tests;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::field::{Field, Visit};
+use tracing::{debug, Event, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::config::LogShippingConfig;
+use crate::iot::client::MqttClient;
+use crate::iot::remote::RemoteIotClient;
+
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+static SHIPPER: OnceLock<Shipper> = OnceLock::new();
+
+/// Who logs: added to every JSON line and shipped event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogSource {
+    pub service: String,
+    pub vehicle_id: String,
+}
+
+/// One log event, as written in the JSON format and shipped.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogRecord {
+    pub timestamp: String,
+    pub level: String,
+    pub service: String,
+    pub vehicle_id: String,
+    pub target: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub fields: Map<String, Value>,
+    /// Names of the spans the event happened in, outermost first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spans: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<u32>,
+}
+
+impl LogRecord {
+    pub fn new(event: &Event<'_>, source: &LogSource) -> Self {
+        let metadata = event.metadata();
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        Self {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            level: metadata.level().to_string(),
+            service: source.service.clone(),
+            vehicle_id: source.vehicle_id.clone(),
+            target: metadata.target().to_string(),
+            message: visitor.message,
+            fields: visitor.fields,
+            spans: Vec::new(),
+            file: metadata.file().map(str::to_string),
+            line: metadata.line(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: Map<String, Value>,
+}
+
+impl FieldVisitor {
+    fn insert(&mut self, field: &Field, value: Value) {
+        match field.name() {
+            "message" => {
+                self.message = match value {
+                    Value::String(message) => message,
+                    value => value.to_string(),
+                }
+            }
+            // Added by tracing-log, already in the metadata.
+            name if name.starts_with("log.") => {}
+            name => {
+                self.fields.insert(name.to_string(), value);
+            }
+        }
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, Value::from(format!("{:?}", value)));
+    }
+}
+
+/// Writes each event as a JSON [`LogRecord`] on one line.
+pub struct JsonFormat {
+    source: LogSource,
+}
+
+impl JsonFormat {
+    pub fn new(source: LogSource) -> Self {
+        Self { source }
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut record = LogRecord::new(event, &self.source);
+        if let Some(scope) = ctx.event_scope() {
+            record.spans = scope
+                .from_root()
+                .map(|span| span.name().to_string())
+                .collect();
+        }
+        let line = serde_json::to_string(&record).map_err(|_| fmt::Error)?;
+        writeln!(writer, "{}", line)
+    }
+}
+
+/// Events waiting to be shipped. When full the oldest are dropped.
+struct LogBuffer {
+    records: Mutex<VecDeque<LogRecord>>,
+    capacity: usize,
+    dropped: AtomicU64,
+}
+
+impl LogBuffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            records: Mutex::new(VecDeque::new()),
+            capacity: capacity.max(1),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    fn push(&self, record: LogRecord) {
+        let mut records = self.records.lock().unwrap();
+        records.push_back(record);
+        self.trim(&mut records);
+    }
+
+    /// Take up to `count` of the oldest events.
+    fn take(&self, count: usize) -> Vec<LogRecord> {
+        let mut records = self.records.lock().unwrap();
+        let count = count.min(records.len());
+        records.drain(..count).collect()
+    }
+
+    /// Put events that could not be shipped back in front.
+    fn requeue(&self, batch: Vec<LogRecord>) {
+        let mut records = self.records.lock().unwrap();
+        for record in batch.into_iter().rev() {
+            records.push_front(record);
+        }
+        self.trim(&mut records);
+    }
+
+    fn trim(&self, records: &mut VecDeque<LogRecord>) {
+        while records.len() > self.capacity {
+            records.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Collects events for [`ship`]; filter it to the shipped levels.
+pub struct ShippingLayer {
+    source: LogSource,
+    buffer: Arc<LogBuffer>,
+}
+
+impl<S: Subscriber> Layer<S> for ShippingLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        // Failures to ship would be shipped in turn.
+        if event.metadata().target() == module_path!() {
+            return;
+        }
+        self.buffer.push(LogRecord::new(event, &self.source));
+    }
+}
+
+/// The events of a batch message.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogBatch {
+    /// Events lost to a full buffer since the previous message.
+    pub dropped: u64,
+    pub logs: Vec<LogRecord>,
+}
+
+/// Allows `per_minute` events in each minute.
+struct RateLimit {
+    per_minute: usize,
+    window: Instant,
+    sent: usize,
+}
+
+impl RateLimit {
+    fn new(per_minute: usize) -> Self {
+        Self {
+            per_minute,
+            window: Instant::now(),
+            sent: 0,
+        }
+    }
+
+    fn available(&mut self) -> usize {
+        if self.window.elapsed() >= Duration::from_secs(60) {
+            self.window = Instant::now();
+            self.sent = 0;
+        }
+        self.per_minute.saturating_sub(self.sent)
+    }
+
+    fn record(&mut self, sent: usize) {
+        self.sent += sent;
+    }
+}
+
+struct Shipper {
+    config: LogShippingConfig,
+    topic: String,
+    buffer: Arc<LogBuffer>,
+    started: AtomicBool,
+}
+
+/// The layer collecting events for [`ship`], when shipping is enabled.
+pub fn shipping_layer(config: &LogShippingConfig, source: LogSource) -> Option<ShippingLayer> {
+    if !config.enable {
+        return None;
+    }
+    let shipper = SHIPPER.get_or_init(|| Shipper {
+        config: config.clone(),
+        topic: format!("{}/logs", source.vehicle_id),
+        buffer: Arc::new(LogBuffer::new(config.buffer_size)),
+        started: AtomicBool::new(false),
+    });
+    Some(ShippingLayer {
+        source,
+        buffer: shipper.buffer.clone(),
+    })
+}
+
+/// Ship the collected events over `link`, buffering while it is offline.
+/// Only the first call per process starts shipping; does nothing when
+/// shipping is disabled or `link` is not started.
+pub fn ship(link: &RemoteIotClient) {
+    let (Some(shipper), Some(client)) = (SHIPPER.get(), link.client()) else {
+        return;
+    };
+    if shipper.started.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    tokio::spawn(shipper.run(client, link.connection_state()));
+}
+
+impl Shipper {
+    async fn run(&'static self, client: MqttClient, mut connected: watch::Receiver<bool>) {
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.interval.max(1)));
+        let mut limit = RateLimit::new(self.config.max_per_minute);
+        loop {
+            ticker.tick().await;
+            if connected.wait_for(|connected| *connected).await.is_err() {
+                return;
+            }
+            let count = limit.available().min(self.config.batch_size);
+            let batch = LogBatch {
+                dropped: self.buffer.dropped.swap(0, Ordering::Relaxed),
+                logs: self.buffer.take(count),
+            };
+            if batch.logs.is_empty() && batch.dropped == 0 {
+                continue;
+            }
+            let payload = match serde_json::to_vec(&batch) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    debug!("Failed to serialize logs: {}", e);
+                    continue;
+                }
+            };
+            match tokio::time::timeout(PUBLISH_TIMEOUT, client.publish(&self.topic, payload)).await
+            {
+                Ok(Ok(())) => limit.record(batch.logs.len()),
+                result => {
+                    debug!("Failed to ship {} logs: {:?}", batch.logs.len(), result);
+                    self.buffer
+                        .dropped
+                        .fetch_add(batch.dropped, Ordering::Relaxed);
+                    self.buffer.requeue(batch.logs);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use super::*;
+use tracing::{error, info, info_span, warn};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+
+fn source() -> LogSource {
+    LogSource {
+        service: "gateway".to_string(),
+        vehicle_id: "boat-1".to_string(),
+    }
+}
+
+fn record(message: &str) -> LogRecord {
+    LogRecord {
+        timestamp: String::new(),
+        level: "WARN".to_string(),
+        service: "gateway".to_string(),
+        vehicle_id: "boat-1".to_string(),
+        target: "test".to_string(),
+        message: message.to_string(),
+        fields: Map::new(),
+        spans: Vec::new(),
+        file: None,
+        line: None,
+    }
+}
+
+fn messages(records: &[LogRecord]) -> Vec<&str> {
+    records.iter().map(|r| r.message.as_str()).collect()
+}
+
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for Output {
+    type Writer = Output;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[test]
+fn test_json_format() {
+    let output = Output::default();
+    let subscriber = tracing_subscriber::registry().with(
+        tracing_subscriber::fmt::layer()
+            .with_writer(output.clone())
+            .event_format(JsonFormat::new(source())),
+    );
+    tracing::subscriber::with_default(subscriber, || {
+        let _span = info_span!("upload").entered();
+        warn!(
+            attempt = 3,
+            path = "a.tlog",
+            "Upload of {} failed",
+            "a.tlog"
+        );
+    });
+
+    let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    let record: LogRecord = serde_json::from_str(output.trim_end()).unwrap();
+    assert_eq!(record.level, "WARN");
+    assert_eq!(record.service, "gateway");
+    assert_eq!(record.vehicle_id, "boat-1");
+    assert_eq!(record.message, "Upload of a.tlog failed");
+    assert_eq!(record.fields["attempt"], 3);
+    assert_eq!(record.fields["path"], "a.tlog");
+    assert_eq!(record.spans, ["upload"]);
+    assert_eq!(output.lines().count(), 1);
+}
+
+#[test]
+fn test_shipping_layer_collects_filtered_events() {
+    let buffer = Arc::new(LogBuffer::new(10));
+    let layer = ShippingLayer {
+        source: source(),
+        buffer: buffer.clone(),
+    };
+    let subscriber = tracing_subscriber::registry().with(layer.with_filter(LevelFilter::WARN));
+    tracing::subscriber::with_default(subscriber, || {
+        info!("not shipped");
+        warn!("low battery");
+        error!("link lost");
+        debug!("ignored");
+    });
+
+    let records = buffer.take(10);
+    assert_eq!(messages(&records), ["low battery", "link lost"]);
+    assert_eq!(records[1].level, "ERROR");
+    assert_eq!(records[1].vehicle_id, "boat-1");
+}
+
+#[test]
+fn test_shipping_takes_events_below_the_level() {
+    let output = Output::default();
+    let buffer = Arc::new(LogBuffer::new(10));
+    let layer = ShippingLayer {
+        source: source(),
+        buffer: buffer.clone(),
+    };
+    let filter = crate::log_filter::LogFilter::new();
+    let subscriber = tracing_subscriber::registry()
+        .with(crate::util::leveled(
+            &filter,
+            "info",
+            vec![tracing_subscriber::fmt::layer()
+                .with_writer(output.clone())
+                .boxed()],
+        ))
+        .with(layer.with_filter(LevelFilter::DEBUG));
+    tracing::subscriber::with_default(subscriber, || {
+        debug!("link quality 87");
+        info!("armed");
+    });
+
+    assert_eq!(messages(&buffer.take(10)), ["link quality 87", "armed"]);
+    let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    assert!(output.contains("armed"));
+    assert!(!output.contains("link quality"));
+}
+
+#[test]
+fn test_buffer_drops_oldest() {
+    let buffer = LogBuffer::new(3);
+    for message in ["a", "b", "c", "d"] {
+        buffer.push(record(message));
+    }
+    assert_eq!(buffer.dropped.load(Ordering::Relaxed), 1);
+
+    let batch = buffer.take(2);
+    assert_eq!(messages(&batch), ["b", "c"]);
+    buffer.push(record("e"));
+    buffer.push(record("f"));
+    // Failed to ship: back in front, and the oldest make room again.
+    buffer.requeue(batch);
+    assert_eq!(buffer.dropped.load(Ordering::Relaxed), 3);
+    assert_eq!(messages(&buffer.take(10)), ["d", "e", "f"]);
+}
+
+#[test]
+fn test_rate_limit() {
+    let mut limit = RateLimit::new(100);
+    assert_eq!(limit.available(), 100);
+    limit.record(60);
+    assert_eq!(limit.available(), 40);
+    limit.record(40);
+    assert_eq!(limit.available(), 0);
+
+    limit.window -= Duration::from_secs(61);
+    assert_eq!(limit.available(), 100);
+}
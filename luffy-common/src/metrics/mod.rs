@@ -0,0 +1,197 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use prometheus::core::Collector;
+use prometheus::{Encoder, Opts, Registry, TextEncoder};
+pub use prometheus::{IntCounter, IntCounterVec, IntGauge, IntGaugeVec};
+use std::sync::LazyLock;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::config::MetricsConfig;
+use crate::supervisor::Service;
+
+/// Metrics of this process, served on `/metrics`.
+pub static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+/// Messages published, by link (`local` or `remote`).
+pub static MQTT_PUBLISHED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    counter_vec(
+        "luffy_mqtt_published_total",
+        "MQTT messages sent to the broker",
+        &["link"],
+    )
+});
+
+pub static MQTT_RECEIVED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    counter_vec(
+        "luffy_mqtt_received_total",
+        "MQTT messages received from the broker",
+        &["link"],
+    )
+});
+
+/// Publishes that could not be queued, e.g. after the client stopped.
+pub static MQTT_PUBLISH_FAILURES: LazyLock<IntCounter> = LazyLock::new(|| {
+    counter(
+        "luffy_mqtt_publish_failures_total",
+        "MQTT publishes that failed",
+    )
+});
+
+pub static MQTT_CONNECTION_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    counter_vec(
+        "luffy_mqtt_connection_errors_total",
+        "MQTT connection errors",
+        &["link"],
+    )
+});
+
+/// Requests of a dropped connection that the client resends once it
+/// reconnects: publishes the broker had not acknowledged and those queued at
+/// the drop. Requests made while the link stays down wait in the client's
+/// channel and are not counted.
+pub static MQTT_REPLAY: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    gauge_vec(
+        "luffy_mqtt_replay_requests",
+        "MQTT requests of a dropped connection waiting to be resent",
+        &["link"],
+    )
+});
+
+/// Package updates by package and outcome: `updated`, `rolled_back` or
+/// `failed`.
+pub static OTA_UPDATES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    counter_vec(
+        "luffy_ota_updates_total",
+        "OTA package updates",
+        &["package", "outcome"],
+    )
+});
+
+/// A counter registered with [`REGISTRY`].
+pub fn counter(name: &str, help: &str) -> IntCounter {
+    register(IntCounter::new(name, help).expect("Invalid counter"))
+}
+
+/// A counter with `labels`, registered with [`REGISTRY`].
+pub fn counter_vec(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    register(IntCounterVec::new(Opts::new(name, help), labels).expect("Invalid counter"))
+}
+
+/// A gauge registered with [`REGISTRY`].
+pub fn gauge(name: &str, help: &str) -> IntGauge {
+    register(IntGauge::new(name, help).expect("Invalid gauge"))
+}
+
+/// A gauge with `labels`, registered with [`REGISTRY`].
+pub fn gauge_vec(name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+    register(IntGaugeVec::new(Opts::new(name, help), labels).expect("Invalid gauge"))
+}
+
+/// Register `metric`. Panics when its name is taken, so each metric is
+/// registered once, from its own static.
+pub fn register<M: Collector + Clone + 'static>(metric: M) -> M {
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Metric registered twice");
+    metric
+}
+
+/// The metrics in the Prometheus text format.
+pub fn render() -> Result<String> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+/// Port of `service` when `[metrics] port` is not set.
+pub fn default_port(service: &str) -> u16 {
+    match service {
+        "gateway" => 9101,
+        "media" => 9102,
+        "launcher" => 9103,
+        _ => 9100,
+    }
+}
+
+/// Serves `/metrics` for Prometheus.
+pub struct MetricsServer {
+    config: MetricsConfig,
+    service: String,
+    shutdown: Option<(watch::Sender<bool>, JoinHandle<()>)>,
+}
+
+impl MetricsServer {
+    pub fn new(config: MetricsConfig, service: &str) -> Self {
+        Self {
+            config,
+            service: service.to_string(),
+            shutdown: None,
+        }
+    }
+
+    pub fn routes() -> Router {
+        Router::new().route(
+            "/metrics",
+            get(|| async {
+                match render() {
+                    Ok(text) => ([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], text).into_response(),
+                    Err(e) => {
+                        error!("Failed to render metrics: {:#}", e);
+                        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                    }
+                }
+            }),
+        )
+    }
+}
+
+#[async_trait]
+impl Service for MetricsServer {
+    fn name(&self) -> &str {
+        "metrics"
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let port = self
+            .config
+            .port
+            .unwrap_or_else(|| default_port(&self.service));
+        let addr = format!("{}:{}", self.config.host, port);
+        let listener = tokio::net::TcpListener::bind(&addr)
+            .await
+            .with_context(|| format!("Failed to bind metrics endpoint to {}", addr))?;
+        info!("Serving metrics on http://{}/metrics", addr);
+
+        let (tx, mut rx) = watch::channel(false);
+        let server = tokio::spawn(async move {
+            let shutdown = async move {
+                let _ = rx.wait_for(|stop| *stop).await;
+            };
+            if let Err(e) = axum::serve(listener, Self::routes())
+                .with_graceful_shutdown(shutdown)
+                .await
+            {
+                error!("Metrics endpoint failed: {}", e);
+            }
+        });
+        self.shutdown = Some((tx, server));
+        Ok(())
+    }
+
+    async fn stop(&mut self) {
+        if let Some((tx, server)) = self.shutdown.take() {
+            let _ = tx.send(true);
+            let _ = server.await;
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use super::*;
+
+#[test]
+fn test_render() {
+    MQTT_PUBLISHED.with_label_values(&["local"]).inc_by(3);
+    let outcomes = counter_vec("luffy_test_outcomes_total", "Test outcomes", &["outcome"]);
+    outcomes.with_label_values(&["ok"]).inc();
+
+    let text = render().unwrap();
+    assert!(text.contains("# TYPE luffy_mqtt_published_total counter"));
+    assert!(text.contains("luffy_test_outcomes_total{outcome=\"ok\"} 1"));
+}
+
+#[tokio::test]
+async fn test_metrics_server() {
+    let port = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    };
+    let config = MetricsConfig {
+        port: Some(port),
+        ..Default::default()
+    };
+    let mut server = MetricsServer::new(config, "test");
+    server.start().await.unwrap();
+    gauge("luffy_test_up", "Test gauge").set(1);
+
+    let response = reqwest::get(format!("http://127.0.0.1:{}/metrics", port))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(response.headers()["content-type"], prometheus::TEXT_FORMAT);
+    assert!(response.text().await.unwrap().contains("luffy_test_up 1"));
+
+    server.stop().await;
+    assert!(reqwest::get(format!("http://127.0.0.1:{}/metrics", port))
+        .await
+        .is_err());
+}
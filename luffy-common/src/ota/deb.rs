@@ -212,6 +212,27 @@ impl DebManager {
         Ok(())
     }
 
+    pub async fn restart_service(&self, service_type: &ServiceType) -> Result<()> {
+        let service_name = self.get_service_name(service_type);
+
+        #[cfg(target_os = "linux")]
+        {
+            let status = Command::new("sudo")
+                .args(["systemctl", "restart", &service_name])
+                .status()
+                .context("Failed to restart service")?;
+            if !status.success() {
+                return Err(anyhow!("Failed to restart {}: {}", service_name, status));
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            warn!("Service control is only supported on Linux systems");
+        }
+        info!("Restarted service {:?}", service_type);
+        Ok(())
+    }
+
     pub fn get_service_name(&self, service_type: &ServiceType) -> String {
         match service_type {
             ServiceType::Gateway => "luffy-gateway".to_string(),
@@ -0,0 +1,192 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn, Instrument, Span};
+use uuid::Uuid;
+
+use crate::iot::client::MqttClient;
+
+/// Served by the launcher: installs gateway and media from a release.
+pub fn apply_topic(vehicle_id: &str) -> String {
+    format!("{}/rpc/ota/apply", vehicle_id)
+}
+
+/// Progress of the updates of `target`, `launcher` or `services`.
+pub fn status_topic(vehicle_id: &str, target: &str) -> String {
+    format!("{}/ota/status/{}", vehicle_id, target)
+}
+
+/// Params of the update RPCs; no version means the latest release.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UpdateCall {
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+/// The reply to an update RPC, sent once the update has started. Its
+/// progress follows on the status topic under the same id.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UpdateAccepted {
+    pub id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateState {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UpdateStatus {
+    pub id: String,
+    pub state: UpdateState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Publishes the progress of one update.
+#[derive(Debug, Clone)]
+pub struct UpdateProgress {
+    client: MqttClient,
+    topic: String,
+    id: String,
+}
+
+impl UpdateProgress {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn step(&self, step: &str) {
+        self.publish(UpdateState::Running, Some(step), None).await
+    }
+
+    async fn finish(&self, result: &Result<()>) {
+        match result {
+            Ok(()) => self.publish(UpdateState::Succeeded, None, None).await,
+            Err(e) => {
+                let error = format!("{:#}", e);
+                self.publish(UpdateState::Failed, None, Some(&error)).await
+            }
+        }
+    }
+
+    async fn publish(&self, state: UpdateState, step: Option<&str>, error: Option<&str>) {
+        let status = UpdateStatus {
+            id: self.id.clone(),
+            state,
+            step: step.map(str::to_string),
+            error: error.map(str::to_string),
+        };
+        let result = match serde_json::to_vec(&status) {
+            Ok(payload) => self.client.publish(&self.topic, payload).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            warn!("Failed to report update {}: {:#}", self.id, e);
+        }
+    }
+}
+
+/// Runs updates in the background, one at a time, so the RPC starting one
+/// returns at once instead of waiting through downloads and restarts.
+#[derive(Debug, Default)]
+pub struct UpdateRunner {
+    running: Mutex<Option<String>>,
+}
+
+impl UpdateRunner {
+    pub const fn new() -> Self {
+        Self {
+            running: Mutex::new(None),
+        }
+    }
+
+    /// Start `update`, publishing its progress on `topic`. While an update
+    /// runs, further calls get its id instead of starting another.
+    pub fn start<F, Fut>(
+        &'static self,
+        client: MqttClient,
+        topic: String,
+        update: F,
+    ) -> UpdateAccepted
+    where
+        F: FnOnce(UpdateProgress) -> Fut,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let mut running = self.running.lock().unwrap();
+        if let Some(id) = running.as_ref() {
+            info!("Update {} is already running", id);
+            return UpdateAccepted { id: id.clone() };
+        }
+        let id = Uuid::new_v4().simple().to_string();
+        *running = Some(id.clone());
+
+        let progress = UpdateProgress {
+            client,
+            topic,
+            id: id.clone(),
+        };
+        let task = update(progress.clone());
+        tokio::spawn(
+            async move {
+                let result = task.await;
+                if let Err(e) = &result {
+                    error!("Update {} failed: {:#}", progress.id, e);
+                }
+                *self.running.lock().unwrap() = None;
+                progress.finish(&result).await;
+            }
+            .instrument(Span::current()),
+        );
+        UpdateAccepted { id }
+    }
+}
+
+/// Wait for update `id` to finish, calling `on_step` with each step it
+/// reports. `statuses` must be subscribed before the update is started.
+pub async fn wait_for<F, Fut>(
+    statuses: &mut broadcast::Receiver<UpdateStatus>,
+    id: &str,
+    timeout: Duration,
+    mut on_step: F,
+) -> Result<()>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let status = match tokio::time::timeout_at(deadline, statuses.recv()).await {
+            Ok(Ok(status)) => status,
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+            Ok(Err(broadcast::error::RecvError::Closed)) => bail!("Update status closed"),
+            Err(_) => bail!("Update {} did not finish within {:?}", id, timeout),
+        };
+        if status.id != id {
+            continue;
+        }
+        match status.state {
+            UpdateState::Running => {
+                if let Some(step) = status.step {
+                    on_step(step).await;
+                }
+            }
+            UpdateState::Succeeded => return Ok(()),
+            UpdateState::Failed => bail!(
+                "{}",
+                status.error.unwrap_or_else(|| "Update failed".to_string())
+            ),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use super::*;
+use tokio::sync::oneshot;
+
+fn client() -> MqttClient {
+    // Publishes queue up without a connection.
+    let options = rumqttc::MqttOptions::new("test-update", "127.0.0.1", 1883);
+    let (client, _eventloop) = rumqttc::AsyncClient::new(options, 100);
+    client.into()
+}
+
+fn status(id: &str, state: UpdateState, step: Option<&str>) -> UpdateStatus {
+    UpdateStatus {
+        id: id.to_string(),
+        state,
+        step: step.map(str::to_string),
+        error: None,
+    }
+}
+
+#[tokio::test]
+async fn test_one_update_at_a_time() {
+    let runner: &'static UpdateRunner = Box::leak(Box::new(UpdateRunner::new()));
+    let (release, released) = oneshot::channel::<()>();
+    let first = runner.start(client(), "v/ota/status/services".into(), |_| async move {
+        let _ = released.await;
+        Ok(())
+    });
+    let second = runner.start(client(), "v/ota/status/services".into(), |_| async {
+        panic!("Started while another update runs")
+    });
+    assert_eq!(first, second);
+
+    release.send(()).unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let third = runner.start(client(), "v/ota/status/services".into(), |_| async {
+        Ok(())
+    });
+    assert_ne!(third, first);
+}
+
+#[tokio::test]
+async fn test_wait_for() {
+    let (tx, mut rx) = broadcast::channel(16);
+    tx.send(status("other", UpdateState::Succeeded, None))
+        .unwrap();
+    tx.send(status("a", UpdateState::Running, Some("downloading")))
+        .unwrap();
+    tx.send(status("a", UpdateState::Succeeded, None)).unwrap();
+    let mut steps = Vec::new();
+    wait_for(&mut rx, "a", Duration::from_secs(1), |step| {
+        steps.push(step);
+        async {}
+    })
+    .await
+    .unwrap();
+    assert_eq!(steps, ["downloading"]);
+
+    let mut failed = status("b", UpdateState::Failed, None);
+    failed.error = Some("No space left".to_string());
+    tx.send(failed).unwrap();
+    let error = wait_for(&mut rx, "b", Duration::from_secs(1), |_| async {})
+        .await
+        .unwrap_err();
+    assert_eq!(error.to_string(), "No space left");
+
+    assert!(
+        wait_for(&mut rx, "c", Duration::from_millis(50), |_| async {})
+            .await
+            .is_err()
+    );
+}
@@ -1,3 +1,5 @@
+use crate::health::HealthFields;
+use crate::metrics;
 use crate::ota::deb::{DebManager, ServiceType};
 use anyhow::{anyhow, Context, Result};
 use reqwest;
@@ -5,9 +7,28 @@ use reqwest;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
 use tokio::time::Duration;
 use tracing::{info, warn};
 
+/// When this process last looked up a release.
+static LAST_CHECK: Mutex<Option<SystemTime>> = Mutex::new(None);
+
+pub fn last_check() -> Option<SystemTime> {
+    *LAST_CHECK.lock().unwrap()
+}
+
+/// `last_ota_check` for health reports, once a check has run.
+pub async fn health_fields() -> HealthFields {
+    let mut fields = HealthFields::new();
+    if let Some(checked) = last_check() {
+        let checked: chrono::DateTime<chrono::Utc> = checked.into();
+        fields.insert("last_ota_check".to_string(), checked.to_rfc3339().into());
+    }
+    fields
+}
+
 #[derive(Debug, Deserialize)]
 pub struct GithubRelease {
     pub tag_name: String,
@@ -59,11 +80,27 @@ impl BaseVersionManager {
     }
 
     pub async fn get_latest_version(&self) -> Result<(String, Vec<(String, String)>)> {
+        self.get_release(None).await
+    }
+
+    /// Fetch the `.deb` assets of a release. `version` selects the `v{version}`
+    /// tag, `None` the latest release.
+    pub async fn get_release(
+        &self,
+        version: Option<&str>,
+    ) -> Result<(String, Vec<(String, String)>)> {
         let client = reqwest::Client::new();
-        let url = format!(
-            "https://api.github.com/repos/{}/releases/latest",
-            self.github_repo
-        );
+        let url = match version {
+            Some(version) => format!(
+                "https://api.github.com/repos/{}/releases/tags/v{}",
+                self.github_repo,
+                version.trim_start_matches('v')
+            ),
+            None => format!(
+                "https://api.github.com/repos/{}/releases/latest",
+                self.github_repo
+            ),
+        };
 
         info!("Requesting GitHub releases from: {}", url);
 
@@ -77,7 +114,11 @@ impl BaseVersionManager {
         }
 
         let response = request.send().await.context("Failed to fetch releases")?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Release lookup failed: {}", response.status()));
+        }
         let release: GithubRelease = response.json().await?;
+        *LAST_CHECK.lock().unwrap() = Some(SystemTime::now());
 
         let deb_assets: Vec<(String, String)> = release
             .assets
@@ -85,15 +126,41 @@ impl BaseVersionManager {
             .filter(|asset| asset.name.ends_with(".deb"))
             .map(|asset| (asset.name.clone(), asset.browser_download_url.clone()))
             .collect();
-        info!("Latest version: {}, {:?}", release.tag_name, deb_assets);
+        info!("Release version: {}, {:?}", release.tag_name, deb_assets);
         Ok((release.tag_name, deb_assets))
     }
 
+    /// Install `packages` and restart the service, going back to the
+    /// installed packages if one fails to install.
     pub async fn update_service_packages(
         &self,
         service_type: &ServiceType,
         packages: &[(String, String)],
     ) -> Result<()> {
+        let result = self.install_packages(service_type, packages).await;
+        let outcome = match &result {
+            Ok(true) => "updated",
+            Ok(false) => "rolled_back",
+            Err(_) => "failed",
+        };
+        for (filename, _) in packages {
+            let package_name = filename.split('_').next().unwrap_or("");
+            metrics::OTA_UPDATES
+                .with_label_values(&[package_name, outcome])
+                .inc();
+        }
+        match result? {
+            true => Ok(()),
+            false => Err(anyhow!("Service update failed")),
+        }
+    }
+
+    /// Whether the packages were installed; `false` after a rollback.
+    async fn install_packages(
+        &self,
+        service_type: &ServiceType,
+        packages: &[(String, String)],
+    ) -> Result<bool> {
         info!("Processing updates for {:?}", service_type);
 
         // Download packages
@@ -137,7 +204,7 @@ impl BaseVersionManager {
                     warn!("Rollback failed for {}", package_name);
                 }
             }
-            return Err(anyhow!("Service update failed"));
+            return Ok(false);
         }
 
         // Start service
@@ -146,7 +213,7 @@ impl BaseVersionManager {
         }
 
         info!("Successfully updated {:?}", service_type);
-        Ok(())
+        Ok(true)
     }
 
     pub async fn needs_update(&self, latest_version: &str) -> Result<bool> {
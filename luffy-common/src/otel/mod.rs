@@ -0,0 +1,134 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::Result;
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{debug, warn, Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::config::OtlpConfig;
+use crate::logging::LogSource;
+
+/// Keys of the W3C trace context carried in RPC headers and, on MQTT v5
+/// only, in user properties.
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACESTATE: &str = "tracestate";
+
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// A provider exporting the spans of `source` to the collector.
+pub fn tracer_provider(config: &OtlpConfig, source: &LogSource) -> Result<SdkTracerProvider> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_protocol(Protocol::HttpBinary)
+        .with_endpoint(&config.endpoint)
+        .with_timeout(Duration::from_secs(config.timeout))
+        .build()?;
+    let resource = Resource::builder()
+        .with_service_name(format!("luffy-{}", source.service))
+        .with_attribute(KeyValue::new("vehicle.id", source.vehicle_id.clone()))
+        .build();
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio,
+        ))))
+        .build())
+}
+
+/// The layer exporting spans over OTLP, when enabled. Only the first call
+/// per process sets up the exporter; [`shutdown`] flushes it.
+pub fn layer<S>(config: &OtlpConfig, source: &LogSource) -> Option<OpenTelemetryLayer<S, SdkTracer>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    if !config.enable {
+        return None;
+    }
+    let provider = match PROVIDER.get() {
+        Some(provider) => provider.clone(),
+        None => match tracer_provider(config, source) {
+            Ok(provider) => PROVIDER.get_or_init(|| provider).clone(),
+            Err(e) => {
+                // Logging is not set up yet.
+                eprintln!(
+                    "Failed to set up OTLP export to {}: {:#}",
+                    config.endpoint, e
+                );
+                return None;
+            }
+        },
+    };
+    Some(tracing_opentelemetry::layer().with_tracer(provider.tracer("luffy")))
+}
+
+/// Export the spans still buffered and stop exporting.
+pub fn shutdown() {
+    if let Some(provider) = PROVIDER.get() {
+        if let Err(e) = provider.shutdown() {
+            warn!("Failed to flush spans: {}", e);
+        }
+    }
+}
+
+/// The trace context of the current span, to send along with a message.
+/// Empty when spans are not exported or the trace is not sampled.
+pub fn current_context() -> Vec<(String, String)> {
+    let mut headers = Headers(Vec::new());
+    TraceContextPropagator::new().inject_context(&Span::current().context(), &mut headers);
+    headers.0
+}
+
+/// Add the trace context of the current span to `headers`, replacing any
+/// it already carried.
+pub fn inject(headers: &mut Vec<(String, String)>) {
+    let context = current_context();
+    if context.is_empty() {
+        return;
+    }
+    headers.retain(|(key, _)| key != TRACEPARENT && key != TRACESTATE);
+    headers.extend(context);
+}
+
+/// Make `span` part of the trace carried in `headers`, if there is one.
+pub fn set_parent(span: &Span, headers: &[(String, String)]) {
+    let headers = Headers(headers.to_vec());
+    if headers.get(TRACEPARENT).is_none() {
+        return;
+    }
+    let context: Context = TraceContextPropagator::new().extract(&headers);
+    if let Err(e) = span.set_parent(context) {
+        debug!("Failed to continue trace: {}", e);
+    }
+}
+
+struct Headers(Vec<(String, String)>);
+
+impl Injector for Headers {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.push((key.to_string(), value));
+    }
+}
+
+impl Extractor for Headers {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|(k, _)| k.as_str()).collect()
+    }
+}
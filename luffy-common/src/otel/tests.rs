@@ -0,0 +1,87 @@
+use super::*;
+use axum::body::Bytes;
+use axum::http::HeaderMap;
+use axum::routing::post;
+use axum::Router;
+use opentelemetry::trace::TraceContextExt;
+use std::sync::{Arc, Mutex};
+use tracing::info_span;
+use tracing_subscriber::layer::SubscriberExt;
+
+fn source() -> LogSource {
+    LogSource {
+        service: "test".to_string(),
+        vehicle_id: "vehicle-1".to_string(),
+    }
+}
+
+fn trace_id(span: &Span) -> opentelemetry::trace::TraceId {
+    span.context().span().span_context().trace_id()
+}
+
+#[test]
+fn test_propagation() {
+    let provider = SdkTracerProvider::builder().build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+    tracing::subscriber::with_default(subscriber, || {
+        assert!(current_context().is_empty());
+
+        let caller = info_span!("rpc.call");
+        let mut headers = vec![("other".to_string(), "1".to_string())];
+        caller.in_scope(|| inject(&mut headers));
+        assert_eq!(headers[0].0, "other");
+        assert_eq!(headers[1].0, TRACEPARENT);
+
+        let handler = info_span!(parent: None, "rpc.serve");
+        set_parent(&handler, &headers);
+        assert_eq!(trace_id(&handler), trace_id(&caller));
+
+        let unrelated = info_span!(parent: None, "mqtt.receive");
+        set_parent(&unrelated, &[]);
+        assert_ne!(trace_id(&unrelated), trace_id(&caller));
+    });
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_export_to_collector() {
+    let requests: Arc<Mutex<Vec<(HeaderMap, Bytes)>>> = Arc::default();
+    let collector = Router::new().route(
+        "/v1/traces",
+        post({
+            let requests = requests.clone();
+            move |headers: HeaderMap, body: Bytes| async move {
+                requests.lock().unwrap().push((headers, body));
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move { axum::serve(listener, collector).await });
+
+    let config = OtlpConfig {
+        enable: true,
+        endpoint: format!("http://127.0.0.1:{}/v1/traces", port),
+        ..Default::default()
+    };
+    let provider = tracer_provider(&config, &source()).unwrap();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    tracing::subscriber::with_default(subscriber, || {
+        info_span!("luffy.test.span").in_scope(|| {});
+    });
+    tokio::task::spawn_blocking(move || provider.shutdown())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    let (headers, body) = &requests[0];
+    assert_eq!(headers["content-type"], "application/x-protobuf");
+    let body = String::from_utf8_lossy(body);
+    assert!(body.contains("luffy.test.span"));
+    assert!(body.contains("luffy-test"));
+    assert!(body.contains("vehicle-1"));
+}
@@ -0,0 +1,41 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use std::path::Path;
+use x509_parser::pem::parse_x509_pem;
+
+/// What the device needs to know about its certificate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertificateInfo {
+    /// Hex serial number, identifies the certificate across processes.
+    pub serial: String,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+}
+
+impl CertificateInfo {
+    pub fn parse(pem: &[u8]) -> Result<Self> {
+        let (_, pem) = parse_x509_pem(pem).context("Invalid certificate PEM")?;
+        let certificate = pem.parse_x509().context("Invalid certificate")?;
+        let validity = certificate.validity();
+        let time = |time: x509_parser::time::ASN1Time| {
+            DateTime::from_timestamp(time.timestamp(), 0)
+                .ok_or_else(|| anyhow!("Certificate date out of range"))
+        };
+        Ok(Self {
+            serial: certificate.raw_serial_as_string(),
+            not_before: time(validity.not_before)?,
+            not_after: time(validity.not_after)?,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let pem =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&pem)
+    }
+
+    /// Whole days until expiry, negative once expired.
+    pub fn days_left(&self, now: DateTime<Utc>) -> i64 {
+        (self.not_after - now).num_days()
+    }
+}
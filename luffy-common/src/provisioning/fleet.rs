@@ -0,0 +1,158 @@
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use rumqttc::{
+    AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS, TlsConfiguration, Transport,
+};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use super::{CertificateRequest, ProvisioningChannel, SignedCertificate};
+use crate::config::{AwsIotConfig, FleetProvisioningConfig};
+
+const CREATE_FROM_CSR_TOPIC: &str = "$aws/certificates/create-from-csr/json";
+
+/// AWS IoT fleet provisioning by claim: connect with the shared claim
+/// certificate, have the CSR signed, then register the thing through the
+/// provisioning template.
+pub struct FleetProvisioningChannel {
+    endpoint: String,
+    port: u16,
+    config: FleetProvisioningConfig,
+}
+
+impl FleetProvisioningChannel {
+    pub fn new(iot: &AwsIotConfig, config: FleetProvisioningConfig) -> Self {
+        Self {
+            endpoint: iot.endpoint.clone(),
+            port: iot.port,
+            config,
+        }
+    }
+
+    fn provision_topic(&self) -> String {
+        format!(
+            "$aws/provisioning-templates/{}/provision/json",
+            self.config.template
+        )
+    }
+
+    fn connect(&self, client_id: String) -> Result<(AsyncClient, EventLoop)> {
+        let claim_cert = std::fs::read(&self.config.claim_cert_path)
+            .with_context(|| format!("Failed to read {}", self.config.claim_cert_path))?;
+        let claim_key = std::fs::read(&self.config.claim_key_path)
+            .with_context(|| format!("Failed to read {}", self.config.claim_key_path))?;
+        let aws_root_cert = include_bytes!("../../certs/AmazonRootCA.pem");
+
+        let mut options = MqttOptions::new(client_id, &self.endpoint, self.port);
+        options
+            .set_keep_alive(Duration::from_secs(30))
+            .set_clean_session(true)
+            .set_transport(Transport::Tls(TlsConfiguration::Simple {
+                ca: aws_root_cert.to_vec(),
+                alpn: Some(vec!["mqtt".as_bytes().to_vec()]),
+                client_auth: Some((claim_cert, claim_key)),
+            }));
+        Ok(AsyncClient::new(options, 10))
+    }
+
+    async fn provision(
+        &self,
+        client: &AsyncClient,
+        eventloop: &mut EventLoop,
+        request: &CertificateRequest,
+    ) -> Result<SignedCertificate> {
+        let provision_topic = self.provision_topic();
+        for topic in [CREATE_FROM_CSR_TOPIC, provision_topic.as_str()] {
+            client
+                .subscribe(format!("{}/+", topic), QoS::AtLeastOnce)
+                .await?;
+        }
+
+        let created = exchange(
+            client,
+            eventloop,
+            CREATE_FROM_CSR_TOPIC,
+            json!({ "certificateSigningRequest": request.certificate_signing_request }),
+        )
+        .await
+        .context("Certificate signing rejected")?;
+        let field = |name: &str| {
+            created[name]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("Missing {} in signing response", name))
+        };
+        let certificate = SignedCertificate {
+            certificate_pem: field("certificatePem")?,
+            certificate_id: Some(field("certificateId")?),
+        };
+
+        exchange(
+            client,
+            eventloop,
+            &provision_topic,
+            json!({
+                "certificateOwnershipToken": field("certificateOwnershipToken")?,
+                "parameters": {
+                    "ThingName": request.vehicle_id,
+                    "SerialNumber": request.hardware_id,
+                    "Model": request.model,
+                }
+            }),
+        )
+        .await
+        .context("Thing registration rejected")?;
+        Ok(certificate)
+    }
+}
+
+/// Publish `payload` on `topic` and wait for its `accepted` or `rejected`
+/// reply.
+async fn exchange(
+    client: &AsyncClient,
+    eventloop: &mut EventLoop,
+    topic: &str,
+    payload: Value,
+) -> Result<Value> {
+    client
+        .publish(topic, QoS::AtLeastOnce, false, payload.to_string())
+        .await?;
+    let accepted = format!("{}/accepted", topic);
+    let rejected = format!("{}/rejected", topic);
+    loop {
+        if let Event::Incoming(Packet::Publish(publish)) = eventloop.poll().await? {
+            let reply: Value = serde_json::from_slice(&publish.payload)?;
+            if publish.topic == accepted {
+                return Ok(reply);
+            }
+            if publish.topic == rejected {
+                bail!(
+                    "{}: {}",
+                    reply["errorCode"].as_str().unwrap_or("unknown"),
+                    reply["errorMessage"].as_str().unwrap_or_default()
+                );
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ProvisioningChannel for FleetProvisioningChannel {
+    fn name(&self) -> &str {
+        "fleet-provisioning"
+    }
+
+    async fn sign(&self, request: &CertificateRequest) -> Result<SignedCertificate> {
+        let (client, mut eventloop) =
+            self.connect(format!("{}-provisioning", request.vehicle_id))?;
+        let result = tokio::time::timeout(
+            Duration::from_secs(self.config.timeout),
+            self.provision(&client, &mut eventloop, request),
+        )
+        .await
+        .map_err(|_| anyhow!("Fleet provisioning timed out"))
+        .and_then(|result| result);
+        let _ = client.try_disconnect();
+        result
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+
+use super::{CertificateRequest, ProvisioningChannel, SignedCertificate};
+use crate::config::HttpProvisioningConfig;
+
+/// Posts the [`CertificateRequest`] as JSON to a signing endpoint, which
+/// answers with a [`SignedCertificate`].
+pub struct HttpChannel {
+    config: HttpProvisioningConfig,
+    client: reqwest::Client,
+}
+
+impl HttpChannel {
+    pub fn new(config: HttpProvisioningConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl ProvisioningChannel for HttpChannel {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    async fn sign(&self, request: &CertificateRequest) -> Result<SignedCertificate> {
+        let mut http_request = self.client.post(&self.config.url).json(request);
+        if let Some(token) = &self.config.token {
+            http_request = http_request.bearer_auth(token);
+        }
+        http_request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", self.config.url))?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Invalid signing response")
+    }
+
+    async fn revoke(&self, vehicle_id: &str, certificate_id: &str) -> Result<()> {
+        let Some(url) = &self.config.revoke_url else {
+            anyhow::bail!("No revoke_url configured");
+        };
+        let mut http_request = self.client.post(url).json(&serde_json::json!({
+            "vehicleId": vehicle_id,
+            "certificateId": certificate_id,
+        }));
+        if let Some(token) = &self.config.token {
+            http_request = http_request.bearer_auth(token);
+        }
+        http_request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", url))?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use super::{CertificateRequest, ProvisioningChannel, SignedCertificate};
+use crate::aws::AwsClient;
+
+/// The registration Lambda, which creates the IoT thing and signs the CSR.
+pub struct LambdaChannel {
+    region: String,
+    function: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LambdaResponse {
+    certificate_pem: String,
+    certificate_arn: String,
+}
+
+impl LambdaChannel {
+    pub fn new(region: &str, function: &str) -> Self {
+        Self {
+            region: region.to_string(),
+            function: function.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl ProvisioningChannel for LambdaChannel {
+    fn name(&self) -> &str {
+        "lambda"
+    }
+
+    async fn sign(&self, request: &CertificateRequest) -> Result<SignedCertificate> {
+        let payload = serde_json::json!({
+            "typeName": "Query",
+            "fieldName": "registerIotThing",
+            "arguments": {
+                "thingName": request.vehicle_id,
+                "thingType": "zoro",
+                "certificateSigningRequest": request.certificate_signing_request,
+                "attributes": {
+                    "hardwareId": request.hardware_id,
+                    "model": request.model,
+                    "macAddress": request.mac_address,
+                }
+            }
+        });
+        let response = AwsClient::instance(&self.region)
+            .await
+            .invoke_lambda(self.function.clone(), payload.to_string())
+            .await?;
+        let response: LambdaResponse = serde_json::from_slice(response.as_ref())
+            .context("Failed to deserialize Lambda response")?;
+        Ok(SignedCertificate {
+            certificate_pem: response.certificate_pem,
+            certificate_id: Some(response.certificate_arn),
+        })
+    }
+
+    async fn revoke(&self, vehicle_id: &str, certificate_id: &str) -> Result<()> {
+        let payload = serde_json::json!({
+            "typeName": "Query",
+            "fieldName": "revokeIotCertificate",
+            "arguments": {
+                "thingName": vehicle_id,
+                "certificateArn": certificate_id,
+            }
+        });
+        AwsClient::instance(&self.region)
+            .await
+            .invoke_lambda(self.function.clone(), payload.to_string())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+#[cfg(test)]
+mod tests;
+
+mod certificate;
+mod fleet;
+mod http;
+mod lambda;
+mod store;
+mod watcher;
+
+pub use certificate::CertificateInfo;
+pub use fleet::FleetProvisioningChannel;
+pub use http::HttpChannel;
+pub use lambda::LambdaChannel;
+pub use store::CredentialStore;
+pub use watcher::CertificateWatcher;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, warn};
+
+use crate::config::{self, BaseConfig, ProvisioningConfig};
+use crate::health::HealthFields;
+use crate::identity::DeviceIdentity;
+
+/// OID of the X.520 `serialNumber` attribute.
+const SERIAL_NUMBER_OID: [u64; 4] = [2, 5, 4, 5];
+
+/// What the device sends to get a certificate. Never contains the key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificateRequest {
+    pub vehicle_id: String,
+    pub hardware_id: String,
+    pub model: String,
+    pub mac_address: Option<String>,
+    pub certificate_signing_request: String,
+}
+
+/// Certificate issued for a [`CertificateRequest`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedCertificate {
+    pub certificate_pem: String,
+    /// Id (or ARN) of the certificate in the cloud, needed to revoke it.
+    #[serde(default)]
+    pub certificate_id: Option<String>,
+}
+
+/// A way to get a CSR signed by the cloud.
+#[async_trait]
+pub trait ProvisioningChannel: Send + Sync {
+    /// Short name used in logs.
+    fn name(&self) -> &str;
+
+    async fn sign(&self, request: &CertificateRequest) -> Result<SignedCertificate>;
+
+    /// Revoke a certificate replaced by rotation.
+    async fn revoke(&self, vehicle_id: &str, certificate_id: &str) -> Result<()> {
+        let _ = (vehicle_id, certificate_id);
+        bail!("{} cannot revoke certificates", self.name())
+    }
+}
+
+/// `certificate_expires` and `certificate_days_left` for health reports,
+/// when the device has a certificate.
+pub async fn health_fields() -> HealthFields {
+    let mut fields = HealthFields::new();
+    let certificate = config::config_dir().join(store::CERTIFICATE_FILE);
+    if let Ok(info) = CertificateInfo::load(&certificate) {
+        fields.insert(
+            "certificate_expires".to_string(),
+            info.not_after.to_rfc3339().into(),
+        );
+        fields.insert(
+            "certificate_days_left".to_string(),
+            info.days_left(chrono::Utc::now()).into(),
+        );
+    }
+    fields
+}
+
+/// Channel selected by the `[provisioning]` config section.
+pub fn from_config(config: &BaseConfig) -> Box<dyn ProvisioningChannel> {
+    match &config.provisioning {
+        ProvisioningConfig::Lambda => Box::new(LambdaChannel::new(
+            &config.aws.region,
+            &config.aws.lambda.register,
+        )),
+        ProvisioningConfig::Fleet(fleet) => Box::new(FleetProvisioningChannel::new(
+            &config.aws.iot,
+            fleet.clone(),
+        )),
+        ProvisioningConfig::Http(http) => Box::new(HttpChannel::new(http.clone())),
+    }
+}
+
+/// CSR in PEM format for `key`, with the vehicle id as common name and the
+/// hardware id as serial number.
+pub fn create_csr(key: &KeyPair, identity: &DeviceIdentity) -> Result<String> {
+    let mut params = CertificateParams::new(Vec::<String>::new())?;
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CommonName, identity.vehicle_id.as_str());
+    name.push(
+        DnType::CustomDnType(SERIAL_NUMBER_OID.to_vec()),
+        identity.hardware_id.as_str(),
+    );
+    params.distinguished_name = name;
+    Ok(params.serialize_request(key)?.pem()?)
+}
+
+/// Gets the device a certificate for a key pair generated on the device.
+pub struct Provisioner<'a> {
+    store: CredentialStore,
+    channel: &'a dyn ProvisioningChannel,
+    revoke_delay: Duration,
+}
+
+impl<'a> Provisioner<'a> {
+    pub fn new(store: CredentialStore, channel: &'a dyn ProvisioningChannel) -> Self {
+        Self {
+            store,
+            channel,
+            revoke_delay: Duration::ZERO,
+        }
+    }
+
+    /// Keep a replaced certificate valid for `delay`, so services that
+    /// follow the change in the store reconnect before it is revoked.
+    pub fn with_revoke_delay(mut self, delay: Duration) -> Self {
+        self.revoke_delay = delay;
+        self
+    }
+
+    pub fn store(&self) -> &CredentialStore {
+        &self.store
+    }
+
+    /// Provision the device unless it already has a certificate. Returns
+    /// whether a certificate was issued.
+    ///
+    /// The key is saved before the CSR is sent, so a retry after a failed or
+    /// interrupted attempt asks for a certificate for the same key.
+    pub async fn provision(&self, identity: &DeviceIdentity) -> Result<bool> {
+        if self.store.is_provisioned() {
+            return Ok(false);
+        }
+        info!(
+            "Provisioning device {} through {}",
+            identity.vehicle_id,
+            self.channel.name()
+        );
+
+        let key = self.store.load_or_create_key()?;
+        let certificate = self.sign(&key, identity).await?;
+        self.store.save_certificate(&certificate)?;
+
+        info!("Device {} provisioned", identity.vehicle_id);
+        Ok(true)
+    }
+
+    /// Replace the certificate with one for a new key. `reconnect` must
+    /// connect with the files in the store; if it fails with the new
+    /// certificate the old one is put back and `reconnect` is called again.
+    /// The old certificate is revoked once the new one is in use and the
+    /// revoke delay has passed, see [`Self::revoke_due`].
+    pub async fn rotate<F, Fut>(&self, identity: &DeviceIdentity, reconnect: F) -> Result<()>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        info!(
+            "Rotating certificate of {} through {}",
+            identity.vehicle_id,
+            self.channel.name()
+        );
+        let old_id = self.store.certificate_id();
+        let key = KeyPair::generate()?;
+        let certificate = self.sign(&key, identity).await?;
+        self.store.replace(&certificate, &key)?;
+
+        if let Err(e) = reconnect().await {
+            error!("Failed to connect with the new certificate: {:#}", e);
+            self.store.restore()?;
+            if let Err(e) = reconnect().await {
+                error!("Failed to reconnect with the old certificate: {:#}", e);
+            }
+            return Err(e.context("New certificate rejected, restored the old one"));
+        }
+        if let Err(e) = self.store.discard_backup() {
+            warn!("Failed to remove the old certificate backup: {:#}", e);
+        }
+        info!("Certificate of {} rotated", identity.vehicle_id);
+
+        match old_id {
+            Some(id) => self.store.schedule_revoke(&id, self.revoke_delay)?,
+            None => warn!("Old certificate id unknown, not revoking it"),
+        }
+        self.revoke_due(identity).await;
+        Ok(())
+    }
+
+    /// Revoke the replaced certificates whose delay has passed. Failures are
+    /// retried on the next call.
+    pub async fn revoke_due(&self, identity: &DeviceIdentity) {
+        for id in self.store.due_revocations(SystemTime::now()) {
+            match self.channel.revoke(&identity.vehicle_id, &id).await {
+                Ok(()) => {
+                    info!("Revoked replaced certificate {}", id);
+                    if let Err(e) = self.store.revoked(&id) {
+                        warn!("Failed to forget revoked certificate {}: {:#}", id, e);
+                    }
+                }
+                Err(e) => warn!("Failed to revoke old certificate {}: {:#}", id, e),
+            }
+        }
+    }
+
+    async fn sign(&self, key: &KeyPair, identity: &DeviceIdentity) -> Result<SignedCertificate> {
+        let request = CertificateRequest {
+            vehicle_id: identity.vehicle_id.clone(),
+            hardware_id: identity.hardware_id.clone(),
+            model: identity.model.clone(),
+            mac_address: identity.mac_address.clone(),
+            certificate_signing_request: create_csr(key, identity)?,
+        };
+        self.channel
+            .sign(&request)
+            .await
+            .with_context(|| format!("Signing through {} failed", self.channel.name()))
+    }
+}
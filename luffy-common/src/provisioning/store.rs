@@ -0,0 +1,297 @@
+use anyhow::{anyhow, Context, Result};
+use rcgen::KeyPair;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+use super::{CertificateInfo, SignedCertificate};
+use crate::config::{self, SecretsConfig};
+use crate::secrets::{KeySource, SecretStore};
+use crate::util;
+
+pub(super) const CERTIFICATE_FILE: &str = "certificate.pem";
+/// Plaintext key of devices provisioned before the secret store, moved into
+/// the store on first use.
+const LEGACY_KEY_FILE: &str = "private.key";
+const CERTIFICATE_ID_FILE: &str = "certificate.id";
+/// Where devices registered before CSR provisioning keep the certificate ARN.
+const LEGACY_CERTIFICATE_ID_FILE: &str = "certificate.arn";
+/// Replaced certificates waiting to be revoked.
+const PENDING_REVOKE_FILE: &str = "certificate.revoke";
+const BACKUP_EXTENSION: &str = "old";
+const STAGED_EXTENSION: &str = "new";
+const KEY_SECRET: &str = "device_key";
+const KEY_BACKUP_SECRET: &str = "device_key.old";
+
+/// A replaced certificate and when to revoke it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct PendingRevoke {
+    certificate_id: String,
+    /// Seconds since the epoch.
+    revoke_after: u64,
+}
+
+/// Device certificate in a directory, normally the config dir, and its
+/// private key in the [`SecretStore`].
+#[derive(Debug, Clone)]
+pub struct CredentialStore {
+    dir: PathBuf,
+    secrets_path: PathBuf,
+    key_source: KeySource,
+}
+
+impl CredentialStore {
+    /// Store in `dir`, with the secret store file in the same directory.
+    pub fn new(dir: impl Into<PathBuf>, key_source: KeySource) -> Self {
+        let dir = dir.into();
+        Self {
+            secrets_path: dir.join("secrets.enc"),
+            dir,
+            key_source,
+        }
+    }
+
+    /// The store in [`config::config_dir`] with the configured secret
+    /// store.
+    pub fn open(secrets: &SecretsConfig) -> Result<Self> {
+        Ok(Self {
+            dir: config::config_dir(),
+            secrets_path: SecretStore::path(secrets)?,
+            key_source: KeySource::from_config(secrets),
+        })
+    }
+
+    pub fn certificate_path(&self) -> PathBuf {
+        self.dir.join(CERTIFICATE_FILE)
+    }
+
+    pub fn certificate_id_path(&self) -> PathBuf {
+        self.dir.join(CERTIFICATE_ID_FILE)
+    }
+
+    pub fn is_provisioned(&self) -> bool {
+        self.certificate_path().exists() && self.key_pem().is_ok()
+    }
+
+    fn secrets(&self) -> Result<SecretStore> {
+        SecretStore::open(&self.secrets_path, &self.key_source)
+    }
+
+    /// The private key in PEM format.
+    pub fn key_pem(&self) -> Result<String> {
+        let mut secrets = self.secrets()?;
+        if let Some(key) = secrets.get(KEY_SECRET) {
+            return Ok(key.to_string());
+        }
+        let legacy = self.dir.join(LEGACY_KEY_FILE);
+        let key = fs::read_to_string(&legacy).context("No device key")?;
+        secrets.set(KEY_SECRET, &key);
+        secrets.save()?;
+        fs::remove_file(&legacy)
+            .with_context(|| format!("Failed to remove {}", legacy.display()))?;
+        info!("Moved {} into the secret store", legacy.display());
+        Ok(key)
+    }
+
+    pub fn certificate_info(&self) -> Result<CertificateInfo> {
+        CertificateInfo::load(&self.certificate_path())
+    }
+
+    /// Cloud id of the current certificate, if it was saved.
+    pub fn certificate_id(&self) -> Option<String> {
+        [CERTIFICATE_ID_FILE, LEGACY_CERTIFICATE_ID_FILE]
+            .iter()
+            .find_map(|file| fs::read_to_string(self.dir.join(file)).ok())
+            .map(|id| id.trim().to_string())
+    }
+
+    /// The saved private key, or a new one saved before it is returned.
+    pub fn load_or_create_key(&self) -> Result<KeyPair> {
+        if let Ok(pem) = self.key_pem() {
+            return KeyPair::from_pem(&pem).map_err(|e| anyhow!("Invalid private key: {}", e));
+        }
+        let key = KeyPair::generate()?;
+        let mut secrets = self.secrets()?;
+        secrets.set(KEY_SECRET, &key.serialize_pem());
+        secrets.save()?;
+        Ok(key)
+    }
+
+    pub fn save_certificate(&self, certificate: &SignedCertificate) -> Result<()> {
+        if let Some(id) = &certificate.certificate_id {
+            util::write_atomic(&self.certificate_id_path(), id.as_bytes(), 0o644)?;
+        }
+        util::write_atomic(
+            &self.certificate_path(),
+            certificate.certificate_pem.as_bytes(),
+            0o644,
+        )
+    }
+
+    /// Replace the certificate and key, keeping a copy of the current ones
+    /// until [`Self::restore`] or [`Self::discard_backup`].
+    ///
+    /// The new files are written next to the current ones first and only
+    /// renamed into place after the key was swapped, so an interruption
+    /// leaves either the old pair or a backup for [`Self::recover`].
+    pub fn replace(&self, certificate: &SignedCertificate, key: &KeyPair) -> Result<()> {
+        for path in self.files() {
+            let backup = Self::side_path(&path, BACKUP_EXTENSION);
+            if path.exists() {
+                fs::copy(&path, &backup)
+                    .with_context(|| format!("Failed to back up {}", path.display()))?;
+            } else {
+                let _ = fs::remove_file(backup);
+            }
+        }
+        let staged_certificate = Self::side_path(&self.certificate_path(), STAGED_EXTENSION);
+        let staged_id = Self::side_path(&self.certificate_id_path(), STAGED_EXTENSION);
+        util::write_atomic(
+            &staged_certificate,
+            certificate.certificate_pem.as_bytes(),
+            0o644,
+        )?;
+        if let Some(id) = &certificate.certificate_id {
+            util::write_atomic(&staged_id, id.as_bytes(), 0o644)?;
+        }
+
+        let old_key = self.key_pem().ok();
+        let mut secrets = self.secrets()?;
+        if let Some(old_key) = old_key {
+            secrets.set(KEY_BACKUP_SECRET, &old_key);
+        }
+        secrets.set(KEY_SECRET, &key.serialize_pem());
+        secrets.save()?;
+
+        if certificate.certificate_id.is_some() {
+            Self::rename(&staged_id, &self.certificate_id_path())?;
+        } else {
+            let _ = fs::remove_file(self.certificate_id_path());
+        }
+        Self::rename(&staged_certificate, &self.certificate_path())
+    }
+
+    /// Put back the certificate and key saved by [`Self::replace`]. Files
+    /// that did not exist before are removed.
+    pub fn restore(&self) -> Result<()> {
+        let mut secrets = self.secrets()?;
+        if let Some(old_key) = secrets.get(KEY_BACKUP_SECRET).map(str::to_string) {
+            secrets.set(KEY_SECRET, &old_key);
+            secrets.remove(KEY_BACKUP_SECRET);
+            secrets.save()?;
+        }
+        if !Self::side_path(&self.certificate_path(), BACKUP_EXTENSION).exists() {
+            return Ok(());
+        }
+        for path in self.files() {
+            let _ = fs::remove_file(Self::side_path(&path, STAGED_EXTENSION));
+            let backup = Self::side_path(&path, BACKUP_EXTENSION);
+            if backup.exists() {
+                Self::rename(&backup, &path)?;
+            } else {
+                let _ = fs::remove_file(&path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Put back the previous certificate and key if a rotation was
+    /// interrupted before it was confirmed. Only the service that rotates
+    /// should call this, before it connects. Returns whether anything was
+    /// restored.
+    pub fn recover(&self) -> Result<bool> {
+        if !self.has_backup() {
+            for path in self.files() {
+                let _ = fs::remove_file(Self::side_path(&path, STAGED_EXTENSION));
+            }
+            return Ok(false);
+        }
+        self.restore()?;
+        Ok(true)
+    }
+
+    pub fn discard_backup(&self) -> Result<()> {
+        let mut secrets = self.secrets()?;
+        if secrets.remove(KEY_BACKUP_SECRET) {
+            secrets.save()?;
+        }
+        for path in self.files() {
+            let _ = fs::remove_file(Self::side_path(&path, BACKUP_EXTENSION));
+        }
+        Ok(())
+    }
+
+    /// Whether a rotation left a backup behind.
+    pub fn has_backup(&self) -> bool {
+        self.files()
+            .iter()
+            .any(|path| Self::side_path(path, BACKUP_EXTENSION).exists())
+            || self
+                .secrets()
+                .is_ok_and(|secrets| secrets.get(KEY_BACKUP_SECRET).is_some())
+    }
+
+    /// Revoke `certificate_id` once `delay` has passed, see
+    /// [`Self::due_revocations`].
+    pub fn schedule_revoke(&self, certificate_id: &str, delay: Duration) -> Result<()> {
+        let mut pending = self.pending_revocations();
+        pending.push(PendingRevoke {
+            certificate_id: certificate_id.to_string(),
+            revoke_after: (SystemTime::now() + delay)
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        });
+        self.save_pending_revocations(&pending)
+    }
+
+    /// Ids of replaced certificates whose revocation is due at `now`.
+    pub fn due_revocations(&self, now: SystemTime) -> Vec<String> {
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.pending_revocations()
+            .into_iter()
+            .filter(|pending| pending.revoke_after <= now)
+            .map(|pending| pending.certificate_id)
+            .collect()
+    }
+
+    /// Forget a scheduled revocation once it went through.
+    pub fn revoked(&self, certificate_id: &str) -> Result<()> {
+        let mut pending = self.pending_revocations();
+        pending.retain(|pending| pending.certificate_id != certificate_id);
+        self.save_pending_revocations(&pending)
+    }
+
+    fn pending_revocations(&self) -> Vec<PendingRevoke> {
+        fs::read(self.dir.join(PENDING_REVOKE_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_pending_revocations(&self, pending: &[PendingRevoke]) -> Result<()> {
+        let path = self.dir.join(PENDING_REVOKE_FILE);
+        if pending.is_empty() {
+            let _ = fs::remove_file(path);
+            return Ok(());
+        }
+        util::write_atomic(&path, &serde_json::to_vec_pretty(pending)?, 0o644)
+    }
+
+    fn files(&self) -> [PathBuf; 2] {
+        [self.certificate_path(), self.certificate_id_path()]
+    }
+
+    fn side_path(path: &Path, extension: &str) -> PathBuf {
+        let mut side = path.as_os_str().to_owned();
+        side.push(".");
+        side.push(extension);
+        PathBuf::from(side)
+    }
+
+    fn rename(from: &Path, to: &Path) -> Result<()> {
+        fs::rename(from, to).with_context(|| format!("Failed to write {}", to.display()))
+    }
+}
@@ -0,0 +1,310 @@
+use super::*;
+use crate::config::HttpProvisioningConfig;
+use crate::secrets::KeySource;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::{Json, Router};
+use rcgen::{BasicConstraints, Certificate, CertificateSigningRequestParams, IsCa};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+fn identity() -> DeviceIdentity {
+    DeviceIdentity {
+        vehicle_id: "vessel-1".to_string(),
+        hardware_id: "board-42".to_string(),
+        mac_address: None,
+        model: "test".to_string(),
+    }
+}
+
+/// Local stand-in for a signing endpoint, signing with its own CA.
+struct Signer {
+    ca: Certificate,
+    ca_key: KeyPair,
+    requests: AtomicUsize,
+    revoked: std::sync::Mutex<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RevokeRequest {
+    certificate_id: String,
+}
+
+async fn revoke(State(signer): State<Arc<Signer>>, Json(request): Json<RevokeRequest>) {
+    signer.revoked.lock().unwrap().push(request.certificate_id);
+}
+
+async fn sign(
+    State(signer): State<Arc<Signer>>,
+    Json(request): Json<CertificateRequest>,
+) -> Result<Json<SignedCertificate>, StatusCode> {
+    let count = signer.requests.fetch_add(1, Ordering::SeqCst) + 1;
+    let csr = CertificateSigningRequestParams::from_pem(&request.certificate_signing_request)
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let certificate = csr
+        .signed_by(&signer.ca, &signer.ca_key)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(SignedCertificate {
+        certificate_pem: certificate.pem(),
+        certificate_id: Some(format!("{}-{}", request.vehicle_id, count)),
+    }))
+}
+
+async fn start_signer() -> (String, Arc<Signer>) {
+    let ca_key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let signer = Arc::new(Signer {
+        ca: params.self_signed(&ca_key).unwrap(),
+        ca_key,
+        requests: AtomicUsize::new(0),
+        revoked: Default::default(),
+    });
+
+    let app = Router::new()
+        .route("/sign", post(sign))
+        .route("/revoke", post(revoke))
+        .with_state(signer.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/sign", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    (url, signer)
+}
+
+fn credential_store(dir: &std::path::Path) -> CredentialStore {
+    let passphrase = dir.join("passphrase");
+    fs::write(&passphrase, "test").unwrap();
+    CredentialStore::new(dir, KeySource::PassphraseFile(passphrase))
+}
+
+fn http_channel(url: String) -> HttpChannel {
+    HttpChannel::new(HttpProvisioningConfig {
+        revoke_url: Some(url.replace("/sign", "/revoke")),
+        url,
+        token: None,
+    })
+}
+
+#[tokio::test]
+async fn test_provision_over_http() {
+    let (url, signer) = start_signer().await;
+    let dir = tempfile::tempdir().unwrap();
+    let channel = http_channel(url);
+    let provisioner = Provisioner::new(credential_store(dir.path()), &channel);
+
+    assert!(provisioner.provision(&identity()).await.unwrap());
+    let store = provisioner.store();
+    assert!(store.is_provisioned());
+    assert_eq!(
+        fs::read_to_string(store.certificate_id_path()).unwrap(),
+        "vessel-1-1"
+    );
+
+    // The certificate is for the key kept on the device.
+    let key = store.load_or_create_key().unwrap();
+    let certificate = fs::read_to_string(store.certificate_path()).unwrap();
+    let params = CertificateParams::from_ca_cert_pem(&certificate).unwrap();
+    assert_eq!(
+        params.distinguished_name.get(&DnType::CommonName),
+        Some(&rcgen::DnValue::Utf8String("vessel-1".to_string()))
+    );
+    let (_, parsed) = x509_parser::pem::parse_x509_pem(certificate.as_bytes()).unwrap();
+    let parsed = parsed.parse_x509().unwrap();
+    assert_eq!(
+        parsed.public_key().subject_public_key.data.as_ref(),
+        key.public_key_raw()
+    );
+
+    // Already provisioned: nothing is sent.
+    assert!(!provisioner.provision(&identity()).await.unwrap());
+    assert_eq!(signer.requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_retry_reuses_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = credential_store(dir.path());
+
+    // First attempt fails after the key was created.
+    let unreachable = http_channel("http://127.0.0.1:1/sign".to_string());
+    assert!(Provisioner::new(store.clone(), &unreachable)
+        .provision(&identity())
+        .await
+        .is_err());
+    assert!(!store.is_provisioned());
+    let key = store.key_pem().unwrap();
+    let secrets = dir.path().join("secrets.enc");
+    let mode = fs::metadata(secrets).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let (url, _) = start_signer().await;
+    let channel = http_channel(url);
+    assert!(Provisioner::new(store.clone(), &channel)
+        .provision(&identity())
+        .await
+        .unwrap());
+    assert_eq!(store.key_pem().unwrap(), key);
+}
+
+#[test]
+fn test_certificate_expiry() {
+    let key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    params.not_before = rcgen::date_time_ymd(2026, 1, 1);
+    params.not_after = rcgen::date_time_ymd(2026, 12, 31);
+    let certificate = params.self_signed(&key).unwrap();
+
+    let info = CertificateInfo::parse(certificate.pem().as_bytes()).unwrap();
+    assert_eq!(info.not_after.to_rfc3339(), "2026-12-31T00:00:00+00:00");
+    let now = "2026-12-01T00:00:00Z".parse().unwrap();
+    assert_eq!(info.days_left(now), 30);
+    assert!(CertificateInfo::parse(b"not a certificate").is_err());
+}
+
+async fn provisioned_store(url: &str) -> (tempfile::TempDir, CredentialStore, HttpChannel) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = credential_store(dir.path());
+    let channel = http_channel(url.to_string());
+    Provisioner::new(store.clone(), &channel)
+        .provision(&identity())
+        .await
+        .unwrap();
+    (dir, store, channel)
+}
+
+#[tokio::test]
+async fn test_rotate_revokes_old_certificate() {
+    let (url, signer) = start_signer().await;
+    let (_dir, store, channel) = provisioned_store(&url).await;
+    let old_key = store.key_pem().unwrap();
+    let old_serial = store.certificate_info().unwrap().serial;
+
+    Provisioner::new(store.clone(), &channel)
+        .rotate(&identity(), || async { Ok(()) })
+        .await
+        .unwrap();
+
+    assert_ne!(store.key_pem().unwrap(), old_key);
+    assert_ne!(store.certificate_info().unwrap().serial, old_serial);
+    assert_eq!(store.certificate_id().as_deref(), Some("vessel-1-2"));
+    assert_eq!(*signer.revoked.lock().unwrap(), vec!["vessel-1-1"]);
+    assert!(!store.has_backup());
+}
+
+#[tokio::test]
+async fn test_rotate_restores_rejected_certificate() {
+    let (url, signer) = start_signer().await;
+    let (_dir, store, channel) = provisioned_store(&url).await;
+    let old_key = store.key_pem().unwrap();
+    let old_serial = store.certificate_info().unwrap().serial;
+
+    // The broker refuses the new certificate but takes the old one back.
+    let attempts = AtomicUsize::new(0);
+    let error = Provisioner::new(store.clone(), &channel)
+        .rotate(&identity(), || async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(anyhow::anyhow!("connection refused")),
+                _ => Ok(()),
+            }
+        })
+        .await
+        .unwrap_err();
+
+    assert!(format!("{:#}", error).contains("connection refused"));
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    assert_eq!(store.key_pem().unwrap(), old_key);
+    assert_eq!(store.certificate_info().unwrap().serial, old_serial);
+    assert_eq!(store.certificate_id().as_deref(), Some("vessel-1-1"));
+    assert!(signer.revoked.lock().unwrap().is_empty());
+    assert!(!store.has_backup());
+}
+
+#[tokio::test]
+async fn test_rotate_delays_revoke() {
+    let (url, signer) = start_signer().await;
+    let (_dir, store, channel) = provisioned_store(&url).await;
+    let provisioner =
+        Provisioner::new(store.clone(), &channel).with_revoke_delay(Duration::from_secs(3600));
+
+    provisioner
+        .rotate(&identity(), || async { Ok(()) })
+        .await
+        .unwrap();
+    assert!(signer.revoked.lock().unwrap().is_empty());
+    assert!(store.due_revocations(SystemTime::now()).is_empty());
+
+    let later = SystemTime::now() + Duration::from_secs(3601);
+    assert_eq!(store.due_revocations(later), vec!["vessel-1-1"]);
+    store.revoked("vessel-1-1").unwrap();
+    assert!(store.due_revocations(later).is_empty());
+}
+
+#[tokio::test]
+async fn test_restore_removes_new_certificate_id() {
+    let (url, _signer) = start_signer().await;
+    let (dir, store, channel) = provisioned_store(&url).await;
+    // Provisioned before certificate ids were kept.
+    fs::remove_file(store.certificate_id_path()).unwrap();
+    fs::write(dir.path().join("certificate.arn"), "arn:legacy").unwrap();
+
+    let error = Provisioner::new(store.clone(), &channel)
+        .rotate(&identity(), || async {
+            anyhow::bail!("connection refused")
+        })
+        .await;
+    assert!(error.is_err());
+    assert!(!store.certificate_id_path().exists());
+    assert_eq!(store.certificate_id().as_deref(), Some("arn:legacy"));
+}
+
+#[tokio::test]
+async fn test_recover_interrupted_rotation() {
+    let (url, _signer) = start_signer().await;
+    let (dir, store, _channel) = provisioned_store(&url).await;
+    let old_key = store.key_pem().unwrap();
+    let old_serial = store.certificate_info().unwrap().serial;
+    assert!(!store.recover().unwrap());
+
+    // Cut off after the swap, before the new certificate connected.
+    let key = KeyPair::generate().unwrap();
+    let certificate = SignedCertificate {
+        certificate_pem: CertificateParams::new(Vec::<String>::new())
+            .unwrap()
+            .self_signed(&key)
+            .unwrap()
+            .pem(),
+        certificate_id: Some("vessel-1-2".to_string()),
+    };
+    store.replace(&certificate, &key).unwrap();
+    assert_ne!(store.key_pem().unwrap(), old_key);
+
+    assert!(store.recover().unwrap());
+    assert_eq!(store.key_pem().unwrap(), old_key);
+    assert_eq!(store.certificate_info().unwrap().serial, old_serial);
+    assert_eq!(store.certificate_id().as_deref(), Some("vessel-1-1"));
+    assert!(!store.has_backup());
+    let leftovers: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.ends_with(".old") || name.ends_with(".new"))
+        .collect();
+    assert!(leftovers.is_empty(), "{:?}", leftovers);
+}
+
+#[tokio::test]
+async fn test_legacy_key_moves_into_secret_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = credential_store(dir.path());
+    let key = KeyPair::generate().unwrap().serialize_pem();
+    fs::write(dir.path().join("private.key"), &key).unwrap();
+
+    assert_eq!(store.key_pem().unwrap(), key);
+    assert!(!dir.path().join("private.key").exists());
+    assert_eq!(store.key_pem().unwrap(), key);
+}
@@ -0,0 +1,98 @@
+use anyhow::Result;
+use chrono::Utc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use super::{CredentialStore, Provisioner, ProvisioningChannel};
+use crate::config::CertificateConfig;
+use crate::identity::DeviceIdentity;
+use crate::iot::remote::Reconnector;
+
+/// Keeps a remote link on a valid certificate: reconnects when the
+/// certificate in the store changes and, with [`Self::with_rotation`],
+/// rotates it before it expires.
+pub struct CertificateWatcher {
+    config: CertificateConfig,
+    store: CredentialStore,
+    reconnector: Reconnector,
+    identity: DeviceIdentity,
+    rotation: Option<Box<dyn ProvisioningChannel>>,
+}
+
+impl CertificateWatcher {
+    pub fn new(
+        config: CertificateConfig,
+        store: CredentialStore,
+        reconnector: Reconnector,
+        identity: DeviceIdentity,
+    ) -> Self {
+        Self {
+            config,
+            store,
+            reconnector,
+            identity,
+            rotation: None,
+        }
+    }
+
+    /// Rotate through `channel` when due. Only one service per device
+    /// should rotate; the others follow the change in the store.
+    pub fn with_rotation(mut self, channel: Box<dyn ProvisioningChannel>) -> Self {
+        self.rotation = Some(channel);
+        self
+    }
+
+    pub async fn run(self) {
+        let mut serial = self.store.certificate_info().ok().map(|info| info.serial);
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.check_interval));
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.check(&mut serial).await {
+                error!("Certificate check failed: {:#}", e);
+            }
+        }
+    }
+
+    async fn check(&self, serial: &mut Option<String>) -> Result<()> {
+        if let Some(channel) = &self.rotation {
+            self.provisioner(channel.as_ref())
+                .revoke_due(&self.identity)
+                .await;
+        }
+        let info = self.store.certificate_info()?;
+        if serial.as_deref() != Some(info.serial.as_str()) {
+            info!("Device certificate changed, reconnecting");
+            self.reconnect().await?;
+            *serial = Some(info.serial);
+            return Ok(());
+        }
+
+        let days_left = info.days_left(Utc::now());
+        if days_left > self.config.rotate_before_days {
+            return Ok(());
+        }
+        let Some(channel) = &self.rotation else {
+            warn!(
+                "Device certificate expires on {} ({} days left)",
+                info.not_after, days_left
+            );
+            return Ok(());
+        };
+        self.provisioner(channel.as_ref())
+            .rotate(&self.identity, || self.reconnect())
+            .await?;
+        *serial = self.store.certificate_info().ok().map(|info| info.serial);
+        Ok(())
+    }
+
+    fn provisioner<'a>(&self, channel: &'a dyn ProvisioningChannel) -> Provisioner<'a> {
+        Provisioner::new(self.store.clone(), channel)
+            .with_revoke_delay(Duration::from_secs(self.config.revoke_delay))
+    }
+
+    async fn reconnect(&self) -> Result<()> {
+        self.reconnector
+            .reconnect(Duration::from_secs(self.config.connect_timeout))
+            .await
+    }
+}
@@ -0,0 +1,267 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::{anyhow, bail, Context, Result};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, pbkdf2};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::num::NonZeroU32;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+use crate::config::{config_dir, SecretsConfig};
+use crate::identity;
+use crate::util;
+
+/// Config values starting with this are looked up in the secret store,
+/// e.g. `password = "secret:camera1"`.
+pub const SECRET_PREFIX: &str = "secret:";
+
+const SECRETS_FILE: &str = "secrets.enc";
+const MAGIC: &[u8; 8] = b"LUFFYSEC";
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = MAGIC.len() + 1 + SALT_LEN;
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+type Salt = [u8; SALT_LEN];
+type Key = [u8; 32];
+/// Digest of the key material and the salt.
+type KeyId = ([u8; 32], Salt);
+
+/// Keys already derived in this process. Deriving takes long enough to notice on every config load.
+static DERIVED_KEYS: LazyLock<Mutex<HashMap<KeyId, Key>>> = LazyLock::new(Default::default);
+
+/// The name referenced by a config value, if it is a secret reference.
+pub fn reference(value: &str) -> Option<&str> {
+    value.strip_prefix(SECRET_PREFIX)
+}
+
+/// Where the store key comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeySource {
+    /// Derived from the machine id and board serial, so the file is useless
+    /// on another device.
+    Machine,
+    /// Derived from the contents of a passphrase file.
+    PassphraseFile(PathBuf),
+}
+
+impl KeySource {
+    pub fn from_config(config: &SecretsConfig) -> Self {
+        match &config.passphrase_file {
+            Some(path) => KeySource::PassphraseFile(PathBuf::from(path)),
+            None => KeySource::Machine,
+        }
+    }
+
+    fn material(&self) -> Result<Vec<u8>> {
+        match self {
+            KeySource::Machine => identity::machine_secret()
+                .map(String::into_bytes)
+                .ok_or_else(|| anyhow!("No machine id to derive the secret store key from")),
+            KeySource::PassphraseFile(path) => {
+                let passphrase =
+                    fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+                if passphrase.trim_ascii().is_empty() {
+                    bail!("Passphrase file {} is empty", path.display());
+                }
+                Ok(passphrase.trim_ascii().to_vec())
+            }
+        }
+    }
+}
+
+/// Named secrets (private keys, camera credentials, API tokens) kept in one
+/// file encrypted with ChaCha20-Poly1305.
+///
+/// Changes are only written by [`Self::save`], which applies them on top of
+/// what other writers saved in the meantime.
+pub struct SecretStore {
+    path: PathBuf,
+    source: KeySource,
+    salt: [u8; SALT_LEN],
+    key: [u8; 32],
+    secrets: BTreeMap<String, String>,
+    /// Names set (`Some`) or removed (`None`) since the store was read.
+    changes: BTreeMap<String, Option<String>>,
+}
+
+impl std::fmt::Debug for SecretStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretStore")
+            .field("path", &self.path)
+            .field("names", &self.secrets.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl SecretStore {
+    /// The store at `path`, empty if the file does not exist yet.
+    pub fn open(path: impl Into<PathBuf>, source: &KeySource) -> Result<Self> {
+        let path = path.into();
+        let material = source.material()?;
+        let (salt, key, secrets) = if path.exists() {
+            read(&path, &material)?
+        } else {
+            let mut salt = [0u8; SALT_LEN];
+            SystemRandom::new()
+                .fill(&mut salt)
+                .map_err(|_| anyhow!("No random source"))?;
+            (salt, derive_key(&material, &salt), BTreeMap::new())
+        };
+        Ok(Self {
+            path,
+            source: source.clone(),
+            salt,
+            key,
+            secrets,
+            changes: BTreeMap::new(),
+        })
+    }
+
+    /// The store selected by the `[secrets]` config section.
+    pub fn from_config(config: &SecretsConfig) -> Result<Self> {
+        Self::open(Self::path(config)?, &KeySource::from_config(config))
+    }
+
+    /// Location of the store file for `config`.
+    pub fn path(config: &SecretsConfig) -> Result<PathBuf> {
+        match &config.path {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => Ok(config_dir().join(SECRETS_FILE)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.secrets.get(name).map(String::as_str)
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.secrets.insert(name.to_string(), value.to_string());
+        self.changes
+            .insert(name.to_string(), Some(value.to_string()));
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.changes.insert(name.to_string(), None);
+        self.secrets.remove(name).is_some()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.secrets.keys().map(String::as_str)
+    }
+
+    /// Apply the changes to the file as it is now, encrypt with a fresh nonce
+    /// and replace it atomically. Writers are serialized by a lock on
+    /// `<path>.lock`.
+    pub fn save(&mut self) -> Result<()> {
+        let _lock = lock(&self.path)?;
+        if self.path.exists() {
+            let (salt, key, secrets) = read(&self.path, &self.source.material()?)?;
+            self.salt = salt;
+            self.key = key;
+            self.secrets = secrets;
+            for (name, value) in &self.changes {
+                match value {
+                    Some(value) => self.secrets.insert(name.clone(), value.clone()),
+                    None => self.secrets.remove(name),
+                };
+            }
+        }
+
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("No random source"))?;
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        header.extend_from_slice(&self.salt);
+
+        let mut ciphertext = serde_json::to_vec(&self.secrets)?;
+        cipher(&self.key)
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(&header),
+                &mut ciphertext,
+            )
+            .map_err(|_| anyhow!("Failed to encrypt secrets"))?;
+        let mut data = header;
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+
+        util::write_atomic(&self.path, &data, 0o600)?;
+        self.changes.clear();
+        Ok(())
+    }
+}
+
+/// Salt, key and secrets of the store file at `path`.
+fn read(path: &Path, material: &[u8]) -> Result<(Salt, Key, BTreeMap<String, String>)> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if data.len() < HEADER_LEN + NONCE_LEN || !data.starts_with(MAGIC) {
+        bail!("{} is not a secret store", path.display());
+    }
+    if data[MAGIC.len()] != VERSION {
+        bail!("Unsupported secret store version {}", data[MAGIC.len()]);
+    }
+    let (header, rest) = data.split_at(HEADER_LEN);
+    let salt: [u8; SALT_LEN] = header[MAGIC.len() + 1..].try_into()?;
+    let key = derive_key(material, &salt);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let mut plaintext = ciphertext.to_vec();
+    let plaintext = cipher(&key)
+        .open_in_place(
+            Nonce::try_assume_unique_for_key(nonce).map_err(|_| anyhow!("Invalid nonce"))?,
+            Aad::from(header),
+            &mut plaintext,
+        )
+        .map_err(|_| anyhow!("Cannot decrypt {}: wrong key", path.display()))?;
+    let secrets = serde_json::from_slice(plaintext).context("Corrupt secret store")?;
+    Ok((salt, key, secrets))
+}
+
+/// Exclusive lock on `<path>.lock`, released when the file is dropped.
+fn lock(path: &Path) -> Result<File> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+    let dir = lock_path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open {}", lock_path.display()))?;
+    util::copy_owner(&lock_path, dir);
+    file.lock()
+        .with_context(|| format!("Failed to lock {}", lock_path.display()))?;
+    Ok(file)
+}
+
+fn derive_key(material: &[u8], salt: &Salt) -> Key {
+    let digest = digest::digest(&digest::SHA256, material);
+    let id: KeyId = (digest.as_ref().try_into().expect("SHA-256 digest"), *salt);
+    if let Some(key) = DERIVED_KEYS.lock().unwrap().get(&id) {
+        return *key;
+    }
+    let mut key = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+        salt,
+        material,
+        &mut key,
+    );
+    DERIVED_KEYS.lock().unwrap().insert(id, key);
+    key
+}
+
+fn cipher(key: &[u8; 32]) -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).expect("32 byte key"))
+}
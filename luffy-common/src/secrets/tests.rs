@@ -0,0 +1,138 @@
+use super::*;
+use std::os::unix::fs::PermissionsExt;
+
+fn passphrase(dir: &Path) -> KeySource {
+    let path = dir.join("passphrase");
+    fs::write(&path, "correct horse battery staple\n").unwrap();
+    KeySource::PassphraseFile(path)
+}
+
+#[test]
+fn test_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = passphrase(dir.path());
+    let path = dir.path().join("secrets.enc");
+
+    let mut store = SecretStore::open(&path, &source).unwrap();
+    assert_eq!(store.names().count(), 0);
+    store.set("camera1", "mt000000");
+    store.set("api_token", "abc");
+    store.save().unwrap();
+
+    let raw = fs::read(&path).unwrap();
+    assert!(!raw.windows(8).any(|w| w == b"mt000000"));
+    assert_eq!(
+        fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+
+    let mut store = SecretStore::open(&path, &source).unwrap();
+    assert_eq!(store.get("camera1"), Some("mt000000"));
+    assert_eq!(store.names().collect::<Vec<_>>(), ["api_token", "camera1"]);
+    assert!(store.remove("api_token"));
+    assert!(!store.remove("api_token"));
+}
+
+#[test]
+fn test_wrong_key_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("secrets.enc");
+    let mut store = SecretStore::open(&path, &passphrase(dir.path())).unwrap();
+    store.set("camera1", "mt000000");
+    store.save().unwrap();
+
+    let other = dir.path().join("other");
+    fs::write(&other, "wrong").unwrap();
+    let error = SecretStore::open(&path, &KeySource::PassphraseFile(other)).unwrap_err();
+    assert!(error.to_string().contains("wrong key"));
+}
+
+#[test]
+fn test_reference() {
+    assert_eq!(reference("secret:camera1"), Some("camera1"));
+    assert_eq!(reference("mt000000"), None);
+}
+
+#[test]
+fn test_config_references_are_resolved() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = passphrase(dir.path());
+    let KeySource::PassphraseFile(passphrase_file) = &source else {
+        unreachable!()
+    };
+    let path = dir.path().join("secrets.enc");
+    let mut store = SecretStore::open(&path, &source).unwrap();
+    store.set("camera1", "mt000000");
+    store.save().unwrap();
+
+    let toml = format!(
+        r#"
+        [secrets]
+        path = "{}"
+        passphrase_file = "{}"
+
+        [[cameras]]
+        username = "admin"
+        password = "secret:camera1"
+        "#,
+        path.display(),
+        passphrase_file.display()
+    );
+    let config = config::Config::builder()
+        .add_source(config::File::from_str(&toml, config::FileFormat::Toml))
+        .build()
+        .unwrap();
+    let config = crate::config::resolve_secrets(config).unwrap();
+    assert_eq!(
+        config.get_string("cameras[0].password").unwrap(),
+        "mt000000"
+    );
+    assert_eq!(config.get_string("cameras[0].username").unwrap(), "admin");
+
+    let missing = config::Config::builder()
+        .add_source(config::File::from_str(
+            &toml.replace("camera1", "camera2"),
+            config::FileFormat::Toml,
+        ))
+        .build()
+        .unwrap();
+    let error = crate::config::resolve_secrets(missing).unwrap_err();
+    assert!(error.to_string().contains("camera2"));
+}
+
+#[test]
+fn test_concurrent_writers_keep_each_others_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = passphrase(dir.path());
+    let path = dir.path().join("secrets.enc");
+
+    // Both start from an empty store, as the services do when migrating at
+    // startup.
+    let mut gateway = SecretStore::open(&path, &source).unwrap();
+    let mut media = SecretStore::open(&path, &source).unwrap();
+    gateway.set("device_key", "key");
+    media.set("camera1", "mt000000");
+    let threads = [
+        std::thread::spawn(move || gateway.save().unwrap()),
+        std::thread::spawn(move || media.save().unwrap()),
+    ];
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let mut store = SecretStore::open(&path, &source).unwrap();
+    assert_eq!(store.get("device_key"), Some("key"));
+    assert_eq!(store.get("camera1"), Some("mt000000"));
+
+    store.remove("camera1");
+    store.save().unwrap();
+    let store = SecretStore::open(&path, &source).unwrap();
+    assert_eq!(store.names().collect::<Vec<_>>(), ["device_key"]);
+
+    let mut names: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["passphrase", "secrets.enc", "secrets.enc.lock"]);
+}
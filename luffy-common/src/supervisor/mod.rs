@@ -0,0 +1,476 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::{error, info, warn};
+
+use crate::config_watch;
+use crate::health::{HealthContributor, HealthFields};
+use crate::systemd;
+
+/// How long a service gets to stop before its task is aborted.
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A service that ran this long before failing starts over with its
+/// restarts and backoff.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Health of the process's supervisor, for [`health_fields`].
+static HEALTH: OnceLock<SupervisorHealth> = OnceLock::new();
+
+/// A long-running part of a binary, run by a [`Supervisor`].
+#[async_trait]
+pub trait Service: Send + 'static {
+    fn name(&self) -> &str;
+
+    /// Start the service. It may return once it is up, leaving the work to
+    /// tasks of its own, or run until it is stopped. An error is handled
+    /// according to the service's [`RestartPolicy`].
+    async fn start(&mut self) -> Result<()>;
+
+    /// Stop what `start` left running; a `start` still running is cancelled
+    /// first. Also called after a failed start, before a restart.
+    async fn stop(&mut self);
+
+    /// Whether `start` runs until the service is stopped. The supervisor
+    /// waits for `start` to return before starting the next service, unless
+    /// it runs until stopped.
+    fn runs_in_start(&self) -> bool {
+        false
+    }
+
+    /// Service-specific health, polled while the service runs. Its
+    /// [`HealthContributor::is_alive`] probe gates the systemd watchdog.
+    fn health(&self) -> Option<Arc<dyn HealthContributor>> {
+        None
+    }
+}
+
+/// Implement [`Service`] for `$service` with its own `start` and `stop`
+/// methods, both async. Add `runs_in_start` for a `start` that runs until
+/// the service is stopped, and `health` to forward its own `health` method.
+#[macro_export]
+macro_rules! forward_service {
+    ($service:ty, $name:literal) => {
+        $crate::forward_service!($service, $name, false);
+    };
+    ($service:ty, $name:literal, health) => {
+        $crate::forward_service!($service, $name, false, health);
+    };
+    ($service:ty, $name:literal, runs_in_start) => {
+        $crate::forward_service!($service, $name, true);
+    };
+    ($service:ty, $name:literal, runs_in_start, health) => {
+        $crate::forward_service!($service, $name, true, health);
+    };
+    ($service:ty, $name:literal, $runs_in_start:literal $(, $health:ident)?) => {
+        #[async_trait::async_trait]
+        impl $crate::supervisor::Service for $service {
+            fn name(&self) -> &str {
+                $name
+            }
+
+            async fn start(&mut self) -> anyhow::Result<()> {
+                (*self).start().await
+            }
+
+            async fn stop(&mut self) {
+                (*self).stop().await
+            }
+
+            fn runs_in_start(&self) -> bool {
+                $runs_in_start
+            }
+
+            $(
+                fn health(
+                    &self,
+                ) -> Option<std::sync::Arc<dyn $crate::health::HealthContributor>> {
+                    let _ = stringify!($health);
+                    (*self).health()
+                }
+            )?
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestartPolicy {
+    /// A failure stops the process, for services that cannot be restarted
+    /// in place; systemd restarts it.
+    Never,
+    /// Restart after `backoff`, doubled with every failure in a row. After
+    /// `max_restarts` the failure stops the process.
+    OnFailure {
+        max_restarts: u32,
+        backoff: Duration,
+    },
+}
+
+impl RestartPolicy {
+    /// Up to 5 restarts in a row, the first after a second.
+    pub const fn on_failure() -> Self {
+        Self::OnFailure {
+            max_restarts: 5,
+            backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// The delay before restart number `attempt`, or `None` when the failure
+    /// is final.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        match *self {
+            Self::OnFailure {
+                max_restarts,
+                backoff,
+            } if attempt >= 1 && attempt <= max_restarts => Some(
+                backoff
+                    .saturating_mul(1 << (attempt - 1).min(16))
+                    .min(MAX_BACKOFF),
+            ),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceState {
+    Running,
+    Restarting,
+    Failed,
+    Stopped,
+}
+
+/// What the supervisor knows about a service, reported in the `services`
+/// health field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceStatus {
+    pub state: ServiceState,
+    /// Restarts since the process started.
+    pub restarts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: HealthFields,
+}
+
+struct Supervised {
+    status: ServiceStatus,
+    health: Option<Arc<dyn HealthContributor>>,
+}
+
+/// The status of every supervised service. Cheap to clone; clones share
+/// the same state.
+#[derive(Clone, Default)]
+pub struct SupervisorHealth {
+    services: Arc<RwLock<BTreeMap<String, Supervised>>>,
+}
+
+impl SupervisorHealth {
+    fn register(&self, name: &str, health: Option<Arc<dyn HealthContributor>>) {
+        self.services.write().unwrap().insert(
+            name.to_string(),
+            Supervised {
+                status: ServiceStatus {
+                    state: ServiceState::Running,
+                    restarts: 0,
+                    last_error: None,
+                    fields: HealthFields::new(),
+                },
+                health,
+            },
+        );
+    }
+
+    fn update(&self, name: &str, update: impl FnOnce(&mut ServiceStatus)) {
+        if let Some(service) = self.services.write().unwrap().get_mut(name) {
+            update(&mut service.status);
+        }
+    }
+
+    /// The status of each service, with its own health fields.
+    pub async fn statuses(&self) -> BTreeMap<String, ServiceStatus> {
+        let services: Vec<_> = self
+            .services
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, service)| (name.clone(), service.status.clone(), service.health.clone()))
+            .collect();
+        let mut statuses = BTreeMap::new();
+        for (name, mut status, health) in services {
+            if let Some(health) = health {
+                status.fields = health.fields().await;
+            }
+            statuses.insert(name, status);
+        }
+        statuses
+    }
+
+    /// Whether no service failed for good and every running one passes its
+    /// [`HealthContributor::is_alive`] probe; services being restarted are
+    /// still alive. Gates the systemd watchdog.
+    pub fn is_alive(&self) -> bool {
+        self.services
+            .read()
+            .unwrap()
+            .values()
+            .all(|service| match service.status.state {
+                ServiceState::Failed => false,
+                ServiceState::Running => service
+                    .health
+                    .as_ref()
+                    .is_none_or(|health| health.is_alive()),
+                ServiceState::Restarting | ServiceState::Stopped => true,
+            })
+    }
+
+    /// Whether no service is failed or waiting to be restarted.
+    pub fn is_healthy(&self) -> bool {
+        self.services.read().unwrap().values().all(|service| {
+            matches!(
+                service.status.state,
+                ServiceState::Running | ServiceState::Stopped
+            )
+        })
+    }
+}
+
+#[async_trait]
+impl HealthContributor for SupervisorHealth {
+    async fn fields(&self) -> HealthFields {
+        HealthFields::from([("services".to_string(), json!(self.statuses().await))])
+    }
+}
+
+/// The `services` health field of the process's supervisor.
+pub async fn health_fields() -> HealthFields {
+    match HEALTH.get() {
+        Some(health) => health.fields().await,
+        None => HealthFields::new(),
+    }
+}
+
+struct Entry {
+    service: Box<dyn Service>,
+    policy: RestartPolicy,
+}
+
+/// Runs services one after the other, restarting them by their
+/// [`RestartPolicy`], and stops them in reverse order on shutdown or when
+/// one fails for good.
+pub struct Supervisor {
+    entries: Vec<Entry>,
+    health: SupervisorHealth,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        let health = SupervisorHealth::default();
+        // The first supervisor is the process's.
+        let _ = HEALTH.set(health.clone());
+        Self {
+            entries: Vec::new(),
+            health,
+        }
+    }
+
+    /// Add a service; services start in the order they are added, each once
+    /// the one before is up.
+    pub fn add(&mut self, service: impl Service, policy: RestartPolicy) -> &mut Self {
+        self.entries.push(Entry {
+            service: Box::new(service),
+            policy,
+        });
+        self
+    }
+
+    pub fn health(&self) -> SupervisorHealth {
+        self.health.clone()
+    }
+
+    /// Run the services until `shutdown` completes or one of them fails for
+    /// good, then stop them in reverse order. Returns that failure. Systemd
+    /// is told the process is ready once every service is up.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let (failed_tx, mut failed) = mpsc::unbounded_channel();
+        let health = self.health.clone();
+        let watchdog = tokio::spawn(systemd::watchdog(move || health.is_alive()));
+        tokio::pin!(shutdown);
+
+        let mut running = Vec::new();
+        let mut interrupted = false;
+        let mut failure = None;
+        for entry in self.entries {
+            let name = entry.service.name().to_string();
+            info!("Starting {}...", name);
+            self.health.register(&name, entry.service.health());
+            let (stop_tx, stop) = watch::channel(false);
+            let (up_tx, up) = oneshot::channel();
+            let task = tokio::spawn(supervise(
+                entry,
+                stop,
+                up_tx,
+                self.health.clone(),
+                failed_tx.clone(),
+            ));
+            running.push((name, stop_tx, task));
+            // A service that failed for good drops `up`; its failure
+            // follows.
+            tokio::select! {
+                _ = up => {}
+                _ = &mut shutdown => {
+                    interrupted = true;
+                    break;
+                }
+                Some(error) = failed.recv() => {
+                    failure = Some(error);
+                    break;
+                }
+            }
+        }
+        drop(failed_tx);
+
+        if !interrupted && failure.is_none() {
+            systemd::notify_ready();
+            failure = tokio::select! {
+                _ = &mut shutdown => None,
+                Some(error) = failed.recv() => Some(error),
+            };
+        }
+        systemd::notify_stopping();
+        for (name, stop, mut task) in running.into_iter().rev() {
+            info!("Stopping {}...", name);
+            let _ = stop.send(true);
+            if tokio::time::timeout(STOP_TIMEOUT, &mut task).await.is_err() {
+                warn!("{} did not stop in {:?}, aborting", name, STOP_TIMEOUT);
+                task.abort();
+            }
+        }
+        watchdog.abort();
+        info!("All services stopped");
+        match failure {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Run one service until it is stopped or fails for good. `up` is sent once
+/// `start` returned, or right away for a service that runs in `start`.
+async fn supervise(
+    mut entry: Entry,
+    mut stop: watch::Receiver<bool>,
+    up: oneshot::Sender<()>,
+    health: SupervisorHealth,
+    failed: mpsc::UnboundedSender<anyhow::Error>,
+) {
+    let name = entry.service.name().to_string();
+    let mut up = Some(up);
+    if entry.service.runs_in_start() {
+        if let Some(up) = up.take() {
+            let _ = up.send(());
+        }
+    }
+    let mut attempt = 0;
+    loop {
+        health.update(&name, |status| status.state = ServiceState::Running);
+        let started = Instant::now();
+        let result = tokio::select! {
+            result = entry.service.start() => Some(result),
+            _ = stop.wait_for(|stop| *stop) => None,
+        };
+        let error = match result {
+            Some(Err(error)) => error,
+            Some(Ok(())) => {
+                // Up, or done; either way it is stopped with the rest.
+                if let Some(up) = up.take() {
+                    let _ = up.send(());
+                }
+                let _ = stop.wait_for(|stop| *stop).await;
+                break;
+            }
+            None => break,
+        };
+
+        error!("{} failed: {:#}", name, error);
+        entry.service.stop().await;
+        if started.elapsed() >= STABLE_AFTER {
+            attempt = 0;
+        }
+        attempt += 1;
+        let Some(delay) = entry.policy.delay(attempt) else {
+            health.update(&name, |status| {
+                status.state = ServiceState::Failed;
+                status.last_error = Some(format!("{:#}", error));
+            });
+            let _ = failed.send(error.context(format!("{} failed", name)));
+            return;
+        };
+        health.update(&name, |status| {
+            status.state = ServiceState::Restarting;
+            status.restarts += 1;
+            status.last_error = Some(format!("{:#}", error));
+        });
+        warn!("Restarting {} in {:?}", name, delay);
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = stop.wait_for(|stop| *stop) => {
+                health.update(&name, |status| status.state = ServiceState::Stopped);
+                return;
+            }
+        }
+    }
+    entry.service.stop().await;
+    health.update(&name, |status| status.state = ServiceState::Stopped);
+}
+
+/// Completes on Ctrl-C or SIGTERM. SIGHUP meanwhile reloads the watched
+/// config files.
+pub async fn shutdown_signal() {
+    let signals = signal(SignalKind::terminate())
+        .and_then(|terminate| signal(SignalKind::hangup()).map(|hangup| (terminate, hangup)));
+    let (mut terminate, mut hangup) = match signals {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("Failed to listen for signals: {}", e);
+            std::future::pending::<()>().await;
+            return;
+        }
+    };
+    loop {
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                if let Err(e) = result {
+                    error!("Failed to listen for Ctrl-C: {}", e);
+                    std::future::pending::<()>().await;
+                }
+                break;
+            }
+            _ = terminate.recv() => break,
+            _ = hangup.recv() => {
+                info!("SIGHUP received, reloading config");
+                systemd::notify_reloading();
+                config_watch::reload();
+                systemd::notify_reloaded();
+            }
+        }
+    }
+    info!("Shutdown signal received, stopping services...");
+}
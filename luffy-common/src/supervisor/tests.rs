@@ -0,0 +1,237 @@
+use super::*;
+use crate::health::Liveness;
+use std::sync::Mutex;
+
+/// Fails `failures` times, then runs until stopped. Records starts and stops
+/// in `log`.
+struct TestService {
+    name: &'static str,
+    failures: u32,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl TestService {
+    fn new(name: &'static str, failures: u32, log: &Arc<Mutex<Vec<String>>>) -> Self {
+        Self {
+            name,
+            failures,
+            log: log.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Service for TestService {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        self.log
+            .lock()
+            .unwrap()
+            .push(format!("start {}", self.name));
+        if self.failures > 0 {
+            self.failures -= 1;
+            anyhow::bail!("{} broke", self.name);
+        }
+        std::future::pending().await
+    }
+
+    async fn stop(&mut self) {
+        self.log.lock().unwrap().push(format!("stop {}", self.name));
+    }
+
+    fn runs_in_start(&self) -> bool {
+        true
+    }
+
+    fn health(&self) -> Option<Arc<dyn HealthContributor>> {
+        Some(Arc::new(|| async {
+            HealthFields::from([("rate".to_string(), json!(1))])
+        }))
+    }
+}
+
+/// Takes a while to come up, then returns from `start`.
+struct SlowService {
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl SlowService {
+    async fn start(&self) -> Result<()> {
+        self.log.lock().unwrap().push("start slow".to_string());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        self.log.lock().unwrap().push("slow up".to_string());
+        Ok(())
+    }
+
+    async fn stop(&self) {
+        self.log.lock().unwrap().push("stop slow".to_string());
+    }
+}
+
+crate::forward_service!(SlowService, "slow");
+
+/// Hangs in the middle of a step of its event loop.
+struct HungService {
+    liveness: Liveness,
+}
+
+impl HungService {
+    async fn start(&self) -> Result<()> {
+        self.liveness.step(Duration::from_millis(50));
+        std::future::pending().await
+    }
+
+    async fn stop(&self) {}
+
+    fn health(&self) -> Option<Arc<dyn HealthContributor>> {
+        Some(Arc::new(vec![self.liveness.clone()]))
+    }
+}
+
+crate::forward_service!(HungService, "hung", runs_in_start, health);
+
+fn quick(max_restarts: u32) -> RestartPolicy {
+    RestartPolicy::OnFailure {
+        max_restarts,
+        backoff: Duration::from_millis(10),
+    }
+}
+
+#[test]
+fn test_restart_delay() {
+    let policy = RestartPolicy::on_failure();
+    assert_eq!(policy.delay(1), Some(Duration::from_secs(1)));
+    assert_eq!(policy.delay(3), Some(Duration::from_secs(4)));
+    assert_eq!(policy.delay(6), None);
+    let policy = RestartPolicy::OnFailure {
+        max_restarts: 100,
+        backoff: Duration::from_secs(1),
+    };
+    assert_eq!(policy.delay(50), Some(MAX_BACKOFF));
+    assert_eq!(RestartPolicy::Never.delay(1), None);
+}
+
+#[tokio::test]
+async fn test_stops_in_reverse_order() {
+    let _env = systemd::TEST_ENV.lock().await;
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut supervisor = Supervisor::new();
+    supervisor
+        .add(TestService::new("a", 0, &log), RestartPolicy::Never)
+        .add(TestService::new("b", 1, &log), quick(3));
+    let health = supervisor.health();
+
+    let (shutdown_tx, shutdown) = tokio::sync::oneshot::channel::<()>();
+    let run = tokio::spawn(supervisor.run(async {
+        let _ = shutdown.await;
+    }));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let statuses = health.statuses().await;
+    assert_eq!(statuses["a"].state, ServiceState::Running);
+    assert_eq!(statuses["b"].state, ServiceState::Running);
+    assert_eq!(statuses["b"].restarts, 1);
+    assert_eq!(statuses["b"].last_error.as_deref(), Some("b broke"));
+    assert_eq!(statuses["b"].fields["rate"], 1);
+    assert!(health.is_healthy());
+
+    shutdown_tx.send(()).unwrap();
+    run.await.unwrap().unwrap();
+    assert_eq!(
+        *log.lock().unwrap(),
+        ["start a", "start b", "stop b", "start b", "stop b", "stop a"]
+    );
+    let statuses = health.statuses().await;
+    assert!(statuses.values().all(|s| s.state == ServiceState::Stopped));
+}
+
+#[tokio::test]
+async fn test_failure_stops_all() {
+    let _env = systemd::TEST_ENV.lock().await;
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut supervisor = Supervisor::new();
+    supervisor
+        .add(TestService::new("a", 0, &log), RestartPolicy::Never)
+        .add(TestService::new("b", 3, &log), quick(2));
+    let health = supervisor.health();
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(5),
+        supervisor.run(std::future::pending()),
+    )
+    .await
+    .expect("supervisor did not stop");
+    let error = result.unwrap_err();
+    assert_eq!(format!("{:#}", error), "b failed: b broke");
+    assert_eq!(log.lock().unwrap().last().unwrap(), "stop a");
+
+    let statuses = health.statuses().await;
+    assert_eq!(statuses["b"].state, ServiceState::Failed);
+    assert_eq!(statuses["b"].restarts, 2);
+    assert_eq!(statuses["a"].state, ServiceState::Stopped);
+    assert!(!health.is_healthy());
+}
+
+#[tokio::test]
+async fn test_starts_in_order() {
+    let _env = systemd::TEST_ENV.lock().await;
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut supervisor = Supervisor::new();
+    supervisor
+        .add(TestService::new("a", 0, &log), RestartPolicy::Never)
+        .add(SlowService { log: log.clone() }, RestartPolicy::Never)
+        .add(TestService::new("b", 0, &log), RestartPolicy::Never);
+
+    let (shutdown_tx, shutdown) = tokio::sync::oneshot::channel::<()>();
+    let run = tokio::spawn(supervisor.run(async {
+        let _ = shutdown.await;
+    }));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    shutdown_tx.send(()).unwrap();
+    run.await.unwrap().unwrap();
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "start a",
+            "start slow",
+            "slow up",
+            "start b",
+            "stop b",
+            "stop slow",
+            "stop a"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_hung_service_is_not_alive() {
+    let _env = systemd::TEST_ENV.lock().await;
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut supervisor = Supervisor::new();
+    supervisor
+        .add(TestService::new("a", 0, &log), RestartPolicy::Never)
+        .add(
+            HungService {
+                liveness: Liveness::new(),
+            },
+            RestartPolicy::Never,
+        );
+    let health = supervisor.health();
+
+    let (shutdown_tx, shutdown) = tokio::sync::oneshot::channel::<()>();
+    let run = tokio::spawn(supervisor.run(async {
+        let _ = shutdown.await;
+    }));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(health.is_alive());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let statuses = health.statuses().await;
+    assert_eq!(statuses["hung"].state, ServiceState::Running);
+    assert!(!health.is_alive());
+
+    shutdown_tx.send(()).unwrap();
+    run.await.unwrap().unwrap();
+}
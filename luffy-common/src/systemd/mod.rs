@@ -0,0 +1,78 @@
+#[cfg(test)]
+mod tests;
+
+use sd_notify::NotifyState;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Held by tests that set the systemd environment or notify systemd, which
+/// share the process environment.
+#[cfg(test)]
+pub(crate) static TEST_ENV: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Tell systemd the service is up. Only the first call counts; outside a
+/// `Type=notify` unit this does nothing.
+pub fn notify_ready() {
+    if !READY.swap(true, Ordering::SeqCst) {
+        info!("Notifying systemd that the service is ready");
+        notify(&[NotifyState::Ready]);
+    }
+}
+
+pub fn notify_stopping() {
+    notify(&[NotifyState::Stopping]);
+}
+
+/// Tell systemd a reload started; [`notify_reloaded`] ends it.
+pub fn notify_reloading() {
+    match NotifyState::monotonic_usec_now() {
+        Ok(now) => notify(&[NotifyState::Reloading, now]),
+        Err(_) => notify(&[NotifyState::Reloading]),
+    }
+}
+
+pub fn notify_reloaded() {
+    // Before the service was ready systemd is still waiting for the first
+    // READY=1.
+    if READY.load(Ordering::SeqCst) {
+        notify(&[NotifyState::Ready]);
+    }
+}
+
+fn notify(state: &[NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        debug!("Failed to notify systemd: {}", e);
+    }
+}
+
+/// How often to ping the systemd watchdog, half its `WatchdogSec`; `None`
+/// when the unit has no watchdog.
+pub fn watchdog_interval() -> Option<Duration> {
+    let mut usec = 0;
+    sd_notify::watchdog_enabled(false, &mut usec).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Ping the systemd watchdog for as long as `alive` holds. Systemd restarts
+/// the service when the pings stop, including when the runtime is stuck.
+/// Returns at once when the unit has no watchdog.
+pub async fn watchdog(alive: impl Fn() -> bool) {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    info!("Pinging the systemd watchdog every {:?}", interval);
+    let mut ticker = tokio::time::interval(interval);
+    let mut withheld = false;
+    loop {
+        ticker.tick().await;
+        if alive() {
+            notify(&[NotifyState::Watchdog]);
+            withheld = false;
+        } else if !withheld {
+            warn!("Service is not alive, withholding the systemd watchdog ping");
+            withheld = true;
+        }
+    }
+}
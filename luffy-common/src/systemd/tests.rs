@@ -0,0 +1,48 @@
+use super::*;
+use std::os::unix::net::UnixDatagram;
+
+fn recv(socket: &UnixDatagram) -> String {
+    let mut buf = [0u8; 256];
+    let len = socket.recv(&mut buf).expect("no notification");
+    String::from_utf8_lossy(&buf[..len]).to_string()
+}
+
+#[test]
+fn test_notify() {
+    let _env = TEST_ENV.blocking_lock();
+    READY.store(false, Ordering::SeqCst);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("notify");
+    let socket = UnixDatagram::bind(&path).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+    std::env::set_var("NOTIFY_SOCKET", &path);
+
+    notify_stopping();
+    assert_eq!(recv(&socket), "STOPPING=1\n");
+    // Only a reload after the first READY=1 repeats it.
+    notify_reloaded();
+    notify_ready();
+    notify_ready();
+    assert_eq!(recv(&socket), "READY=1\n");
+    notify_reloaded();
+    assert_eq!(recv(&socket), "READY=1\n");
+    notify_reloading();
+    assert!(recv(&socket).starts_with("RELOADING=1\nMONOTONIC_USEC="));
+    std::env::remove_var("NOTIFY_SOCKET");
+}
+
+#[test]
+fn test_watchdog_interval() {
+    let _env = TEST_ENV.blocking_lock();
+    std::env::set_var("WATCHDOG_USEC", "10000000");
+    std::env::set_var("WATCHDOG_PID", std::process::id().to_string());
+    assert_eq!(watchdog_interval(), Some(Duration::from_secs(5)));
+
+    // Meant for another process.
+    std::env::set_var("WATCHDOG_PID", "1");
+    assert_eq!(watchdog_interval(), None);
+    std::env::remove_var("WATCHDOG_USEC");
+    std::env::remove_var("WATCHDOG_PID");
+}
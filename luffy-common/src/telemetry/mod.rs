@@ -0,0 +1,134 @@
+pub mod proto;
+mod state;
+
+#[cfg(test)]
+mod tests;
+
+pub use state::{json_schema, VehicleState, SCHEMA_VERSION};
+
+use anyhow::{anyhow, Context, Result};
+use prost::Message;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use strum_macros::Display;
+
+/// Wire encoding of a telemetry payload.
+///
+/// JSON keeps the plain `{vehicle_id}/telemetry` topic for backwards
+/// compatibility, binary encodings are advertised through a topic suffix
+/// (`{vehicle_id}/telemetry/cbor`, `{vehicle_id}/telemetry/pb`).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Cbor,
+    Protobuf,
+}
+
+impl Encoding {
+    pub fn topic_suffix(&self) -> Option<&'static str> {
+        match self {
+            Encoding::Json => None,
+            Encoding::Cbor => Some("cbor"),
+            Encoding::Protobuf => Some("pb"),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::Cbor => "application/cbor",
+            Encoding::Protobuf => "application/x-protobuf",
+        }
+    }
+
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "json" => Some(Encoding::Json),
+            "cbor" => Some(Encoding::Cbor),
+            "pb" => Some(Encoding::Protobuf),
+            _ => None,
+        }
+    }
+
+    /// Topic a payload with this encoding is published on.
+    pub fn topic(&self, base: &str) -> String {
+        match self.topic_suffix() {
+            Some(suffix) => format!("{}/{}", base, suffix),
+            None => base.to_string(),
+        }
+    }
+
+    /// Split `{vehicle_id}/telemetry[/suffix]` into the vehicle id and the encoding.
+    pub fn from_topic(topic: &str) -> Option<(&str, Self)> {
+        let mut parts = topic.split('/');
+        let vehicle_id = parts.next()?;
+        if parts.next()? != "telemetry" {
+            return None;
+        }
+        let encoding = match parts.next() {
+            Some(suffix) => Self::from_suffix(suffix)?,
+            None => Encoding::Json,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some((vehicle_id, encoding))
+    }
+}
+
+/// Conversion between a crate's telemetry struct and the protobuf message.
+/// JSON and CBOR go straight through serde.
+pub trait TelemetryCodec: Serialize + DeserializeOwned {
+    fn to_proto(&self) -> proto::VehicleState;
+    fn from_proto(message: proto::VehicleState) -> Self;
+}
+
+pub fn encode<T: TelemetryCodec>(state: &T, encoding: Encoding) -> Result<Vec<u8>> {
+    match encoding {
+        Encoding::Json => serde_json::to_vec(state).context("Failed to encode JSON telemetry"),
+        Encoding::Cbor => {
+            let mut buf = Vec::new();
+            ciborium::into_writer(state, &mut buf)
+                .map_err(|e| anyhow!("Failed to encode CBOR telemetry: {}", e))?;
+            Ok(buf)
+        }
+        Encoding::Protobuf => Ok(state.to_proto().encode_to_vec()),
+    }
+}
+
+pub fn decode<T: TelemetryCodec>(payload: &[u8], encoding: Encoding) -> Result<T> {
+    match encoding {
+        Encoding::Json => serde_json::from_slice(payload).context("Failed to decode JSON telemetry"),
+        Encoding::Cbor => ciborium::from_reader(payload)
+            .map_err(|e| anyhow!("Failed to decode CBOR telemetry: {}", e)),
+        Encoding::Protobuf => {
+            let message = proto::VehicleState::decode(payload)
+                .context("Failed to decode protobuf telemetry")?;
+            Ok(T::from_proto(message))
+        }
+    }
+}
+
+/// Decode a message from `{vehicle_id}/telemetry[/suffix]`, in the encoding
+/// its topic names. Returns the vehicle id with the state.
+pub fn decode_message(topic: &str, payload: &[u8]) -> Result<(String, VehicleState)> {
+    let (vehicle_id, encoding) =
+        Encoding::from_topic(topic).ok_or_else(|| anyhow!("Not a telemetry topic: {}", topic))?;
+    let state = decode(payload, encoding)
+        .with_context(|| format!("Failed to parse {} telemetry data", encoding))?;
+    Ok((vehicle_id.to_string(), state))
+}
+
+pub fn system_time_to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+pub fn millis_to_system_time(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
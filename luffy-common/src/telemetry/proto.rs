@@ -0,0 +1,32 @@
+// Hand-written prost message matching `proto/vehicle_state.proto`.
+// Tags must stay identical to the schema file.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct VehicleState {
+    #[prost(float, tag = "1")]
+    pub yaw_degree: f32,
+    #[prost(float, tag = "2")]
+    pub pitch_degree: f32,
+    #[prost(float, tag = "3")]
+    pub roll_degree: f32,
+    #[prost(float, tag = "4")]
+    pub altitude: f32,
+    #[prost(float, tag = "5")]
+    pub battery_percentage: f32,
+    #[prost(double, tag = "6")]
+    pub latitude: f64,
+    #[prost(double, tag = "7")]
+    pub longitude: f64,
+    #[prost(bool, tag = "8")]
+    pub armed: bool,
+    #[prost(string, tag = "9")]
+    pub flight_mode: String,
+    #[prost(uint64, tag = "10")]
+    pub last_heartbeat_ms: u64,
+    #[prost(string, repeated, tag = "11")]
+    pub errors: Vec<String>,
+    #[prost(string, tag = "12")]
+    pub luffy: String,
+    #[prost(uint32, tag = "13")]
+    pub schema_version: u32,
+}
@@ -0,0 +1,130 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::time::SystemTime;
+
+use super::{proto, system_time_to_millis, TelemetryCodec};
+
+/// Version of [`VehicleState`] written by this build. Bump it when the
+/// meaning of a field changes; adding a field does not need a bump.
+///
+/// 2: `last_heartbeat` is in epoch milliseconds.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// The vehicle telemetry published on `{vehicle_id}/telemetry`, shared by
+/// every luffy service.
+///
+/// Missing fields are unknown: zero, false or empty. Unknown fields are
+/// ignored, so older and newer services can read each other's payloads.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VehicleState {
+    /// Schema version of the sender, 0 for payloads from before it was
+    /// added.
+    #[serde(default)]
+    pub schema_version: u32,
+
+    // Flight data
+    #[serde(default)]
+    pub yaw_degree: f32,
+    #[serde(default)]
+    pub pitch_degree: f32,
+    #[serde(default)]
+    pub roll_degree: f32,
+    #[serde(default)]
+    pub altitude: f32,
+    #[serde(default)]
+    pub battery_percentage: f32,
+    #[serde(default)]
+    pub location: (f64, f64), // (latitude, longitude)
+    #[serde(default)]
+    pub armed: bool,
+    #[serde(default)]
+    pub flight_mode: String,
+
+    // System status
+    /// Unix epoch milliseconds of the last MAVLink heartbeat, 0 before the
+    /// first.
+    #[serde(default, deserialize_with = "deserialize_heartbeat")]
+    pub last_heartbeat: u64,
+    #[serde(default)]
+    pub errors: Vec<String>,
+    /// Version of the sending service, which fills it in; empty by default.
+    #[serde(default)]
+    pub luffy: String,
+}
+
+/// Schema version 1 wrote `last_heartbeat` as a serialized `SystemTime`.
+fn deserialize_heartbeat<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Heartbeat {
+        Millis(u64),
+        SystemTime(SystemTime),
+    }
+    Ok(match Heartbeat::deserialize(deserializer)? {
+        Heartbeat::Millis(millis) => millis,
+        Heartbeat::SystemTime(time) => system_time_to_millis(time),
+    })
+}
+
+impl Default for VehicleState {
+    fn default() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            yaw_degree: 0.0,
+            pitch_degree: 0.0,
+            roll_degree: 0.0,
+            altitude: 0.0,
+            battery_percentage: 0.0,
+            location: (0.0, 0.0),
+            armed: false,
+            flight_mode: "MANUAL".to_string(),
+            last_heartbeat: 0,
+            errors: Vec::new(),
+            luffy: String::new(),
+        }
+    }
+}
+
+impl TelemetryCodec for VehicleState {
+    fn to_proto(&self) -> proto::VehicleState {
+        proto::VehicleState {
+            yaw_degree: self.yaw_degree,
+            pitch_degree: self.pitch_degree,
+            roll_degree: self.roll_degree,
+            altitude: self.altitude,
+            battery_percentage: self.battery_percentage,
+            latitude: self.location.0,
+            longitude: self.location.1,
+            armed: self.armed,
+            flight_mode: self.flight_mode.clone(),
+            last_heartbeat_ms: self.last_heartbeat,
+            errors: self.errors.clone(),
+            luffy: self.luffy.clone(),
+            schema_version: self.schema_version,
+        }
+    }
+
+    fn from_proto(message: proto::VehicleState) -> Self {
+        Self {
+            schema_version: message.schema_version,
+            yaw_degree: message.yaw_degree,
+            pitch_degree: message.pitch_degree,
+            roll_degree: message.roll_degree,
+            altitude: message.altitude,
+            battery_percentage: message.battery_percentage,
+            location: (message.latitude, message.longitude),
+            armed: message.armed,
+            flight_mode: message.flight_mode,
+            last_heartbeat: message.last_heartbeat_ms,
+            errors: message.errors,
+            luffy: message.luffy,
+        }
+    }
+}
+
+/// The JSON Schema of [`VehicleState`], as committed in
+/// `schema/vehicle_state.schema.json`.
+pub fn json_schema() -> String {
+    let schema = schemars::schema_for!(VehicleState);
+    serde_json::to_string_pretty(&schema).expect("Schema is valid JSON") + "\n"
+}
@@ -0,0 +1,148 @@
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TestState {
+    yaw_degree: f32,
+    location: (f64, f64),
+    armed: bool,
+    flight_mode: String,
+    errors: Vec<String>,
+}
+
+impl TelemetryCodec for TestState {
+    fn to_proto(&self) -> proto::VehicleState {
+        proto::VehicleState {
+            yaw_degree: self.yaw_degree,
+            latitude: self.location.0,
+            longitude: self.location.1,
+            armed: self.armed,
+            flight_mode: self.flight_mode.clone(),
+            errors: self.errors.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn from_proto(message: proto::VehicleState) -> Self {
+        Self {
+            yaw_degree: message.yaw_degree,
+            location: (message.latitude, message.longitude),
+            armed: message.armed,
+            flight_mode: message.flight_mode,
+            errors: message.errors,
+        }
+    }
+}
+
+fn sample() -> TestState {
+    TestState {
+        yaw_degree: 12.5,
+        location: (43.65, -79.38),
+        armed: true,
+        flight_mode: "GUIDED".to_string(),
+        errors: vec!["low battery".to_string()],
+    }
+}
+
+#[test]
+fn test_round_trip_all_encodings() {
+    for encoding in [Encoding::Json, Encoding::Cbor, Encoding::Protobuf] {
+        let payload = encode(&sample(), encoding).unwrap();
+        let decoded: TestState = decode(&payload, encoding).unwrap();
+        assert_eq!(decoded, sample(), "round trip failed for {}", encoding);
+    }
+}
+
+#[test]
+fn test_binary_encodings_are_smaller() {
+    let json = encode(&sample(), Encoding::Json).unwrap();
+    let cbor = encode(&sample(), Encoding::Cbor).unwrap();
+    let pb = encode(&sample(), Encoding::Protobuf).unwrap();
+    assert!(cbor.len() < json.len());
+    assert!(pb.len() < cbor.len());
+}
+
+#[test]
+fn test_topic_suffix() {
+    assert_eq!(Encoding::Json.topic("v1/telemetry"), "v1/telemetry");
+    assert_eq!(Encoding::Cbor.topic("v1/telemetry"), "v1/telemetry/cbor");
+    assert_eq!(Encoding::Protobuf.topic("v1/telemetry"), "v1/telemetry/pb");
+
+    assert_eq!(
+        Encoding::from_topic("v1/telemetry"),
+        Some(("v1", Encoding::Json))
+    );
+    assert_eq!(
+        Encoding::from_topic("v1/telemetry/pb"),
+        Some(("v1", Encoding::Protobuf))
+    );
+    assert_eq!(Encoding::from_topic("v1/telemetry/xml"), None);
+    assert_eq!(Encoding::from_topic("v1/command/mode"), None);
+}
+
+#[test]
+fn test_vehicle_state_round_trip() {
+    let state = VehicleState {
+        battery_percentage: 87.5,
+        location: (43.65, -79.38),
+        armed: true,
+        last_heartbeat: 1_700_000_000_000,
+        ..Default::default()
+    };
+    for encoding in [Encoding::Json, Encoding::Cbor, Encoding::Protobuf] {
+        let payload = encode(&state, encoding).unwrap();
+        let (vehicle_id, decoded) =
+            decode_message(&encoding.topic("v1/telemetry"), &payload).unwrap();
+        assert_eq!(vehicle_id, "v1");
+        assert_eq!(decoded, state, "round trip failed for {}", encoding);
+        assert_eq!(decoded.schema_version, SCHEMA_VERSION);
+    }
+    let json: serde_json::Value =
+        serde_json::from_slice(&encode(&state, Encoding::Json).unwrap()).unwrap();
+    assert_eq!(json["last_heartbeat"], 1_700_000_000_000u64);
+    assert!(decode_message("v1/command/mode", b"{}").is_err());
+}
+
+#[test]
+fn test_vehicle_state_compatibility() {
+    // From before schema_version, without the newer fields.
+    let old = r#"{"yaw_degree": 90.0, "armed": true, "flight_mode": "HOLD"}"#;
+    let state: VehicleState = decode(old.as_bytes(), Encoding::Json).unwrap();
+    assert_eq!(state.schema_version, 0);
+    assert_eq!(state.yaw_degree, 90.0);
+    assert!(state.armed);
+    assert!(state.errors.is_empty());
+    // Unknown rather than this receiver's clock and version.
+    assert_eq!(state.last_heartbeat, 0);
+    assert!(state.luffy.is_empty());
+    assert!(VehicleState::default().luffy.is_empty());
+
+    // Schema version 1 wrote the heartbeat as a SystemTime.
+    let v1 = r#"{"schema_version": 1, "last_heartbeat": {"secs_since_epoch": 1700000000, "nanos_since_epoch": 5000000}}"#;
+    let state: VehicleState = decode(v1.as_bytes(), Encoding::Json).unwrap();
+    assert_eq!(state.last_heartbeat, 1_700_000_000_005);
+
+    // From a newer sender, with fields this build does not know.
+    let new = r#"{"schema_version": 7, "armed": true, "heading": 12.0}"#;
+    let state: VehicleState = decode(new.as_bytes(), Encoding::Json).unwrap();
+    assert_eq!(state.schema_version, 7);
+    assert!(state.armed);
+}
+
+/// Run with `LUFFY_UPDATE_SCHEMA=1` to regenerate the file.
+#[test]
+fn test_json_schema_is_current() {
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/schema/vehicle_state.schema.json"
+    );
+    let schema = json_schema();
+    if std::env::var_os("LUFFY_UPDATE_SCHEMA").is_some() {
+        std::fs::write(path, &schema).unwrap();
+    }
+    let committed = std::fs::read_to_string(path).unwrap_or_default();
+    assert!(
+        committed == schema,
+        "{} is out of date, run the tests with LUFFY_UPDATE_SCHEMA=1",
+        path
+    );
+}
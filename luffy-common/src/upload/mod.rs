@@ -0,0 +1,371 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::util;
+
+/// S3 takes parts of at least 5 MiB, except the last.
+pub const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Where multipart uploads go: S3 or a compatible store such as MinIO.
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    /// Start an upload to `key`, returning its id.
+    async fn create_upload(&self, key: &str) -> Result<String>;
+    /// Upload part `number`, counted from 1, returning its ETag.
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: i32,
+        body: Vec<u8>,
+    ) -> Result<String>;
+    async fn complete_upload(&self, key: &str, upload_id: &str, parts: &[Part]) -> Result<()>;
+}
+
+/// The store no longer knows the upload, for instance after it expired or
+/// was aborted. Retrying cannot help; the upload starts over.
+#[derive(Debug)]
+pub struct NoSuchUpload;
+
+impl fmt::Display for NoSuchUpload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("The upload no longer exists")
+    }
+}
+
+impl std::error::Error for NoSuchUpload {}
+
+/// `error` as [`NoSuchUpload`] when S3 says so.
+fn s3_error<E>(error: E) -> anyhow::Error
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    if error.code() == Some("NoSuchUpload") {
+        NoSuchUpload.into()
+    } else {
+        error.into()
+    }
+}
+
+pub struct S3Store {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+}
+
+impl S3Store {
+    pub fn new(client: aws_sdk_s3::Client, bucket: impl Into<String>) -> Self {
+        Self {
+            client,
+            bucket: bucket.into(),
+        }
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+}
+
+#[async_trait]
+impl ObjectStore for S3Store {
+    async fn create_upload(&self, key: &str) -> Result<String> {
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .with_context(|| format!("Failed to start upload of {}", key))?;
+        upload
+            .upload_id()
+            .map(str::to_string)
+            .context("S3 returned no upload id")
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: i32,
+        body: Vec<u8>,
+    ) -> Result<String> {
+        let part = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(number)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(s3_error)
+            .with_context(|| format!("Failed to upload part {} of {}", number, key))?;
+        part.e_tag()
+            .map(str::to_string)
+            .context("S3 returned no ETag")
+    }
+
+    async fn complete_upload(&self, key: &str, upload_id: &str, parts: &[Part]) -> Result<()> {
+        let parts = parts
+            .iter()
+            .map(|part| {
+                CompletedPart::builder()
+                    .part_number(part.number)
+                    .e_tag(&part.etag)
+                    .build()
+            })
+            .collect();
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(s3_error)
+            .with_context(|| format!("Failed to complete upload of {}", key))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Part {
+    pub number: i32,
+    pub etag: String,
+}
+
+/// Progress of an upload, kept next to the file so an upload cut short,
+/// by the link or a restart, goes on from the last part.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadState {
+    pub key: String,
+    pub upload_id: Option<String>,
+    pub part_size: u64,
+    pub parts: Vec<Part>,
+}
+
+impl UploadState {
+    pub fn path(file: &Path) -> PathBuf {
+        let mut name = file.as_os_str().to_owned();
+        name.push(".upload");
+        PathBuf::from(name)
+    }
+
+    pub fn load(file: &Path) -> Result<Option<Self>> {
+        let path = Self::path(file);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let state = serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid upload state in {}", path.display()))?;
+        Ok(Some(state))
+    }
+
+    fn save(&self, file: &Path) -> Result<()> {
+        util::write_atomic(&Self::path(file), &serde_json::to_vec(self)?, 0o644)
+    }
+}
+
+/// How far an upload got.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    pub parts_sent: u64,
+    pub parts: u64,
+}
+
+/// Uploads files in parts, retrying each with backoff.
+pub struct Uploader<S> {
+    store: S,
+    part_size: u64,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl<S: ObjectStore> Uploader<S> {
+    /// Parts of `part_size`, at least [`MIN_PART_SIZE`], each tried up to
+    /// `retries` more times.
+    pub fn new(store: S, part_size: u64, retries: u32) -> Self {
+        Self {
+            store,
+            part_size: part_size.max(MIN_PART_SIZE),
+            retries,
+            backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// Upload `file` to `key`, going on from a previous attempt if one was
+    /// cut short, and report each part sent to `progress`. The file and its
+    /// state are removed once it is uploaded; after a failure both are kept
+    /// to try again later.
+    pub async fn upload(
+        &self,
+        file: &Path,
+        key: &str,
+        progress: &(dyn Fn(Progress) + Send + Sync),
+    ) -> Result<u64> {
+        let state = match UploadState::load(file)? {
+            Some(state) if state.key == key => state,
+            _ => {
+                let state = self.new_state(key);
+                state.save(file)?;
+                state
+            }
+        };
+        match self.run(file, state, progress).await {
+            Err(e) if e.downcast_ref::<NoSuchUpload>().is_some() => {
+                warn!("{:#}, starting the upload of {} over", e, file.display());
+                let state = self.new_state(key);
+                state.save(file)?;
+                self.run(file, state, progress).await
+            }
+            result => result,
+        }
+    }
+
+    fn new_state(&self, key: &str) -> UploadState {
+        UploadState {
+            key: key.to_string(),
+            upload_id: None,
+            part_size: self.part_size,
+            parts: Vec::new(),
+        }
+    }
+
+    async fn run(
+        &self,
+        file: &Path,
+        mut state: UploadState,
+        progress: &(dyn Fn(Progress) + Send + Sync),
+    ) -> Result<u64> {
+        let size = std::fs::metadata(file)
+            .with_context(|| format!("Failed to read {}", file.display()))?
+            .len();
+        let upload_id = match &state.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                let upload_id = self.retry(|| self.store.create_upload(&state.key)).await?;
+                state.upload_id = Some(upload_id.clone());
+                state.save(file)?;
+                upload_id
+            }
+        };
+
+        let count = size.div_ceil(state.part_size).max(1);
+        progress(Progress {
+            parts_sent: state.parts.len() as u64,
+            parts: count,
+        });
+        for number in state.parts.len() as u64 + 1..=count {
+            let offset = (number - 1) * state.part_size;
+            let body = read_part(file, offset, state.part_size)?;
+            let part = number as i32;
+            let etag = self
+                .retry(|| {
+                    self.store
+                        .upload_part(&state.key, &upload_id, part, body.clone())
+                })
+                .await?;
+            state.parts.push(Part { number: part, etag });
+            state.save(file)?;
+            progress(Progress {
+                parts_sent: number,
+                parts: count,
+            });
+        }
+
+        self.retry(|| {
+            self.store
+                .complete_upload(&state.key, &upload_id, &state.parts)
+        })
+        .await?;
+        info!(
+            "Uploaded {} ({} bytes) to {}",
+            file.display(),
+            size,
+            state.key
+        );
+        let _ = std::fs::remove_file(UploadState::path(file));
+        let _ = std::fs::remove_file(file);
+        Ok(size)
+    }
+
+    async fn retry<T, F, Fut>(&self, attempt: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut delay = self.backoff;
+        let mut tries = 0;
+        loop {
+            match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(e) if tries < self.retries && e.downcast_ref::<NoSuchUpload>().is_none() => {
+                    tries += 1;
+                    warn!("{:#}, retrying in {:?}", e, delay);
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_BACKOFF);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// The files in `dir` with an upload cut short, and their keys. State
+/// left without its file is removed.
+pub fn pending(dir: &Path) -> Vec<(PathBuf, String)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut pending = Vec::new();
+    for entry in entries.flatten() {
+        let state_path = entry.path();
+        if state_path.extension().is_none_or(|ext| ext != "upload") {
+            continue;
+        }
+        let file = state_path.with_extension("");
+        match UploadState::load(&file) {
+            Ok(Some(state)) if file.exists() => pending.push((file, state.key)),
+            Ok(_) => {
+                warn!(
+                    "Dropping upload state without a file: {}",
+                    state_path.display()
+                );
+                let _ = std::fs::remove_file(&state_path);
+            }
+            Err(e) => warn!("{:#}", e),
+        }
+    }
+    pending.sort();
+    pending
+}
+
+fn read_part(file: &Path, offset: u64, len: u64) -> Result<Vec<u8>> {
+    let mut reader =
+        std::fs::File::open(file).with_context(|| format!("Failed to open {}", file.display()))?;
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut body = Vec::new();
+    reader.take(len).read_to_end(&mut body)?;
+    Ok(body)
+}
@@ -0,0 +1,277 @@
+use super::*;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Keeps uploads in memory. The next `failures` part uploads fail, and all
+/// of them once `link_up_for` parts went through.
+#[derive(Default)]
+struct MemoryStore {
+    uploads: Mutex<BTreeMap<String, BTreeMap<i32, Vec<u8>>>>,
+    objects: Mutex<BTreeMap<String, Vec<u8>>>,
+    failures: AtomicU32,
+    link_up_for: Mutex<Option<u32>>,
+    parts_uploaded: AtomicU32,
+}
+
+#[async_trait]
+impl ObjectStore for &MemoryStore {
+    async fn create_upload(&self, key: &str) -> Result<String> {
+        let upload_id = format!("upload-{}", key);
+        self.uploads
+            .lock()
+            .unwrap()
+            .insert(upload_id.clone(), BTreeMap::new());
+        Ok(upload_id)
+    }
+
+    async fn upload_part(
+        &self,
+        _key: &str,
+        upload_id: &str,
+        number: i32,
+        body: Vec<u8>,
+    ) -> Result<String> {
+        if self
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            anyhow::bail!("link down");
+        }
+        if let Some(parts) = self.link_up_for.lock().unwrap().as_mut() {
+            if *parts == 0 {
+                anyhow::bail!("link down");
+            }
+            *parts -= 1;
+        }
+        self.parts_uploaded.fetch_add(1, Ordering::SeqCst);
+        let mut uploads = self.uploads.lock().unwrap();
+        let upload = uploads.get_mut(upload_id).context("no such upload")?;
+        upload.insert(number, body);
+        Ok(format!("etag-{}", number))
+    }
+
+    async fn complete_upload(&self, key: &str, upload_id: &str, parts: &[Part]) -> Result<()> {
+        let upload = self
+            .uploads
+            .lock()
+            .unwrap()
+            .remove(upload_id)
+            .context("no such upload")?;
+        let numbers: Vec<i32> = parts.iter().map(|part| part.number).collect();
+        assert_eq!(numbers, upload.keys().copied().collect::<Vec<_>>());
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), upload.into_values().flatten().collect());
+        Ok(())
+    }
+}
+
+fn uploader(store: &MemoryStore, retries: u32) -> Uploader<&MemoryStore> {
+    let mut uploader = Uploader::new(store, 0, retries);
+    uploader.part_size = 4;
+    uploader.backoff = Duration::from_millis(1);
+    uploader
+}
+
+#[tokio::test]
+async fn test_upload_in_parts() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("logs.tar.gz");
+    std::fs::write(&file, b"0123456789").unwrap();
+    let store = MemoryStore::default();
+    // A dropped link is retried.
+    store.failures.store(2, Ordering::SeqCst);
+
+    let reported = Mutex::new(Vec::new());
+    let size = uploader(&store, 2)
+        .upload(&file, "a/logs.tar.gz", &|progress| {
+            reported.lock().unwrap().push(progress.parts_sent)
+        })
+        .await
+        .unwrap();
+    assert_eq!(size, 10);
+    assert_eq!(*reported.lock().unwrap(), [0, 1, 2, 3]);
+    assert_eq!(
+        store.objects.lock().unwrap()["a/logs.tar.gz"],
+        b"0123456789"
+    );
+    assert_eq!(store.parts_uploaded.load(Ordering::SeqCst), 3);
+    assert!(!file.exists());
+    assert!(!UploadState::path(&file).exists());
+}
+
+#[tokio::test]
+async fn test_resume_after_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("tlogs.tar.gz");
+    std::fs::write(&file, b"0123456789").unwrap();
+    let store = MemoryStore::default();
+
+    // The first part goes through, then the link stays down.
+    *store.link_up_for.lock().unwrap() = Some(1);
+    let uploader = uploader(&store, 1);
+    assert!(uploader
+        .upload(&file, "b/tlogs.tar.gz", &|_| {})
+        .await
+        .is_err());
+    let state = UploadState::load(&file).unwrap().unwrap();
+    assert_eq!(state.parts.len(), 1);
+    assert!(file.exists());
+
+    // After a restart only the missing parts are sent.
+    *store.link_up_for.lock().unwrap() = None;
+    let pending = pending(dir.path());
+    assert_eq!(pending, [(file.clone(), "b/tlogs.tar.gz".to_string())]);
+    let size = uploader
+        .upload(&file, "b/tlogs.tar.gz", &|_| {})
+        .await
+        .unwrap();
+    assert_eq!(size, 10);
+    assert_eq!(store.parts_uploaded.load(Ordering::SeqCst), 3);
+    assert_eq!(
+        store.objects.lock().unwrap()["b/tlogs.tar.gz"],
+        b"0123456789"
+    );
+    assert!(!file.exists());
+}
+
+/// Enough of the S3 multipart API, over HTTP, to stand in for MinIO.
+#[derive(Default)]
+struct S3StandIn {
+    uploads: Mutex<BTreeMap<String, BTreeMap<i32, Vec<u8>>>>,
+    objects: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+type Query = axum::extract::Query<BTreeMap<String, String>>;
+type ObjectPath = axum::extract::Path<(String, String)>;
+
+impl S3StandIn {
+    fn no_such_upload() -> axum::response::Response {
+        use axum::response::IntoResponse;
+        (
+            axum::http::StatusCode::NOT_FOUND,
+            "<Error><Code>NoSuchUpload</Code><Message>The specified upload does not exist</Message></Error>",
+        )
+            .into_response()
+    }
+
+    async fn post(
+        axum::extract::State(s3): axum::extract::State<Arc<Self>>,
+        axum::extract::Path((bucket, key)): ObjectPath,
+        axum::extract::Query(query): Query,
+    ) -> axum::response::Response {
+        use axum::response::IntoResponse;
+        if query.contains_key("uploads") {
+            let upload_id = uuid::Uuid::new_v4().simple().to_string();
+            s3.uploads
+                .lock()
+                .unwrap()
+                .insert(upload_id.clone(), BTreeMap::new());
+            return format!(
+                "<InitiateMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key>\
+                 <UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                bucket, key, upload_id
+            )
+            .into_response();
+        }
+        let Some(parts) = query
+            .get("uploadId")
+            .and_then(|id| s3.uploads.lock().unwrap().remove(id))
+        else {
+            return Self::no_such_upload();
+        };
+        s3.objects
+            .lock()
+            .unwrap()
+            .insert(key.clone(), parts.into_values().flatten().collect());
+        format!(
+            "<CompleteMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key>\
+             <ETag>\"done\"</ETag></CompleteMultipartUploadResult>",
+            bucket, key
+        )
+        .into_response()
+    }
+
+    async fn put(
+        axum::extract::State(s3): axum::extract::State<Arc<Self>>,
+        axum::extract::Query(query): Query,
+        body: axum::body::Bytes,
+    ) -> axum::response::Response {
+        use axum::response::IntoResponse;
+        let number: i32 = query["partNumber"].parse().unwrap();
+        let mut uploads = s3.uploads.lock().unwrap();
+        let Some(parts) = uploads.get_mut(&query["uploadId"]) else {
+            return Self::no_such_upload();
+        };
+        parts.insert(number, body.to_vec());
+        ([("ETag", format!("\"etag-{}\"", number))], "").into_response()
+    }
+
+    /// Serve on a free port, returning the endpoint.
+    async fn start(self: &Arc<Self>) -> String {
+        let app = axum::Router::new()
+            .route(
+                "/:bucket/*key",
+                axum::routing::post(Self::post).put(Self::put),
+            )
+            .with_state(self.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        endpoint
+    }
+}
+
+fn s3_client(endpoint: &str) -> aws_sdk_s3::Client {
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+        .endpoint_url(endpoint)
+        .force_path_style(true)
+        .build();
+    aws_sdk_s3::Client::from_conf(config)
+}
+
+#[tokio::test]
+async fn test_s3_store() {
+    let s3 = Arc::new(S3StandIn::default());
+    let endpoint = s3.start().await;
+    let mut uploader = Uploader::new(S3Store::new(s3_client(&endpoint), "logs"), 0, 1);
+    uploader.part_size = 4;
+    uploader.backoff = Duration::from_millis(1);
+    let dir = tempfile::tempdir().unwrap();
+
+    let file = dir.path().join("logs.tar.gz");
+    std::fs::write(&file, b"0123456789").unwrap();
+    let size = uploader
+        .upload(&file, "a/logs.tar.gz", &|_| {})
+        .await
+        .unwrap();
+    assert_eq!(size, 10);
+    assert_eq!(s3.objects.lock().unwrap()["a/logs.tar.gz"], b"0123456789");
+
+    // An upload the store dropped starts over instead of failing for good.
+    let file = dir.path().join("tlogs.tar.gz");
+    std::fs::write(&file, b"abcdef").unwrap();
+    let stale = UploadState {
+        key: "a/tlogs.tar.gz".to_string(),
+        upload_id: Some("expired".to_string()),
+        part_size: 4,
+        parts: vec![Part {
+            number: 1,
+            etag: "\"etag-1\"".to_string(),
+        }],
+    };
+    stale.save(&file).unwrap();
+    uploader
+        .upload(&file, "a/tlogs.tar.gz", &|_| {})
+        .await
+        .unwrap();
+    assert_eq!(s3.objects.lock().unwrap()["a/tlogs.tar.gz"], b"abcdef");
+    assert!(!UploadState::path(&file).exists());
+}
@@ -1,81 +1,149 @@
-use crate::config::BaseConfig;
+use anyhow::{Context, Result};
+use crate::config::{BaseConfig, LogConfig, LogFormat};
+use crate::log_filter::{LogFilter, LOG_FILTER};
+use crate::logging::{self, JsonFormat, LogSource};
+use crate::otel;
 use network_interface::{NetworkInterface, NetworkInterfaceConfig};
-use uuid::Uuid;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
 
+use tracing::{info, warn, Subscriber};
+use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::util::SubscriberInitExt;
-use tracing_subscriber::Layer;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
 
 use glob::Pattern;
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
-use tracing_subscriber::EnvFilter;
 
+/// `VEHICLE_ID` from the environment, else the configured id. Services use
+/// [`crate::identity::DeviceIdentity`], which resolves it once.
 pub fn get_vehicle_id(config: &BaseConfig) -> String {
     std::env::var("VEHICLE_ID").unwrap_or_else(|_| config.vehicle_id.clone())
 }
 
-pub fn get_mac_address() -> String {
+/// Replace `path` with `content` through a temporary file in the same
+/// directory, so readers never see a partial file. The temporary name is
+/// unique per call, so concurrent writers do not clobber each other's.
+///
+/// The file keeps the owner of the one it replaces, or takes the owner of
+/// the directory, so files written by root tools stay readable by the
+/// services.
+pub fn write_atomic(path: &Path, content: &[u8], mode: u32) -> Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = dir.join(format!(".{}.{}.tmp", name, uuid::Uuid::new_v4().simple()));
+    let result = fs::write(&tmp, content)
+        .and_then(|_| fs::set_permissions(&tmp, fs::Permissions::from_mode(mode)))
+        .map(|_| copy_owner(&tmp, if path.exists() { path } else { dir }))
+        .and_then(|_| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result.with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Give `path` the owner and group of `like`. Only root can change them,
+/// so this does nothing for other users.
+pub fn copy_owner(path: &Path, like: &Path) {
+    if let (Ok(file), Ok(like)) = (fs::metadata(path), fs::metadata(like)) {
+        if (file.uid(), file.gid()) != (like.uid(), like.gid()) {
+            let _ = std::os::unix::fs::chown(path, Some(like.uid()), Some(like.gid()));
+        }
+    }
+}
+
+/// MAC address of the first preferred interface present, `None` when there
+/// is none.
+pub fn get_mac_address() -> Option<String> {
     let preferred_interfaces = ["eth0", "en0", "wlan0", "enp0s3"];
 
     if let Ok(interfaces) = NetworkInterface::show() {
         for preferred_name in preferred_interfaces {
             if let Some(interface) = interfaces.iter().find(|iface| iface.name == preferred_name) {
                 if let Some(mac) = &interface.mac_addr {
-                    return mac
-                        .to_string()
-                        .chars()
-                        .filter(|c| c.is_alphanumeric())
-                        .collect::<String>()
-                        .to_uppercase();
+                    return Some(
+                        mac.to_string()
+                            .chars()
+                            .filter(|c| c.is_alphanumeric())
+                            .collect::<String>()
+                            .to_uppercase(),
+                    );
                 }
             }
         }
     }
 
-    Uuid::new_v4().to_string()
+    None
 }
 
-fn setup_dev_logging(log_level: &str) {
-    let console_layer = tracing_subscriber::fmt::layer()
+/// A fmt layer writing `format` to `writer`; `pretty` spreads text events
+/// over several lines.
+fn fmt_layer<S, W>(
+    writer: W,
+    format: LogFormat,
+    source: &LogSource,
+    pretty: bool,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
         .with_thread_ids(true)
         .with_thread_names(true)
         .with_target(true)
         .with_file(true)
-        .with_line_number(true)
-        .pretty();
+        .with_line_number(true);
+    match format {
+        LogFormat::Json => layer.event_format(JsonFormat::new(source.clone())).boxed(),
+        LogFormat::Pretty if pretty => layer.pretty().boxed(),
+        LogFormat::Pretty => layer.boxed(),
+    }
+}
+
+/// `layers` under `filter`, at `log_level`. Layers added beside them keep
+/// their own filter, so shipping can take events finer than the console's.
+pub(crate) fn leveled(
+    filter: &LogFilter,
+    log_level: &str,
+    layers: Vec<Box<dyn Layer<Registry> + Send + Sync>>,
+) -> impl Layer<Registry> {
+    layers.with_filter(filter.layer(log_level))
+}
+
+fn setup_dev_logging(log_level: &str, config: &LogConfig, source: LogSource) {
+    let shipping = logging::shipping_layer(&config.shipping, source.clone())
+        .map(|layer| layer.with_filter(EnvFilter::new(&config.shipping.level)));
 
     tracing_subscriber::registry()
-        .with(console_layer)
-        .with(
-            EnvFilter::from_default_env()
-                .add_directive(log_level.parse().unwrap())
-                .add_directive("tokio=debug".parse().unwrap())
-                .add_directive("runtime=debug".parse().unwrap())
-                .add_directive("rumqttc=info".parse().unwrap())
-                .add_directive("rumqttd=info".parse().unwrap()),
-        )
+        .with(leveled(
+            &LOG_FILTER,
+            log_level,
+            vec![
+                fmt_layer(std::io::stdout, config.format, &source, true),
+                otel::layer(&config.otlp, &source).boxed(),
+            ],
+        ))
+        .with(shipping)
         .try_init()
         .expect("Failed to initialize logging");
 }
 
-fn setup_prod_logging(log_level: &str, service_name: &str) -> bool {
+fn setup_prod_logging(log_level: &str, config: &LogConfig, source: LogSource) -> bool {
     let log_dir = "/var/log/luffy";
     if std::fs::create_dir_all(log_dir).is_err() {
         return false;
     }
 
-    let console_layer = tracing_subscriber::fmt::layer()
-        .with_thread_ids(true)
-        .with_thread_names(true)
-        .with_target(true)
-        .with_file(true)
-        .with_line_number(true)
-        .pretty();
-
 
     let all_log_appender = RollingFileAppender::builder()
         .rotation(Rotation::DAILY)
-        .filename_prefix(format!("{}-all", service_name)) // base name
+        .filename_prefix(format!("{}-all", source.service)) // base name
         .filename_suffix("log") // extension
         .max_log_files(30)
         .build(log_dir)
@@ -83,55 +151,68 @@ fn setup_prod_logging(log_level: &str, service_name: &str) -> bool {
 
     let error_log_appender = RollingFileAppender::builder()
         .rotation(Rotation::DAILY)
-        .filename_prefix(format!("{}-error", service_name))
+        .filename_prefix(format!("{}-error", source.service))
         .filename_suffix("log")
         .max_log_files(30)
         .build(log_dir)
         .unwrap();
 
+    let shipping = logging::shipping_layer(&config.shipping, source.clone())
+        .map(|layer| layer.with_filter(EnvFilter::new(&config.shipping.level)));
+
     tracing_subscriber::registry()
-        .with(console_layer)
-        .with(
-            tracing_subscriber::fmt::layer()
-                .with_writer(all_log_appender)
-                .with_thread_ids(true)
-                .with_thread_names(true)
-                .with_target(true)
-                .with_file(true)
-                .with_line_number(true),
-        )
-        .with(
-            tracing_subscriber::fmt::layer()
-                .with_writer(error_log_appender)
-                .with_thread_ids(true)
-                .with_thread_names(true)
-                .with_target(true)
-                .with_file(true)
-                .with_line_number(true)
-                .with_filter(EnvFilter::new("error")),
-        )
-        .with(
-            EnvFilter::from_default_env()
-                .add_directive(log_level.parse().unwrap())
-                .add_directive("tokio=debug".parse().unwrap())
-                .add_directive("runtime=debug".parse().unwrap())
-                .add_directive("rumqttc=info".parse().unwrap())
-                .add_directive("rumqttd=info".parse().unwrap()),
-        )
+        .with(leveled(
+            &LOG_FILTER,
+            log_level,
+            vec![
+                fmt_layer(std::io::stdout, config.format, &source, true),
+                fmt_layer(all_log_appender, config.format, &source, false),
+                fmt_layer(error_log_appender, config.format, &source, false)
+                    .with_filter(EnvFilter::new("error"))
+                    .boxed(),
+                otel::layer(&config.otlp, &source).boxed(),
+            ],
+        ))
+        .with(shipping)
         .try_init()
         .expect("Failed to initialize logging");
 
     true
 }
 
-pub fn setup_logging(log_level: &str, service_name: &str) {
+/// Change the level set up by [`setup_logging`], keeping the directives
+/// raised through [`crate::log_filter`].
+pub fn set_log_level(log_level: &str) -> Result<()> {
+    LOG_FILTER.set_level(log_level)
+}
+
+/// Apply `log_level` from a reloaded config if it changed.
+pub fn reload_log_level(previous: &str, log_level: &str) {
+    if previous == log_level {
+        return;
+    }
+    match set_log_level(log_level) {
+        Ok(()) => info!("Log level changed to {}", log_level),
+        Err(e) => warn!("Failed to change log level to {}: {:#}", log_level, e),
+    }
+}
+
+/// Log to the console, and in production to daily files in
+/// `/var/log/luffy`, in the format of `config.log`. Events are also shipped
+/// to the cloud when enabled, see [`logging::ship`], and spans exported to an
+/// OpenTelemetry collector, see [`otel::layer`].
+pub fn setup_logging(log_level: &str, service_name: &str, config: &BaseConfig) {
     let is_dev = std::env::var("RUST_ENV")
         .unwrap_or("test".to_string())
         .to_lowercase()
         == "dev";
+    let source = LogSource {
+        service: service_name.to_string(),
+        vehicle_id: get_vehicle_id(config),
+    };
 
-    if is_dev || !setup_prod_logging(log_level, service_name) {
-        setup_dev_logging(log_level)
+    if is_dev || !setup_prod_logging(log_level, &config.log, source.clone()) {
+        setup_dev_logging(log_level, &config.log, source)
     }
 }
 
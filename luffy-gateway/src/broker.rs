@@ -1,22 +1,20 @@
-use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use anyhow::Result;
 use config;
+use luffy_common::forward_service;
 use rumqttd::{Broker, Config, Notification};
 use tracing::{debug, error, info};
 
 pub struct MqttBroker {
-    broker: Option<Broker>,
     running: Arc<AtomicBool>,
-    broker_handle: Option<tokio::task::JoinHandle<Result<()>>>,
+    broker_handle: Option<std::thread::JoinHandle<Result<()>>>,
 }
 
 impl MqttBroker {
     pub async fn new() -> Self {
         Self {
-            broker: None,
             running: Arc::new(AtomicBool::new(false)),
             broker_handle: None,
         }
@@ -24,28 +22,11 @@ impl MqttBroker {
 
     pub async fn start(&mut self) -> Result<()> {
         info!("Loading config from rumqttd.toml...");
-        let config_paths = [
-            "luffy-deploy/config/development/rumqttd.toml",
-            "/etc/luffy/rumqttd.toml",
-        ];
-        let config_path = config_paths
-            .iter()
-            .find(|&path| Path::new(path).exists())
-            .ok_or_else(|| {
-                error!(
-                    "Config file not found in any of the locations: {:?}",
-                    config_paths
-                );
-                anyhow::anyhow!("Config file not found")
-            })?;
-
-        info!(
-            "Loading config from: {:?}",
-            Path::new(config_path).canonicalize()?
-        );
+        let config_path = luffy_common::config::config_dir().join("rumqttd.toml");
+        info!("Loading config from: {:?}", config_path);
 
         let raw_config = config::Config::builder()
-            .add_source(config::File::with_name(config_path))
+            .add_source(config::File::from(config_path))
             .build()
             .map_err(|e| {
                 error!("Failed to build config: {}", e);
@@ -65,15 +46,18 @@ impl MqttBroker {
         let (mut link_tx, mut link_rx) = broker.link("singlenode")?;
         info!("Broker links established");
 
-        // Spawn the broker in a separate task and store its handle
-        let broker_handle = tokio::spawn(async move {
-            info!("Starting MQTT broker...");
-            if let Err(e) = broker.start() {
-                error!("Broker failed to start: {}", e);
-                return Err(anyhow::anyhow!("Broker failed to start: {}", e));
-            }
-            Ok(())
-        });
+        // The broker blocks until the process exits, so it gets a thread of
+        // its own rather than a runtime worker.
+        let broker_handle = std::thread::Builder::new()
+            .name("mqtt-broker".to_string())
+            .spawn(move || {
+                info!("Starting MQTT broker...");
+                if let Err(e) = broker.start() {
+                    error!("Broker failed to start: {}", e);
+                    return Err(anyhow::anyhow!("Broker failed to start: {}", e));
+                }
+                Ok(())
+            })?;
         self.broker_handle = Some(broker_handle);
 
         // Sleep to allow broker to start
@@ -117,9 +101,53 @@ impl MqttBroker {
     pub async fn stop(&mut self) {
         info!("Stopping MQTT broker...");
         self.running.store(false, Ordering::SeqCst);
-        if let Some(handle) = self.broker_handle.take() {
-            handle.abort();
-        }
+        // rumqttd cannot be stopped; its threads end with the process.
+        self.broker_handle.take();
         info!("MQTT broker stopped");
     }
 }
+
+forward_service!(MqttBroker, "broker");
+
+/// Start an in-process broker on a free port, standing in for the remote
+/// broker in tests. Returns the port.
+#[cfg(test)]
+pub(crate) fn start_test_broker() -> u16 {
+    // rumqttd cannot bind to port 0 and report the port it got.
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .unwrap()
+        .port();
+    let toml = format!(
+        r#"
+        id = 0
+        [router]
+        id = 0
+        max_connections = 100
+        max_outgoing_packet_count = 200
+        max_segment_size = 104857600
+        max_segment_count = 10
+        [v4.1]
+        name = "v4-1"
+        listen = "127.0.0.1:{}"
+        next_connection_delay_ms = 1
+        [v4.1.connections]
+        connection_timeout_ms = 60000
+        max_payload_size = 20480
+        max_inflight_count = 100
+        dynamic_filters = true
+        "#,
+        port
+    );
+    let config: Config = config::Config::builder()
+        .add_source(config::File::from_str(&toml, config::FileFormat::Toml))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+    std::thread::spawn(move || {
+        let mut broker = Broker::new(config);
+        broker.start().unwrap();
+    });
+    port
+}
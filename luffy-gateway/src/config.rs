@@ -1,13 +1,23 @@
+use std::collections::BTreeMap;
 use std::sync::LazyLock;
 
-use luffy_common::config::{BaseConfig, LoadConfig};
+use luffy_common::config::{BaseConfig, LoadConfig, ServiceConfig};
+use luffy_common::telemetry::Encoding;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-pub static CONFIG: LazyLock<GatewayConfig> =
-    LazyLock::new(|| GatewayConfig::load_config("gateway").expect("Failed to load configuration"));
+/// Loaded at startup; [`ServiceConfig::watch`] follows changes on disk for
+/// settings applied without a restart.
+pub static CONFIG: LazyLock<ServiceConfig<GatewayConfig>> = LazyLock::new(|| {
+    // Tests read the development files of the source tree.
+    #[cfg(test)]
+    luffy_common::config::set_config_dir(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../luffy-deploy/config/development"),
+    );
+    GatewayConfig::service_config("gateway").expect("Failed to load configuration")
+});
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct GatewayConfig {
     #[serde(flatten)]
     pub base: BaseConfig,
@@ -18,9 +28,15 @@ pub struct GatewayConfig {
     pub mavlink: MavlinkConfig,
     pub iot: IotConfig,
     pub ota: OtaConfig,
+    #[serde(default)]
+    pub shadow: ShadowConfig,
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    #[serde(default)]
+    pub log_upload: LogUploadConfig,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct FeatureConfig {
     pub local_iot: bool,
     pub remote_iot: bool,
@@ -28,43 +44,140 @@ pub struct FeatureConfig {
     pub mavlink: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct GeneralConfig {
     pub log_level: String,
     pub vehicle_id: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AwsConfig {
     pub region: String,
     pub iot: AwsIotConfig,
     pub lambda: LambdaConfig,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AwsIotConfig {
     pub endpoint: String,
     pub port: u16,
     pub root_ca_path: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct LambdaConfig {
     pub register: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct IotConfig {
     pub local_interval: u64,
     pub remote_interval: u64,
+    #[serde(default)]
+    pub local_encoding: Encoding,
+    #[serde(default)]
+    pub remote_encoding: Encoding,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShadowConfig {
+    pub enable: bool,
+    pub name: String,
+    pub report_interval: u64,
+    #[serde(default = "default_shadow_topic_root")]
+    pub topic_root: String,
+}
+
+fn default_shadow_topic_root() -> String {
+    "$aws".to_string()
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            name: "luffy".to_string(),
+            report_interval: 60,
+            topic_root: default_shadow_topic_root(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobsConfig {
+    pub enable: bool,
+    #[serde(default = "default_shadow_topic_root")]
+    pub topic_root: String,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            topic_root: default_shadow_topic_root(),
+        }
+    }
+}
+
+/// Uploads of the service logs and other configured directories to S3 on
+/// request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogUploadConfig {
+    pub enable: bool,
+    pub bucket: String,
+    /// Keys are `{prefix}/{vehicle_id}/{source}-{time}.tar.gz`.
+    pub prefix: String,
+    /// An S3-compatible store such as MinIO instead of AWS.
+    pub endpoint: Option<String>,
+    /// MiB per part, at least 5.
+    pub part_size: u64,
+    /// Retries of each part; after that the upload resumes when the link
+    /// comes back or on the next start.
+    pub retries: u32,
+    /// Where tarballs wait until they are uploaded.
+    pub staging_dir: String,
+    /// Directory of each source; `logs` holds the service logs. Luffy writes
+    /// nothing else, other sources are directories filled by other programs,
+    /// like the tlogs of a MAVLink router.
+    pub sources: BTreeMap<String, String>,
+}
+
+impl Default for LogUploadConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            bucket: "luffy-logs".to_string(),
+            prefix: "vehicles".to_string(),
+            endpoint: None,
+            part_size: 8,
+            retries: 10,
+            staging_dir: "/var/lib/luffy/uploads".to_string(),
+            sources: BTreeMap::from([("logs".to_string(), "/var/log/luffy".to_string())]),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct MavlinkConfig {
     pub connection_string: String,
+    /// Seconds without a heartbeat before the link counts as lost.
+    #[serde(default = "default_link_timeout")]
+    pub link_timeout: u64,
+    /// Seconds to wait for the COMMAND_ACK of a command.
+    #[serde(default = "default_ack_timeout")]
+    pub ack_timeout: u64,
+}
+
+fn default_link_timeout() -> u64 {
+    5
+}
+
+fn default_ack_timeout() -> u64 {
+    3
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OtaConfig {
     pub enable: bool,
     pub strategy: String,
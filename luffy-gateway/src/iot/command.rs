@@ -0,0 +1,83 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use tracing::info;
+
+use crate::iot::settings::{RuntimeSettings, SETTINGS};
+use crate::mav_server::{parse_rover_mode, MavCommand};
+use crate::vehicle::Vehicle;
+
+pub const OTA_STRATEGIES: [&str; 3] = ["auto", "manual", "disabled"];
+
+/// Commands accepted on `{vehicle_id}/command/{name}`. Desired state from the
+/// device shadow is translated into the same commands.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    SetMode(String),
+    Arm(bool),
+    SetTelemetryInterval(u64),
+    SetOtaStrategy(String),
+}
+
+impl Command {
+    /// Parse the payload of a `{vehicle_id}/command/{name}` message.
+    pub fn from_message(name: &str, payload: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(payload)
+            .with_context(|| format!("Invalid {} command payload: {}", name, payload))?;
+        Self::from_value(name, &value)
+    }
+
+    /// Parse an already decoded command payload, e.g. the params of a
+    /// `{vehicle_id}/rpc/command` call.
+    pub fn from_value(name: &str, value: &Value) -> Result<Self> {
+        match name {
+            "mode" => value["mode"]
+                .as_str()
+                .map(|mode| Command::SetMode(mode.to_string()))
+                .ok_or_else(|| anyhow!("Missing mode")),
+            "arm" => value
+                .as_bool()
+                .or_else(|| value["arm"].as_bool())
+                .map(Command::Arm)
+                .ok_or_else(|| anyhow!("Missing arm flag")),
+            "telemetry" => value["interval"]
+                .as_u64()
+                .map(Command::SetTelemetryInterval)
+                .ok_or_else(|| anyhow!("Missing telemetry interval")),
+            "ota" => value["strategy"]
+                .as_str()
+                .map(|strategy| Command::SetOtaStrategy(strategy.to_string()))
+                .ok_or_else(|| anyhow!("Missing OTA strategy")),
+            _ => Err(anyhow!("Unknown command: {}", name)),
+        }
+    }
+}
+
+pub async fn execute(command: Command) -> Result<()> {
+    execute_with(command, &SETTINGS).await
+}
+
+/// Execute `command`, changing `settings` rather than the process-wide
+/// [`SETTINGS`].
+pub async fn execute_with(command: Command, settings: &RuntimeSettings) -> Result<()> {
+    info!("Executing command: {:?}", command);
+    match command {
+        Command::SetMode(mode) => {
+            parse_rover_mode(&mode).ok_or_else(|| anyhow!("Unknown flight mode: {}", mode))?;
+            Vehicle::instance()
+                .await
+                .send_command(MavCommand::SetMode(mode))
+        }
+        Command::Arm(arm) => Vehicle::instance().await.send_command(MavCommand::Arm(arm)),
+        Command::SetTelemetryInterval(seconds) => {
+            settings.set_telemetry_interval(seconds);
+            Ok(())
+        }
+        Command::SetOtaStrategy(strategy) => {
+            if !OTA_STRATEGIES.contains(&strategy.as_str()) {
+                return Err(anyhow!("Unknown OTA strategy: {}", strategy));
+            }
+            settings.set_ota_strategy(&strategy);
+            Ok(())
+        }
+    }
+}
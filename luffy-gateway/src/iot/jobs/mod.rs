@@ -0,0 +1,349 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::process::Command;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::Duration;
+use tracing::{debug, error, info, warn};
+
+use crate::iot::logs::{LogRequest, LOG_UPLOADER};
+use crate::ota::version::VersionManager;
+use luffy_common::iot::client::MqttClient;
+use luffy_common::iot::local::LocalIotClient;
+use luffy_common::iot::router::Message;
+use luffy_common::iot::rpc;
+use luffy_common::ota::update::{self, UpdateAccepted, UpdateCall, UpdateStatus};
+
+pub static JOBS: OnceLock<JobsClient> = OnceLock::new();
+
+/// How long the launcher may take to install gateway and media.
+const SERVICES_UPDATE_TIMEOUT: Duration = Duration::from_secs(1800);
+
+/// Job documents understood by the gateway, selected by `operation`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "operation", rename_all = "kebab-case")]
+pub enum JobDocument {
+    /// Update luffy to a release, the latest one when no version is given.
+    Update {
+        #[serde(default)]
+        version: Option<String>,
+    },
+    Reboot {
+        #[serde(default = "default_reboot_delay")]
+        delay: u64,
+    },
+    /// Upload logs to S3, selected as in a `{vehicle_id}/logs/request`.
+    UploadLogs(LogRequest),
+}
+
+fn default_reboot_delay() -> u64 {
+    5
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobExecution {
+    pub job_id: String,
+    /// Counts the runs of the job on this thing.
+    #[serde(default)]
+    pub execution_number: u64,
+    #[serde(default)]
+    pub job_document: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum JobStatus {
+    InProgress,
+    Succeeded,
+    Failed,
+    Rejected,
+}
+
+/// AWS only accepts string values in `statusDetails`.
+pub type StatusDetails = BTreeMap<String, String>;
+
+/// Executes AWS IoT Jobs targeted at this vehicle, one at a time.
+///
+/// The next pending job is requested with `start-next` on start, after every
+/// reconnect, when `notify-next` announces one and after each job finishes.
+/// A job cut short by a restart stays in progress and is handed out again;
+/// one handed out again while it runs or just after is skipped.
+pub struct JobsClient {
+    client: MqttClient,
+    prefix: String,
+    vehicle_id: String,
+    local: Option<Arc<Mutex<LocalIotClient>>>,
+    /// Progress of the service updates run by the launcher.
+    updates: broadcast::Sender<UpdateStatus>,
+    busy: Mutex<()>,
+    /// Job id and execution number of the job running or last run.
+    claimed: std::sync::Mutex<Option<(String, u64)>>,
+}
+
+impl JobsClient {
+    /// `topic_root` is `$aws` for AWS IoT, see `ShadowSync::new`.
+    pub fn new(
+        client: MqttClient,
+        topic_root: &str,
+        vehicle_id: &str,
+        local: Option<Arc<Mutex<LocalIotClient>>>,
+    ) -> Self {
+        Self {
+            client,
+            prefix: format!("{}/things/{}/jobs", topic_root, vehicle_id),
+            vehicle_id: vehicle_id.to_string(),
+            local,
+            updates: broadcast::channel(16).0,
+            busy: Mutex::new(()),
+            claimed: std::sync::Mutex::new(None),
+        }
+    }
+
+    pub fn topic(&self, suffix: &str) -> String {
+        format!("{}/{}", self.prefix, suffix)
+    }
+
+    pub fn topics(&self) -> Vec<String> {
+        [
+            "notify-next",
+            "start-next/accepted",
+            "start-next/rejected",
+            "+/update/rejected",
+        ]
+        .iter()
+        .map(|suffix| self.topic(suffix))
+        .collect()
+    }
+
+    /// Start picking up jobs. The caller must already be subscribed to
+    /// [`Self::topics`] and route matching messages to [`Self::handle_message`].
+    pub async fn start(&'static self, mut connections: broadcast::Receiver<()>) -> Result<()> {
+        info!("Starting jobs client on {}", self.prefix);
+        if let Some(local) = &self.local {
+            let updates = self.updates.clone();
+            local
+                .lock()
+                .await
+                .on(
+                    &update::status_topic(&self.vehicle_id, "services"),
+                    move |message: Message| {
+                        let updates = updates.clone();
+                        async move {
+                            let status: UpdateStatus = serde_json::from_slice(&message.payload)
+                                .context("Invalid update status")?;
+                            let _ = updates.send(status);
+                            Ok(())
+                        }
+                    },
+                )
+                .await?;
+        }
+        self.start_next().await?;
+
+        tokio::spawn(async move {
+            loop {
+                match connections.recv().await {
+                    Ok(()) => {
+                        if let Err(e) = self.start_next().await {
+                            error!("Failed to request next job: {}", e);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn start_next(&self) -> Result<()> {
+        debug!("Requesting next job");
+        self.client.publish(&self.topic("start-next"), "{}").await?;
+        Ok(())
+    }
+
+    pub async fn handle_message(&'static self, topic: &str, payload: &str) -> Result<()> {
+        let suffix = topic
+            .strip_prefix(&self.prefix)
+            .unwrap_or(topic)
+            .trim_start_matches('/');
+        debug!("Jobs message on {}: {}", suffix, payload);
+
+        match suffix {
+            "notify-next" => {
+                let document: Value = serde_json::from_str(payload)?;
+                if document.get("execution").is_some() {
+                    self.start_next().await?;
+                }
+            }
+            "start-next/accepted" => {
+                let document: Value = serde_json::from_str(payload)?;
+                if let Some(execution) = document.get("execution") {
+                    let execution: JobExecution = serde_json::from_value(execution.clone())
+                        .context("Invalid job execution")?;
+                    if self.claim(&execution) {
+                        tokio::spawn(self.run(execution));
+                    } else {
+                        debug!("Job {} already started", execution.job_id);
+                    }
+                }
+            }
+            "start-next/rejected" => warn!("Start next job rejected: {}", payload),
+            s if s.ends_with("/update/rejected") => {
+                warn!("Job status update rejected: {}", payload)
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Whether `execution` is new. AWS hands out a job in progress on every
+    /// `start-next`, so a reconnect would otherwise run it again.
+    fn claim(&self, execution: &JobExecution) -> bool {
+        let execution = (execution.job_id.clone(), execution.execution_number);
+        let mut claimed = self.claimed.lock().unwrap();
+        if claimed.as_ref() == Some(&execution) {
+            return false;
+        }
+        *claimed = Some(execution);
+        true
+    }
+
+    async fn run(&'static self, execution: JobExecution) {
+        let _busy = self.busy.lock().await;
+        let job_id = execution.job_id;
+        info!("Running job {}: {}", job_id, execution.job_document);
+
+        let (status, details) = match serde_json::from_value::<JobDocument>(execution.job_document)
+        {
+            Ok(document) => match self.execute(&job_id, document).await {
+                Ok(result) => (JobStatus::Succeeded, result),
+                Err(e) => {
+                    error!("Job {} failed: {:#}", job_id, e);
+                    (JobStatus::Failed, details("error", &format!("{:#}", e)))
+                }
+            },
+            Err(e) => {
+                warn!("Rejecting job {}: {}", job_id, e);
+                (JobStatus::Rejected, details("error", &e.to_string()))
+            }
+        };
+
+        if let Err(e) = self.update_status(&job_id, status, details).await {
+            error!("Failed to report job {} status: {}", job_id, e);
+        }
+        if let Err(e) = self.start_next().await {
+            error!("Failed to request next job: {}", e);
+        }
+    }
+
+    pub async fn update_status(
+        &self,
+        job_id: &str,
+        status: JobStatus,
+        details: StatusDetails,
+    ) -> Result<()> {
+        debug!("Job {} -> {:?} {:?}", job_id, status, details);
+        let payload = json!({ "status": status, "statusDetails": details });
+        self.client
+            .publish(
+                &self.topic(&format!("{}/update", job_id)),
+                payload.to_string(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn progress(&self, job_id: &str, step: &str) {
+        if let Err(e) = self
+            .update_status(job_id, JobStatus::InProgress, details("step", step))
+            .await
+        {
+            warn!("Failed to report job {} progress: {}", job_id, e);
+        }
+    }
+
+    async fn execute(&self, job_id: &str, document: JobDocument) -> Result<StatusDetails> {
+        match document {
+            JobDocument::Update { version } => {
+                self.progress(job_id, "updating-launcher").await;
+                let updated = VersionManager::new()
+                    .update_to_version(version.as_deref())
+                    .await?;
+
+                // The launcher updates gateway and media; the gateway would be
+                // stopped halfway if it installed its own package.
+                self.progress(job_id, "updating-services").await;
+                let services = self.update_services(job_id, version).await?;
+
+                let mut result =
+                    details("launcher", if updated { "updated" } else { "up-to-date" });
+                result.insert("services".to_string(), services.to_string());
+                Ok(result)
+            }
+            JobDocument::Reboot { delay } => {
+                // Report success first, nothing runs after the reboot.
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_secs(delay)).await;
+                    info!("Rebooting for job");
+                    if let Err(e) = Command::new("sudo").args(["systemctl", "reboot"]).status() {
+                        error!("Failed to reboot: {}", e);
+                    }
+                });
+                Ok(details("reboot_in", &delay.to_string()))
+            }
+            JobDocument::UploadLogs(request) => {
+                let uploader = LOG_UPLOADER.get().context("Log upload is disabled")?;
+                self.progress(job_id, "uploading-logs").await;
+                let status = uploader.upload_logs(request).await?;
+                if let Some(error) = status.error {
+                    bail!(error);
+                }
+                Ok(BTreeMap::from([
+                    ("bucket".to_string(), uploader.bucket().to_string()),
+                    ("key".to_string(), status.key),
+                    ("size".to_string(), status.size.unwrap_or(0).to_string()),
+                ]))
+            }
+        }
+    }
+
+    /// Have the launcher install gateway and media and wait until it is
+    /// done. When that restarts the gateway, the job is picked up again and
+    /// finds the services up to date.
+    async fn update_services(&self, job_id: &str, version: Option<String>) -> Result<&str> {
+        let Some(local) = &self.local else {
+            return Ok("skipped");
+        };
+        let mut updates = self.updates.subscribe();
+        let caller = local.lock().await.caller().await?;
+        let accepted: UpdateAccepted = caller
+            .call(
+                &update::apply_topic(&self.vehicle_id),
+                &UpdateCall { version },
+                rpc::DEFAULT_TIMEOUT,
+            )
+            .await
+            .context("Launcher did not accept the update")?;
+        debug!("Services update {} started", accepted.id);
+        update::wait_for(
+            &mut updates,
+            &accepted.id,
+            SERVICES_UPDATE_TIMEOUT,
+            |step| async move { self.progress(job_id, &step).await },
+        )
+        .await
+        .context("Services update failed")?;
+        Ok("updated")
+    }
+}
+
+fn details(key: &str, value: &str) -> StatusDetails {
+    BTreeMap::from([(key.to_string(), value.to_string())])
+}
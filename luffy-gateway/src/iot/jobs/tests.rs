@@ -0,0 +1,209 @@
+use super::*;
+use anyhow::Result;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use tokio::sync::mpsc;
+
+use crate::broker::start_test_broker;
+
+#[test]
+fn test_parse_job_documents() {
+    let update: JobDocument =
+        serde_json::from_value(json!({ "operation": "update", "version": "0.6.0" })).unwrap();
+    assert_eq!(
+        update,
+        JobDocument::Update {
+            version: Some("0.6.0".to_string())
+        }
+    );
+
+    let reboot: JobDocument = serde_json::from_value(json!({ "operation": "reboot" })).unwrap();
+    assert_eq!(reboot, JobDocument::Reboot { delay: 5 });
+
+    let logs: JobDocument = serde_json::from_value(
+        json!({ "operation": "upload-logs", "services": ["gateway"], "level": "warn" }),
+    )
+    .unwrap();
+    let JobDocument::UploadLogs(request) = logs else {
+        panic!("Not an upload: {:?}", logs);
+    };
+    assert_eq!(request.source, "logs");
+    assert_eq!(request.services, ["gateway"]);
+    assert_eq!(request.level.as_deref(), Some("warn"));
+
+    assert!(serde_json::from_value::<JobDocument>(json!({ "operation": "format-disk" })).is_err());
+}
+
+// The jobs service is emulated on a local broker under `aws/things/...`,
+// rumqttd refuses `$` filters.
+#[tokio::test]
+async fn test_unknown_job_is_rejected() -> Result<()> {
+    let port = start_test_broker();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let prefix = "aws/things/test-vehicle/jobs";
+    let (updates_tx, mut updates_rx) = mpsc::channel::<(String, Value)>(10);
+
+    // Emulated jobs service: hands out one job, then reports no more jobs.
+    let (cloud, mut cloud_loop) =
+        AsyncClient::new(MqttOptions::new("jobs-service", "127.0.0.1", port), 10);
+    cloud
+        .subscribe(format!("{}/start-next", prefix), QoS::AtLeastOnce)
+        .await?;
+    cloud
+        .subscribe(format!("{}/+/update", prefix), QoS::AtLeastOnce)
+        .await?;
+    let responder = cloud.clone();
+    tokio::spawn(async move {
+        let mut handed_out = false;
+        while let Ok(event) = cloud_loop.poll().await {
+            let Event::Incoming(Packet::Publish(p)) = event else {
+                continue;
+            };
+            if p.topic.ends_with("/start-next") {
+                let document = if handed_out {
+                    json!({})
+                } else {
+                    handed_out = true;
+                    json!({ "execution": {
+                        "jobId": "job-1",
+                        "status": "IN_PROGRESS",
+                        "jobDocument": { "operation": "format-disk" }
+                    }})
+                };
+                responder
+                    .publish(
+                        format!("{}/start-next/accepted", prefix),
+                        QoS::AtLeastOnce,
+                        false,
+                        document.to_string(),
+                    )
+                    .await
+                    .unwrap();
+            } else {
+                let document: Value = serde_json::from_slice(&p.payload).unwrap();
+                updates_tx.send((p.topic.clone(), document)).await.unwrap();
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (device, mut device_loop) =
+        AsyncClient::new(MqttOptions::new("test-vehicle", "127.0.0.1", port), 10);
+    let jobs: &'static JobsClient = Box::leak(Box::new(JobsClient::new(
+        device.clone().into(),
+        "aws",
+        "test-vehicle",
+        None,
+    )));
+    for topic in jobs.topics() {
+        device.subscribe(topic, QoS::AtLeastOnce).await?;
+    }
+    tokio::spawn(async move {
+        while let Ok(event) = device_loop.poll().await {
+            if let Event::Incoming(Packet::Publish(p)) = event {
+                let payload = String::from_utf8_lossy(&p.payload).to_string();
+                jobs.handle_message(&p.topic, &payload).await.unwrap();
+            }
+        }
+    });
+
+    let (_connections_tx, connections_rx) = broadcast::channel(1);
+    jobs.start(connections_rx).await?;
+
+    let (topic, update) = tokio::time::timeout(Duration::from_secs(5), updates_rx.recv())
+        .await?
+        .expect("no job status update");
+    assert_eq!(topic, format!("{}/job-1/update", prefix));
+    assert_eq!(update["status"], "REJECTED");
+    assert!(update["statusDetails"]["error"].is_string());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_job_in_progress_runs_once() -> Result<()> {
+    let port = start_test_broker();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let prefix = "aws/things/test-vehicle-2/jobs";
+    let (updates_tx, mut updates_rx) = mpsc::channel::<(String, Value)>(10);
+
+    // Emulated jobs service: hands out the same job in progress on every
+    // start-next, as AWS does until its status is final.
+    let (cloud, mut cloud_loop) =
+        AsyncClient::new(MqttOptions::new("jobs-service-2", "127.0.0.1", port), 10);
+    cloud
+        .subscribe(format!("{}/start-next", prefix), QoS::AtLeastOnce)
+        .await?;
+    cloud
+        .subscribe(format!("{}/+/update", prefix), QoS::AtLeastOnce)
+        .await?;
+    let responder = cloud.clone();
+    tokio::spawn(async move {
+        while let Ok(event) = cloud_loop.poll().await {
+            let Event::Incoming(Packet::Publish(p)) = event else {
+                continue;
+            };
+            if p.topic.ends_with("/start-next") {
+                let document = json!({ "execution": {
+                    "jobId": "job-1",
+                    "executionNumber": 1,
+                    "status": "IN_PROGRESS",
+                    "jobDocument": { "operation": "upload-logs" }
+                }});
+                responder
+                    .publish(
+                        format!("{}/start-next/accepted", prefix),
+                        QoS::AtLeastOnce,
+                        false,
+                        document.to_string(),
+                    )
+                    .await
+                    .unwrap();
+            } else {
+                let document: Value = serde_json::from_slice(&p.payload).unwrap();
+                updates_tx.send((p.topic.clone(), document)).await.unwrap();
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (device, mut device_loop) =
+        AsyncClient::new(MqttOptions::new("test-vehicle-2", "127.0.0.1", port), 10);
+    let jobs: &'static JobsClient = Box::leak(Box::new(JobsClient::new(
+        device.clone().into(),
+        "aws",
+        "test-vehicle-2",
+        None,
+    )));
+    for topic in jobs.topics() {
+        device.subscribe(topic, QoS::AtLeastOnce).await?;
+    }
+    tokio::spawn(async move {
+        while let Ok(event) = device_loop.poll().await {
+            if let Event::Incoming(Packet::Publish(p)) = event {
+                let payload = String::from_utf8_lossy(&p.payload).to_string();
+                jobs.handle_message(&p.topic, &payload).await.unwrap();
+            }
+        }
+    });
+
+    let (connections_tx, connections_rx) = broadcast::channel(1);
+    jobs.start(connections_rx).await?;
+    // A reconnect asks for the next job again.
+    connections_tx.send(())?;
+
+    // Log upload is not set up, so the one run fails.
+    let (topic, update) = tokio::time::timeout(Duration::from_secs(5), updates_rx.recv())
+        .await?
+        .expect("no job status update");
+    assert_eq!(topic, format!("{}/job-1/update", prefix));
+    assert_eq!(update["status"], "FAILED");
+    assert!(
+        tokio::time::timeout(Duration::from_secs(1), updates_rx.recv())
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
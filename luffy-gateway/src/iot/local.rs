@@ -1,36 +1,64 @@
 use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+use std::future::Future;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tokio::time::Duration;
+use tokio::time::{Duration, Instant};
 use tracing::{debug, error, info};
 
 use crate::config::CONFIG;
+use crate::iot::settings::SETTINGS;
+use crate::mav_server::MESSAGES_RECEIVED;
 use crate::vehicle::Vehicle;
+use luffy_common::health::{HealthContributor, HealthFields, Liveness};
+use luffy_common::identity::DeviceIdentity;
 use luffy_common::iot::local::LocalIotClient;
+use luffy_common::iot::router::{HandlerId, MessageHandler};
+use luffy_common::ota::version;
+use luffy_common::provisioning;
+use luffy_common::supervisor;
+use luffy_common::telemetry;
 
 pub struct LocalIotHandler {
     mqtt_client: Arc<Mutex<LocalIotClient>>,
+    liveness: Liveness,
     running: Arc<AtomicBool>,
-    on_message: fn(topic: String, payload: String),
+}
+
+impl Default for LocalIotHandler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LocalIotHandler {
-    pub fn new(on_message: fn(topic: String, payload: String)) -> Self {
+    pub fn new() -> Self {
+        let client = LocalIotClient::new(
+            "gateway".to_string(),
+            CONFIG.base.mqtt_host.clone(),
+            CONFIG.base.mqtt_port,
+            CONFIG.base.health_report_interval,
+            env!("CARGO_PKG_VERSION").to_string(),
+        )
+        .with_client_id(DeviceIdentity::get(&CONFIG.base).client_id("gateway"));
         Self {
-            mqtt_client: Arc::new(Mutex::new(LocalIotClient::new(
-                "gateway".to_string(),
-                CONFIG.base.mqtt_host.clone(),
-                CONFIG.base.mqtt_port,
-                Some(on_message),
-                CONFIG.base.health_report_interval,
-                env!("CARGO_PKG_VERSION").to_string(),
-            ))),
+            liveness: client.liveness(),
+            mqtt_client: Arc::new(Mutex::new(client)),
             running: Arc::new(AtomicBool::new(true)),
-            on_message,
         }
     }
 
+    pub fn liveness(&self) -> Liveness {
+        self.liveness.clone()
+    }
+
+    pub fn client(&self) -> Arc<Mutex<LocalIotClient>> {
+        self.mqtt_client.clone()
+    }
+
     pub async fn start(&mut self) -> Result<()> {
         info!("Starting local IoT client...");
         let mqtt_client = Arc::clone(&self.mqtt_client);
@@ -39,46 +67,83 @@ impl LocalIotHandler {
         // Connect to broker
         {
             let mut client = mqtt_client.lock().await;
+            client.add_health_fields(Self::mavlink_rate());
+            client.add_health_fields(version::health_fields);
+            client.add_health_fields(provisioning::health_fields);
+            client.add_health_fields(supervisor::health_fields);
             client.connect().await?;
         }
 
+        let health = mqtt_client.lock().await.health();
         tokio::spawn(async move {
             if let Err(e) = Self::telemetry_loop(mqtt_client, running).await {
                 error!("Telemetry loop error: {}", e);
+                health.record_fatal(format!("Telemetry loop stopped: {}", e));
             }
         });
         Ok(())
     }
 
+    /// MAVLink messages per second since the previous health report.
+    fn mavlink_rate() -> impl HealthContributor {
+        let last = Arc::new(std::sync::Mutex::new((0u64, Instant::now())));
+        move || {
+            let last = last.clone();
+            async move {
+                let count = MESSAGES_RECEIVED.load(Ordering::Relaxed);
+                let mut last = last.lock().unwrap();
+                let elapsed = last.1.elapsed().as_secs_f64();
+                let rate = if elapsed > 0.0 {
+                    (count - last.0) as f64 / elapsed
+                } else {
+                    0.0
+                };
+                *last = (count, Instant::now());
+                HealthFields::from([
+                    ("mavlink_messages".to_string(), json!(count)),
+                    (
+                        "mavlink_rate".to_string(),
+                        json!((rate * 10.0).round() / 10.0),
+                    ),
+                ])
+            }
+        }
+    }
+
     async fn telemetry_loop(
         mqtt_client: Arc<Mutex<LocalIotClient>>,
         running: Arc<AtomicBool>,
     ) -> Result<()> {
         let vehicle = Vehicle::instance().await;
-        let local_interval = CONFIG.iot.local_interval;
-        let mut interval = tokio::time::interval(Duration::from_secs(local_interval));
-        let topic = format!("{}/telemetry", vehicle.vehicle_id);
+        let encoding = CONFIG.iot.local_encoding;
+        let topic = encoding.topic(&format!("{}/telemetry", vehicle.vehicle_id));
 
         while running.load(Ordering::SeqCst) {
-            interval.tick().await;
+            tokio::time::sleep(Duration::from_secs(SETTINGS.local_interval())).await;
 
             let state = vehicle.get_state_snapshot().map_err(|e| {
                 error!("Failed to get state snapshot: {}", e);
                 e
             })?;
 
-            let payload = serde_json::to_string(&state).map_err(|e| {
+            let payload = telemetry::encode(&state, encoding).map_err(|e| {
                 error!("Failed to serialize state: {}", e);
                 e
             })?;
 
-            debug!("Publishing telemetry: {}", payload);
+            debug!("Publishing {} telemetry: {} bytes", encoding, payload.len());
 
             let mqtt_client = mqtt_client.lock().await;
-            mqtt_client.publish(&topic, &payload).await.map_err(|e| {
-                error!("Failed to publish telemetry: {}", e);
-                e
-            })?;
+            mqtt_client
+                .publish_bytes(&topic, payload)
+                .await
+                .map_err(|e| {
+                    error!("Failed to publish telemetry: {}", e);
+                    mqtt_client
+                        .health()
+                        .record_error(format!("Failed to publish telemetry: {}", e));
+                    e
+                })?;
 
             debug!("Successfully published telemetry");
         }
@@ -88,11 +153,31 @@ impl LocalIotHandler {
 
     pub async fn stop(&self) {
         self.running.store(false, Ordering::SeqCst);
+        if let Err(e) = self.mqtt_client.lock().await.disconnect().await {
+            error!("Failed to disconnect local IoT client: {}", e);
+        }
     }
 
-    pub async fn subscribe(&self, topic: String) -> Result<()> {
-        let mut mqtt_client = self.mqtt_client.lock().await;
-        mqtt_client.subscribe(&topic).await?;
-        Ok(())
+    pub async fn publish(&self, topic: &str, payload: &str) -> Result<()> {
+        let mqtt_client = self.mqtt_client.lock().await;
+        mqtt_client.publish(topic, payload).await
+    }
+
+    pub async fn on(
+        &self,
+        filter: &str,
+        handler: impl MessageHandler + 'static,
+    ) -> Result<HandlerId> {
+        self.mqtt_client.lock().await.on(filter, handler).await
+    }
+
+    pub async fn serve<P, R, F, Fut>(&self, topic: &str, handler: F) -> Result<HandlerId>
+    where
+        P: DeserializeOwned + Send + 'static,
+        R: Serialize + Send + 'static,
+        F: Fn(P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R>> + Send + 'static,
+    {
+        self.mqtt_client.lock().await.serve(topic, handler).await
     }
 }
@@ -0,0 +1,507 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tokio::sync::{mpsc, Mutex};
+use tracing::{error, info, warn, Level};
+
+use crate::config::LogUploadConfig;
+use luffy_common::aws::AwsClient;
+use luffy_common::iot::client::MqttClient;
+use luffy_common::upload::{self, Progress, S3Store, Uploader};
+
+pub static LOG_UPLOADER: OnceLock<LogUploader> = OnceLock::new();
+
+/// The source holding the service logs, which can be filtered by service
+/// and level.
+pub const SERVICE_LOGS: &str = "logs";
+
+/// Params of `{vehicle_id}/logs/request`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogRequest {
+    /// `logs` or another configured source.
+    #[serde(default = "default_source")]
+    pub source: String,
+    /// Services whose logs to include, all when empty.
+    #[serde(default)]
+    pub services: Vec<String>,
+    /// First and last day, `YYYY-MM-DD`, both included.
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub to: Option<String>,
+    /// Keep only lines at this level and above.
+    #[serde(default)]
+    pub level: Option<String>,
+}
+
+fn default_source() -> String {
+    SERVICE_LOGS.to_string()
+}
+
+/// The reply to a request, sent once the files are selected. The upload's
+/// progress follows on `{vehicle_id}/logs/status` under the same id.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogUploadAccepted {
+    pub id: String,
+    pub bucket: String,
+    pub key: String,
+    pub files: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogUploadState {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// Published on `{vehicle_id}/logs/status`, also for uploads resumed after
+/// the link comes back or a restart.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogUploadStatus {
+    pub id: String,
+    pub key: String,
+    pub state: LogUploadState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<Progress>,
+    /// Bytes uploaded, once succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl LogUploadStatus {
+    fn new(id: &str, key: &str, state: LogUploadState) -> Self {
+        Self {
+            id: id.to_string(),
+            key: key.to_string(),
+            state,
+            progress: None,
+            size: None,
+            error: None,
+        }
+    }
+}
+
+/// What a request selects, checked up front.
+#[derive(Debug, PartialEq)]
+struct Selection {
+    source: String,
+    services: Vec<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    level: Option<Level>,
+}
+
+impl TryFrom<&LogRequest> for Selection {
+    type Error = anyhow::Error;
+
+    fn try_from(request: &LogRequest) -> Result<Self> {
+        let date = |date: &Option<String>| {
+            date.as_deref()
+                .map(|date| {
+                    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                        .with_context(|| format!("Invalid date {}, expected YYYY-MM-DD", date))
+                })
+                .transpose()
+        };
+        let level = request
+            .level
+            .as_deref()
+            .map(|level| {
+                level
+                    .parse::<Level>()
+                    .map_err(|_| anyhow!("Invalid level {}", level))
+            })
+            .transpose()?;
+        let is_logs = request.source == SERVICE_LOGS;
+        if !is_logs && (level.is_some() || !request.services.is_empty()) {
+            bail!("Services and level only apply to the service logs");
+        }
+        Ok(Self {
+            source: request.source.clone(),
+            services: request.services.clone(),
+            from: date(&request.from)?,
+            to: date(&request.to)?,
+            level,
+        })
+    }
+}
+
+impl Selection {
+    fn includes(&self, date: NaiveDate) -> bool {
+        self.from.is_none_or(|from| date >= from) && self.to.is_none_or(|to| date <= to)
+    }
+
+    /// The files under `dir` it selects, with their names in the tarball.
+    fn files(&self, dir: &Path) -> Result<Vec<(PathBuf, String)>> {
+        let mut files = Vec::new();
+        if self.source == SERVICE_LOGS {
+            let entries = std::fs::read_dir(dir)
+                .with_context(|| format!("Failed to read {}", dir.display()))?;
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let Some((service, date)) = parse_log_name(&name) else {
+                    continue;
+                };
+                if (self.services.is_empty() || self.services.iter().any(|s| s == service))
+                    && self.includes(date)
+                {
+                    files.push((entry.path(), name));
+                }
+            }
+        } else {
+            self.walk(dir, dir, &mut files)?;
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Files of other sources are selected by the day they were last
+    /// written.
+    fn walk(&self, root: &Path, dir: &Path, files: &mut Vec<(PathBuf, String)>) -> Result<()> {
+        let entries =
+            std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                self.walk(root, &path, files)?;
+            } else if metadata.is_file() {
+                let modified: DateTime<Utc> = metadata.modified()?.into();
+                if self.includes(modified.date_naive()) {
+                    let name = path.strip_prefix(root)?.to_string_lossy().to_string();
+                    files.push((path, name));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The service and day of a daily log file, `{service}-all.{date}.log`.
+/// The `-error` files are left out; their lines are in the others.
+fn parse_log_name(name: &str) -> Option<(&str, NaiveDate)> {
+    let (prefix, date) = name.strip_suffix(".log")?.rsplit_once('.')?;
+    let service = prefix.strip_suffix("-all")?;
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    Some((service, date))
+}
+
+/// The level of a log line, in JSON or text. `None` for lines continuing
+/// an event, like the location lines of the pretty format.
+fn line_level(line: &str) -> Option<Level> {
+    if line.starts_with('{') {
+        let record: serde_json::Value = serde_json::from_str(line).ok()?;
+        return record["level"].as_str()?.parse().ok();
+    }
+    strip_ansi(line)
+        .split_whitespace()
+        .take(3)
+        // Levels also parse from digits.
+        .filter(|word| word.chars().all(|c| c.is_ascii_alphabetic()))
+        .find_map(|word| word.parse().ok())
+}
+
+fn strip_ansi(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip to the end of the escape sequence.
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+/// The lines of events at `level` and above.
+fn filter_lines(contents: &str, level: Level) -> String {
+    let mut keep = false;
+    let mut filtered = String::new();
+    for line in contents.lines() {
+        if let Some(line_level) = line_level(line) {
+            keep = line_level <= level;
+        }
+        if keep {
+            filtered.push_str(line);
+            filtered.push('\n');
+        }
+    }
+    filtered
+}
+
+/// Write `files` to a gzipped tarball at `out`, with only the lines at
+/// `level` and above when set.
+fn write_archive(files: &[(PathBuf, String)], level: Option<Level>, out: &Path) -> Result<()> {
+    let file = std::fs::File::create(out)
+        .with_context(|| format!("Failed to create {}", out.display()))?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    for (path, name) in files {
+        match level {
+            Some(level) => {
+                let contents = std::fs::read(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                let filtered = filter_lines(&String::from_utf8_lossy(&contents), level);
+                let mut header = tar::Header::new_gnu();
+                header.set_size(filtered.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(Utc::now().timestamp() as u64);
+                archive.append_data(&mut header, name, filtered.as_bytes())?;
+            }
+            None => archive
+                .append_path_with_name(path, name)
+                .with_context(|| format!("Failed to add {}", path.display()))?,
+        }
+    }
+    archive.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// A selection waiting to be packed into `archive` and uploaded.
+struct PendingUpload {
+    id: String,
+    key: String,
+    archive: PathBuf,
+    files: Vec<(PathBuf, String)>,
+    level: Option<Level>,
+}
+
+/// Serves `{vehicle_id}/logs/request`: packs the selected files and uploads
+/// them to S3 in the background, one upload at a time.
+pub struct LogUploader {
+    config: LogUploadConfig,
+    vehicle_id: String,
+    uploader: Uploader<S3Store>,
+    client: MqttClient,
+    uploading: Mutex<()>,
+}
+
+impl LogUploader {
+    /// Uploads through the shared S3 client; `client` is the remote link.
+    pub fn new(
+        config: LogUploadConfig,
+        aws: &AwsClient,
+        vehicle_id: &str,
+        client: MqttClient,
+    ) -> Self {
+        let s3 = match &config.endpoint {
+            Some(endpoint) => aws.s3_at(endpoint),
+            None => aws.s3().clone(),
+        };
+        let uploader = Uploader::new(
+            S3Store::new(s3, config.bucket.clone()),
+            config.part_size * 1024 * 1024,
+            config.retries,
+        );
+        Self {
+            config,
+            vehicle_id: vehicle_id.to_string(),
+            uploader,
+            client,
+            uploading: Mutex::new(()),
+        }
+    }
+
+    pub fn request_topic(&self) -> String {
+        format!("{}/logs/request", self.vehicle_id)
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.config.bucket
+    }
+
+    pub fn status_topic(&self) -> String {
+        format!("{}/logs/status", self.vehicle_id)
+    }
+
+    /// Select the files and reply; packing and uploading them goes on in
+    /// the background.
+    pub async fn handle(&'static self, request: LogRequest) -> Result<LogUploadAccepted> {
+        let (accepted, upload) = self.prepare(request).await?;
+        tokio::spawn(self.run(upload));
+        Ok(accepted)
+    }
+
+    /// Select the files, then pack and upload them. The final status is
+    /// returned as well as published.
+    pub async fn upload_logs(&self, request: LogRequest) -> Result<LogUploadStatus> {
+        let (_, upload) = self.prepare(request).await?;
+        Ok(self.run(upload).await)
+    }
+
+    async fn prepare(&self, request: LogRequest) -> Result<(LogUploadAccepted, PendingUpload)> {
+        let selection = Selection::try_from(&request)?;
+        let dir = self
+            .config
+            .sources
+            .get(&selection.source)
+            .with_context(|| format!("Unknown source {}", selection.source))?;
+        let staging = PathBuf::from(&self.config.staging_dir);
+        std::fs::create_dir_all(&staging)
+            .with_context(|| format!("Failed to create {}", staging.display()))?;
+
+        let dir = PathBuf::from(dir);
+        let (selection, files) = tokio::task::spawn_blocking(move || -> Result<_> {
+            let files = selection.files(&dir)?;
+            if files.is_empty() {
+                bail!("No {} match the request", selection.source);
+            }
+            Ok((selection, files))
+        })
+        .await??;
+
+        // The id names the tarball, so a resumed upload keeps it.
+        let id = format!(
+            "{}-{}",
+            selection.source,
+            Utc::now().format("%Y%m%dT%H%M%S%3fZ")
+        );
+        let key = self.key(&id);
+        info!(
+            "Uploading {} files of {} to {}",
+            files.len(),
+            selection.source,
+            key
+        );
+        let accepted = LogUploadAccepted {
+            id: id.clone(),
+            bucket: self.config.bucket.clone(),
+            key: key.clone(),
+            files: files.len(),
+        };
+        let upload = PendingUpload {
+            archive: staging.join(format!("{}.tar.gz", id)),
+            id,
+            key,
+            files,
+            level: selection.level,
+        };
+        Ok((accepted, upload))
+    }
+
+    async fn run(&self, upload: PendingUpload) -> LogUploadStatus {
+        let PendingUpload {
+            id,
+            key,
+            archive,
+            files,
+            level,
+        } = upload;
+        self.publish(LogUploadStatus::new(&id, &key, LogUploadState::Running))
+            .await;
+        let packed = tokio::task::spawn_blocking({
+            let archive = archive.clone();
+            move || write_archive(&files, level, &archive)
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|packed| packed);
+        if let Err(e) = packed {
+            error!("Failed to pack {}: {:#}", id, e);
+            let _ = std::fs::remove_file(&archive);
+            let status = LogUploadStatus {
+                error: Some(format!("{:#}", e)),
+                ..LogUploadStatus::new(&id, &key, LogUploadState::Failed)
+            };
+            self.publish(status.clone()).await;
+            return status;
+        }
+        let _uploading = self.uploading.lock().await;
+        self.upload(&archive, &id, &key).await
+    }
+
+    fn key(&self, id: &str) -> String {
+        format!("{}/{}/{}.tar.gz", self.config.prefix, self.vehicle_id, id)
+    }
+
+    /// Go on with the uploads cut short by the link or a restart.
+    pub async fn resume(&self) {
+        let _uploading = self.uploading.lock().await;
+        let staging = PathBuf::from(&self.config.staging_dir);
+        for (file, key) in upload::pending(&staging) {
+            let Some(id) = file
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".tar.gz"))
+                .map(str::to_string)
+            else {
+                continue;
+            };
+            info!("Resuming upload of {}", key);
+            self.upload(&file, &id, &key).await;
+        }
+    }
+
+    /// Upload `file`, publishing its progress. The caller holds `uploading`.
+    async fn upload(&self, file: &Path, id: &str, key: &str) -> LogUploadStatus {
+        // Progress is reported from inside the upload; publish it from a
+        // task of its own, in order.
+        let (progress_tx, mut progress_rx) = mpsc::unbounded_channel::<Progress>();
+        let status = LogUploadStatus::new(id, key, LogUploadState::Running);
+        let publisher = async {
+            while let Some(progress) = progress_rx.recv().await {
+                self.publish(LogUploadStatus {
+                    progress: Some(progress),
+                    ..status.clone()
+                })
+                .await;
+            }
+        };
+        let upload = async {
+            let result = self
+                .uploader
+                .upload(file, key, &|progress| {
+                    let _ = progress_tx.send(progress);
+                })
+                .await;
+            drop(progress_tx);
+            result
+        };
+        let (result, ()) = tokio::join!(upload, publisher);
+
+        let status = match result {
+            Ok(size) => {
+                info!("Uploaded {} ({} bytes)", key, size);
+                LogUploadStatus {
+                    size: Some(size),
+                    ..LogUploadStatus::new(id, key, LogUploadState::Succeeded)
+                }
+            }
+            Err(e) => {
+                error!("Failed to upload {}: {:#}", key, e);
+                LogUploadStatus {
+                    error: Some(format!("{:#}", e)),
+                    ..LogUploadStatus::new(id, key, LogUploadState::Failed)
+                }
+            }
+        };
+        self.publish(status.clone()).await;
+        status
+    }
+
+    async fn publish(&self, status: LogUploadStatus) {
+        let result = match serde_json::to_vec(&status) {
+            Ok(payload) => self.client.publish(&self.status_topic(), payload).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            warn!("Failed to publish status of {}: {:#}", status.id, e);
+        }
+    }
+}
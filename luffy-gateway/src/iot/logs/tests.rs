@@ -0,0 +1,172 @@
+use super::*;
+use flate2::read::GzDecoder;
+use std::io::Read;
+
+fn request(json: serde_json::Value) -> LogRequest {
+    serde_json::from_value(json).unwrap()
+}
+
+fn names(files: &[(PathBuf, String)]) -> Vec<&str> {
+    files.iter().map(|(_, name)| name.as_str()).collect()
+}
+
+#[test]
+fn test_parse_log_name() {
+    let date = NaiveDate::from_ymd_opt(2024, 11, 3).unwrap();
+    assert_eq!(
+        parse_log_name("gateway-all.2024-11-03.log"),
+        Some(("gateway", date))
+    );
+    assert_eq!(parse_log_name("media-error.2024-11-03.log"), None);
+    assert_eq!(parse_log_name("gateway-all.log"), None);
+}
+
+#[test]
+fn test_request_validation() {
+    let selection = Selection::try_from(&request(serde_json::json!({
+        "from": "2024-11-02",
+        "level": "warn",
+    })))
+    .unwrap();
+    assert_eq!(selection.source, SERVICE_LOGS);
+    assert_eq!(selection.level, Some(Level::WARN));
+    assert!(!selection.includes(NaiveDate::from_ymd_opt(2024, 11, 1).unwrap()));
+    assert!(selection.includes(NaiveDate::from_ymd_opt(2024, 11, 2).unwrap()));
+
+    let invalid = [
+        serde_json::json!({ "from": "02/11/2024" }),
+        serde_json::json!({ "level": "loud" }),
+        serde_json::json!({ "source": "tlogs", "level": "warn" }),
+    ];
+    for params in invalid {
+        assert!(Selection::try_from(&request(params)).is_err());
+    }
+}
+
+#[test]
+fn test_select_service_logs() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in [
+        "gateway-all.2024-11-01.log",
+        "gateway-all.2024-11-02.log",
+        "gateway-error.2024-11-02.log",
+        "media-all.2024-11-02.log",
+        "media-all.2024-11-03.log",
+        "notes.txt",
+    ] {
+        std::fs::write(dir.path().join(name), "").unwrap();
+    }
+
+    let all = Selection::try_from(&request(serde_json::json!({}))).unwrap();
+    assert_eq!(all.files(dir.path()).unwrap().len(), 4);
+
+    let selection = Selection::try_from(&request(serde_json::json!({
+        "services": ["gateway", "media"],
+        "from": "2024-11-02",
+        "to": "2024-11-02",
+    })))
+    .unwrap();
+    assert_eq!(
+        names(&selection.files(dir.path()).unwrap()),
+        ["gateway-all.2024-11-02.log", "media-all.2024-11-02.log"]
+    );
+}
+
+#[test]
+fn test_select_other_sources() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("2024-11-02")).unwrap();
+    std::fs::write(dir.path().join("2024-11-02/flight.tlog"), "").unwrap();
+    std::fs::write(dir.path().join("flight.tlog"), "").unwrap();
+
+    let selection =
+        Selection::try_from(&request(serde_json::json!({ "source": "tlogs" }))).unwrap();
+    assert_eq!(
+        names(&selection.files(dir.path()).unwrap()),
+        ["2024-11-02/flight.tlog", "flight.tlog"]
+    );
+
+    // Selected by the day they were written.
+    let selection = Selection::try_from(&request(serde_json::json!({
+        "source": "tlogs",
+        "to": "2000-01-01",
+    })))
+    .unwrap();
+    assert!(selection.files(dir.path()).unwrap().is_empty());
+}
+
+#[test]
+fn test_filter_lines() {
+    let text = "\
+2024-11-02T10:00:00.000000Z  INFO ThreadId(01) luffy_gateway: starting
+2024-11-02T10:00:01.000000Z \x1b[33m WARN\x1b[0m ThreadId(01) luffy_gateway: low battery
+    at luffy-gateway/src/main.rs:20
+2024-11-02T10:00:02.000000Z ERROR ThreadId(01) luffy_gateway: link lost
+2 retries left
+2024-11-02T10:00:03.000000Z DEBUG ThreadId(01) luffy_gateway: polling
+";
+    assert_eq!(
+        filter_lines(text, Level::WARN),
+        "\
+2024-11-02T10:00:01.000000Z \x1b[33m WARN\x1b[0m ThreadId(01) luffy_gateway: low battery
+    at luffy-gateway/src/main.rs:20
+2024-11-02T10:00:02.000000Z ERROR ThreadId(01) luffy_gateway: link lost
+2 retries left
+"
+    );
+
+    let json = "\
+{\"level\":\"INFO\",\"message\":\"starting\"}
+{\"level\":\"ERROR\",\"message\":\"link lost\"}
+";
+    assert_eq!(
+        filter_lines(json, Level::ERROR),
+        "{\"level\":\"ERROR\",\"message\":\"link lost\"}\n"
+    );
+}
+
+#[test]
+fn test_write_archive() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("gateway-all.2024-11-02.log");
+    std::fs::write(
+        &log,
+        "2024-11-02T10:00:00Z  INFO a: starting\n2024-11-02T10:00:01Z ERROR a: link lost\n",
+    )
+    .unwrap();
+    let files = vec![(log, "gateway-all.2024-11-02.log".to_string())];
+    let out = dir.path().join("logs.tar.gz");
+    write_archive(&files, Some(Level::ERROR), &out).unwrap();
+
+    let mut archive = tar::Archive::new(GzDecoder::new(std::fs::File::open(&out).unwrap()));
+    let mut entries = archive.entries().unwrap();
+    let mut entry = entries.next().unwrap().unwrap();
+    assert_eq!(
+        entry.path().unwrap().to_str(),
+        Some("gateway-all.2024-11-02.log")
+    );
+    let mut contents = String::new();
+    entry.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "2024-11-02T10:00:01Z ERROR a: link lost\n");
+    assert!(entries.next().is_none());
+}
+
+#[test]
+fn test_status_format() {
+    let status = LogUploadStatus {
+        progress: Some(Progress {
+            parts_sent: 1,
+            parts: 2,
+        }),
+        ..LogUploadStatus::new("logs-1", "v/b/logs-1.tar.gz", LogUploadState::Running)
+    };
+    assert_eq!(
+        serde_json::to_value(&status).unwrap(),
+        serde_json::json!({
+            "id": "logs-1",
+            "key": "v/b/logs-1.tar.gz",
+            "state": "running",
+            "progress": { "parts_sent": 1, "parts": 2 },
+        })
+    );
+}
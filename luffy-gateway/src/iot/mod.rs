@@ -1,4 +1,9 @@
+pub mod command;
+pub mod jobs;
 pub mod local;
+pub mod logs;
 pub mod remote;
+pub mod settings;
+pub mod shadow;
 
 pub mod server;
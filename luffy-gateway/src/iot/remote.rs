@@ -1,45 +1,69 @@
 use anyhow::{Context, Result};
-use rumqttc::{AsyncClient, QoS};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::future::Future;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::fs;
+use tokio::sync::broadcast;
 
 use tokio::time::Duration;
-use tracing::{debug, error, info};
+use tracing::{debug, error, warn};
 
-use crate::aws_client::AwsClient;
 use crate::config::CONFIG;
+use crate::iot::settings::SETTINGS;
 use crate::vehicle::Vehicle;
-use luffy_common::util;
+use luffy_common::health::Liveness;
+use luffy_common::identity::DeviceIdentity;
+use luffy_common::iot::backend;
+use luffy_common::iot::client::MqttClient;
+use luffy_common::iot::router::{HandlerId, MessageHandler};
+use luffy_common::logging;
+use luffy_common::provisioning::{self, CertificateWatcher};
+use luffy_common::telemetry;
 
 pub struct RemoteIotClient {
-    client: Option<AsyncClient>,
+    link: luffy_common::iot::remote::RemoteIotClient,
     running: Arc<AtomicBool>,
-    on_message: fn(topic: String, payload: String),
+}
+
+impl Default for RemoteIotClient {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RemoteIotClient {
-    pub fn new(on_message: fn(topic: String, payload: String)) -> Self {
+    pub fn new() -> Self {
+        let identity = DeviceIdentity::get(&CONFIG.base);
         Self {
-            client: None,
+            link: luffy_common::iot::remote::RemoteIotClient::new(
+                identity.vehicle_id.clone(),
+                backend::from_config(&CONFIG.base),
+            )
+            .with_client_id(identity.client_id("gateway")),
             running: Arc::new(AtomicBool::new(true)),
-            on_message,
         }
     }
 
-    pub async fn start(&mut self) -> Result<()> {
-        info!("Starting IoT client...");
-        if !self.is_registered() {
-            let aws_client = AwsClient::instance().await;
-            aws_client
-                .register_device()
-                .await
-                .context("Failed to register device")?;
-            //TODO: remove aws credentials on production
-        }
+    pub fn client(&self) -> Option<MqttClient> {
+        self.link.client()
+    }
 
-        let mqtt_client = self.connect().await?;
-        self.client = Some(mqtt_client.clone());
+    pub fn liveness(&self) -> Liveness {
+        self.link.liveness()
+    }
+
+    /// Notified on every (re)connection to the broker.
+    pub fn connections(&self) -> broadcast::Receiver<()> {
+        self.link.connections()
+    }
+
+    pub async fn start(&mut self) -> Result<()> {
+        self.recover_certificate();
+        self.link.start().await?;
+        let mqtt_client = self.link.client().context("Remote client not started")?;
+        self.watch_certificate();
+        logging::ship(&self.link);
 
         let running = self.running.clone();
 
@@ -52,168 +76,98 @@ impl RemoteIotClient {
         Ok(())
     }
 
-    async fn telemetry_loop(client: AsyncClient, running: Arc<AtomicBool>) {
-        let remote_interval = CONFIG.iot.remote_interval;
-        let mut interval = tokio::time::interval(Duration::from_secs(remote_interval));
+    /// Put back the previous certificate if a rotation was cut off before
+    /// the new one connected.
+    fn recover_certificate(&self) {
+        if !CONFIG.base.certificate.enable {
+            return;
+        }
+        let Some(store) = self.link.credentials() else {
+            return;
+        };
+        match store.recover() {
+            Ok(true) => warn!("Restored the certificate of an interrupted rotation"),
+            Ok(false) => {}
+            Err(e) => error!("Failed to recover the device certificate: {:#}", e),
+        }
+    }
+
+    /// Rotate the device certificate before it expires. The gateway is the
+    /// only service that rotates; the others reconnect when it changed.
+    fn watch_certificate(&self) {
+        if !CONFIG.base.certificate.enable {
+            return;
+        }
+        let (Some(store), Some(reconnector)) = (self.link.credentials(), self.link.reconnector())
+        else {
+            return;
+        };
+        let watcher = CertificateWatcher::new(
+            CONFIG.base.certificate.clone(),
+            store,
+            reconnector,
+            DeviceIdentity::get(&CONFIG.base).clone(),
+        )
+        .with_rotation(provisioning::from_config(&CONFIG.base));
+        tokio::spawn(watcher.run());
+    }
+
+    async fn telemetry_loop(client: MqttClient, running: Arc<AtomicBool>) {
+        let encoding = CONFIG.iot.remote_encoding;
         let vehicle = Vehicle::instance().await;
+        let topic = encoding.topic(&format!("{}/telemetry", vehicle.vehicle_id));
         while running.load(Ordering::SeqCst) {
-            interval.tick().await;
+            tokio::time::sleep(Duration::from_secs(SETTINGS.remote_interval())).await;
 
             let state = match vehicle.get_state_snapshot() {
                 Ok(state) => state,
                 Err(e) => {
-                    error!("AWS - Failed to get state snapshot: {}", e);
+                    error!("Remote - Failed to get state snapshot: {}", e);
                     continue;
                 }
             };
 
-            let payload = match serde_json::to_string(&state) {
+            let payload = match telemetry::encode(&state, encoding) {
                 Ok(payload) => payload,
                 Err(e) => {
-                    error!("AWS - Failed to serialize state: {}", e);
+                    error!("Remote - Failed to serialize state: {}", e);
                     continue;
                 }
             };
 
-            let topic = format!("{}/telemetry", vehicle.vehicle_id);
-            debug!("AWS - Publishing telemetry: {}", payload);
+            debug!(
+                "Remote - Publishing {} telemetry: {} bytes",
+                encoding,
+                payload.len()
+            );
 
-            match client
-                .publish(&topic, QoS::AtLeastOnce, false, payload)
-                .await
-            {
-                Ok(_) => debug!("AWS - Successfully published telemetry"),
-                Err(e) => error!("AWS - Failed to publish telemetry: {}", e),
+            match client.publish(&topic, payload).await {
+                Ok(_) => debug!("Remote - Successfully published telemetry"),
+                Err(e) => error!("Remote - Failed to publish telemetry: {}", e),
             }
         }
     }
 
-    async fn connect(&self) -> Result<AsyncClient> {
-        let config_dir = match std::env::var("RUST_ENV").as_deref() {
-            Ok("dev") => dirs::config_dir()
-                .context("Failed to get config directory")?
-                .join("luffy"),
-            _ => std::path::PathBuf::from("/etc/luffy"),
-        };
-
-        let cert_path = config_dir.join("certificate.pem");
-        let key_path = config_dir.join("private.key");
-
-        let cert_pem = fs::read(&cert_path).await?;
-        let key_pem = fs::read(&key_path).await?;
-        let aws_root_cert = include_bytes!("../../certs/AmazonRootCA.pem");
-
-        let vehicle_id = util::get_vehicle_id(&CONFIG.base);
-        let aws_iot_endpoint = &CONFIG.base.aws.iot.endpoint;
-        let aws_iot_port = CONFIG.base.aws.iot.port;
-        let client_id = format!("{}_{}", vehicle_id, uuid::Uuid::new_v4());
-        let mut mqtt_options = rumqttc::MqttOptions::new(client_id, aws_iot_endpoint, aws_iot_port);
-
-        mqtt_options
-            .set_keep_alive(Duration::from_secs(30))
-            .set_clean_session(true);
-
-        let transport = rumqttc::Transport::Tls(rumqttc::TlsConfiguration::Simple {
-            ca: aws_root_cert.to_vec(),
-            alpn: Some(vec!["mqtt".as_bytes().to_vec()]),
-            client_auth: Some((cert_pem, key_pem)),
-        });
-
-        mqtt_options.set_transport(transport);
-        let (client, mut eventloop) = rumqttc::AsyncClient::new(mqtt_options, 10);
-        let on_message = self.on_message;
-        tokio::spawn(async move {
-            debug!("Starting iot event loop...");
-            loop {
-                match eventloop.poll().await {
-                    Ok(rumqttc::Event::Incoming(rumqttc::Packet::SubAck(_))) => {
-                        debug!("Subscription confirmed by iot");
-                    }
-                    Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_))) => {
-                        debug!("[IOT]Connected..... ");
-                    }
-                    Ok(rumqttc::Event::Incoming(rumqttc::Packet::Publish(p))) => {
-                        debug!(
-                            "[IOT]Received message - Topic: {}, Payload: {:?}",
-                            p.topic,
-                            String::from_utf8_lossy(&p.payload)
-                        );
-                        // if let Err(e) = Self::handle_message(&p.topic, &p.payload).await {
-                        //     error!("[IOT]Failed to handle message: {}", e);
-                        // }
-                        let payload_str = String::from_utf8_lossy(&p.payload).to_string();
-                        on_message(p.topic, payload_str);
-                    }
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("[IOT]MQTT Error: {:?}", e);
-                        tokio::time::sleep(Duration::from_secs(1)).await;
-                    }
-                }
-            }
-        });
-
-        Ok(client)
-    }
-
-    async fn handle_message(topic: &str, payload: &[u8]) -> Result<()> {
-        let vehicle = Vehicle::instance().await;
-        let payload_str = String::from_utf8_lossy(payload);
-        debug!("Received message on {}: {}", topic, payload_str);
-
-        match topic {
-            t if t == format!("{}/command/mode", vehicle.vehicle_id) => {
-                let payload_json: serde_json::Value = serde_json::from_str(&payload_str)?;
-                debug!("Payload: {}", payload_json);
-                let mode = payload_json["mode"].as_str().unwrap_or("unknown");
-                vehicle.update_flight_mode(mode.to_string())?;
-            }
-            t if t == format!("{}/command/arm", vehicle.vehicle_id) => {
-                let should_arm: bool = serde_json::from_str(&payload_str)?;
-                if should_arm {
-                    // self.vehicle.arm()?;
-                } else {
-                    // self.vehicle.disarm()?;
-                }
-            }
-            _ => {
-                debug!("Unhandled topic: {}", topic);
-            }
-        }
-        Ok(())
-    }
-
     pub async fn stop(&self) {
         self.running.store(false, Ordering::SeqCst);
-
-        if let Some(client) = &self.client {
-            if let Err(e) = client
-                .disconnect()
-                .await
-                .context("Failed to disconnect from broker")
-            {
-                error!("Failed to disconnect from AWS IoT broker: {}", e);
-            }
-        }
+        self.link.stop().await;
     }
 
-    fn is_registered(&self) -> bool {
-        let config_dir = match std::env::var("RUST_ENV").as_deref() {
-            Ok("dev") => dirs::config_dir()
-                .context("Failed to get config directory")
-                .unwrap()
-                .join("luffy"),
-            _ => std::path::PathBuf::from("/etc/luffy"),
-        };
-
-        let cert_path = config_dir.join("certificate.pem");
-        cert_path.exists()
+    pub async fn on(
+        &self,
+        filter: &str,
+        handler: impl MessageHandler + 'static,
+    ) -> Result<HandlerId> {
+        self.link.on(filter, handler).await
     }
 
-    pub async fn subscribe(&self, topic: String) -> Result<()> {
-        if let Some(client) = &self.client {
-            client.subscribe(topic, QoS::AtLeastOnce).await?
-        }
-        Ok(())
+    pub async fn serve<P, R, F, Fut>(&self, topic: &str, handler: F) -> Result<HandlerId>
+    where
+        P: DeserializeOwned + Send + 'static,
+        R: Serialize + Send + 'static,
+        F: Fn(P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R>> + Send + 'static,
+    {
+        self.link.serve(topic, handler).await
     }
 }
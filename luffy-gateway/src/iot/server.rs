@@ -1,11 +1,43 @@
-use anyhow::Result;
-use tracing::info;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
 
-use crate::config::CONFIG;
+use crate::config::{GatewayConfig, CONFIG};
+use crate::iot::command::{self, Command};
+use crate::iot::jobs::{JobsClient, JOBS};
 use crate::iot::local::LocalIotHandler;
+use crate::iot::logs::{LogRequest, LogUploader, LOG_UPLOADER};
 use crate::iot::remote::RemoteIotClient;
+use crate::iot::settings::SETTINGS;
+use crate::iot::shadow::{ShadowSync, SHADOW};
 use crate::ota::version::VersionManager;
 use crate::vehicle::Vehicle;
+use luffy_common::aws::AwsClient;
+use luffy_common::config_update::{self, ConfigChange, ConfigChangeResult};
+use luffy_common::forward_service;
+use luffy_common::health::{HealthContributor, Liveness};
+use luffy_common::iot::client::MqttClient;
+use luffy_common::iot::router::Message;
+use luffy_common::log_filter::{self, LogFilterChange, LogFilterStatus};
+use luffy_common::ota::update::{self, UpdateAccepted, UpdateCall, UpdateRunner};
+
+const LOG_FILTER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Params of `{vehicle_id}/rpc/command`; `args` is what would be published
+/// on `{vehicle_id}/command/{name}`.
+#[derive(Debug, Deserialize)]
+struct CommandCall {
+    name: String,
+    #[serde(default)]
+    args: Value,
+}
+
+/// Launcher updates started by `{vehicle_id}/rpc/ota/update`.
+static LAUNCHER_UPDATES: UpdateRunner = UpdateRunner::new();
 
 pub struct IotServer {
     remote_client: Option<RemoteIotClient>,
@@ -15,8 +47,8 @@ pub struct IotServer {
 impl IotServer {
     pub async fn new() -> Self {
         Self {
-            remote_client: Some(RemoteIotClient::new(Self::on_message)),
-            local_client: Some(LocalIotHandler::new(Self::on_message)),
+            remote_client: Some(RemoteIotClient::new()),
+            local_client: Some(LocalIotHandler::new()),
         }
     }
 
@@ -35,10 +67,120 @@ impl IotServer {
             }
         }
 
-        self.subscribe_topics().await;
+        self.register_handlers().await?;
+        self.register_config_handlers().await?;
+        self.register_log_filter_handlers().await?;
+
+        if CONFIG.feature.remote_iot && CONFIG.shadow.enable {
+            self.start_shadow().await?;
+        }
+        if CONFIG.feature.remote_iot && CONFIG.jobs.enable {
+            self.start_jobs().await?;
+        }
+        if CONFIG.feature.remote_iot && CONFIG.log_upload.enable {
+            self.start_log_upload().await?;
+        }
         Ok(())
     }
 
+    async fn start_shadow(&self) -> Result<()> {
+        let Some(remote) = &self.remote_client else {
+            return Ok(());
+        };
+        let Some(client) = remote.client() else {
+            return Ok(());
+        };
+        let vehicle_id = Vehicle::instance().await.vehicle_id.clone();
+        let shadow = SHADOW.get_or_init(|| {
+            ShadowSync::new(
+                client,
+                &CONFIG.shadow.topic_root,
+                &vehicle_id,
+                &CONFIG.shadow.name,
+                &SETTINGS,
+            )
+        });
+        for topic in shadow.topics() {
+            remote
+                .on(&topic, move |message: Message| async move {
+                    shadow
+                        .handle_message(&message.topic, &message.text_lossy())
+                        .await
+                })
+                .await?;
+        }
+        shadow
+            .start(remote.connections(), CONFIG.shadow.report_interval)
+            .await
+    }
+
+    async fn start_jobs(&self) -> Result<()> {
+        let Some(remote) = &self.remote_client else {
+            return Ok(());
+        };
+        let Some(client) = remote.client() else {
+            return Ok(());
+        };
+        let vehicle_id = Vehicle::instance().await.vehicle_id.clone();
+        let local = self
+            .local_client
+            .as_ref()
+            .filter(|_| CONFIG.feature.local_iot)
+            .map(|local| local.client());
+        let jobs = JOBS
+            .get_or_init(|| JobsClient::new(client, &CONFIG.jobs.topic_root, &vehicle_id, local));
+        for topic in jobs.topics() {
+            remote
+                .on(&topic, move |message: Message| async move {
+                    jobs.handle_message(&message.topic, &message.text_lossy())
+                        .await
+                })
+                .await?;
+        }
+        jobs.start(remote.connections()).await
+    }
+
+    async fn start_log_upload(&self) -> Result<()> {
+        let Some(remote) = &self.remote_client else {
+            return Ok(());
+        };
+        let Some(client) = remote.client() else {
+            return Ok(());
+        };
+        let vehicle_id = Vehicle::instance().await.vehicle_id.clone();
+        let aws = AwsClient::instance(&CONFIG.base.aws.region).await;
+        let uploader = LOG_UPLOADER
+            .get_or_init(|| LogUploader::new(CONFIG.log_upload.clone(), aws, &vehicle_id, client));
+        remote
+            .serve(&uploader.request_topic(), move |request: LogRequest| {
+                uploader.handle(request)
+            })
+            .await?;
+        let mut connections = remote.connections();
+        tokio::spawn(async move {
+            uploader.resume().await;
+            loop {
+                match connections.recv().await {
+                    Ok(()) => uploader.resume().await,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Probes of the links' event loops.
+    pub fn health(&self) -> Option<Arc<dyn HealthContributor>> {
+        let loops: Vec<Liveness> = self
+            .remote_client
+            .iter()
+            .map(RemoteIotClient::liveness)
+            .chain(self.local_client.iter().map(LocalIotHandler::liveness))
+            .collect();
+        Some(Arc::new(loops))
+    }
+
     pub async fn stop(&self) {
         if let Some(client) = &self.remote_client {
             client.stop().await;
@@ -48,42 +190,198 @@ impl IotServer {
         }
     }
 
-    async fn subscribe_topics(&self) {
-        let vehicle = Vehicle::instance().await;
-        let vehicle_id = vehicle.vehicle_id.clone();
-        let topics = vec![
-            format!("{}/ota/#", vehicle_id),
-            format!("{}/command/#", vehicle_id),
-        ];
+    async fn register_handlers(&self) -> Result<()> {
+        let vehicle_id = Vehicle::instance().await.vehicle_id.clone();
+        let command_filter = format!("{}/command/+", vehicle_id);
+        let ota_filter = format!("{}/ota/request", vehicle_id);
+        let command_rpc = format!("{}/rpc/command", vehicle_id);
+        let ota_rpc = format!("{}/rpc/ota/update", vehicle_id);
+        let status_topic = update::status_topic(&vehicle_id, "launcher");
+
         if let Some(client) = &self.remote_client {
-            for topic in topics.clone() {
-                client.subscribe(topic).await.unwrap();
+            client.on(&command_filter, Self::handle_command).await?;
+            client.on(&ota_filter, Self::handle_ota_request).await?;
+            if CONFIG.feature.remote_iot {
+                client.serve(&command_rpc, Self::serve_command).await?;
+                if let Some(publisher) = client.client() {
+                    client
+                        .serve(
+                            &ota_rpc,
+                            Self::serve_ota_update(publisher, status_topic.clone()),
+                        )
+                        .await?;
+                }
             }
         }
-
         if let Some(client) = &self.local_client {
-            for topic in topics.clone() {
-                client.subscribe(topic).await.unwrap();
+            client.on(&command_filter, Self::handle_command).await?;
+            client.on(&ota_filter, Self::handle_ota_request).await?;
+            if CONFIG.feature.local_iot {
+                client.serve(&command_rpc, Self::serve_command).await?;
+                if let Some(publisher) = client.client().lock().await.client() {
+                    client
+                        .serve(&ota_rpc, Self::serve_ota_update(publisher, status_topic))
+                        .await?;
+                }
             }
         }
+        Ok(())
     }
 
-    pub fn on_message(topic: String, payload: String) {
-        tokio::spawn(async move {
-            Self::handle_command(topic, payload).await.unwrap();
-        });
+    /// Config changes are applied by the launcher. The gateway validates its
+    /// own changes, forwards calls from the cloud and relays the published
+    /// effective configs back.
+    async fn register_config_handlers(&self) -> Result<()> {
+        let Some(local) = self
+            .local_client
+            .as_ref()
+            .filter(|_| CONFIG.feature.local_iot)
+        else {
+            return Ok(());
+        };
+        let vehicle_id = Vehicle::instance().await.vehicle_id.clone();
+        config_update::serve_validation::<GatewayConfig>(
+            &*local.client().lock().await,
+            &vehicle_id,
+            "gateway",
+        )
+        .await?;
+
+        let Some(remote) = self
+            .remote_client
+            .as_ref()
+            .filter(|_| CONFIG.feature.remote_iot)
+        else {
+            return Ok(());
+        };
+        let caller = local.client().lock().await.caller().await?;
+        let set_topic = config_update::set_topic(&vehicle_id);
+        remote
+            .serve(&set_topic.clone(), move |change: ConfigChange| {
+                let caller = caller.clone();
+                let set_topic = set_topic.clone();
+                async move {
+                    caller
+                        .call::<_, ConfigChangeResult>(
+                            &set_topic,
+                            &change,
+                            config_update::APPLY_TIMEOUT,
+                        )
+                        .await
+                }
+            })
+            .await?;
+        if let Some(client) = remote.client() {
+            local
+                .on(
+                    &config_update::effective_topic(&vehicle_id, "+"),
+                    move |message: Message| {
+                        let client = client.clone();
+                        async move { client.publish(&message.topic, message.payload).await }
+                    },
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Each service serves its own log filter; calls from the cloud name the
+    /// service and are forwarded to it.
+    async fn register_log_filter_handlers(&self) -> Result<()> {
+        let Some(local) = self
+            .local_client
+            .as_ref()
+            .filter(|_| CONFIG.feature.local_iot)
+        else {
+            return Ok(());
+        };
+        let vehicle_id = Vehicle::instance().await.vehicle_id.clone();
+        log_filter::serve(&*local.client().lock().await, &vehicle_id, "gateway").await?;
+
+        let Some(remote) = self
+            .remote_client
+            .as_ref()
+            .filter(|_| CONFIG.feature.remote_iot)
+        else {
+            return Ok(());
+        };
+        let caller = local.client().lock().await.caller().await?;
+        remote
+            .serve(
+                &log_filter::remote_topic(&vehicle_id),
+                move |change: LogFilterChange| {
+                    let caller = caller.clone();
+                    let topic = log_filter::topic(&vehicle_id, &change.service);
+                    async move {
+                        if change.service.is_empty() {
+                            anyhow::bail!("No service given");
+                        }
+                        caller
+                            .call::<_, LogFilterStatus>(&topic, &change, LOG_FILTER_TIMEOUT)
+                            .await
+                    }
+                },
+            )
+            .await?;
+        Ok(())
     }
 
-    async fn handle_command(topic: String, payload: String) -> Result<()> {
-        info!("Received command: topic={}, payload={}", topic, payload);
-        let vehicle = Vehicle::instance().await;
-        let vehicle_id = vehicle.vehicle_id.clone();
-        if topic.starts_with(&format!("{}/command/", vehicle_id)) {
-            //TODO: handle command
-        } else if topic.starts_with(&format!("{}/ota/request", vehicle_id)) {
-            let version_manager = VersionManager::new();
-            version_manager.check_and_apply_updates().await?;
+    async fn handle_command(message: Message) -> Result<()> {
+        let payload = message.text().context("Command payload is not UTF-8")?;
+        info!(
+            "Received command: topic={}, payload={}",
+            message.topic, payload
+        );
+        let name = message.topic.rsplit('/').next().unwrap_or_default();
+        Self::run_command(Command::from_message(name, payload)?).await
+    }
+
+    async fn serve_command(call: CommandCall) -> Result<()> {
+        info!("Received command call: {:?}", call);
+        Self::run_command(Command::from_value(&call.name, &call.args)?).await
+    }
+
+    async fn run_command(command: Command) -> Result<()> {
+        command::execute(command).await?;
+        if let Some(shadow) = SHADOW.get() {
+            // Keep the shadow's reported state in step with local commands.
+            if let Err(e) = shadow.report(false).await {
+                debug!("Failed to report shadow after command: {}", e);
+            }
         }
         Ok(())
     }
+
+    /// Starts updating the launcher and returns at once, as the launcher
+    /// is replaced on the way. Progress is published on `status_topic`
+    /// through `client`, the link the call came in on.
+    fn serve_ota_update(
+        client: MqttClient,
+        status_topic: String,
+    ) -> impl Fn(UpdateCall) -> std::future::Ready<Result<UpdateAccepted>> + Send + Sync + 'static
+    {
+        move |call: UpdateCall| {
+            let accepted = LAUNCHER_UPDATES.start(
+                client.clone(),
+                status_topic.clone(),
+                |progress| async move {
+                    progress.step("updating-launcher").await;
+                    let updated = VersionManager::new()
+                        .update_to_version(call.version.as_deref())
+                        .await?;
+                    if !updated {
+                        progress.step("up-to-date").await;
+                    }
+                    Ok(())
+                },
+            );
+            std::future::ready(Ok(accepted))
+        }
+    }
+
+    async fn handle_ota_request(_: Message) -> Result<()> {
+        VersionManager::new().check_and_apply_updates().await
+    }
 }
+
+forward_service!(IotServer, "iot", health);
@@ -0,0 +1,93 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, RwLock};
+
+use luffy_common::config_watch;
+use luffy_common::util;
+use tracing::info;
+
+use crate::config::{GatewayConfig, CONFIG};
+
+/// Settings that start from the config file but can be changed at runtime
+/// by commands (and the device shadow).
+pub static SETTINGS: LazyLock<RuntimeSettings> = LazyLock::new(RuntimeSettings::from_config);
+
+#[derive(Debug)]
+pub struct RuntimeSettings {
+    local_interval: AtomicU64,
+    remote_interval: AtomicU64,
+    ota_strategy: RwLock<String>,
+}
+
+impl RuntimeSettings {
+    pub fn new(local_interval: u64, remote_interval: u64, ota_strategy: &str) -> Self {
+        Self {
+            local_interval: AtomicU64::new(local_interval),
+            remote_interval: AtomicU64::new(remote_interval),
+            ota_strategy: RwLock::new(ota_strategy.to_string()),
+        }
+    }
+
+    fn from_config() -> Self {
+        Self::new(
+            CONFIG.iot.local_interval,
+            CONFIG.iot.remote_interval,
+            &CONFIG.ota.strategy,
+        )
+    }
+
+    pub fn local_interval(&self) -> u64 {
+        self.local_interval.load(Ordering::SeqCst)
+    }
+
+    pub fn remote_interval(&self) -> u64 {
+        self.remote_interval.load(Ordering::SeqCst)
+    }
+
+    pub fn set_telemetry_interval(&self, seconds: u64) {
+        let seconds = seconds.max(1);
+        self.local_interval.store(seconds, Ordering::SeqCst);
+        self.remote_interval.store(seconds, Ordering::SeqCst);
+    }
+
+    pub fn ota_strategy(&self) -> String {
+        self.ota_strategy.read().unwrap().clone()
+    }
+
+    pub fn set_ota_strategy(&self, strategy: &str) {
+        *self.ota_strategy.write().unwrap() = strategy.to_string();
+    }
+
+    /// Take over the values that changed between two versions of the config
+    /// file. Values changed by commands since startup are kept otherwise.
+    pub fn reload(&self, old: &GatewayConfig, new: &GatewayConfig) {
+        if old.iot.local_interval != new.iot.local_interval {
+            info!(
+                "Local telemetry interval changed to {}s",
+                new.iot.local_interval
+            );
+            self.local_interval
+                .store(new.iot.local_interval.max(1), Ordering::SeqCst);
+        }
+        if old.iot.remote_interval != new.iot.remote_interval {
+            info!(
+                "Remote telemetry interval changed to {}s",
+                new.iot.remote_interval
+            );
+            self.remote_interval
+                .store(new.iot.remote_interval.max(1), Ordering::SeqCst);
+        }
+        if old.ota.strategy != new.ota.strategy {
+            info!("OTA strategy changed to {}", new.ota.strategy);
+            self.set_ota_strategy(&new.ota.strategy);
+        }
+    }
+}
+
+/// Apply changes to `gateway.toml` that do not need a restart.
+pub async fn follow_config() {
+    config_watch::follow(CONFIG.watch(), |old, new| async move {
+        util::reload_log_level(&old.log_level, &new.log_level);
+        SETTINGS.reload(&old, &new);
+    })
+    .await
+}
@@ -0,0 +1,264 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::Duration;
+use tracing::{debug, error, info, warn};
+
+use crate::iot::command::{self, Command};
+use crate::iot::settings::RuntimeSettings;
+use crate::vehicle::Vehicle;
+use luffy_common::iot::client::MqttClient;
+use luffy_common::ota::deb::DebManager;
+
+pub static SHADOW: OnceLock<ShadowSync> = OnceLock::new();
+
+const SERVICES: [&str; 3] = ["luffy-gateway", "luffy-launcher", "luffy-media"];
+
+/// Desired state the cloud can set on the shadow. Every field maps to a
+/// regular `{vehicle_id}/command/...` command.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DesiredState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ota_strategy: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub telemetry_interval: Option<u64>,
+}
+
+impl DesiredState {
+    pub fn into_commands(self) -> Vec<Command> {
+        let mut commands = Vec::new();
+        if let Some(mode) = self.mode {
+            commands.push(Command::SetMode(mode));
+        }
+        if let Some(strategy) = self.ota_strategy {
+            commands.push(Command::SetOtaStrategy(strategy));
+        }
+        if let Some(interval) = self.telemetry_interval {
+            commands.push(Command::SetTelemetryInterval(interval));
+        }
+        commands
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportedState {
+    pub mode: String,
+    pub ota_strategy: String,
+    pub telemetry_interval: u64,
+    pub armed: bool,
+    pub battery_percentage: f32,
+    pub location: (f64, f64),
+    pub services: BTreeMap<String, String>,
+}
+
+impl ReportedState {
+    pub async fn collect(settings: &RuntimeSettings) -> Result<Self> {
+        let state = Vehicle::instance().await.get_state_snapshot()?;
+        let deb_manager = DebManager::new(PathBuf::new());
+
+        let mut services = BTreeMap::new();
+        for service in SERVICES {
+            let version = deb_manager
+                .get_package_version(service)
+                .unwrap_or_else(|_| "unknown".to_string());
+            services.insert(service.to_string(), version);
+        }
+        // The running binary knows its own version even outside a deb install.
+        services.insert(
+            "luffy-gateway".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        );
+
+        Ok(Self {
+            mode: state
+                .flight_mode
+                .strip_prefix("ROVER_MODE_")
+                .unwrap_or(&state.flight_mode)
+                .to_string(),
+            ota_strategy: settings.ota_strategy(),
+            telemetry_interval: settings.remote_interval(),
+            armed: state.armed,
+            battery_percentage: state.battery_percentage,
+            location: state.location,
+            services,
+        })
+    }
+}
+
+/// Keeps a named AWS IoT Device Shadow in sync with the vehicle.
+///
+/// Reported state is published on start, on every reconnect and whenever it
+/// changes. Desired state arrives through `get/accepted` (reconciliation after
+/// a reconnect) and `update/delta`, and is applied as regular commands.
+pub struct ShadowSync {
+    client: MqttClient,
+    prefix: String,
+    /// Changed by desired state and reported back.
+    settings: &'static RuntimeSettings,
+    last_reported: Mutex<Option<Value>>,
+}
+
+impl ShadowSync {
+    /// `topic_root` is `$aws` for AWS IoT. Brokers that reject `$` topics
+    /// (rumqttd does) can emulate the shadow service under another root.
+    pub fn new(
+        client: MqttClient,
+        topic_root: &str,
+        thing_name: &str,
+        shadow_name: &str,
+        settings: &'static RuntimeSettings,
+    ) -> Self {
+        Self {
+            client,
+            settings,
+            prefix: format!(
+                "{}/things/{}/shadow/name/{}",
+                topic_root, thing_name, shadow_name
+            ),
+            last_reported: Mutex::new(None),
+        }
+    }
+
+    pub fn topic(&self, suffix: &str) -> String {
+        format!("{}/{}", self.prefix, suffix)
+    }
+
+    pub fn topics(&self) -> Vec<String> {
+        [
+            "get/accepted",
+            "get/rejected",
+            "update/delta",
+            "update/rejected",
+        ]
+        .iter()
+        .map(|suffix| self.topic(suffix))
+        .collect()
+    }
+
+    /// Start syncing. The caller must already be subscribed to [`Self::topics`]
+    /// and route matching messages to [`Self::handle_message`].
+    pub async fn start(
+        &'static self,
+        mut connections: broadcast::Receiver<()>,
+        report_interval: u64,
+    ) -> Result<()> {
+        info!("Starting shadow sync on {}", self.prefix);
+        self.request_state().await?;
+
+        // Reconcile after every reconnect; desired state may have changed
+        // while the link was down.
+        tokio::spawn(async move {
+            loop {
+                match connections.recv().await {
+                    Ok(()) => {
+                        *self.last_reported.lock().await = None;
+                        if let Err(e) = self.request_state().await {
+                            error!("Failed to request shadow state: {}", e);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(report_interval));
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.report(false).await {
+                    error!("Failed to report shadow state: {}", e);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn request_state(&self) -> Result<()> {
+        debug!("Requesting shadow state");
+        self.client.publish(&self.topic("get"), "{}").await?;
+        Ok(())
+    }
+
+    /// Publish the reported state. Unless `force` is set, nothing is sent when
+    /// the state has not changed since the last report.
+    pub async fn report(&self, force: bool) -> Result<()> {
+        let reported = serde_json::to_value(ReportedState::collect(self.settings).await?)?;
+        let mut last_reported = self.last_reported.lock().await;
+        if !force && last_reported.as_ref() == Some(&reported) {
+            return Ok(());
+        }
+
+        let document = json!({ "state": { "reported": reported } });
+        self.client
+            .publish(&self.topic("update"), document.to_string())
+            .await?;
+        *last_reported = Some(reported);
+        Ok(())
+    }
+
+    pub async fn handle_message(&self, topic: &str, payload: &str) -> Result<()> {
+        let suffix = topic
+            .strip_prefix(&self.prefix)
+            .unwrap_or(topic)
+            .trim_start_matches('/');
+        debug!("Shadow message on {}: {}", suffix, payload);
+
+        match suffix {
+            "get/accepted" => {
+                let document: Value =
+                    serde_json::from_str(payload).context("Invalid shadow document")?;
+                // AWS only includes state.delta when desired and reported differ.
+                if let Some(delta) = document.pointer("/state/delta") {
+                    self.apply_delta(delta.clone()).await;
+                }
+                self.report(true).await
+            }
+            "update/delta" => {
+                let document: Value =
+                    serde_json::from_str(payload).context("Invalid shadow delta")?;
+                if let Some(delta) = document.get("state") {
+                    self.apply_delta(delta.clone()).await;
+                }
+                self.report(true).await
+            }
+            "get/rejected" => {
+                // 404 means no shadow exists yet; our first report creates it.
+                debug!("Shadow get rejected: {}", payload);
+                self.report(true).await
+            }
+            "update/rejected" => {
+                warn!("Shadow update rejected: {}", payload);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn apply_delta(&self, delta: Value) {
+        let desired: DesiredState = match serde_json::from_value(delta) {
+            Ok(desired) => desired,
+            Err(e) => {
+                warn!("Ignoring invalid shadow delta: {}", e);
+                return;
+            }
+        };
+        info!("Applying shadow delta: {:?}", desired);
+
+        for command in desired.into_commands() {
+            if let Err(e) = command::execute_with(command, self.settings).await {
+                error!("Failed to apply shadow delta: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use super::*;
+use anyhow::Result;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use tokio::sync::mpsc;
+
+use crate::broker::start_test_broker;
+use crate::iot::settings::RuntimeSettings;
+
+// The shadow service is emulated by a second client answering on the shadow
+// topics. rumqttd refuses `$` filters, so they live under `aws/things/...`.
+fn client(id: &str, port: u16) -> (AsyncClient, rumqttc::EventLoop) {
+    let mut options = MqttOptions::new(id, "127.0.0.1", port);
+    options.set_keep_alive(Duration::from_secs(5));
+    AsyncClient::new(options, 10)
+}
+
+#[test]
+fn test_desired_state_into_commands() {
+    let desired: DesiredState = serde_json::from_value(json!({
+        "mode": "HOLD",
+        "telemetry_interval": 10,
+        "unknown": true
+    }))
+    .unwrap();
+
+    assert_eq!(
+        desired.into_commands(),
+        vec![
+            Command::SetMode("HOLD".to_string()),
+            Command::SetTelemetryInterval(10),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_shadow_reconciles_desired_state() -> Result<()> {
+    let port = start_test_broker();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let prefix = "aws/things/test-vehicle/shadow/name/luffy";
+    let (reported_tx, mut reported_rx) = mpsc::channel::<Value>(10);
+
+    // Emulated shadow service
+    let (cloud, mut cloud_loop) = client("shadow-service", port);
+    cloud
+        .subscribe(format!("{}/get", prefix), QoS::AtLeastOnce)
+        .await?;
+    cloud
+        .subscribe(format!("{}/update", prefix), QoS::AtLeastOnce)
+        .await?;
+    let responder = cloud.clone();
+    tokio::spawn(async move {
+        while let Ok(event) = cloud_loop.poll().await {
+            let Event::Incoming(Packet::Publish(p)) = event else {
+                continue;
+            };
+            if p.topic.ends_with("/get") {
+                let document = json!({
+                    "state": {
+                        "desired": { "telemetry_interval": 7, "ota_strategy": "auto" },
+                        "delta": { "telemetry_interval": 7, "ota_strategy": "auto" }
+                    },
+                    "version": 1
+                });
+                responder
+                    .publish(
+                        format!("{}/get/accepted", prefix),
+                        QoS::AtLeastOnce,
+                        false,
+                        document.to_string(),
+                    )
+                    .await
+                    .unwrap();
+            } else if p.topic.ends_with("/update") {
+                let document: Value = serde_json::from_slice(&p.payload).unwrap();
+                reported_tx.send(document).await.unwrap();
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Device side
+    let (device, mut device_loop) = client("test-vehicle", port);
+    let settings: &'static RuntimeSettings =
+        Box::leak(Box::new(RuntimeSettings::new(5, 5, "manual")));
+    let shadow: &'static ShadowSync = Box::leak(Box::new(ShadowSync::new(
+        device.clone().into(),
+        "aws",
+        "test-vehicle",
+        "luffy",
+        settings,
+    )));
+    for topic in shadow.topics() {
+        device.subscribe(topic, QoS::AtLeastOnce).await?;
+    }
+    tokio::spawn(async move {
+        while let Ok(event) = device_loop.poll().await {
+            if let Event::Incoming(Packet::Publish(p)) = event {
+                let payload = String::from_utf8_lossy(&p.payload).to_string();
+                shadow.handle_message(&p.topic, &payload).await.unwrap();
+            }
+        }
+    });
+
+    let (_connections_tx, connections_rx) = broadcast::channel(1);
+    shadow.start(connections_rx, 3600).await?;
+
+    // The periodic report may go out before the desired state is applied.
+    let reported = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let document = reported_rx.recv().await.expect("no reported state");
+            if document["state"]["reported"]["telemetry_interval"] == 7 {
+                return document["state"]["reported"].clone();
+            }
+        }
+    })
+    .await?;
+    assert_eq!(reported["ota_strategy"], "auto");
+    assert_eq!(settings.remote_interval(), 7);
+    assert_eq!(settings.ota_strategy(), "auto");
+
+    Ok(())
+}
@@ -1,4 +1,3 @@
-pub mod aws_client;
 pub mod broker;
 pub mod config;
 
@@ -0,0 +1,363 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::{Context, Result};
+use mavlink::{self, ardupilotmega::*, MavConnection, MavHeader, Message};
+use num_traits::FromPrimitive;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, info_span, warn, Span};
+
+use crate::config::CONFIG;
+use crate::vehicle::Vehicle;
+use luffy_common::forward_service;
+use luffy_common::health::{HealthContributor, Liveness};
+use luffy_common::metrics::{self, IntCounter, IntCounterVec, IntGauge};
+
+/// How long handling one message or command may take before the server
+/// counts as hung.
+const HANDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// MAVLink messages received since startup.
+pub static MESSAGES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+static MESSAGES_BY_TYPE: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    metrics::counter_vec(
+        "luffy_mavlink_messages_total",
+        "MAVLink messages received",
+        &["type"],
+    )
+});
+static LINK_UP: LazyLock<IntGauge> = LazyLock::new(|| {
+    metrics::gauge(
+        "luffy_mavlink_link_up",
+        "Whether heartbeats arrive from the vehicle",
+    )
+});
+static LINK_LOSSES: LazyLock<IntCounter> = LazyLock::new(|| {
+    metrics::counter(
+        "luffy_mavlink_link_losses_total",
+        "Times the vehicle's heartbeats stopped",
+    )
+});
+
+/// Whether the vehicle's heartbeats arrive. The link counts as lost once
+/// none came for the timeout, after having been up.
+#[derive(Debug, Default)]
+pub struct LinkState {
+    last_heartbeat: Option<Instant>,
+    up: bool,
+}
+
+impl LinkState {
+    /// Returns whether the link came up with it.
+    pub fn heartbeat(&mut self, now: Instant) -> bool {
+        self.last_heartbeat = Some(now);
+        !std::mem::replace(&mut self.up, true)
+    }
+
+    /// Returns whether the link was lost since the last check.
+    pub fn check(&mut self, now: Instant, timeout: Duration) -> bool {
+        let lost = self.up
+            && self
+                .last_heartbeat
+                .is_none_or(|last| now.duration_since(last) > timeout);
+        if lost {
+            self.up = false;
+        }
+        lost
+    }
+}
+
+/// Commands sent and waiting for their COMMAND_ACK, with the span each
+/// ends.
+#[derive(Default)]
+pub struct PendingAcks {
+    commands: Vec<(MavCmd, Instant, Span)>,
+}
+
+impl PendingAcks {
+    /// An unanswered command's span ends when it is sent again.
+    pub fn sent(&mut self, command: MavCmd, now: Instant, span: Span) {
+        self.commands.retain(|(cmd, _, _)| *cmd != command);
+        self.commands.push((command, now, span));
+    }
+
+    /// The span of `command`, if it was waiting for this ack.
+    pub fn acked(&mut self, command: MavCmd) -> Option<Span> {
+        let index = self
+            .commands
+            .iter()
+            .position(|(cmd, _, _)| *cmd == command)?;
+        Some(self.commands.remove(index).2)
+    }
+
+    /// Remove the commands unanswered for longer than `timeout`.
+    pub fn expire(&mut self, now: Instant, timeout: Duration) -> Vec<(MavCmd, Span)> {
+        let mut expired = Vec::new();
+        self.commands.retain(|(cmd, sent, span)| {
+            let keep = now.duration_since(*sent) <= timeout;
+            if !keep {
+                expired.push((*cmd, span.clone()));
+            }
+            keep
+        });
+        expired
+    }
+}
+
+pub struct MavlinkServer {
+    vehicle: &'static Vehicle,
+    running: Arc<AtomicBool>,
+    command_rx: mpsc::Receiver<(MavCommand, Span)>,
+    connection: Arc<Mutex<Option<Box<dyn MavConnection<MavMessage> + Send + Sync>>>>,
+    link: Arc<Mutex<LinkState>>,
+    /// Cancels the link watcher of the current start.
+    link_watch: CancellationToken,
+    /// Spans of the commands sent, ended by their COMMAND_ACK or the ack
+    /// timeout.
+    pending_acks: Arc<Mutex<PendingAcks>>,
+    /// Probe of the message loop; waiting for the vehicle is not a hang.
+    liveness: Liveness,
+}
+
+// Commands that can be sent to the vehicle
+#[derive(Debug)]
+pub enum MavCommand {
+    Arm(bool),
+    SetMode(String),
+}
+
+/// Queues commands with the span of their sender.
+pub type CommandSender = mpsc::Sender<(MavCommand, Span)>;
+
+impl MavlinkServer {
+    pub async fn new() -> Self {
+        Self {
+            vehicle: Vehicle::instance().await,
+            running: Arc::new(AtomicBool::new(false)),
+            command_rx: mpsc::channel(100).1,
+            connection: Arc::new(Mutex::new(None)),
+            link: Arc::new(Mutex::new(LinkState::default())),
+            link_watch: CancellationToken::new(),
+            pending_acks: Arc::new(Mutex::new(PendingAcks::default())),
+            liveness: Liveness::new(),
+        }
+    }
+
+    pub async fn start(&mut self) -> Result<()> {
+        info!("Starting MAVLink server...");
+        let (command_tx, command_rx) = mpsc::channel(100);
+
+        // Store command_tx in Vehicle for other components to send commands
+        Vehicle::instance().await.set_command_sender(command_tx)?;
+
+        info!("Connecting to vehicle {}", CONFIG.mavlink.connection_string);
+        // Connect to the vehicle using MAVLink
+        let connection = Arc::new(Mutex::new(Some(
+            mavlink::connect(&CONFIG.mavlink.connection_string)
+                .context("Failed to connect to MAVLink vehicle")?,
+        )));
+        self.command_rx = command_rx;
+        self.connection = connection;
+        self.running.store(true, Ordering::SeqCst);
+        self.watch_link();
+
+        while self.running.load(Ordering::SeqCst) {
+            tokio::select! {
+                // Handle incoming MAVLink messages
+                Ok(result) = tokio::task::spawn_blocking({
+                    let connection = Arc::clone(&self.connection);
+                    move || connection.lock().unwrap().as_mut().unwrap().recv()
+                }) => {
+                    if let Ok((header, message)) = result {
+                        self.liveness.step(HANDLE_TIMEOUT);
+                        self.handle_mavlink_message(header, message).await?;
+                    }
+                }
+
+                // Handle command requests
+                Some((command, parent)) = self.command_rx.recv() => {
+                    self.liveness.step(HANDLE_TIMEOUT);
+                    let span = info_span!(parent: &parent, "mavlink.command", command = ?command);
+                    self.handle_command(command, span).await?;
+                }
+            }
+            self.liveness.idle();
+
+            // tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        Ok(())
+    }
+
+    /// Count link losses and expire unanswered commands until the server
+    /// stops. Replaces the watcher of an earlier start.
+    fn watch_link(&mut self) {
+        self.link_watch.cancel();
+        self.link_watch = CancellationToken::new();
+        let cancelled = self.link_watch.clone();
+        let link = self.link.clone();
+        let pending_acks = self.pending_acks.clone();
+        let timeout = Duration::from_secs(CONFIG.mavlink.link_timeout);
+        let ack_timeout = Duration::from_secs(CONFIG.mavlink.ack_timeout);
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+                    _ = cancelled.cancelled() => break,
+                }
+                if link.lock().unwrap().check(Instant::now(), timeout) {
+                    warn!("No MAVLink heartbeat for {:?}, link lost", timeout);
+                    LINK_UP.set(0);
+                    LINK_LOSSES.inc();
+                }
+                let expired = pending_acks
+                    .lock()
+                    .unwrap()
+                    .expire(Instant::now(), ack_timeout);
+                for (command, span) in expired {
+                    warn!(parent: &span, "No COMMAND_ACK for {:?} within {:?}", command, ack_timeout);
+                }
+            }
+        });
+    }
+
+    async fn handle_mavlink_message(&self, _header: MavHeader, message: MavMessage) -> Result<()> {
+        MESSAGES_RECEIVED.fetch_add(1, Ordering::Relaxed);
+        MESSAGES_BY_TYPE
+            .with_label_values(&[message.message_name()])
+            .inc();
+        match message {
+            MavMessage::ATTITUDE(attitude) => {
+                self.vehicle.update_attitude(
+                    attitude.yaw.to_degrees(),
+                    attitude.pitch.to_degrees(),
+                    attitude.roll.to_degrees(),
+                )?;
+            }
+            MavMessage::HEARTBEAT(heartbeat) => {
+                if self.link.lock().unwrap().heartbeat(Instant::now()) {
+                    info!("MAVLink link up");
+                    LINK_UP.set(1);
+                }
+                self.vehicle.update_heartbeat()?;
+                let armed = heartbeat
+                    .base_mode
+                    .contains(MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED);
+                self.vehicle.update_armed_state(armed)?;
+
+                let mode = RoverMode::from_u32(heartbeat.custom_mode).unwrap_or(RoverMode::DEFAULT);
+
+                self.vehicle.update_flight_mode(format!("{:?}", mode))?;
+            }
+            MavMessage::GLOBAL_POSITION_INT(pos) => {
+                self.vehicle.update_position(
+                    pos.lat as f64 / 1e7,
+                    pos.lon as f64 / 1e7,
+                    pos.relative_alt as f32 / 1000.0,
+                )?;
+            }
+            MavMessage::SYS_STATUS(status) => {
+                self.vehicle
+                    .update_battery(status.battery_remaining as f32)?;
+            }
+            MavMessage::COMMAND_ACK(ack) => {
+                let span = self.pending_acks.lock().unwrap().acked(ack.command);
+                if let Some(span) = span {
+                    info!(parent: &span, "COMMAND_ACK {:?}: {:?}", ack.command, ack.result);
+                }
+            }
+            _ => {} // Handle other message types as needed
+        }
+        Ok(())
+    }
+
+    /// Send `command` to the vehicle. `span` lasts until its COMMAND_ACK or
+    /// the ack timeout.
+    async fn handle_command(&mut self, command: MavCommand, span: Span) -> Result<()> {
+        let message = match command {
+            MavCommand::Arm(arm) => MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+                target_system: 1,
+                target_component: 1,
+                command: MavCmd::MAV_CMD_COMPONENT_ARM_DISARM,
+                confirmation: 0,
+                param1: if arm { 1.0 } else { 0.0 },
+                param2: 0.0,
+                param3: 0.0,
+                param4: 0.0,
+                param5: 0.0,
+                param6: 0.0,
+                param7: 0.0,
+            }),
+            MavCommand::SetMode(mode) => {
+                let Some(custom_mode) = parse_rover_mode(&mode) else {
+                    warn!("Unknown flight mode: {}", mode);
+                    return Ok(());
+                };
+                MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+                    target_system: 1,
+                    target_component: 1,
+                    command: MavCmd::MAV_CMD_DO_SET_MODE,
+                    param1: 1.0, // Custom mode
+                    param2: custom_mode as u32 as f32,
+                    ..Default::default()
+                })
+            } // Implement other commands...
+        };
+
+        span.in_scope(|| info!("Sending {:?}", message));
+        self.connection
+            .lock()
+            .unwrap()
+            .as_mut()
+            .unwrap()
+            .send(&mavlink::MavHeader::default(), &message)?;
+        if let MavMessage::COMMAND_LONG(data) = &message {
+            self.pending_acks
+                .lock()
+                .unwrap()
+                .sent(data.command, Instant::now(), span);
+        }
+        Ok(())
+    }
+
+    pub fn health(&self) -> Option<Arc<dyn HealthContributor>> {
+        Some(Arc::new(vec![self.liveness.clone()]))
+    }
+
+    pub async fn stop(&self) {
+        self.liveness.idle();
+        self.running.store(false, Ordering::SeqCst);
+        self.link_watch.cancel();
+    }
+}
+
+forward_service!(MavlinkServer, "mavlink", runs_in_start, health);
+
+/// Accepts a numeric custom mode or a rover mode name, with or without the
+/// `ROVER_MODE_` prefix (`"4"`, `"HOLD"`, `"ROVER_MODE_HOLD"`).
+pub fn parse_rover_mode(mode: &str) -> Option<RoverMode> {
+    if let Ok(value) = mode.trim().parse::<u32>() {
+        return RoverMode::from_u32(value);
+    }
+    let name = mode.trim().to_uppercase();
+    match name.strip_prefix("ROVER_MODE_").unwrap_or(&name) {
+        "MANUAL" => Some(RoverMode::ROVER_MODE_MANUAL),
+        "ACRO" => Some(RoverMode::ROVER_MODE_ACRO),
+        "STEERING" => Some(RoverMode::ROVER_MODE_STEERING),
+        "HOLD" => Some(RoverMode::ROVER_MODE_HOLD),
+        "LOITER" => Some(RoverMode::ROVER_MODE_LOITER),
+        "FOLLOW" => Some(RoverMode::ROVER_MODE_FOLLOW),
+        "SIMPLE" => Some(RoverMode::ROVER_MODE_SIMPLE),
+        "AUTO" => Some(RoverMode::ROVER_MODE_AUTO),
+        "RTL" => Some(RoverMode::ROVER_MODE_RTL),
+        "SMART_RTL" => Some(RoverMode::ROVER_MODE_SMART_RTL),
+        "GUIDED" => Some(RoverMode::ROVER_MODE_GUIDED),
+        "INITIALIZING" => Some(RoverMode::ROVER_MODE_INITIALIZING),
+        _ => None,
+    }
+}
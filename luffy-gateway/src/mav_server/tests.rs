@@ -0,0 +1,55 @@
+use super::*;
+
+#[test]
+fn test_link_state() {
+    let timeout = Duration::from_secs(5);
+    let start = Instant::now();
+    let mut link = LinkState::default();
+    // Not lost before it was ever up.
+    assert!(!link.check(start + timeout * 2, timeout));
+
+    assert!(link.heartbeat(start));
+    assert!(!link.heartbeat(start + Duration::from_secs(1)));
+    assert!(!link.check(start + Duration::from_secs(6), timeout));
+
+    assert!(link.check(start + Duration::from_secs(7), timeout));
+    // Counted once per loss.
+    assert!(!link.check(start + Duration::from_secs(8), timeout));
+    assert!(link.heartbeat(start + Duration::from_secs(9)));
+}
+
+#[test]
+fn test_pending_acks() {
+    let timeout = Duration::from_secs(3);
+    let start = Instant::now();
+    let mut pending = PendingAcks::default();
+    pending.sent(MavCmd::MAV_CMD_COMPONENT_ARM_DISARM, start, Span::none());
+    pending.sent(MavCmd::MAV_CMD_DO_SET_MODE, start, Span::none());
+    assert!(pending.acked(MavCmd::MAV_CMD_DO_SET_MODE).is_some());
+    assert!(pending.acked(MavCmd::MAV_CMD_DO_SET_MODE).is_none());
+
+    assert!(pending.expire(start + timeout, timeout).is_empty());
+    let expired = pending.expire(start + Duration::from_secs(4), timeout);
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].0, MavCmd::MAV_CMD_COMPONENT_ARM_DISARM);
+    // A late ack finds nothing to end.
+    assert!(pending
+        .acked(MavCmd::MAV_CMD_COMPONENT_ARM_DISARM)
+        .is_none());
+}
+
+#[test]
+fn test_parse_rover_mode() {
+    assert_eq!(parse_rover_mode("4"), Some(RoverMode::ROVER_MODE_HOLD));
+    assert_eq!(parse_rover_mode("hold"), Some(RoverMode::ROVER_MODE_HOLD));
+    assert_eq!(
+        parse_rover_mode("ROVER_MODE_SMART_RTL"),
+        Some(RoverMode::ROVER_MODE_SMART_RTL)
+    );
+    assert_eq!(
+        parse_rover_mode(" guided "),
+        Some(RoverMode::ROVER_MODE_GUIDED)
+    );
+    assert_eq!(parse_rover_mode("2"), None);
+    assert_eq!(parse_rover_mode("FLY"), None);
+}
@@ -1,10 +1,12 @@
 use std::sync::{atomic::AtomicBool, Arc};
 
 use crate::config::CONFIG;
+use crate::iot::settings::SETTINGS;
 use anyhow::Result;
+use luffy_common::forward_service;
 use luffy_common::ota::deb::ServiceType;
 use luffy_common::ota::version::BaseVersionManager;
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 
 #[derive(Clone)]
 pub struct VersionManager {
@@ -27,7 +29,16 @@ impl VersionManager {
     }
 
     pub async fn check_updates(&self) -> Result<Vec<(String, String)>> {
-        let (_, all_packages) = self.base.get_latest_version().await?;
+        self.check_release_updates(None).await
+    }
+
+    /// Launcher packages of a release (`None` for the latest) that are newer
+    /// than the installed launcher.
+    pub async fn check_release_updates(
+        &self,
+        version: Option<&str>,
+    ) -> Result<Vec<(String, String)>> {
+        let (_, all_packages) = self.base.get_release(version).await?;
 
         // Filter launcher packages that need updates
         let updates = all_packages
@@ -49,7 +60,7 @@ impl VersionManager {
     }
 
     pub async fn strategy_updates(&self) -> Result<()> {
-        match self.base.strategy.as_str() {
+        match SETTINGS.ota_strategy().as_str() {
             "auto" => {
                 let updates = self.check_updates().await?;
                 if !updates.is_empty() {
@@ -76,6 +87,17 @@ impl VersionManager {
         Ok(())
     }
 
+    /// Update the launcher to a release. Returns false when it is already
+    /// up to date.
+    pub async fn update_to_version(&self, version: Option<&str>) -> Result<bool> {
+        let updates = self.check_release_updates(version).await?;
+        if updates.is_empty() {
+            return Ok(false);
+        }
+        self.update_launcher(updates).await?;
+        Ok(true)
+    }
+
     async fn update_launcher(&self, packages: Vec<(String, String)>) -> Result<()> {
         let service_type = ServiceType::Other("luffy-launcher".to_string());
         self.base
@@ -83,7 +105,7 @@ impl VersionManager {
             .await
     }
 
-    pub fn stop(&self) {
+    pub async fn stop(&self) {
         self.running
             .store(false, std::sync::atomic::Ordering::Relaxed);
     }
@@ -95,70 +117,54 @@ impl VersionManager {
         self.running
             .store(true, std::sync::atomic::Ordering::Relaxed);
 
-        match self.base.strategy.as_str() {
-            "auto" => {
-                info!(
-                    "Starting auto update task with interval: {:?}",
-                    self.base.check_interval
-                );
+        info!(
+            "Starting update task with interval: {:?}",
+            self.base.check_interval
+        );
 
-                while self.running.load(std::sync::atomic::Ordering::Relaxed) {
-                    interval.tick().await;
+        // The strategy is read on every tick so it can be changed at runtime.
+        while self.running.load(std::sync::atomic::Ordering::Relaxed) {
+            interval.tick().await;
+            match SETTINGS.ota_strategy().as_str() {
+                "auto" => {
                     if let Err(e) = manager.strategy_updates().await {
                         warn!("Auto update check failed: {}", e);
                     }
                 }
-                Ok(())
-            }
-            "manual" => {
-                info!(
-                    "Starting manual update check with interval: {:?}",
-                    self.base.check_interval
-                );
-
-                while self.running.load(std::sync::atomic::Ordering::Relaxed) {
-                    interval.tick().await;
-                    match manager.check_updates().await {
-                        Ok(updates) => {
-                            if !updates.is_empty() {
-                                let update_info: Vec<_> = updates
+                "manual" => match manager.check_updates().await {
+                    Ok(updates) => {
+                        if !updates.is_empty() {
+                            let update_info: Vec<_> = updates
+                                .iter()
+                                .filter_map(|(filename, _)| {
+                                    let new_version =
+                                        self.base.deb_manager.extract_package_version(filename)?;
+                                    let current_version = self
+                                        .base
+                                        .deb_manager
+                                        .get_package_version("luffy-launcher")
+                                        .ok()?;
+                                    Some(("luffy-launcher", current_version, new_version))
+                                })
+                                .collect();
+
+                            info!(
+                                "Launcher update available: {}",
+                                update_info
                                     .iter()
-                                    .filter_map(|(filename, _)| {
-                                        let new_version = self
-                                            .base
-                                            .deb_manager
-                                            .extract_package_version(filename)?;
-                                        let current_version = self
-                                            .base
-                                            .deb_manager
-                                            .get_package_version("luffy-launcher")
-                                            .ok()?;
-                                        Some(("luffy-launcher", current_version, new_version))
-                                    })
-                                    .collect();
-
-                                info!(
-                                    "Launcher update available: {}",
-                                    update_info
-                                        .iter()
-                                        .map(|(pkg, curr, new)| format!(
-                                            "{}: {} -> {}",
-                                            pkg, curr, new
-                                        ))
-                                        .collect::<Vec<_>>()
-                                        .join(", ")
-                                );
-                            }
+                                    .map(|(pkg, curr, new)| format!("{}: {} -> {}", pkg, curr, new))
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            );
                         }
-                        Err(e) => warn!("Manual update check failed: {}", e),
                     }
-                }
-                Ok(())
-            }
-            _ => {
-                info!("Updates are disabled");
-                Ok(())
+                    Err(e) => warn!("Manual update check failed: {}", e),
+                },
+                _ => debug!("Updates are disabled"),
             }
         }
+        Ok(())
     }
 }
+
+forward_service!(VersionManager, "ota", runs_in_start);
@@ -1,56 +1,22 @@
 use anyhow::anyhow;
 use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
 use std::sync::{Arc, RwLock};
-use tokio::sync::mpsc;
+use std::time::SystemTime;
 use tokio::sync::OnceCell;
+use tracing::Span;
 
 use crate::config::CONFIG;
-use crate::mav_server::MavCommand;
-use luffy_common::util;
+use crate::mav_server::{CommandSender, MavCommand};
+use luffy_common::identity::DeviceIdentity;
+use luffy_common::telemetry;
+pub use luffy_common::telemetry::VehicleState;
 static VEHICLE: OnceCell<Vehicle> = OnceCell::const_new();
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct VehicleState {
-    // Flight data
-    pub yaw_degree: f32,
-    pub pitch_degree: f32,
-    pub roll_degree: f32,
-    pub altitude: f32,
-    pub battery_percentage: f32,
-    pub location: (f64, f64), // (latitude, longitude)
-    pub armed: bool,
-    pub flight_mode: String,
-
-    // System status
-    pub last_heartbeat: std::time::SystemTime,
-    pub errors: Vec<String>,
-    pub luffy: String,
-}
-
-impl Default for VehicleState {
-    fn default() -> Self {
-        Self {
-            yaw_degree: 0.0,
-            pitch_degree: 0.0,
-            roll_degree: 0.0,
-            altitude: 0.0,
-            battery_percentage: 0.0,
-            location: (0.0, 0.0),
-            armed: false,
-            flight_mode: "MANUAL".to_string(),
-            last_heartbeat: std::time::SystemTime::now(),
-            errors: Vec::new(),
-            luffy: env!("CARGO_PKG_VERSION").to_string(),
-        }
-    }
-}
-
 #[derive(Debug)]
 pub struct Vehicle {
     pub vehicle_id: String,
     state: Arc<RwLock<VehicleState>>,
-    command_tx: Arc<RwLock<Option<mpsc::Sender<MavCommand>>>>,
+    command_tx: Arc<RwLock<Option<CommandSender>>>,
 }
 
 impl Vehicle {
@@ -58,8 +24,11 @@ impl Vehicle {
         VEHICLE
             .get_or_init(|| async {
                 Self {
-                    vehicle_id: util::get_vehicle_id(&CONFIG.base),
-                    state: Arc::new(RwLock::new(VehicleState::default())),
+                    vehicle_id: DeviceIdentity::get(&CONFIG.base).vehicle_id.clone(),
+                    state: Arc::new(RwLock::new(VehicleState {
+                        luffy: env!("CARGO_PKG_VERSION").to_string(),
+                        ..Default::default()
+                    })),
                     command_tx: Arc::new(RwLock::new(None)),
                 }
             })
@@ -106,7 +75,7 @@ impl Vehicle {
         Ok(())
     }
 
-    pub fn set_command_sender(&self, sender: mpsc::Sender<MavCommand>) -> Result<()> {
+    pub fn set_command_sender(&self, sender: CommandSender) -> Result<()> {
         let mut tx = self
             .command_tx
             .write()
@@ -115,19 +84,32 @@ impl Vehicle {
         Ok(())
     }
 
+    /// Queue `command` for the MAVLink server, in the current span's trace.
     pub fn send_command(&self, command: MavCommand) -> Result<()> {
         let tx = self
             .command_tx
             .read()
             .map_err(|e| anyhow!("Lock error: {}", e))?;
         if let Some(sender) = tx.as_ref() {
-            sender.try_send(command).context("Failed to send command")?;
+            sender
+                .try_send((command, Span::current()))
+                .context("Failed to send command")?;
             Ok(())
         } else {
             Err(anyhow!("Command sender not initialized"))
         }
     }
 
+    /// Stamp the time of a MAVLink heartbeat.
+    pub fn update_heartbeat(&self) -> Result<()> {
+        let mut state = self
+            .state
+            .write()
+            .map_err(|e| anyhow!("Lock error: {}", e))?;
+        state.last_heartbeat = telemetry::system_time_to_millis(SystemTime::now());
+        Ok(())
+    }
+
     pub fn update_armed_state(&self, armed: bool) -> Result<()> {
         let mut state = self
             .state
@@ -0,0 +1,67 @@
+//! Manage the encrypted secret store that `secret:<name>` config values and
+//! the device key live in.
+
+use anyhow::{bail, Context, Result};
+use luffy_common::config::{build_config, SecretsConfig};
+use luffy_common::secrets::SecretStore;
+use std::io::{self, BufRead};
+
+const USAGE: &str = "\
+Usage:
+    luffy-secrets list
+    luffy-secrets set <name> [value]    read the value from stdin when omitted
+    luffy-secrets remove <name>";
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    // The raw config: resolving references would need the secrets we are
+    // about to set.
+    let config: SecretsConfig = build_config("launcher")
+        .ok()
+        .and_then(|config| config.get("secrets").ok())
+        .unwrap_or_default();
+    let mut store = SecretStore::from_config(&config)?;
+
+    match args.as_slice() {
+        ["list"] => {
+            for name in store.names() {
+                println!("{}", name);
+            }
+        }
+        ["set", name] => {
+            let value = read_value()?;
+            store.set(name, &value);
+            store.save()?;
+        }
+        ["set", name, value] => {
+            store.set(name, value);
+            store.save()?;
+        }
+        ["remove", name] => {
+            if !store.remove(name) {
+                bail!("No secret named {}", name);
+            }
+            store.save()?;
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+    Ok(())
+}
+
+fn read_value() -> Result<String> {
+    let mut value = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut value)
+        .context("Failed to read the secret from stdin")?;
+    let value = value.trim_end_matches(['\r', '\n']);
+    if value.is_empty() {
+        bail!("Empty secret");
+    }
+    Ok(value.to_string())
+}
@@ -1,28 +1,42 @@
+use std::collections::HashMap;
 use std::sync::LazyLock;
 
-use luffy_common::config::{BaseConfig, LoadConfig};
-use serde::Deserialize;
+use luffy_common::config::{BaseConfig, LoadConfig, ServiceConfig};
+use serde::{Deserialize, Serialize};
 
-pub static CFG: LazyLock<LauncherConfig> = LazyLock::new(|| {
-    LauncherConfig::load_config("launcher").expect("Failed to load configuration")
+/// Loaded at startup; [`ServiceConfig::watch`] follows changes on disk for
+/// settings applied without a restart.
+pub static CFG: LazyLock<ServiceConfig<LauncherConfig>> = LazyLock::new(|| {
+    // Tests read the development files of the source tree.
+    #[cfg(test)]
+    luffy_common::config::set_config_dir(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../luffy-deploy/config/development"),
+    );
+    LauncherConfig::service_config("launcher").expect("Failed to load configuration")
 });
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct LauncherConfig {
     #[serde(flatten)]
     pub base: BaseConfig,
     pub log_level: String,
     pub web: WebConfig,
     pub ota: OtaConfig,
+    #[serde(default)]
+    pub system: SystemMonitorConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
+    pub config_update: ConfigUpdateConfig,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct WebConfig {
     pub host: String,
     pub port: u16,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OtaConfig {
     pub strategy: String,
     pub check_interval: u32,
@@ -32,6 +46,118 @@ pub struct OtaConfig {
     pub download_dir: Option<String>,
 }
 
+/// Host metrics sampling, published on `{vehicle_id}/system`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SystemMonitorConfig {
+    pub enable: bool,
+    /// Sampling interval in seconds, at least 1.
+    pub interval: u64,
+    pub thresholds: SystemThresholds,
+}
+
+impl Default for SystemMonitorConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            interval: 10,
+            thresholds: SystemThresholds::default(),
+        }
+    }
+}
+
+/// Alarm thresholds; a metric at or above its threshold raises an alarm.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SystemThresholds {
+    pub cpu_percent: f64,
+    pub memory_percent: f64,
+    pub disk_percent: f64,
+    pub temperature_celsius: f64,
+}
+
+impl Default for SystemThresholds {
+    fn default() -> Self {
+        Self {
+            cpu_percent: 90.0,
+            memory_percent: 90.0,
+            disk_percent: 90.0,
+            temperature_celsius: 80.0,
+        }
+    }
+}
+
+/// Restarts services whose health reports stop or turn fatal.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    pub enable: bool,
+    /// Seconds between checks.
+    pub check_interval: u64,
+    /// Delay before the first restart is repeated, doubled after each one.
+    pub backoff_initial: u64,
+    pub backoff_max: u64,
+    /// Give up after this many restarts within `crash_loop_window` seconds.
+    pub crash_loop_limit: usize,
+    pub crash_loop_window: u64,
+    pub services: HashMap<String, ServicePolicy>,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            check_interval: 30,
+            backoff_initial: 30,
+            backoff_max: 900,
+            crash_loop_limit: 5,
+            crash_loop_window: 3600,
+            services: ["gateway", "media"]
+                .into_iter()
+                .map(|name| (name.to_string(), ServicePolicy::default()))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ServicePolicy {
+    pub enable: bool,
+    /// Restart after this many health report intervals without a report.
+    pub missed_reports: u32,
+    pub restart_on_fatal: bool,
+}
+
+impl Default for ServicePolicy {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            missed_reports: 3,
+            restart_on_fatal: true,
+        }
+    }
+}
+
+/// Config changes pushed over `{vehicle_id}/rpc/config/set`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ConfigUpdateConfig {
+    pub enable: bool,
+    /// Seconds a restarted service has to report healthy before the change
+    /// is rolled back.
+    pub health_timeout: u64,
+}
+
+impl Default for ConfigUpdateConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            health_timeout: 120,
+        }
+    }
+}
+
 impl LoadConfig for LauncherConfig {}
 
 impl From<OtaConfig> for luffy_common::ota::version::VersionConfig {
@@ -1,103 +1,44 @@
 use luffy_launcher::{
-    config::CFG, monitor::mqtt::MqttMonitor, ota::version::VersionManager, web::server::WebServer,
+    config::{LauncherConfig, CFG},
+    monitor::{config_manager, mqtt::MqttMonitor, system::SYSTEM_MONITOR, watchdog::WATCHDOG},
+    ota::version::VersionManager,
+    web::server::WebServer,
 };
 
-use tokio::signal;
-use tokio::sync::broadcast;
-use tracing::{error, info};
+use luffy_common::metrics::MetricsServer;
+use luffy_common::otel;
+use luffy_common::supervisor::{self, RestartPolicy, Supervisor};
+use luffy_common::{config_check, config_watch, util};
+use tracing::info;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    config_check::handle_args::<LauncherConfig>("launcher");
+    config_manager::check_own_change();
     let log_level = CFG.log_level.clone();
-    luffy_common::util::setup_logging(&log_level, "launcher");
+    util::setup_logging(&log_level, "launcher", &CFG.base);
     info!("Application starting...");
 
-    // Create a shutdown signal channel
-    let (shutdown_tx, _) = broadcast::channel(1);
-
-    // Spawn OTA checker task with shutdown signal
-    let ota_handle = spawn_ota_checker(shutdown_tx.subscribe()).await;
-
-    // Spawn web console task with shutdown signal
-    let web_handle = spawn_web_server(shutdown_tx.subscribe()).await;
-
-    let monitor_handle = spawn_monitor_server(shutdown_tx.subscribe()).await;
-
-    // Handle shutdown signal
-    let shutdown_signal = async {
-        match signal::ctrl_c().await {
-            Ok(()) => {
-                info!("Shutdown signal received...");
-                shutdown_tx
-                    .send(())
-                    .expect("Failed to send shutdown signal");
-            }
-            Err(err) => {
-                error!("Failed to listen for shutdown signal: {}", err);
-            }
-        }
-    };
-
-    // Wait for all tasks to complete
-    let results = tokio::join!(ota_handle, web_handle, monitor_handle, shutdown_signal);
-
-    // Check for errors
-    for (result, name) in [results.0, results.1, results.2].into_iter().zip([
-        "OTA checker",
-        "Web console",
-        "MQTT monitor",
-    ]) {
-        if let Err(e) = result {
-            error!("{} join error: {}", name, e);
-        }
+    tokio::spawn(config_watch::follow(CFG.watch(), |old, new| async move {
+        util::reload_log_level(&old.log_level, &new.log_level);
+    }));
+
+    // The monitor registers its handlers once, so a failure restarts the
+    // process; it is stopped last as the others publish through it.
+    let mut supervisor = Supervisor::new();
+    supervisor
+        .add(MqttMonitor::get().await.as_ref(), RestartPolicy::Never)
+        .add(&*SYSTEM_MONITOR, RestartPolicy::on_failure())
+        .add(&*WATCHDOG, RestartPolicy::on_failure())
+        .add(WebServer::new().await, RestartPolicy::on_failure())
+        .add(VersionManager::new(), RestartPolicy::on_failure());
+    if CFG.base.metrics.enable {
+        supervisor.add(
+            MetricsServer::new(CFG.base.metrics.clone(), "launcher"),
+            RestartPolicy::on_failure(),
+        );
     }
-
-    Ok(())
-}
-
-async fn spawn_ota_checker(mut shutdown: broadcast::Receiver<()>) -> tokio::task::JoinHandle<()> {
-    let ota = VersionManager::new();
-    tokio::spawn(async move {
-        tokio::select! {
-            result = ota.start() => {
-                if let Err(e) = result {
-                    error!("OTA checker error: {}", e);
-                }
-            }
-            _ = shutdown.recv() => {
-                info!("Shutting down OTA checker...");
-                ota.stop();
-            }
-        }
-    })
-}
-
-async fn spawn_web_server(mut shutdown: broadcast::Receiver<()>) -> tokio::task::JoinHandle<()> {
-    let web = WebServer::new().await;
-    tokio::spawn(async move {
-        tokio::select! {
-            _ = web.start() => {}
-            _ = shutdown.recv() => {
-                info!("Shutting down web console...");
-            }
-        }
-    })
-}
-
-async fn spawn_monitor_server(
-    mut shutdown: broadcast::Receiver<()>,
-) -> tokio::task::JoinHandle<()> {
-    let monitor = MqttMonitor::instance().await;
-    tokio::spawn(async move {
-        tokio::select! {
-            result = monitor.start() => {
-                if let Err(e) = result {
-                    error!("MQTT monitor error: {}", e);
-                }
-            }
-            _ = shutdown.recv() => {
-                info!("Shutting down MQTT monitor...");
-            }
-        }
-    })
+    let result = supervisor.run(supervisor::shutdown_signal()).await;
+    otel::shutdown();
+    Ok(result?)
 }
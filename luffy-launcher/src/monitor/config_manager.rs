@@ -0,0 +1,265 @@
+use crate::config::{ConfigUpdateConfig, LauncherConfig, CFG};
+use crate::monitor::mqtt::{MqttMonitor, MQTT_MONITOR};
+use crate::monitor::service::{ServiceState, ServiceStatus};
+use crate::monitor::watchdog::WATCHDOG;
+use anyhow::{bail, Context, Result};
+use luffy_common::config_update::{
+    self, ConfigChange, ConfigChangeResult, ConfigFile, ValidateCall,
+};
+use luffy_common::identity::DeviceIdentity;
+use luffy_common::iot::rpc::DEFAULT_TIMEOUT;
+use luffy_common::ota::deb::DebManager;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+pub static CONFIG_MANAGER: LazyLock<ConfigManager> =
+    LazyLock::new(|| ConfigManager::new(CFG.config_update.clone()));
+
+/// Services whose config can be changed remotely.
+pub(crate) const SERVICES: [&str; 3] = ["gateway", "launcher", "media"];
+
+/// The service remote config calls come in through.
+const RELAY_SERVICE: &str = "gateway";
+
+/// Starts of the launcher allowed with an unconfirmed change to its own
+/// config before the change is rolled back.
+const MAX_STARTS_WITH_CHANGE: u32 = 1;
+
+/// Roll back a change to the launcher's own config that a previous start
+/// did not confirm. Runs before the config is loaded, so a config that
+/// cannot be loaded is rolled back too.
+pub fn check_own_change() {
+    let Ok(file) = ConfigFile::locate("launcher") else {
+        return;
+    };
+    match file.check_pending(MAX_STARTS_WITH_CHANGE) {
+        Ok(true) => eprintln!("Rolled back a launcher config change the launcher failed with"),
+        Ok(false) => {}
+        Err(e) => eprintln!("Failed to check the launcher config change: {:#}", e),
+    }
+}
+
+/// Whether `state` holds a report from a process started after `since`
+/// without a fatal error.
+pub fn is_healthy(state: &ServiceState, since: SystemTime, now: SystemTime) -> bool {
+    let Some(health) = &state.health else {
+        return false;
+    };
+    let restarted = now.duration_since(since).unwrap_or_default();
+    matches!(state.status, ServiceStatus::Running)
+        && state.last_health_report > since
+        && Duration::from_secs(health.uptime_secs) <= restarted
+        && health.fatal_error.is_none()
+}
+
+/// Applies config changes pushed over MQTT: the change is validated by the
+/// service it is for, written with a backup, and rolled back when the
+/// restarted service does not report healthy in time.
+pub struct ConfigManager {
+    config: ConfigUpdateConfig,
+    /// One change at a time.
+    busy: Mutex<()>,
+}
+
+impl ConfigManager {
+    pub fn new(config: ConfigUpdateConfig) -> Self {
+        Self {
+            config,
+            busy: Mutex::new(()),
+        }
+    }
+
+    pub async fn start(&'static self) -> Result<()> {
+        if !self.config.enable {
+            info!("Remote config changes disabled");
+            return Ok(());
+        }
+        let vehicle_id = &DeviceIdentity::get(&CFG.base).vehicle_id;
+        MqttMonitor::instance()
+            .await
+            .client
+            .lock()
+            .await
+            .serve(
+                &config_update::set_topic(vehicle_id),
+                |change: ConfigChange| async move { CONFIG_MANAGER.apply(change).await },
+            )
+            .await?;
+        tokio::spawn(self.confirm_own_change());
+        for service in SERVICES {
+            if let Ok(file) = ConfigFile::locate(service) {
+                Self::publish(service, &file).await;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn apply(&'static self, change: ConfigChange) -> Result<ConfigChangeResult> {
+        let busy = self.busy.lock().await;
+        let service = change.service.clone();
+        if !SERVICES.contains(&service.as_str()) {
+            bail!("Unknown service {}", service);
+        }
+        let file = ConfigFile::locate(&service)?;
+        let merged = file.merge(&change.changes)?;
+        Self::validate(&service, &file, &merged).await?;
+
+        let mut changes = change.changes.clone();
+        config_update::redact(&mut changes);
+        info!("Applying config change to {}: {}", service, changes);
+        if service == "launcher" {
+            // Checked by the next launcher on start, see `check_own_change`.
+            file.write_pending(&merged)?;
+        } else {
+            file.write(&merged)?;
+        }
+        let pending = service == "launcher" || service == RELAY_SERVICE;
+        let result = ConfigChangeResult {
+            service: service.clone(),
+            config: file.effective()?,
+            pending,
+        };
+        if !pending {
+            let _suspended = WATCHDOG.suspend(&service);
+            self.restart_or_rollback(&service, &file).await?;
+            return Ok(result);
+        }
+
+        // Restarting the service now would drop this call, so it restarts
+        // once the reply is out.
+        tokio::spawn(async move {
+            let _busy = busy;
+            let _suspended = WATCHDOG.suspend(&service);
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            let outcome = if service == "launcher" {
+                restart(&service).await
+            } else {
+                self.restart_or_rollback(&service, &file).await
+            };
+            if let Err(e) = outcome {
+                error!("Config change to {} failed: {:#}", service, e);
+            }
+        });
+        Ok(result)
+    }
+
+    /// Restart `service` with its new config, putting the old one back if
+    /// it does not report healthy in time.
+    async fn restart_or_rollback(&self, service: &str, file: &ConfigFile) -> Result<()> {
+        if let Err(e) = self.restart_and_wait(service).await {
+            warn!("Rolling back config change to {}: {:#}", service, e);
+            file.rollback()?;
+            if let Err(e) = restart(service).await {
+                error!("Failed to restart {} after rollback: {}", service, e);
+            }
+            Self::publish(service, file).await;
+            return Err(e.context(format!("Config change to {} rolled back", service)));
+        }
+        Self::publish(service, file).await;
+        Ok(())
+    }
+
+    /// Keep a change to the launcher's own config once the launcher has run
+    /// with it for `health_timeout`. A launcher that fails before that is
+    /// restarted by systemd and [`check_own_change`] rolls the change back.
+    pub async fn confirm_own_change(&self) {
+        let Ok(file) = ConfigFile::locate("launcher") else {
+            return;
+        };
+        if !file.pending_path().exists() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_secs(self.config.health_timeout)).await;
+        if file.confirm() {
+            info!("Keeping the launcher config change");
+            Self::publish("launcher", &file).await;
+        }
+    }
+
+    /// Check `merged` against the service's config type. Other services are
+    /// asked over MQTT, so a service that is down cannot accept changes.
+    async fn validate(service: &str, file: &ConfigFile, merged: &str) -> Result<()> {
+        if service == "launcher" {
+            file.validate::<LauncherConfig>(merged)?;
+            return Ok(());
+        }
+        let topic =
+            config_update::validate_topic(&DeviceIdentity::get(&CFG.base).vehicle_id, service);
+        let caller = MqttMonitor::instance()
+            .await
+            .client
+            .lock()
+            .await
+            .caller()
+            .await?;
+        caller
+            .call::<_, ()>(
+                &topic,
+                &ValidateCall {
+                    config: merged.to_string(),
+                },
+                DEFAULT_TIMEOUT,
+            )
+            .await
+            .with_context(|| format!("{} rejected the config change", service))
+    }
+
+    async fn restart_and_wait(&self, service: &str) -> Result<()> {
+        let since = SystemTime::now();
+        restart(service).await?;
+        let timeout = Duration::from_secs(self.config.health_timeout);
+        let monitor = MqttMonitor::instance().await;
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let now = SystemTime::now();
+            let services = monitor.get_services_snapshot().await?;
+            if services
+                .services
+                .get(service)
+                .is_some_and(|state| is_healthy(state, since, now))
+            {
+                info!("{} is healthy with the new config", service);
+                return Ok(());
+            }
+            if now.duration_since(since).unwrap_or_default() > timeout {
+                bail!("{} not healthy after {}s", service, timeout.as_secs());
+            }
+        }
+    }
+
+    async fn publish(service: &str, file: &ConfigFile) {
+        let Some(monitor) = MQTT_MONITOR.get() else {
+            return;
+        };
+        let topic =
+            config_update::effective_topic(&DeviceIdentity::get(&CFG.base).vehicle_id, service);
+        let result = match file.effective() {
+            Ok(config) => {
+                monitor
+                    .client
+                    .lock()
+                    .await
+                    .publish(&topic, &config.to_string())
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!("Failed to publish {} config: {}", service, e);
+        }
+    }
+}
+
+async fn restart(service: &str) -> Result<()> {
+    let deb_manager = DebManager::new(PathBuf::from(
+        CFG.ota
+            .download_dir
+            .clone()
+            .unwrap_or("/home/luffy/.deb".to_string()),
+    ));
+    let service_type = deb_manager.get_service_type(&format!("luffy-{}", service));
+    deb_manager.restart_service(&service_type).await
+}
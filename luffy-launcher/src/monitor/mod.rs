@@ -1,3 +1,8 @@
+#[cfg(test)]
+mod tests;
+
+pub mod config_manager;
 pub mod mqtt;
 pub mod service;
-pub mod vehicle;
+pub mod system;
+pub mod watchdog;
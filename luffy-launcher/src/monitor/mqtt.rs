@@ -1,67 +1,78 @@
 use crate::config::CFG;
+use crate::monitor::config_manager::CONFIG_MANAGER;
 use crate::monitor::service::{HealthReport, ServiceStatus, Services};
-use crate::monitor::vehicle::VehicleState;
-use anyhow::Result;
+use crate::ota::version::VersionManager;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
 
-use luffy_common::iot::local::LocalIotClient;
-use luffy_common::util::glob_match;
-use serde::Deserialize;
+use luffy_common::health::{HealthContributor, Liveness};
+use luffy_common::identity::DeviceIdentity;
+use luffy_common::iot::client::MqttClient;
+use luffy_common::iot::local::{LocalIotClient, STATUS_OFFLINE, STATUS_ONLINE, STATUS_STOPPED};
+use luffy_common::iot::router::Message;
+use luffy_common::log_filter;
+use luffy_common::ota::update::{self, UpdateAccepted, UpdateCall, UpdateRunner, UpdateStatus};
+use luffy_common::ota::version;
+use luffy_common::supervisor::{self, Service};
+use luffy_common::telemetry::{self, VehicleState};
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::OnceCell;
 use tokio::sync::{Mutex, RwLock};
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
 // Add static instance
 pub static MQTT_MONITOR: OnceCell<Arc<MqttMonitor>> = OnceCell::const_new();
 
-// Add this struct to deserialize telemetry data
-#[derive(Debug, Deserialize)]
-struct TelemetryData {
-    location: (f64, f64),
-    yaw_degree: f32,
-    battery_percentage: f32,
-    armed: bool,
-    flight_mode: String,
-}
+static SERVICE_UPDATES: UpdateRunner = UpdateRunner::new();
 
 pub struct MqttMonitor {
     pub services: Arc<RwLock<Services>>,
     pub vehicle: Arc<RwLock<VehicleState>>,
+    /// Last status of each update target, `launcher` or `services`.
+    pub updates: Arc<RwLock<BTreeMap<String, UpdateStatus>>>,
     pub client: Arc<Mutex<LocalIotClient>>,
+    /// Probe of the client's event loop.
+    liveness: Liveness,
 }
 
 impl MqttMonitor {
     pub async fn instance() -> Arc<Self> {
+        Self::get().await.clone()
+    }
+
+    /// The instance itself, e.g. to run it as a [`Service`].
+    pub async fn get() -> &'static Arc<Self> {
         MQTT_MONITOR
             .get_or_init(|| async {
                 let version = env!("CARGO_PKG_VERSION");
+                let client = LocalIotClient::new(
+                    "launcher".to_string(),
+                    CFG.base.mqtt_host.to_string(),
+                    CFG.base.mqtt_port,
+                    CFG.base.health_report_interval,
+                    version.to_string(),
+                )
+                .with_client_id(DeviceIdentity::get(&CFG.base).client_id("launcher"));
 
                 Arc::new(Self {
                     services: Arc::new(RwLock::new(Services::new())),
                     vehicle: Arc::new(RwLock::new(VehicleState::default())),
-                    client: Arc::new(Mutex::new(LocalIotClient::new(
-                        "launcher".to_string(),
-                        CFG.base.mqtt_host.to_string(),
-                        CFG.base.mqtt_port,
-                        None,
-                        CFG.base.health_report_interval,
-                        version.to_string(),
-                    ))),
+                    updates: Arc::new(RwLock::new(BTreeMap::new())),
+                    liveness: client.liveness(),
+                    client: Arc::new(Mutex::new(client)),
                 })
             })
             .await
-            .clone()
     }
 
     pub async fn start(&self) -> Result<()> {
         info!("Starting MQTT monitor");
 
         let mut client = self.client.lock().await;
-        client.set_on_message(|topic, payload| {
-            tokio::spawn(Self::handle_message(topic, payload));
-        });
-
+        client.add_health_fields(version::health_fields);
+        client.add_health_fields(supervisor::health_fields);
         client.connect().await?;
         let timeout = Duration::from_secs(30);
         let start = std::time::Instant::now();
@@ -76,51 +87,143 @@ impl MqttMonitor {
         }
         info!("MQTT connected");
 
-        client.subscribe("luffy/+/health").await?;
-        client.subscribe("+/telemetry").await?;
+        let services = self.services.clone();
+        client
+            .on("luffy/+/health", move |message| {
+                let services = services.clone();
+                async move { Self::handle_health(&services, message).await }
+            })
+            .await?;
+        let services = self.services.clone();
+        client
+            .on("luffy/+/status", move |message| {
+                let services = services.clone();
+                async move { Self::handle_status(&services, message).await }
+            })
+            .await?;
+        for filter in ["+/telemetry", "+/telemetry/+"] {
+            let vehicle = self.vehicle.clone();
+            client
+                .on(filter, move |message| {
+                    let vehicle = vehicle.clone();
+                    async move { Self::handle_telemetry(&vehicle, message).await }
+                })
+                .await?;
+        }
+        let vehicle_id = DeviceIdentity::get(&CFG.base).vehicle_id.clone();
+        let updates = self.updates.clone();
+        client
+            .on(&update::status_topic(&vehicle_id, "+"), move |message| {
+                let updates = updates.clone();
+                async move { Self::handle_update_status(&updates, message).await }
+            })
+            .await?;
+        let publisher = client.client().context("MQTT client not connected")?;
+        let status_topic = update::status_topic(&vehicle_id, "services");
+        client
+            .serve(
+                &update::apply_topic(&vehicle_id),
+                move |call: UpdateCall| {
+                    let accepted =
+                        Self::apply_update(publisher.clone(), status_topic.clone(), call.version);
+                    async move { Ok(accepted) }
+                },
+            )
+            .await?;
+        log_filter::serve(
+            &client,
+            &DeviceIdentity::get(&CFG.base).vehicle_id,
+            "launcher",
+        )
+        .await?;
         Ok(())
     }
 
-    async fn handle_message(topic: String, payload: String) {
+    pub(crate) async fn handle_health(services: &RwLock<Services>, message: Message) -> Result<()> {
+        let service_name = message.topic.split('/').nth(1).unwrap_or("unknown");
+        let health: HealthReport = serde_json::from_slice(&message.payload)
+            .with_context(|| format!("Invalid health report: {}", message.text_lossy()))?;
+
         debug!(
-            "Monitor received message: topic={}, payload={}",
-            topic, payload
+            "Service {} is running with version {}",
+            service_name, health.version
         );
-        let instance = MQTT_MONITOR.get().unwrap();
-
-        if glob_match("luffy/+/health", &topic) {
-            let service_name = topic.split('/').nth(1).unwrap_or("unknown");
-
-            if let Ok(health) = serde_json::from_str::<HealthReport>(&payload) {
-                let mut services = instance.services.write().await;
-                let version = health.version.clone();
-                services.set_service(
-                    service_name,
-                    Some(ServiceStatus::Running),
-                    Some(version),
-                    None,
-                );
-                debug!(
-                    "Service {} is running with version {}",
-                    service_name, health.version
-                );
-            } else {
-                debug!("Failed to parse health report: {}", payload);
-            }
-        } else if glob_match("+/telemetry", &topic) {
-            // Handle telemetry data
-            if let Ok(telemetry) = serde_json::from_str::<TelemetryData>(&payload) {
-                let mut vehicle = instance.vehicle.write().await;
-                vehicle.location = telemetry.location;
-                vehicle.yaw_degree = telemetry.yaw_degree;
-                vehicle.battery_percentage = telemetry.battery_percentage;
-                vehicle.armed = telemetry.armed;
-                vehicle.flight_mode = telemetry.flight_mode;
-                debug!("Updated vehicle state from telemetry");
-            } else {
-                debug!("Failed to parse telemetry data: {}", payload);
+        let mut services = services.write().await;
+        services.set_service(
+            service_name,
+            Some(ServiceStatus::Running),
+            Some(health.version.clone()),
+            None,
+        );
+        services.set_health(service_name, health);
+        Ok(())
+    }
+
+    /// Retained presence, set to `offline` by the broker when a service's
+    /// connection drops and to `stopped` by a clean shutdown.
+    pub(crate) async fn handle_status(services: &RwLock<Services>, message: Message) -> Result<()> {
+        let service_name = message.topic.split('/').nth(1).unwrap_or("unknown");
+        let mut services = services.write().await;
+        match message.text() {
+            Some(STATUS_ONLINE) => {
+                services.set_service(service_name, Some(ServiceStatus::Running), None, None)
             }
+            Some(STATUS_OFFLINE) => services.set_stopped(service_name, false),
+            Some(STATUS_STOPPED) => services.set_stopped(service_name, true),
+            _ => anyhow::bail!("Invalid status: {}", message.text_lossy()),
         }
+        info!("Service {} is {}", service_name, message.text_lossy());
+        Ok(())
+    }
+
+    pub(crate) async fn handle_telemetry(
+        vehicle: &RwLock<VehicleState>,
+        message: Message,
+    ) -> Result<()> {
+        let (_, state) = telemetry::decode_message(&message.topic, &message.payload)?;
+        if state.schema_version > telemetry::SCHEMA_VERSION {
+            debug!(
+                "Telemetry schema {} is newer than {}, ignoring unknown fields",
+                state.schema_version,
+                telemetry::SCHEMA_VERSION
+            );
+        }
+        *vehicle.write().await = state;
+        debug!("Updated vehicle state from {}", message.topic);
+        Ok(())
+    }
+
+    pub(crate) async fn handle_update_status(
+        updates: &RwLock<BTreeMap<String, UpdateStatus>>,
+        message: Message,
+    ) -> Result<()> {
+        let target = message.topic.rsplit('/').next().unwrap_or("unknown");
+        let status: UpdateStatus =
+            serde_json::from_slice(&message.payload).context("Invalid update status")?;
+        info!("Update of {} is {:?}", target, status.state);
+        updates.write().await.insert(target.to_string(), status);
+        Ok(())
+    }
+
+    /// Update requested by the gateway, e.g. from an AWS IoT job. Runs in
+    /// the background; the gateway follows it on the status topic.
+    fn apply_update(
+        client: MqttClient,
+        status_topic: String,
+        version: Option<String>,
+    ) -> UpdateAccepted {
+        SERVICE_UPDATES.start(client, status_topic, |progress| async move {
+            info!(
+                "Applying update {} to {}",
+                progress.id(),
+                version.as_deref().unwrap_or("latest")
+            );
+            progress.step("updating-services").await;
+            VersionManager::new()
+                .update_to_version(version.as_deref())
+                .await
+                .context("Failed to apply update")
+        })
     }
 
     pub async fn get_services_snapshot(&self) -> Result<Services> {
@@ -129,8 +232,34 @@ impl MqttMonitor {
         Ok(services.clone())
     }
 
+    pub async fn get_updates_snapshot(&self) -> BTreeMap<String, UpdateStatus> {
+        self.updates.read().await.clone()
+    }
+
     pub async fn get_vehicle_snapshot(&self) -> Result<VehicleState> {
         let vehicle = self.vehicle.read().await;
         Ok(vehicle.clone())
     }
 }
+
+#[async_trait]
+impl Service for &'static MqttMonitor {
+    fn name(&self) -> &str {
+        "monitor"
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        MqttMonitor::start(self).await?;
+        CONFIG_MANAGER.start().await
+    }
+
+    async fn stop(&mut self) {
+        if let Err(e) = self.client.lock().await.disconnect().await {
+            error!("Failed to disconnect MQTT monitor: {}", e);
+        }
+    }
+
+    fn health(&self) -> Option<Arc<dyn HealthContributor>> {
+        Some(Arc::new(vec![self.liveness.clone()]))
+    }
+}
@@ -1,4 +1,3 @@
-use serde::Deserialize;
 use std::collections::HashMap;
 use std::time::{Duration, SystemTime};
 use tracing::info;
@@ -15,6 +14,13 @@ pub struct ServiceState {
     pub last_health_report: std::time::SystemTime,
     pub version: String,
     pub latest_version: Option<String>,
+    /// The last health report, with process metrics and service fields.
+    pub health: Option<HealthReport>,
+    /// Whether the service has been online since the launcher started.
+    /// Until then `last_health_report` is not from the service.
+    pub seen: bool,
+    /// Shut down cleanly rather than lost, e.g. by `systemctl stop`.
+    pub stopped_on_purpose: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -24,10 +30,7 @@ pub enum ServiceStatus {
     Stopped,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct HealthReport {
-    pub version: String,
-}
+pub use luffy_common::health::HealthReport;
 
 impl Services {
     pub fn new() -> Self {
@@ -60,10 +63,15 @@ impl Services {
             name, status, version, latest_version
         );
         let service_name = name.to_lowercase();
+        let running = matches!(status, Some(ServiceStatus::Running));
         if let Some(service) = self.services.get_mut(&service_name) {
             if let Some(status) = status {
                 service.status = status;
             }
+            if running {
+                service.seen = true;
+                service.stopped_on_purpose = false;
+            }
             if let Some(version) = version {
                 service.version = version;
             }
@@ -80,18 +88,37 @@ impl Services {
                     last_health_report: std::time::SystemTime::now(),
                     version: version.unwrap_or("Unknown".to_string()),
                     latest_version,
+                    health: None,
+                    seen: running,
+                    stopped_on_purpose: false,
                 },
             );
         }
     }
 
+    /// Mark a service stopped, `on_purpose` when it shut down cleanly.
+    pub fn set_stopped(&mut self, name: &str, on_purpose: bool) {
+        self.set_service(name, Some(ServiceStatus::Stopped), None, None);
+        if let Some(service) = self.services.get_mut(&name.to_lowercase()) {
+            service.stopped_on_purpose = on_purpose;
+        }
+    }
+
+    pub fn set_health(&mut self, name: &str, health: HealthReport) {
+        if let Some(service) = self.services.get_mut(&name.to_lowercase()) {
+            service.health = Some(health);
+        }
+    }
+
     pub fn get_service_status(&self, name: &str) -> ServiceStatus {
         if let Some(service) = self.services.get(name) {
             let elapsed = SystemTime::now()
                 .duration_since(service.last_health_report)
                 .unwrap_or(Duration::from_secs(61));
 
-            if elapsed.as_secs() > 60 {
+            // A stopped service sends no health reports, its status is
+            // final until it comes back online.
+            if elapsed.as_secs() > 60 && !matches!(service.status, ServiceStatus::Stopped) {
                 ServiceStatus::Unknown
             } else {
                 service.status.clone()
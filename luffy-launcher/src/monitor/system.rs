@@ -0,0 +1,349 @@
+use crate::config::{SystemMonitorConfig, SystemThresholds, CFG};
+use crate::monitor::mqtt::MQTT_MONITOR;
+use anyhow::Result;
+use luffy_common::forward_service;
+use luffy_common::identity::DeviceIdentity;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use sysinfo::{Disks, Networks, System};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, info, warn};
+
+pub static SYSTEM_MONITOR: LazyLock<SystemMonitor> = LazyLock::new(|| {
+    let mut disk_paths = vec![PathBuf::from("/")];
+    if let Some(dir) = &CFG.ota.download_dir {
+        disk_paths.push(PathBuf::from(dir));
+    }
+    SystemMonitor::new(CFG.system.clone(), disk_paths)
+});
+
+/// Host metrics published on `{vehicle_id}/system`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SystemSnapshot {
+    pub timestamp: u64,
+    pub cpu_percent: f32,
+    /// 1, 5 and 15 minute load averages.
+    pub load_average: [f64; 3],
+    pub memory_used_bytes: u64,
+    pub memory_total_bytes: u64,
+    pub disks: Vec<DiskUsage>,
+    pub temperatures: Vec<Temperature>,
+    pub networks: Vec<NetworkThroughput>,
+    pub default_interface: Option<String>,
+    pub alarms: Vec<Alarm>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiskUsage {
+    pub path: String,
+    pub used_bytes: u64,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Temperature {
+    pub zone: String,
+    pub celsius: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkThroughput {
+    pub interface: String,
+    pub rx_bytes_per_sec: f64,
+    pub tx_bytes_per_sec: f64,
+}
+
+/// A metric above its configured threshold.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alarm {
+    pub metric: String,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+impl std::fmt::Display for Alarm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at {:.1} (threshold {:.1})",
+            self.metric, self.value, self.threshold
+        )
+    }
+}
+
+fn percent(used: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        used as f64 * 100.0 / total as f64
+    }
+}
+
+impl SystemSnapshot {
+    pub fn memory_percent(&self) -> f64 {
+        percent(self.memory_used_bytes, self.memory_total_bytes)
+    }
+}
+
+impl DiskUsage {
+    pub fn percent(&self) -> f64 {
+        percent(self.used_bytes, self.total_bytes)
+    }
+}
+
+impl SystemThresholds {
+    pub fn check(&self, snapshot: &SystemSnapshot) -> Vec<Alarm> {
+        let mut alarms = Vec::new();
+        let mut check = |metric: String, value: f64, threshold: f64| {
+            if value >= threshold {
+                alarms.push(Alarm {
+                    metric,
+                    value,
+                    threshold,
+                });
+            }
+        };
+        check(
+            "cpu".to_string(),
+            snapshot.cpu_percent as f64,
+            self.cpu_percent,
+        );
+        check(
+            "memory".to_string(),
+            snapshot.memory_percent(),
+            self.memory_percent,
+        );
+        for disk in &snapshot.disks {
+            check(
+                format!("disk {}", disk.path),
+                disk.percent(),
+                self.disk_percent,
+            );
+        }
+        for temperature in &snapshot.temperatures {
+            check(
+                format!("temperature {}", temperature.zone),
+                temperature.celsius as f64,
+                self.temperature_celsius,
+            );
+        }
+        alarms
+    }
+}
+
+/// Temperatures of the kernel thermal zones under `root`, normally
+/// `/sys/class/thermal`.
+pub fn read_thermal_zones(root: &Path) -> Vec<Temperature> {
+    let Ok(entries) = std::fs::read_dir(root) else {
+        return Vec::new();
+    };
+    let mut zones: Vec<Temperature> = entries
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with("thermal_zone")
+        })
+        .filter_map(|entry| {
+            let path = entry.path();
+            let millis: f32 = std::fs::read_to_string(path.join("temp"))
+                .ok()?
+                .trim()
+                .parse()
+                .ok()?;
+            let zone = std::fs::read_to_string(path.join("type"))
+                .map(|zone| zone.trim().to_string())
+                .unwrap_or_else(|_| entry.file_name().to_string_lossy().to_string());
+            Some(Temperature {
+                zone,
+                celsius: millis / 1000.0,
+            })
+        })
+        .collect();
+    zones.sort_by(|a, b| a.zone.cmp(&b.zone));
+    zones
+}
+
+/// The interface of the default route in `/proc/net/route` format.
+pub fn parse_default_route(route_table: &str) -> Option<String> {
+    route_table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [interface, "00000000", ..] => Some(interface.to_string()),
+            _ => None,
+        }
+    })
+}
+
+/// Keeps the sysinfo state between samples; CPU usage and network
+/// throughput are measured over the time since the previous sample.
+struct SystemSampler {
+    system: System,
+    disks: Disks,
+    networks: Networks,
+    last_sample: Instant,
+    disk_paths: Vec<PathBuf>,
+}
+
+impl SystemSampler {
+    fn new(disk_paths: Vec<PathBuf>) -> Self {
+        let mut system = System::new();
+        system.refresh_cpu_usage();
+        Self {
+            system,
+            disks: Disks::new_with_refreshed_list(),
+            networks: Networks::new_with_refreshed_list(),
+            last_sample: Instant::now(),
+            disk_paths,
+        }
+    }
+
+    fn sample(&mut self) -> SystemSnapshot {
+        self.system.refresh_cpu_usage();
+        self.system.refresh_memory();
+        self.disks.refresh(true);
+        self.networks.refresh(true);
+        let elapsed = self.last_sample.elapsed().as_secs_f64().max(0.001);
+        self.last_sample = Instant::now();
+
+        let load = System::load_average();
+        let mut networks: Vec<NetworkThroughput> = self
+            .networks
+            .iter()
+            .filter(|(interface, _)| interface.as_str() != "lo")
+            .map(|(interface, data)| NetworkThroughput {
+                interface: interface.clone(),
+                rx_bytes_per_sec: data.received() as f64 / elapsed,
+                tx_bytes_per_sec: data.transmitted() as f64 / elapsed,
+            })
+            .collect();
+        networks.sort_by(|a, b| a.interface.cmp(&b.interface));
+
+        SystemSnapshot {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            cpu_percent: self.system.global_cpu_usage(),
+            load_average: [load.one, load.five, load.fifteen],
+            memory_used_bytes: self.system.used_memory(),
+            memory_total_bytes: self.system.total_memory(),
+            disks: self.disk_usage(),
+            temperatures: read_thermal_zones(Path::new("/sys/class/thermal")),
+            networks,
+            default_interface: std::fs::read_to_string("/proc/net/route")
+                .ok()
+                .and_then(|table| parse_default_route(&table)),
+            alarms: Vec::new(),
+        }
+    }
+
+    /// Usage of the filesystem holding each configured path.
+    fn disk_usage(&self) -> Vec<DiskUsage> {
+        self.disk_paths
+            .iter()
+            .filter_map(|path| {
+                let disk = self
+                    .disks
+                    .iter()
+                    .filter(|disk| path.starts_with(disk.mount_point()))
+                    .max_by_key(|disk| disk.mount_point().as_os_str().len())?;
+                Some(DiskUsage {
+                    path: path.display().to_string(),
+                    used_bytes: disk.total_space() - disk.available_space(),
+                    total_bytes: disk.total_space(),
+                })
+            })
+            .collect()
+    }
+}
+
+pub struct SystemMonitor {
+    config: SystemMonitorConfig,
+    sampler: Mutex<SystemSampler>,
+    latest: RwLock<Option<SystemSnapshot>>,
+    running: AtomicBool,
+}
+
+impl SystemMonitor {
+    /// Reports the usage of the filesystems holding `disk_paths`.
+    pub fn new(config: SystemMonitorConfig, disk_paths: Vec<PathBuf>) -> Self {
+        Self {
+            config,
+            sampler: Mutex::new(SystemSampler::new(disk_paths)),
+            latest: RwLock::new(None),
+            running: AtomicBool::new(false),
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        if !self.config.enable {
+            info!("System monitor disabled");
+            return Ok(());
+        }
+        info!(
+            "Starting system monitor, interval {}s",
+            self.config.interval
+        );
+        self.running.store(true, Ordering::SeqCst);
+        let topic = format!("{}/system", DeviceIdentity::get(&CFG.base).vehicle_id);
+        // A zero period panics.
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval.max(1)));
+
+        while self.running.load(Ordering::SeqCst) {
+            interval.tick().await;
+            let snapshot = self.sample().await;
+            if let Err(e) = Self::publish(&topic, &snapshot).await {
+                debug!("Failed to publish system metrics: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    /// Take a sample, log alarms that were not raised before and keep it as
+    /// the latest snapshot.
+    pub async fn sample(&self) -> SystemSnapshot {
+        let mut snapshot = self.sampler.lock().await.sample();
+        snapshot.alarms = self.config.thresholds.check(&snapshot);
+
+        let mut latest = self.latest.write().await;
+        let previous = latest
+            .as_ref()
+            .map(|latest| latest.alarms.as_slice())
+            .unwrap_or_default();
+        for alarm in &snapshot.alarms {
+            if !previous.iter().any(|old| old.metric == alarm.metric) {
+                warn!("System alarm: {}", alarm);
+            }
+        }
+        for alarm in previous {
+            if !snapshot.alarms.iter().any(|new| new.metric == alarm.metric) {
+                info!("System alarm cleared: {}", alarm.metric);
+            }
+        }
+        *latest = Some(snapshot.clone());
+        snapshot
+    }
+
+    pub async fn latest(&self) -> Option<SystemSnapshot> {
+        self.latest.read().await.clone()
+    }
+
+    async fn publish(topic: &str, snapshot: &SystemSnapshot) -> Result<()> {
+        let Some(monitor) = MQTT_MONITOR.get() else {
+            return Ok(());
+        };
+        let payload = serde_json::to_string(snapshot)?;
+        monitor.client.lock().await.publish(topic, &payload).await
+    }
+}
+
+forward_service!(&'static SystemMonitor, "system", runs_in_start);
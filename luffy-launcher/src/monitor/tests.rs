@@ -0,0 +1,352 @@
+use super::config_manager::is_healthy;
+use super::mqtt::MqttMonitor;
+use super::service::{HealthReport, ServiceState, ServiceStatus, Services};
+use super::system::{
+    parse_default_route, read_thermal_zones, DiskUsage, SystemSnapshot, Temperature,
+};
+use super::watchdog::{Action, Unhealthy, Watchdog};
+use crate::config::{SystemThresholds, WatchdogConfig};
+use luffy_common::iot::router::Message;
+use luffy_common::ota::update::UpdateState;
+use luffy_common::telemetry::{self, Encoding, VehicleState};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+use tokio::sync::RwLock;
+
+#[tokio::test]
+async fn test_health_report_marks_service_running() {
+    let services = RwLock::new(Services::new());
+    let message = Message::new(
+        "luffy/gateway/health",
+        r#"{"version":"0.5.1","pid":42,"memory_bytes":1048576,"connected":true,"fields":{"mavlink_rate":12.5}}"#,
+    );
+
+    MqttMonitor::handle_health(&services, message)
+        .await
+        .unwrap();
+
+    let services = services.read().await;
+    let gateway = &services.services["gateway"];
+    assert!(matches!(gateway.status, ServiceStatus::Running));
+    assert_eq!(gateway.version, "0.5.1");
+    let health = gateway.health.as_ref().unwrap();
+    assert_eq!(health.pid, 42);
+    assert!(health.connected);
+    assert_eq!(health.fields["mavlink_rate"], 12.5);
+}
+
+#[tokio::test]
+async fn test_invalid_health_report_is_rejected() {
+    let services = RwLock::new(Services::new());
+    let message = Message::new("luffy/gateway/health", "not json");
+
+    assert!(MqttMonitor::handle_health(&services, message)
+        .await
+        .is_err());
+    assert!(matches!(
+        services.read().await.services["gateway"].status,
+        ServiceStatus::Unknown
+    ));
+}
+
+#[tokio::test]
+async fn test_telemetry_updates_vehicle_state() {
+    let vehicle = RwLock::new(VehicleState::default());
+    let state = VehicleState {
+        battery_percentage: 42.0,
+        flight_mode: "ROVER_MODE_HOLD".to_string(),
+        ..Default::default()
+    };
+    let payload = telemetry::encode(&state, Encoding::Cbor).unwrap();
+
+    MqttMonitor::handle_telemetry(&vehicle, Message::new("vessel-1/telemetry/cbor", payload))
+        .await
+        .unwrap();
+
+    let vehicle = vehicle.read().await;
+    assert_eq!(vehicle.battery_percentage, 42.0);
+    assert_eq!(vehicle.flight_mode, "ROVER_MODE_HOLD");
+}
+
+#[tokio::test]
+async fn test_status_marks_service_stopped() {
+    let services = RwLock::new(Services::new());
+
+    MqttMonitor::handle_status(&services, Message::new("luffy/media/status", "online"))
+        .await
+        .unwrap();
+    assert!(matches!(
+        services.read().await.get_service_status("media"),
+        ServiceStatus::Running
+    ));
+
+    MqttMonitor::handle_status(&services, Message::new("luffy/media/status", "offline"))
+        .await
+        .unwrap();
+    let mut services = services.into_inner();
+    assert!(matches!(
+        services.get_service_status("media"),
+        ServiceStatus::Stopped
+    ));
+
+    // Stopped does not decay to Unknown like a missed health report.
+    services
+        .services
+        .get_mut("media")
+        .unwrap()
+        .last_health_report = SystemTime::now() - Duration::from_secs(120);
+    assert!(matches!(
+        services.get_service_status("media"),
+        ServiceStatus::Stopped
+    ));
+}
+
+#[tokio::test]
+async fn test_update_status_is_kept_per_target() {
+    let updates = RwLock::new(BTreeMap::new());
+    MqttMonitor::handle_update_status(
+        &updates,
+        Message::new(
+            "vessel-1/ota/status/launcher",
+            r#"{"id":"a","state":"running","step":"updating-launcher"}"#,
+        ),
+    )
+    .await
+    .unwrap();
+    MqttMonitor::handle_update_status(
+        &updates,
+        Message::new(
+            "vessel-1/ota/status/launcher",
+            r#"{"id":"a","state":"succeeded"}"#,
+        ),
+    )
+    .await
+    .unwrap();
+
+    let updates = updates.into_inner();
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates["launcher"].state, UpdateState::Succeeded);
+}
+
+#[test]
+fn test_parse_default_route() {
+    let table = "Iface\tDestination\tGateway\tFlags\tRefCnt\tUse\tMetric\tMask\n\
+                 wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\n\
+                 eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\n";
+    assert_eq!(parse_default_route(table), Some("eth0".to_string()));
+    assert_eq!(parse_default_route("Iface\tDestination\n"), None);
+}
+
+#[test]
+fn test_read_thermal_zones() {
+    let root = tempfile::tempdir().unwrap();
+    let zone = root.path().join("thermal_zone0");
+    std::fs::create_dir(&zone).unwrap();
+    std::fs::write(zone.join("temp"), "61500\n").unwrap();
+    std::fs::write(zone.join("type"), "cpu-thermal\n").unwrap();
+    std::fs::create_dir(root.path().join("cooling_device0")).unwrap();
+
+    let zones = read_thermal_zones(root.path());
+    assert_eq!(zones.len(), 1);
+    assert_eq!(zones[0].zone, "cpu-thermal");
+    assert_eq!(zones[0].celsius, 61.5);
+}
+
+#[test]
+fn test_threshold_alarms() {
+    let snapshot = SystemSnapshot {
+        cpu_percent: 20.0,
+        memory_used_bytes: 95,
+        memory_total_bytes: 100,
+        disks: vec![DiskUsage {
+            path: "/".to_string(),
+            used_bytes: 50,
+            total_bytes: 100,
+        }],
+        temperatures: vec![Temperature {
+            zone: "cpu-thermal".to_string(),
+            celsius: 85.0,
+        }],
+        ..Default::default()
+    };
+
+    let alarms = SystemThresholds::default().check(&snapshot);
+    let metrics: Vec<&str> = alarms.iter().map(|alarm| alarm.metric.as_str()).collect();
+    assert_eq!(metrics, vec!["memory", "temperature cpu-thermal"]);
+}
+
+fn watchdog() -> Watchdog {
+    let config = WatchdogConfig {
+        backoff_initial: 10,
+        backoff_max: 25,
+        crash_loop_limit: 3,
+        crash_loop_window: 600,
+        ..Default::default()
+    };
+    Watchdog::with_health_interval(config, Duration::from_secs(60))
+}
+
+fn gateway_state(last_health_report: SystemTime, fatal_error: Option<&str>) -> ServiceState {
+    ServiceState {
+        name: "gateway".to_string(),
+        status: ServiceStatus::Running,
+        last_health_report,
+        version: "0.5.0".to_string(),
+        latest_version: None,
+        health: Some(HealthReport {
+            fatal_error: fatal_error.map(str::to_string),
+            ..Default::default()
+        }),
+        seen: true,
+        stopped_on_purpose: false,
+    }
+}
+
+#[test]
+fn test_watchdog_detects_unhealthy_services() {
+    let watchdog = watchdog();
+    let now = SystemTime::now();
+
+    let healthy = gateway_state(now - Duration::from_secs(60), None);
+    assert_eq!(watchdog.check("gateway", &healthy, now), None);
+
+    let silent = gateway_state(now - Duration::from_secs(181), None);
+    assert!(matches!(
+        watchdog.check("gateway", &silent, now),
+        Some(Unhealthy::Silent(_))
+    ));
+    // Services without a policy are left alone.
+    assert_eq!(watchdog.check("launcher", &silent, now), None);
+
+    let fatal = gateway_state(now, Some("telemetry loop stopped"));
+    assert_eq!(
+        watchdog.check("gateway", &fatal, now),
+        Some(Unhealthy::Fatal("telemetry loop stopped".to_string()))
+    );
+
+    let _suspended = watchdog.suspend("Gateway");
+    assert_eq!(watchdog.check("gateway", &silent, now), None);
+}
+
+#[test]
+fn test_watchdog_backoff_and_crash_loop_limit() {
+    let watchdog = watchdog();
+    let start = SystemTime::now();
+    let at = |secs| start + Duration::from_secs(secs);
+
+    assert_eq!(
+        watchdog.decide("gateway", at(0)),
+        Action::Restart {
+            attempt: 1,
+            backoff: Duration::from_secs(10)
+        }
+    );
+    assert_eq!(watchdog.decide("gateway", at(5)), Action::Wait);
+    assert_eq!(
+        watchdog.decide("gateway", at(10)),
+        Action::Restart {
+            attempt: 2,
+            backoff: Duration::from_secs(20)
+        }
+    );
+    assert_eq!(
+        watchdog.decide("gateway", at(30)),
+        Action::Restart {
+            attempt: 3,
+            backoff: Duration::from_secs(25)
+        }
+    );
+    assert_eq!(watchdog.decide("gateway", at(55)), Action::GiveUp);
+    assert_eq!(watchdog.decide("gateway", at(100)), Action::None);
+
+    assert!(watchdog.recovered("gateway"));
+    assert!(!watchdog.recovered("gateway"));
+    assert!(matches!(
+        watchdog.decide("gateway", at(200)),
+        Action::Restart { attempt: 1, .. }
+    ));
+}
+
+#[test]
+fn test_watchdog_ignores_fatal_report_from_before_restart() {
+    let watchdog = watchdog();
+    let now = SystemTime::now();
+    let fatal = gateway_state(now, Some("boom"));
+
+    assert!(matches!(
+        watchdog.decide("gateway", now + Duration::from_secs(1)),
+        Action::Restart { .. }
+    ));
+    assert_eq!(
+        watchdog.check("gateway", &fatal, now + Duration::from_secs(2)),
+        None
+    );
+}
+
+#[test]
+fn test_watchdog_never_seen_and_stopped_services() {
+    let watchdog = watchdog();
+    let now = SystemTime::now();
+    watchdog.set_started(now - Duration::from_secs(100));
+
+    // Silent since the watchdog started, not since the entry was made.
+    let mut never_seen = gateway_state(now - Duration::from_secs(1000), None);
+    never_seen.seen = false;
+    assert_eq!(watchdog.check("gateway", &never_seen, now), None);
+    assert!(matches!(
+        watchdog.check("gateway", &never_seen, now + Duration::from_secs(81)),
+        Some(Unhealthy::Silent(_))
+    ));
+
+    // A restarted service gets the full time to report again.
+    let restart = now + Duration::from_secs(81);
+    assert!(matches!(
+        watchdog.decide("gateway", restart),
+        Action::Restart { .. }
+    ));
+    assert_eq!(
+        watchdog.check("gateway", &never_seen, restart + Duration::from_secs(60)),
+        None
+    );
+
+    let mut stopped = gateway_state(now - Duration::from_secs(1000), None);
+    stopped.status = ServiceStatus::Stopped;
+    stopped.stopped_on_purpose = true;
+    assert_eq!(watchdog.check("gateway", &stopped, now), None);
+}
+
+#[tokio::test]
+async fn test_clean_shutdown_is_stopped_on_purpose() {
+    let services = RwLock::new(Services::new());
+    MqttMonitor::handle_status(&services, Message::new("luffy/media/status", "stopped"))
+        .await
+        .unwrap();
+    assert!(services.read().await.services["media"].stopped_on_purpose);
+
+    MqttMonitor::handle_status(&services, Message::new("luffy/media/status", "online"))
+        .await
+        .unwrap();
+    let services = services.into_inner();
+    assert!(services.services["media"].seen);
+    assert!(!services.services["media"].stopped_on_purpose);
+}
+
+#[test]
+fn test_config_change_waits_for_report_from_restarted_service() {
+    let restarted = SystemTime::now();
+    let now = restarted + Duration::from_secs(20);
+
+    let mut state = gateway_state(restarted - Duration::from_secs(5), None);
+    assert!(!is_healthy(&state, restarted, now));
+
+    // A report from the old process, which has been up for longer.
+    state.last_health_report = restarted + Duration::from_secs(1);
+    state.health.as_mut().unwrap().uptime_secs = 3600;
+    assert!(!is_healthy(&state, restarted, now));
+
+    state.health.as_mut().unwrap().uptime_secs = 15;
+    assert!(is_healthy(&state, restarted, now));
+
+    let fatal = gateway_state(restarted + Duration::from_secs(10), Some("no camera"));
+    assert!(!is_healthy(&fatal, restarted, now));
+}
@@ -0,0 +1,353 @@
+use crate::config::{ServicePolicy, WatchdogConfig, CFG};
+use crate::monitor::mqtt::{MqttMonitor, MQTT_MONITOR};
+use crate::monitor::service::ServiceState;
+use anyhow::Result;
+use luffy_common::forward_service;
+use luffy_common::identity::DeviceIdentity;
+use luffy_common::ota::deb::DebManager;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, error, info, warn};
+
+pub static WATCHDOG: LazyLock<Watchdog> = LazyLock::new(|| Watchdog::new(CFG.watchdog.clone()));
+
+/// Why a service needs a restart.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Unhealthy {
+    /// No health report for longer than the policy allows.
+    Silent(Duration),
+    /// The service reported a fatal error.
+    Fatal(String),
+}
+
+impl std::fmt::Display for Unhealthy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Unhealthy::Silent(elapsed) => write!(f, "no health report for {}s", elapsed.as_secs()),
+            Unhealthy::Fatal(error) => write!(f, "fatal error: {}", error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Restart {
+        attempt: usize,
+        backoff: Duration,
+    },
+    /// Waiting for the backoff of the previous restart.
+    Wait,
+    /// Crash loop limit reached; only reported once.
+    GiveUp,
+    /// Already gave up, nothing left to do until the service recovers.
+    None,
+}
+
+/// Published on `{vehicle_id}/watchdog` for each action taken.
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchdogEvent {
+    pub service: String,
+    pub action: String,
+    pub reason: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attempt: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Restart bookkeeping of one service.
+#[derive(Debug, Default)]
+struct Supervision {
+    restarts: VecDeque<SystemTime>,
+    backoff: Option<Duration>,
+    next_restart: Option<SystemTime>,
+    gave_up: bool,
+}
+
+impl Supervision {
+    fn last_restart(&self) -> Option<SystemTime> {
+        self.restarts.back().copied()
+    }
+}
+
+pub struct Watchdog {
+    config: WatchdogConfig,
+    health_interval: Duration,
+    supervision: Mutex<HashMap<String, Supervision>>,
+    suspended: Mutex<HashSet<String>>,
+    /// When the watchdog started, the grace period of services never seen.
+    started: Mutex<Option<SystemTime>>,
+    running: AtomicBool,
+}
+
+/// Keeps the watchdog off a service while it is stopped on purpose, e.g.
+/// during an update. Resumes on drop.
+pub struct SuspendGuard<'a> {
+    watchdog: &'a Watchdog,
+    service: String,
+}
+
+impl Drop for SuspendGuard<'_> {
+    fn drop(&mut self) {
+        self.watchdog
+            .suspended
+            .lock()
+            .unwrap()
+            .remove(&self.service);
+    }
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        Self::with_health_interval(config, Duration::from_secs(CFG.base.health_report_interval))
+    }
+
+    pub fn with_health_interval(config: WatchdogConfig, health_interval: Duration) -> Self {
+        Self {
+            config,
+            health_interval,
+            supervision: Mutex::new(HashMap::new()),
+            suspended: Mutex::new(HashSet::new()),
+            started: Mutex::new(None),
+            running: AtomicBool::new(false),
+        }
+    }
+
+    pub fn suspend(&self, service: &str) -> SuspendGuard<'_> {
+        let service = service.to_lowercase();
+        self.suspended.lock().unwrap().insert(service.clone());
+        SuspendGuard {
+            watchdog: self,
+            service,
+        }
+    }
+
+    pub(crate) fn set_started(&self, at: SystemTime) {
+        *self.started.lock().unwrap() = Some(at);
+    }
+
+    fn policy(&self, service: &str) -> Option<&ServicePolicy> {
+        self.config
+            .services
+            .get(service)
+            .filter(|policy| policy.enable)
+    }
+
+    /// Whether `state` calls for a restart under the service's policy.
+    pub fn check(&self, name: &str, state: &ServiceState, now: SystemTime) -> Option<Unhealthy> {
+        let policy = self.policy(name)?;
+        if state.stopped_on_purpose || self.suspended.lock().unwrap().contains(name) {
+            return None;
+        }
+        let last_restart = self
+            .supervision
+            .lock()
+            .unwrap()
+            .get(name)
+            .and_then(Supervision::last_restart);
+
+        // Reports from before the last restart are stale.
+        let fresh_report = last_restart.is_none_or(|restart| state.last_health_report > restart);
+        if policy.restart_on_fatal && fresh_report {
+            if let Some(error) = state
+                .health
+                .as_ref()
+                .and_then(|health| health.fatal_error.clone())
+            {
+                return Some(Unhealthy::Fatal(error));
+            }
+        }
+
+        // A service never seen is silent since the watchdog started, and a
+        // restarted one gets the same time to report again.
+        let last_report = state.seen.then_some(state.last_health_report);
+        let since = [last_report, last_restart, *self.started.lock().unwrap()]
+            .into_iter()
+            .flatten()
+            .max()
+            .unwrap_or(state.last_health_report);
+        let silent = now.duration_since(since).unwrap_or_default();
+        if silent > self.health_interval * policy.missed_reports {
+            return Some(Unhealthy::Silent(silent));
+        }
+        None
+    }
+
+    /// Decide what to do about an unhealthy service, applying backoff and the
+    /// crash loop limit. Records a restart when returning [`Action::Restart`].
+    pub fn decide(&self, name: &str, now: SystemTime) -> Action {
+        let mut supervision = self.supervision.lock().unwrap();
+        let entry = supervision.entry(name.to_string()).or_default();
+        if entry.gave_up {
+            return Action::None;
+        }
+        if entry.next_restart.is_some_and(|next| now < next) {
+            return Action::Wait;
+        }
+
+        let window = Duration::from_secs(self.config.crash_loop_window);
+        while entry
+            .restarts
+            .front()
+            .is_some_and(|restart| now.duration_since(*restart).unwrap_or_default() > window)
+        {
+            entry.restarts.pop_front();
+        }
+        if entry.restarts.len() >= self.config.crash_loop_limit {
+            entry.gave_up = true;
+            return Action::GiveUp;
+        }
+
+        let backoff = entry
+            .backoff
+            .map(|backoff| (backoff * 2).min(Duration::from_secs(self.config.backoff_max)))
+            .unwrap_or(Duration::from_secs(self.config.backoff_initial));
+        entry.backoff = Some(backoff);
+        entry.next_restart = Some(now + backoff);
+        entry.restarts.push_back(now);
+        Action::Restart {
+            attempt: entry.restarts.len(),
+            backoff,
+        }
+    }
+
+    /// Forget backoff and crash loop state once a service is healthy again.
+    /// Returns whether it had been restarted.
+    pub fn recovered(&self, name: &str) -> bool {
+        let mut supervision = self.supervision.lock().unwrap();
+        match supervision.get(name) {
+            Some(entry) if entry.backoff.is_some() || entry.gave_up => {
+                supervision.remove(name);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        if !self.config.enable {
+            info!("Watchdog disabled");
+            return Ok(());
+        }
+        info!("Starting watchdog for {:?}", self.config.services.keys());
+        self.running.store(true, Ordering::SeqCst);
+        self.set_started(SystemTime::now());
+        let topic = format!("{}/watchdog", DeviceIdentity::get(&CFG.base).vehicle_id);
+        let deb_manager = DebManager::new(PathBuf::from(
+            CFG.ota
+                .download_dir
+                .clone()
+                .unwrap_or("/home/luffy/.deb".to_string()),
+        ));
+        // A zero period panics.
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.config.check_interval.max(1)));
+
+        while self.running.load(Ordering::SeqCst) {
+            interval.tick().await;
+            let services = match MqttMonitor::instance().await.get_services_snapshot().await {
+                Ok(services) => services,
+                Err(e) => {
+                    warn!("Watchdog: failed to read service states: {:#}", e);
+                    continue;
+                }
+            };
+            for (name, state) in &services.services {
+                self.supervise(name, state, &deb_manager, &topic).await;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    async fn supervise(
+        &self,
+        name: &str,
+        state: &ServiceState,
+        deb_manager: &DebManager,
+        topic: &str,
+    ) {
+        let now = SystemTime::now();
+        let Some(reason) = self.check(name, state, now) else {
+            if self.recovered(name) {
+                info!("Watchdog: {} recovered", name);
+                Self::publish(topic, Self::event(name, "recovered", "", None, None)).await;
+            }
+            return;
+        };
+
+        match self.decide(name, now) {
+            Action::Restart { attempt, backoff } => {
+                warn!(
+                    "Watchdog: restarting {} ({}), attempt {}, next in {}s",
+                    name,
+                    reason,
+                    attempt,
+                    backoff.as_secs()
+                );
+                let service_type = deb_manager.get_service_type(&format!("luffy-{}", name));
+                let error = deb_manager
+                    .restart_service(&service_type)
+                    .await
+                    .err()
+                    .map(|e| {
+                        error!("Watchdog: failed to restart {}: {}", name, e);
+                        e.to_string()
+                    });
+                let event = Self::event(name, "restart", &reason.to_string(), Some(attempt), error);
+                Self::publish(topic, event).await;
+            }
+            Action::GiveUp => {
+                error!(
+                    "Watchdog: {} is crash looping ({}), giving up after {} restarts",
+                    name, reason, self.config.crash_loop_limit
+                );
+                Self::publish(
+                    topic,
+                    Self::event(name, "give_up", &reason.to_string(), None, None),
+                )
+                .await;
+            }
+            Action::Wait => debug!("Watchdog: {} unhealthy ({}), backing off", name, reason),
+            Action::None => {}
+        }
+    }
+
+    fn event(
+        service: &str,
+        action: &str,
+        reason: &str,
+        attempt: Option<usize>,
+        error: Option<String>,
+    ) -> WatchdogEvent {
+        WatchdogEvent {
+            service: service.to_string(),
+            action: action.to_string(),
+            reason: reason.to_string(),
+            attempt,
+            error,
+        }
+    }
+
+    async fn publish(topic: &str, event: WatchdogEvent) {
+        let Some(monitor) = MQTT_MONITOR.get() else {
+            return;
+        };
+        let result = match serde_json::to_string(&event) {
+            Ok(payload) => monitor.client.lock().await.publish(topic, &payload).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            warn!("Failed to publish watchdog event: {}", e);
+        }
+    }
+}
+
+forward_service!(&'static Watchdog, "watchdog", runs_in_start);
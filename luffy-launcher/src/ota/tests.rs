@@ -11,7 +11,7 @@ mod ota_tests {
     fn init() {
         env::set_var("RUST_ENV", "dev");
 
-        luffy_common::util::setup_logging("debug", "luffy-launcher");
+        luffy_common::util::setup_logging("debug", "luffy-launcher", &CFG.base);
     }
 
     #[tokio::test]
@@ -1,6 +1,8 @@
 use crate::config::CFG;
 use crate::monitor::mqtt::MQTT_MONITOR;
+use crate::monitor::watchdog::WATCHDOG;
 use anyhow::{anyhow, Result};
+use luffy_common::forward_service;
 use luffy_common::ota::deb::ServiceType;
 use luffy_common::ota::version::BaseVersionManager;
 use std::collections::HashMap;
@@ -49,6 +51,14 @@ impl VersionManager {
         self.update_package(service_packages).await
     }
 
+    /// Install gateway and media from a release, the latest one when `version`
+    /// is `None`. Requested by the gateway when it runs an update job.
+    pub async fn update_to_version(&self, version: Option<&str>) -> Result<()> {
+        let (tag, packages) = self.base.get_release(version).await?;
+        info!("Updating services to release {}", tag);
+        self.update_package(packages).await
+    }
+
     pub async fn update_package(&self, packages: Vec<(String, String)>) -> Result<()> {
         let service_packages: Vec<(String, String)> = packages
             .into_iter()
@@ -87,6 +97,8 @@ impl VersionManager {
         }
 
         for (service_type, packages) in &updates_by_service {
+            // The service is stopped while its packages are installed.
+            let _suspended = WATCHDOG.suspend(&service_type.to_string());
             if let Err(e) = self
                 .base
                 .update_service_packages(service_type, packages)
@@ -161,83 +173,85 @@ impl VersionManager {
         }
     }
 
-    pub fn stop(&self) {
+    pub async fn stop(&self) {
         self.running
             .store(false, std::sync::atomic::Ordering::Relaxed);
     }
 
+    /// Check for updates every `check_interval` with the strategy from the
+    /// live config, so changing it takes effect at the next check.
     pub async fn start(&self) -> Result<()> {
         let mut interval = tokio::time::interval(self.base.check_interval);
-        let manager = self.clone();
+        let mut manager = self.clone();
 
         self.running
             .store(true, std::sync::atomic::Ordering::Relaxed);
+        info!(
+            "Starting {} update checks with interval: {:?}",
+            manager.base.strategy, self.base.check_interval
+        );
 
-        match self.base.strategy.as_str() {
-            "auto" => {
-                info!(
-                    "Starting auto update task with interval: {:?}",
-                    self.base.check_interval
-                );
-
-                while self.running.load(std::sync::atomic::Ordering::Relaxed) {
-                    interval.tick().await;
+        let mut config = CFG.watch();
+        while self.running.load(std::sync::atomic::Ordering::Relaxed) {
+            interval.tick().await;
+            let strategy = config.borrow_and_update().ota.strategy.clone();
+            if strategy != manager.base.strategy {
+                info!("Update strategy changed to {}", strategy);
+                manager.base.strategy = strategy;
+            }
+            match manager.base.strategy.as_str() {
+                "auto" => {
                     if let Err(e) = manager.check_and_apply_updates().await {
                         warn!("Auto update check failed: {}", e);
                     }
                 }
-                Ok(())
-            }
-            "manual" => {
-                info!(
-                    "Starting manual update check with interval: {:?}",
-                    self.base.check_interval
-                );
-
-                while self.running.load(std::sync::atomic::Ordering::Relaxed) {
-                    interval.tick().await;
-                    match manager.check_updates().await {
-                        Ok(updates) => {
-                            if !updates.is_empty() {
-                                let update_info: Vec<_> = updates
-                                    .iter()
-                                    .filter_map(|(filename, _)| {
-                                        let package_name = filename.split('_').next()?;
-                                        let new_version = self
-                                            .base
-                                            .deb_manager
-                                            .extract_package_version(filename)?;
-                                        let current_version = self
-                                            .base
-                                            .deb_manager
-                                            .get_package_version(package_name)
-                                            .ok()?;
-                                        Some((package_name, current_version, new_version))
-                                    })
-                                    .collect();
-
-                                info!(
-                                    "Updates available: {}",
-                                    update_info
-                                        .iter()
-                                        .map(|(pkg, curr, new)| format!(
-                                            "{}: {} -> {}",
-                                            pkg, curr, new
-                                        ))
-                                        .collect::<Vec<_>>()
-                                        .join(", ")
-                                );
-                            }
-                        }
-                        Err(e) => warn!("Manual update check failed: {}", e),
+                "manual" => manager.report_updates().await,
+                _ => {
+                    info!("Updates are disabled");
+                    // Nothing to check until the strategy changes, and it
+                    // never does when the config is not watched.
+                    if config.changed().await.is_err() {
+                        return Ok(());
                     }
+                    interval.reset_immediately();
                 }
-                Ok(())
             }
-            _ => {
-                info!("Updates are disabled");
-                Ok(())
+        }
+        Ok(())
+    }
+
+    async fn report_updates(&self) {
+        match self.check_updates().await {
+            Ok(updates) => {
+                if !updates.is_empty() {
+                    let update_info: Vec<_> = updates
+                        .iter()
+                        .filter_map(|(filename, _)| {
+                            let package_name = filename.split('_').next()?;
+                            let new_version =
+                                self.base.deb_manager.extract_package_version(filename)?;
+                            let current_version = self
+                                .base
+                                .deb_manager
+                                .get_package_version(package_name)
+                                .ok()?;
+                            Some((package_name, current_version, new_version))
+                        })
+                        .collect();
+
+                    info!(
+                        "Updates available: {}",
+                        update_info
+                            .iter()
+                            .map(|(pkg, curr, new)| format!("{}: {} -> {}", pkg, curr, new))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                }
             }
+            Err(e) => warn!("Manual update check failed: {}", e),
         }
     }
 }
+
+forward_service!(VersionManager, "ota", runs_in_start);
@@ -1,24 +1,31 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use askama::Template;
 use axum::{
     http::StatusCode,
-    response::{Html, IntoResponse},
+    response::{Html, IntoResponse, Response},
     routing::get,
     routing::post,
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
 use std::env;
-use tracing::info;
+use tracing::{error, info, info_span, Instrument};
 
 use std::time::{Duration, SystemTime};
 
 use crate::{
     config::CFG,
-    monitor::{mqtt::MqttMonitor, service::ServiceStatus, vehicle::VehicleState},
+    monitor::{
+        mqtt::MqttMonitor,
+        service::{HealthReport, ServiceStatus},
+        system::{SystemSnapshot, SYSTEM_MONITOR},
+    },
 };
 use crate::{monitor::mqtt::MQTT_MONITOR, ota::version::VersionManager};
-use luffy_common::util;
+use luffy_common::identity::DeviceIdentity;
+use luffy_common::iot::rpc;
+use luffy_common::ota::update::{UpdateAccepted, UpdateCall};
+use luffy_common::telemetry::VehicleState;
 
 use semver::Version;
 
@@ -38,6 +45,10 @@ pub struct StatusViewModel {
 
     // Services
     pub services: Vec<ServiceStatusViewModel>,
+
+    // Host metrics
+    pub system: Vec<HealthItemViewModel>,
+    pub system_alarms: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -48,6 +59,134 @@ pub struct ServiceStatusViewModel {
     pub version: String,
     pub update_available: bool,
     pub available_version: String,
+    /// Process metrics and service fields from the last health report.
+    pub health: Vec<HealthItemViewModel>,
+    pub last_error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthItemViewModel {
+    pub label: String,
+    pub value: String,
+}
+
+impl HealthItemViewModel {
+    fn new(label: &str, value: impl ToString) -> Self {
+        Self {
+            label: label.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn from_report(report: &HealthReport) -> Vec<Self> {
+        let mut items = vec![
+            Self::new("Uptime", format_duration(report.uptime_secs)),
+            Self::new("Memory", format_bytes(report.memory_bytes)),
+            Self::new("CPU", format!("{:.1}%", report.cpu_percent)),
+            Self::new("PID", report.pid),
+            Self::new(
+                "MQTT",
+                if report.connected {
+                    "connected"
+                } else {
+                    "disconnected"
+                },
+            ),
+        ];
+        items.extend(report.fields.iter().map(|(key, value)| {
+            let value = match value {
+                serde_json::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            Self::new(key, value)
+        }));
+        items
+    }
+
+    fn from_system(system: &SystemSnapshot) -> Vec<Self> {
+        let mut items = vec![
+            Self::new(
+                "CPU",
+                format!(
+                    "{:.1}% (load {:.2} {:.2} {:.2})",
+                    system.cpu_percent,
+                    system.load_average[0],
+                    system.load_average[1],
+                    system.load_average[2]
+                ),
+            ),
+            Self::new(
+                "Memory",
+                format!(
+                    "{} / {} ({:.0}%)",
+                    format_bytes(system.memory_used_bytes),
+                    format_bytes(system.memory_total_bytes),
+                    system.memory_percent()
+                ),
+            ),
+        ];
+        items.extend(system.disks.iter().map(|disk| {
+            Self::new(
+                &format!("Disk {}", disk.path),
+                format!(
+                    "{} / {} ({:.0}%)",
+                    format_bytes(disk.used_bytes),
+                    format_bytes(disk.total_bytes),
+                    disk.percent()
+                ),
+            )
+        }));
+        items.extend(system.temperatures.iter().map(|temperature| {
+            Self::new(
+                &format!("Temp {}", temperature.zone),
+                format!("{:.1}°C", temperature.celsius),
+            )
+        }));
+        items.extend(system.networks.iter().map(|network| {
+            let label = match &system.default_interface {
+                Some(default) if default == &network.interface => {
+                    format!("Net {} (default)", network.interface)
+                }
+                _ => format!("Net {}", network.interface),
+            };
+            Self::new(
+                &label,
+                format!(
+                    "↓ {}/s ↑ {}/s",
+                    format_bytes(network.rx_bytes_per_sec as u64),
+                    format_bytes(network.tx_bytes_per_sec as u64)
+                ),
+            )
+        }));
+        items
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m", secs / 60)
+    } else if secs < 86400 {
+        format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+    } else {
+        format!("{}d {}h", secs / 86400, secs % 86400 / 3600)
+    }
 }
 
 // Template
@@ -61,31 +200,46 @@ impl From<VehicleState> for StatusViewModel {
     fn from(state: VehicleState) -> Self {
         Self {
             version: env!("CARGO_PKG_VERSION").to_string(),
-            vehicle_id: util::get_vehicle_id(&CFG.base),
+            vehicle_id: DeviceIdentity::get(&CFG.base).vehicle_id.clone(),
             location: format!("{:.6}, {:.6}", state.location.0, state.location.1),
             yaw: state.yaw_degree,
             battery: state.battery_percentage,
             armed: state.armed,
             flight_mode: state.flight_mode,
             services: Vec::new(),
+            system: Vec::new(),
+            system_alarms: Vec::new(),
         }
     }
 }
 // Implementation
 impl StatusViewModel {
     async fn new() -> Self {
-        let (state, services_view) =
-            tokio::join!(Self::get_vehicle_state(), Self::get_services_state());
+        let (state, services_view, system) = tokio::join!(
+            Self::get_vehicle_state(),
+            Self::get_services_state(),
+            SYSTEM_MONITOR.latest()
+        );
+        let system_alarms = system
+            .as_ref()
+            .map(|system| system.alarms.iter().map(ToString::to_string).collect())
+            .unwrap_or_default();
+        let system = system
+            .as_ref()
+            .map(HealthItemViewModel::from_system)
+            .unwrap_or_default();
 
         Self {
             version: env!("CARGO_PKG_VERSION").to_string(),
-            vehicle_id: util::get_vehicle_id(&CFG.base),
+            vehicle_id: DeviceIdentity::get(&CFG.base).vehicle_id.clone(),
             location: format!("{:.6}, {:.6}", state.location.0, state.location.1),
             yaw: state.yaw_degree,
             battery: state.battery_percentage,
             armed: state.armed,
             flight_mode: state.flight_mode,
             services: services_view,
+            system,
+            system_alarms,
         }
     }
 
@@ -104,16 +258,20 @@ impl StatusViewModel {
             .iter()
             .map(|&name| {
                 let state = services.services.get(name);
+                let health = state
+                    .and_then(|state| state.health.as_ref())
+                    .map(HealthItemViewModel::from_report)
+                    .unwrap_or_default();
+                let last_error = state
+                    .and_then(|state| state.health.as_ref())
+                    .and_then(|health| health.last_error.clone())
+                    .unwrap_or_default();
                 let (status, last_report, version, latest_version) = if let Some(state) = state {
                     let elapsed = SystemTime::now()
                         .duration_since(state.last_health_report)
                         .unwrap_or(Duration::from_secs(0));
 
-                    let status = if elapsed.as_secs() > 60 {
-                        ServiceStatus::Unknown
-                    } else {
-                        state.status.clone()
-                    };
+                    let status = services.get_service_status(name);
 
                     let time_str = if elapsed.as_secs() < 60 {
                         format!("{}s", elapsed.as_secs())
@@ -150,6 +308,8 @@ impl StatusViewModel {
                     version,
                     update_available,
                     available_version: latest_version,
+                    health,
+                    last_error,
                 }
             })
             .collect()
@@ -173,6 +333,7 @@ pub async fn routes() -> Router {
         .route("/", get(index_page))
         .route("/api/status", get(status_api))
         .route("/api/update", post(update_service))
+        .route("/api/update/status", get(update_status_api))
 }
 
 async fn index_page() -> impl IntoResponse {
@@ -195,32 +356,54 @@ async fn status_api() -> impl IntoResponse {
     Json(status)
 }
 
-async fn update_service(Json(payload): Json<UpdateRequest>) -> impl IntoResponse {
+async fn update_service(Json(payload): Json<UpdateRequest>) -> Response {
     info!("Updating service {:?}", payload);
     let version_manager = VersionManager::new();
     let service = payload.service.to_lowercase();
     if service == "launcher" {
-        send_update_request().await.unwrap();
-        return StatusCode::OK;
+        return match send_update_request()
+            .instrument(info_span!("ota.update_request"))
+            .await
+        {
+            Ok(accepted) => (StatusCode::ACCEPTED, Json(accepted)).into_response(),
+            Err(e) => {
+                error!("Launcher update request failed: {:#}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        };
     }
     match version_manager.manual_update(&payload.service).await {
-        Ok(_) => StatusCode::OK,
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        Ok(_) => StatusCode::OK.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
 
-async fn send_update_request() -> Result<()> {
+/// The last reported status of the launcher and services updates.
+async fn update_status_api() -> impl IntoResponse {
+    Json(MqttMonitor::instance().await.get_updates_snapshot().await)
+}
+
+/// Ask the gateway to update the launcher. Returns once the gateway has
+/// started; its progress shows in [`update_status_api`].
+async fn send_update_request() -> Result<UpdateAccepted> {
     info!("Sending update request");
-    let vehicle_id = util::get_vehicle_id(&CFG.base);
-    let monitor = MQTT_MONITOR.get().unwrap();
-    let mqtt_client = monitor.client.lock().await;
-    mqtt_client
-        .publish(&format!("{}/ota/request", vehicle_id), "update")
-        .await
+    let vehicle_id = &DeviceIdentity::get(&CFG.base).vehicle_id;
+    let monitor = MQTT_MONITOR
+        .get()
+        .ok_or_else(|| anyhow!("MQTT monitor not started"))?;
+    let mqtt_client = monitor.client.lock().await.clone();
+    let accepted: UpdateAccepted = mqtt_client
+        .call(
+            &format!("{}/rpc/ota/update", vehicle_id),
+            &UpdateCall::default(),
+            rpc::DEFAULT_TIMEOUT,
+        )
+        .await?;
+    info!("Launcher update {} started", accepted.id);
+    Ok(accepted)
 }
 
 #[derive(Deserialize, Debug)]
 struct UpdateRequest {
     service: String,
-    version: String,
 }
@@ -0,0 +1,95 @@
+use anyhow::{anyhow, Result};
+use askama::Template;
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse},
+    routing::get,
+    Json, Router,
+};
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::config::CFG;
+use crate::monitor::config_manager::SERVICES;
+use crate::monitor::mqtt::MQTT_MONITOR;
+use luffy_common::identity::DeviceIdentity;
+use luffy_common::log_filter::{self, LogFilterChange, LogFilterStatus, LOG_FILTER};
+
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The filter of one service, or why it could not be read.
+#[derive(Debug, Serialize)]
+struct ServiceFilter {
+    service: String,
+    #[serde(flatten)]
+    status: Option<LogFilterStatus>,
+    error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "logs.html")]
+struct LogPage {
+    vehicle_id: String,
+    services: Vec<&'static str>,
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/logs", get(log_page))
+        .route("/api/log-filter", get(filters_api).post(change_filter))
+}
+
+async fn log_page() -> impl IntoResponse {
+    let template = LogPage {
+        vehicle_id: DeviceIdentity::get(&CFG.base).vehicle_id.clone(),
+        services: SERVICES.to_vec(),
+    };
+    Html(template.render().unwrap())
+}
+
+async fn filters_api() -> impl IntoResponse {
+    let mut filters = Vec::new();
+    for service in SERVICES {
+        let (status, error) = match apply(service, LogFilterChange::default()).await {
+            Ok(status) => (Some(status), None),
+            Err(e) => (None, Some(format!("{:#}", e))),
+        };
+        filters.push(ServiceFilter {
+            service: service.to_string(),
+            status,
+            error,
+        });
+    }
+    Json(filters)
+}
+
+async fn change_filter(Json(change): Json<LogFilterChange>) -> impl IntoResponse {
+    let service = change.service.clone();
+    match apply(&service, change).await {
+        Ok(status) => Json(status).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("{:#}", e)).into_response(),
+    }
+}
+
+/// The launcher's own filter is changed in place, the others through their
+/// RPC.
+async fn apply(service: &str, change: LogFilterChange) -> Result<LogFilterStatus> {
+    if !SERVICES.contains(&service) {
+        return Err(anyhow!("Unknown service {}", service));
+    }
+    if service == "launcher" {
+        return LOG_FILTER.apply(&change);
+    }
+    let vehicle_id = &DeviceIdentity::get(&CFG.base).vehicle_id;
+    let monitor = MQTT_MONITOR
+        .get()
+        .ok_or_else(|| anyhow!("MQTT monitor not started"))?;
+    let client = monitor.client.lock().await.clone();
+    client
+        .call(
+            &log_filter::topic(vehicle_id, service),
+            &change,
+            CALL_TIMEOUT,
+        )
+        .await
+}
@@ -1,2 +1,3 @@
 pub mod index_page;
+pub mod log_page;
 pub mod server;
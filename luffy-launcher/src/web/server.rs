@@ -11,9 +11,10 @@ use tower_http::services::ServeDir;
 
 use crate::config::CFG;
 
-use super::index_page;
+use super::{index_page, log_page};
 
 use anyhow::{Context, Result};
+use luffy_common::forward_service;
 
 pub struct WebServer {
     running: Arc<AtomicBool>,
@@ -27,6 +28,7 @@ impl WebServer {
     }
 
     pub async fn start(&self) -> Result<()> {
+        self.running.store(true, Ordering::SeqCst);
         // Get static directory path
         let static_dir = if cfg!(debug_assertions) {
             std::env::current_dir()?
@@ -52,19 +54,22 @@ impl WebServer {
 
         let app = Router::new()
             .merge(index_page::routes().await)
+            .merge(log_page::routes())
             .nest_service("/static", ServeDir::new(&static_dir));
 
         let host = CFG.web.host.clone();
         let port = CFG.web.port;
 
         let listener = tokio::net::TcpListener::bind(format!("{}:{}", host, port)).await?;
-        axum::serve(listener, app).await?;
+        axum::serve(listener, app)
+            .with_graceful_shutdown(Self::shutdown_signal(self.running.clone()))
+            .await?;
 
         Ok(())
     }
 
-    async fn shutdown_signal(&self) {
-        while self.running.load(Ordering::SeqCst) {
+    async fn shutdown_signal(running: Arc<AtomicBool>) {
+        while running.load(Ordering::SeqCst) {
             tokio::time::sleep(Duration::from_millis(100)).await;
         }
     }
@@ -73,3 +78,5 @@ impl WebServer {
         self.running.store(false, Ordering::SeqCst);
     }
 }
+
+forward_service!(WebServer, "web", runs_in_start);
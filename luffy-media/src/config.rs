@@ -1,13 +1,21 @@
 use std::sync::LazyLock;
 
-use luffy_common::config::{BaseConfig, LoadConfig};
+use luffy_common::config::{BaseConfig, LoadConfig, ServiceConfig};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-pub static CONFIG: LazyLock<MediaConfig> =
-    LazyLock::new(|| MediaConfig::load_config("media").expect("Failed to load configuration"));
+/// Loaded at startup; [`ServiceConfig::watch`] follows changes on disk for
+/// settings applied without a restart.
+pub static CONFIG: LazyLock<ServiceConfig<MediaConfig>> = LazyLock::new(|| {
+    // Tests read the development files of the source tree.
+    #[cfg(test)]
+    luffy_common::config::set_config_dir(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../luffy-deploy/config/development"),
+    );
+    MediaConfig::service_config("media").expect("Failed to load configuration")
+});
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct MediaConfig {
     #[serde(flatten)]
     pub base: BaseConfig,
@@ -16,7 +24,7 @@ pub struct MediaConfig {
     pub websocket_port: u16,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct CameraConfig {
     pub id: String,
     pub name: String,
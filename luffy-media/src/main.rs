@@ -2,36 +2,48 @@ use anyhow::Result;
 
 use tracing::info;
 
-use luffy_media::config::CONFIG;
+use luffy_common::metrics::MetricsServer;
+use luffy_common::otel;
+use luffy_common::supervisor::{self, RestartPolicy, Supervisor};
+use luffy_common::{config_check, config_watch, util};
+use luffy_media::config::{MediaConfig, CONFIG};
 use luffy_media::media::service::MEDIA_SERVICE;
 use luffy_media::mqtt::MQTT_HANDLER;
 use luffy_media::ws::WS_SERVER;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    config_check::handle_args::<MediaConfig>("media");
+
     // Initialize logging
     let log_level = &CONFIG.log_level;
-    luffy_common::util::setup_logging(log_level, "media");
+    luffy_common::util::setup_logging(log_level, "media", &CONFIG.base);
     info!("Starting luffy-media...");
 
-    // Create media server
-    let media_task = tokio::spawn(async move { MEDIA_SERVICE.start().await });
-
-    let mqtt_task = tokio::spawn(async move { MQTT_HANDLER.start().await });
-
-    let ws_task = tokio::spawn(async move { WS_SERVER.start().await });
-
-    // Join all tasks and handle any errors
-    let (media_result, mqtt_result, ws_result) = tokio::join!(media_task, mqtt_task, ws_task);
-
-    // Handle results
-    media_result.unwrap()?;
-    mqtt_result.unwrap()?;
-    ws_result.unwrap()?;
-
-    // Keep the main thread alive
-    tokio::signal::ctrl_c().await?;
-    info!("Shutting down...");
-
-    Ok(())
+    tokio::spawn(config_watch::follow(
+        CONFIG.watch(),
+        |old, new| async move {
+            util::reload_log_level(&old.log_level, &new.log_level);
+            MEDIA_SERVICE
+                .reload_cameras(&old.cameras, &new.cameras)
+                .await;
+        },
+    ));
+
+    // Cameras, handlers and the listener are set up once, so a failure
+    // restarts the process.
+    let mut supervisor = Supervisor::new();
+    supervisor
+        .add(&**MEDIA_SERVICE, RestartPolicy::Never)
+        .add(&*MQTT_HANDLER, RestartPolicy::Never)
+        .add(&*WS_SERVER, RestartPolicy::Never);
+    if CONFIG.base.metrics.enable {
+        supervisor.add(
+            MetricsServer::new(CONFIG.base.metrics.clone(), "media"),
+            RestartPolicy::on_failure(),
+        );
+    }
+    let result = supervisor.run(supervisor::shutdown_signal()).await;
+    otel::shutdown();
+    result
 }
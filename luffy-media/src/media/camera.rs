@@ -2,6 +2,7 @@ use crate::config::CameraConfig;
 use crate::ws::WS_SERVER;
 use anyhow::{bail, Result};
 use futures::StreamExt;
+use luffy_common::metrics::{self, IntCounterVec, IntGaugeVec};
 use retina::client::{Credentials, PlayOptions};
 use retina::client::{Session, SessionOptions, SetupOptions};
 use retina::codec::{CodecItem, VideoFrame};
@@ -9,7 +10,8 @@ use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::fmt;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use tracing::{debug, error, info};
 use webrtc::peer_connection::policy::bundle_policy::RTCBundlePolicy;
@@ -22,7 +24,6 @@ use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::peer_connection::RTCPeerConnection;
 use webrtc::rtp_transceiver::RTCPFeedback;
 use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
-use webrtc::track::track_local::TrackLocal;
 use webrtc::{
     api::media_engine::MediaEngine,
     ice_transport::ice_server::RTCIceServer,
@@ -33,12 +34,81 @@ use webrtc::{
     rtp_transceiver::rtp_codec::RTCRtpCodecCapability,
 };
 
+type PendingCandidates = HashMap<String, VecDeque<(String, u32)>>;
+
+static PEERS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    metrics::gauge_vec(
+        "luffy_webrtc_peers",
+        "WebRTC peers connected to a camera",
+        &["camera"],
+    )
+});
+static FRAMES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    metrics::counter_vec(
+        "luffy_camera_frames_total",
+        "Video frames received from a camera",
+        &["camera"],
+    )
+});
+static BYTES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    metrics::counter_vec(
+        "luffy_camera_bytes_total",
+        "Video bytes received from a camera",
+        &["camera"],
+    )
+});
+static BITRATE: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    metrics::gauge_vec(
+        "luffy_camera_bitrate_bps",
+        "Bitrate of a camera's stream over the last second",
+        &["camera"],
+    )
+});
+
+/// Counts the frames of a stream and measures its bitrate.
+struct StreamMeter {
+    camera: String,
+    since: Instant,
+    bytes: u64,
+}
+
+impl StreamMeter {
+    fn new(camera: &str) -> Self {
+        Self {
+            camera: camera.to_string(),
+            since: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    fn frame(&mut self, len: usize) {
+        let labels = [self.camera.as_str()];
+        FRAMES.with_label_values(&labels).inc();
+        BYTES.with_label_values(&labels).inc_by(len as u64);
+        self.bytes += len as u64;
+        let elapsed = self.since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            BITRATE
+                .with_label_values(&labels)
+                .set((self.bytes as f64 * 8.0 / elapsed.as_secs_f64()) as i64);
+            self.since = Instant::now();
+            self.bytes = 0;
+        }
+    }
+}
+
+impl Drop for StreamMeter {
+    fn drop(&mut self) {
+        BITRATE.with_label_values(&[self.camera.as_str()]).set(0);
+    }
+}
+
 #[derive(Clone)]
 pub struct Camera {
     config: CameraConfig,
     pub running: Arc<AtomicBool>,
     pub peer_connections: Arc<Mutex<HashMap<String, Arc<RTCPeerConnection>>>>,
-    pending_candidates: Arc<Mutex<HashMap<String, VecDeque<(String, u32)>>>>,
+    pending_candidates: Arc<Mutex<PendingCandidates>>,
     video_tracks: Arc<Mutex<HashMap<String, Arc<TrackLocalStaticSample>>>>,
 }
 
@@ -135,9 +205,16 @@ impl Camera {
             }
         }
         peers.clear();
+        self.count_peers(&peers);
         Ok(())
     }
 
+    fn count_peers(&self, peers: &HashMap<String, Arc<RTCPeerConnection>>) {
+        PEERS
+            .with_label_values(&[self.id()])
+            .set(peers.len() as i64);
+    }
+
     pub async fn add_peer(&self, peer_id: &str) -> Result<()> {
         let config = RTCConfiguration {
             ice_servers: vec![RTCIceServer {
@@ -154,16 +231,21 @@ impl Camera {
                 .await?,
         );
 
-        self.peer_connections
-            .lock()
-            .await
-            .insert(peer_id.to_string(), peer_connection);
+        let mut peers = self.peer_connections.lock().await;
+        peers.insert(peer_id.to_string(), peer_connection);
+        self.count_peers(&peers);
 
         Ok(())
     }
 
     pub async fn remove_peer(&self, peer_id: &str) -> Result<()> {
-        if let Some(peer) = self.peer_connections.lock().await.remove(peer_id) {
+        let peer = {
+            let mut peers = self.peer_connections.lock().await;
+            let peer = peers.remove(peer_id);
+            self.count_peers(&peers);
+            peer
+        };
+        if let Some(peer) = peer {
             if let Err(e) = peer.close().await {
                 error!("Error closing peer connection: {}", e);
             }
@@ -221,10 +303,12 @@ impl Camera {
         }
         drop(tracks);
 
+        let mut meter = StreamMeter::new(camera_id);
         while let Some(frame) = frames.next().await {
             match frame {
                 Ok(CodecItem::VideoFrame(video_frame)) => {
                     let frame_data = Self::convert_h264(video_frame)?;
+                    meter.frame(frame_data.len());
 
                     let sample = Sample {
                         data: frame_data.into(),
@@ -287,10 +371,11 @@ impl Camera {
         };
 
         // Store peer connection before setting descriptions
-        self.peer_connections
-            .lock()
-            .await
-            .insert(request_id.clone(), peer_connection.clone());
+        {
+            let mut peers = self.peer_connections.lock().await;
+            peers.insert(request_id.clone(), peer_connection.clone());
+            self.count_peers(&peers);
+        }
 
         // Create video track
         let video_track: Arc<TrackLocalStaticSample> = Arc::new(TrackLocalStaticSample::new(
@@ -514,7 +599,9 @@ impl Camera {
         // Remove and close peer connection
         let peer = {
             let mut peers = self.peer_connections.lock().await;
-            peers.remove(request_id)
+            let peer = peers.remove(request_id);
+            self.count_peers(&peers);
+            peer
         };
 
         if let Some(peer) = peer {
@@ -1,4 +1,5 @@
 use anyhow::Result;
+use luffy_common::forward_service;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::future::Future;
@@ -8,7 +9,7 @@ use std::sync::{Arc, LazyLock};
 use tokio::sync::Mutex;
 use tracing::{debug, error, info};
 
-use crate::config::CONFIG;
+use crate::config::{CameraConfig, CONFIG};
 use crate::media::camera::Camera;
 
 pub static MEDIA_SERVICE: LazyLock<Arc<MediaService>> = LazyLock::new(|| {
@@ -44,6 +45,25 @@ pub enum WebRTCResponse {
     },
 }
 
+/// Cameras to remove and to add to go from `old` to `new`; a changed camera
+/// is removed and added again.
+pub fn camera_changes(
+    old: &[CameraConfig],
+    new: &[CameraConfig],
+) -> (Vec<String>, Vec<CameraConfig>) {
+    let removed = old
+        .iter()
+        .filter(|camera| !new.contains(camera))
+        .map(|camera| camera.id.clone())
+        .collect();
+    let added = new
+        .iter()
+        .filter(|camera| !old.contains(camera))
+        .cloned()
+        .collect();
+    (removed, added)
+}
+
 #[derive(Debug)]
 pub struct MediaService {
     cameras: Arc<Mutex<HashMap<String, Arc<Camera>>>>,
@@ -83,7 +103,7 @@ impl MediaService {
     }
 
     // Camera management
-    pub async fn add_camera(&self, camera_config: crate::config::CameraConfig) -> Result<()> {
+    pub async fn add_camera(&self, camera_config: CameraConfig) -> Result<()> {
         info!(
             "Adding camera {} with pipeline {}",
             camera_config.id.clone(),
@@ -96,6 +116,24 @@ impl MediaService {
         Ok(())
     }
 
+    /// Apply a change to the configured cameras without touching the
+    /// cameras that stayed the same.
+    pub async fn reload_cameras(&self, old: &[CameraConfig], new: &[CameraConfig]) {
+        let (removed, added) = camera_changes(old, new);
+        for id in removed {
+            info!("Removing camera {} after config change", id);
+            if let Err(e) = self.remove_camera(&id).await {
+                error!("Failed to remove camera {}: {}", id, e);
+            }
+        }
+        for camera in added {
+            let id = camera.id.clone();
+            if let Err(e) = self.add_camera(camera).await {
+                error!("Failed to add camera {}: {}", id, e);
+            }
+        }
+    }
+
     pub async fn remove_camera(&self, camera_id: &str) -> Result<()> {
         let mut cameras = self.cameras.lock().await;
         if let Some(camera) = cameras.remove(camera_id) {
@@ -114,6 +152,16 @@ impl MediaService {
         cameras.keys().cloned().collect()
     }
 
+    /// WebRTC peers connected across all cameras.
+    pub async fn peer_count(&self) -> usize {
+        let cameras: Vec<Arc<Camera>> = self.cameras.lock().await.values().cloned().collect();
+        let mut count = 0;
+        for camera in cameras {
+            count += camera.peer_connections.lock().await.len();
+        }
+        count
+    }
+
     // WebRTC handling
     pub async fn handle_webrtc_message(&self, connection_id: &str, message: &str) -> Result<()> {
         info!("Handling WebRTC message, connection_id: {}", connection_id);
@@ -158,3 +206,5 @@ impl MediaService {
         action.await
     }
 }
+
+forward_service!(&'static MediaService, "media");
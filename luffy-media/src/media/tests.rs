@@ -28,7 +28,7 @@ async fn setup_test() -> Result<Camera> {
     };
 
     // Create and return camera instance
-    Ok(Camera::new(config).await?)
+    Camera::new(config).await
 }
 
 #[tokio::test]
@@ -73,3 +73,29 @@ async fn test_webrtc_peer_creation() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_camera_changes() {
+    let camera = |id: &str, url: &str| crate::config::CameraConfig {
+        id: id.to_string(),
+        name: id.to_string(),
+        url: url.to_string(),
+        username: "admin".to_string(),
+        password: "mt000000".to_string(),
+    };
+    let old = vec![
+        camera("deck", "rtsp://10.0.0.1/deck"),
+        camera("engine", "rtsp://10.0.0.1/engine"),
+        camera("bow", "rtsp://10.0.0.1/bow"),
+    ];
+    let new = vec![
+        camera("deck", "rtsp://10.0.0.1/deck"),
+        camera("engine", "rtsp://10.0.0.2/engine"),
+        camera("stern", "rtsp://10.0.0.1/stern"),
+    ];
+
+    let (removed, added) = super::service::camera_changes(&old, &new);
+    assert_eq!(removed, vec!["engine", "bow"]);
+    let added: Vec<&str> = added.iter().map(|camera| camera.id.as_str()).collect();
+    assert_eq!(added, vec!["engine", "stern"]);
+}
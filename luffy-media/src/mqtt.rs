@@ -1,6 +1,15 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use luffy_common::config_update;
+use luffy_common::health::{HealthContributor, HealthFields, Liveness};
+use luffy_common::identity::DeviceIdentity;
+use luffy_common::iot::backend;
 use luffy_common::iot::local::LocalIotClient;
 use luffy_common::iot::remote::RemoteIotClient;
+use luffy_common::iot::router::Message;
+use luffy_common::log_filter;
+use luffy_common::logging;
+use luffy_common::provisioning::{self, CertificateWatcher};
+use luffy_common::{forward_service, supervisor};
 use serde_json::json;
 use std::sync::Arc;
 use std::sync::LazyLock;
@@ -8,7 +17,7 @@ use tokio::sync::Mutex;
 
 use tracing::{error, info};
 
-use crate::config::CONFIG;
+use crate::config::{MediaConfig, CONFIG};
 use crate::media::service::MEDIA_SERVICE;
 
 pub static MQTT_HANDLER: LazyLock<MqttHandler> = LazyLock::new(MqttHandler::new);
@@ -17,6 +26,8 @@ pub struct MqttHandler {
     remote_client: Arc<Mutex<RemoteIotClient>>,
     vehicle_id: String,
     local_client: Arc<Mutex<LocalIotClient>>,
+    /// Probes of both links' event loops.
+    liveness: Vec<Liveness>,
 }
 
 impl Default for MqttHandler {
@@ -27,54 +38,85 @@ impl Default for MqttHandler {
 
 impl MqttHandler {
     pub fn new() -> Self {
-        let vehicle_id = CONFIG.base.vehicle_id.clone();
-        let remote_client = Arc::new(Mutex::new(RemoteIotClient::new(
-            MqttHandler::on_message,
-            vehicle_id.clone(),
-            CONFIG.base.aws.iot.endpoint.clone(),
-            CONFIG.base.aws.iot.port,
-        )));
-
-        let local_client = Arc::new(Mutex::new(LocalIotClient::new(
+        let identity = DeviceIdentity::get(&CONFIG.base);
+        let vehicle_id = identity.vehicle_id.clone();
+        let remote = RemoteIotClient::new(vehicle_id.clone(), backend::from_config(&CONFIG.base))
+            .with_client_id(identity.client_id("media"));
+
+        let local = LocalIotClient::new(
             "media".to_string(),
             CONFIG.base.mqtt_host.clone(),
             CONFIG.base.mqtt_port,
-            None,
             CONFIG.base.health_report_interval,
             env!("CARGO_PKG_VERSION").to_string(),
-        )));
+        )
+        .with_client_id(DeviceIdentity::get(&CONFIG.base).client_id("media"));
 
         MqttHandler {
-            remote_client,
+            liveness: vec![remote.liveness(), local.liveness()],
+            remote_client: Arc::new(Mutex::new(remote)),
             vehicle_id,
-            local_client,
+            local_client: Arc::new(Mutex::new(local)),
         }
     }
 
+    pub fn health(&self) -> Option<Arc<dyn HealthContributor>> {
+        Some(Arc::new(self.liveness.clone()))
+    }
+
     pub async fn start(&self) -> Result<()> {
         info!("Starting MQTT handler...");
         let mut remote = self.remote_client.lock().await;
         remote.start().await?;
+        logging::ship(&remote);
+        if CONFIG.base.certificate.enable {
+            if let (Some(store), Some(reconnector)) = (remote.credentials(), remote.reconnector()) {
+                tokio::spawn(
+                    CertificateWatcher::new(
+                        CONFIG.base.certificate.clone(),
+                        store,
+                        reconnector,
+                        DeviceIdentity::get(&CONFIG.base).clone(),
+                    )
+                    .run(),
+                );
+            }
+        }
         remote
-            .subscribe(&format!("{}/webrtc/request/#", self.vehicle_id))
+            .on(
+                &format!("{}/webrtc/request/#", self.vehicle_id),
+                Self::handle_webrtc_request,
+            )
             .await?;
         let mut local = self.local_client.lock().await;
+        local.add_health_fields(|| async {
+            HealthFields::from([(
+                "webrtc_peers".to_string(),
+                json!(MEDIA_SERVICE.peer_count().await),
+            )])
+        });
+        local.add_health_fields(provisioning::health_fields);
+        local.add_health_fields(supervisor::health_fields);
         local.connect().await?;
+        config_update::serve_validation::<MediaConfig>(&local, &self.vehicle_id, "media").await?;
+        log_filter::serve(&local, &self.vehicle_id, "media").await?;
         Ok(())
     }
 
-    fn on_message(topic: String, payload: String) {
-        tokio::spawn(async move {
-            Self::handle_message(topic, payload).await;
-        });
+    pub async fn stop(&self) {
+        info!("Stopping MQTT handler...");
+        self.remote_client.lock().await.stop().await;
+        if let Err(e) = self.local_client.lock().await.disconnect().await {
+            error!("Failed to disconnect local MQTT client: {}", e);
+        }
     }
 
-    async fn handle_message(topic: String, payload: String) {
-        if topic.contains("/webrtc/request/") {
-            if let Err(e) = MEDIA_SERVICE.handle_webrtc_message("mqtt", &payload).await {
-                error!("Failed to handle WebRTC request: {}", e);
-            }
-        }
+    async fn handle_webrtc_request(message: Message) -> Result<()> {
+        let payload = message.text().context("WebRTC request is not UTF-8")?;
+        MEDIA_SERVICE
+            .handle_webrtc_message("mqtt", payload)
+            .await
+            .context("Failed to handle WebRTC request")
     }
 
     pub async fn send_webrtc_response(
@@ -137,10 +179,12 @@ impl MqttHandler {
     }
 }
 
+forward_service!(&'static MqttHandler, "mqtt", health);
+
 pub async fn init_mqtt() -> Result<()> {
     if let Err(e) = MQTT_HANDLER.start().await {
         error!("Failed to start MQTT handler: {}", e);
-        return Err(e.into());
+        return Err(e);
     }
     Ok(())
 }
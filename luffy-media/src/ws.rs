@@ -11,6 +11,8 @@ use std::{collections::HashMap, sync::LazyLock};
 use tokio::sync::Mutex;
 use tracing::{debug, error, info};
 
+use luffy_common::forward_service;
+
 use crate::{config::CONFIG, media::service::MEDIA_SERVICE};
 
 pub static WS_SERVER: LazyLock<WebSocketServer> = LazyLock::new(|| WebSocketServer {
@@ -47,6 +49,10 @@ impl WebSocketServer {
         Ok(())
     }
 
+    pub async fn stop(&self) {
+        // The listener runs until the process exits.
+    }
+
     async fn handle_socket(&self, socket: WebSocket) {
         let (ws_sink, mut ws_stream) = socket.split();
         debug!("New WebSocket connection established");
@@ -123,3 +129,5 @@ impl WebSocketServer {
             .await
     }
 }
+
+forward_service!(&'static WebSocketServer, "websocket");